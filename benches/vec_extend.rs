@@ -0,0 +1,40 @@
+#![feature(test)]
+
+extern crate allocoll;
+extern crate test;
+
+use allocoll::alloc::DefaultAlloc;
+use allocoll::vec::Vec;
+use test::Bencher;
+
+const N: usize = 64 * 1024;
+
+#[bench]
+fn push_loop(b: &mut Bencher) {
+    let src = vec![7u8; N];
+    b.iter(|| {
+        let mut v: Vec<u8> = Vec::with_alloc(DefaultAlloc);
+        for &x in &src { v.push(x); }
+        test::black_box(&v);
+    });
+}
+
+#[bench]
+fn extend_from_slice(b: &mut Bencher) {
+    let src = vec![7u8; N];
+    b.iter(|| {
+        let mut v: Vec<u8> = Vec::with_alloc(DefaultAlloc);
+        v.extend_from_slice(&src);
+        test::black_box(&v);
+    });
+}
+
+#[bench]
+fn extend_from_slice_chunks(b: &mut Bencher) {
+    let src = vec![7u8; N];
+    b.iter(|| {
+        let mut v: Vec<u8> = Vec::with_alloc(DefaultAlloc);
+        for c in src.chunks(4096) { v.extend_from_slice(c); }
+        test::black_box(&v);
+    });
+}
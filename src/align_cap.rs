@@ -0,0 +1,147 @@
+// Serving alignments the backend cannot.
+//
+// Plain `malloc`, `HeapAlloc` and many C allocators only promise
+// alignment up to some small bound (8 or 16 bytes), yet `Kind`s for
+// cache-line or SIMD data ask for 64 or more. `align_cap::Alloc<A>`
+// declares the bound of the backend `A` and deals with every request
+// above it according to its `Mode`:
+//
+//  * `Reject` returns null (`AllocError::Unsupported`), so callers
+//    find out instead of getting a misaligned block;
+//  * `Emulate` over-allocates by the alignment, returns the first
+//    suitably aligned address inside, and stores the backend's pointer
+//    in the word just before it, to be found again at `dealloc`.
+//
+// Requests within the bound pass straight through. Emulated blocks
+// never grow in place, and `realloc` moves them with a copy, since the
+// backend might return a block whose aligned offset differs. The same
+// goes for a zero-sized block (which is never emulated) being resized
+// to an over-aligned size: it has no header yet, so it is copied into
+// a fresh emulated block rather than handed to the backend.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use std::cmp;
+use std::mem;
+use std::ptr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Reject,
+    Emulate,
+}
+
+pub struct Alloc<A> {
+    inner: A,
+    max_align: usize,
+    mode: Mode,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Wraps `inner`, which aligns its blocks to at least `max_align`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_align` is not a power of two or is smaller than
+    /// a pointer (emulation stores one in front of each block).
+    pub fn new(inner: A, max_align: usize, mode: Mode) -> Alloc<A> {
+        assert!(max_align.is_power_of_two() && max_align >= mem::size_of::<usize>(),
+                "align_cap: unusable alignment bound {}", max_align);
+        Alloc { inner: inner, max_align: max_align, mode: mode }
+    }
+
+    pub fn max_align(&self) -> usize { self.max_align }
+
+    pub fn mode(&self) -> Mode { self.mode }
+
+    pub fn into_inner(self) -> A { self.inner }
+
+    fn over_aligned(&self, kind: Kind) -> bool {
+        kind.align() > self.max_align && !kind.is_zero_sized()
+    }
+
+    // The backend block behind an emulated block of `kind`, or `None`
+    // if the padded size overflows.
+    fn padded(&self, kind: Kind) -> Option<Kind> {
+        kind.size().checked_add(kind.align())
+            .map(|size| unsafe { Kind::from_size_align(size, self.max_align) })
+    }
+
+    unsafe fn alloc_emulated(&mut self, kind: Kind) -> Address {
+        let padded = match self.padded(kind) { Some(k) => k, None => return ptr::null_mut() };
+        let raw = self.inner.alloc(padded);
+        if raw.is_null() { return raw; }
+        // `raw` and the alignment are both multiples of `max_align`,
+        // so this is at least `max_align` bytes in: room for the word.
+        let aligned = ((raw as usize + kind.align()) & !(kind.align() - 1)) as Address;
+        *(aligned as *mut Address).offset(-1) = raw;
+        aligned
+    }
+
+    unsafe fn dealloc_emulated(&mut self, ptr: Address, kind: Kind) {
+        let raw = *(ptr as *mut Address).offset(-1);
+        self.inner.dealloc(raw, self.padded(kind).unwrap());
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn oom(&mut self) -> ! { self.inner.oom() }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if !self.over_aligned(kind) { return self.inner.alloc(kind); }
+        match self.mode {
+            Mode::Reject => ptr::null_mut(),
+            Mode::Emulate => self.alloc_emulated(kind),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if self.over_aligned(kind) { self.dealloc_emulated(ptr, kind) } else { self.inner.dealloc(ptr, kind) }
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        if self.over_aligned(kind) { self.dealloc_emulated(ptr, kind) } else { self.inner.dealloc_hot(ptr, kind) }
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        if self.over_aligned(kind) { kind.size() } else { self.inner.usable_size(kind) }
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        if !self.over_aligned(kind) { return self.inner.alloc_error(kind); }
+        match self.mode {
+            Mode::Reject => AllocError::Unsupported,
+            Mode::Emulate if self.padded(kind).is_none() => AllocError::CapacityOverflow,
+            Mode::Emulate => self.inner.alloc_error(self.padded(kind).unwrap()),
+        }
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        if self.over_aligned(Kind::from_size_align(new_size, kind.align())) {
+            return Err(AllocError::Unsupported);
+        }
+        self.inner.grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        if self.over_aligned(kind) { return Err(AllocError::Unsupported); }
+        self.inner.shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        if !self.over_aligned(kind) && !self.over_aligned(new_kind) {
+            return self.inner.realloc(ptr, kind, new_size);
+        }
+        let new_ptr = self.alloc(new_kind);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(kind.size(), new_size));
+            self.dealloc(ptr, kind);
+        }
+        new_ptr
+    }
+
+    fn compatible_with(&self, other: &Self) -> bool {
+        self.max_align == other.max_align && self.inner.compatible_with(&other.inner)
+    }
+}
@@ -0,0 +1,48 @@
+// Over-aligned values.
+//
+// `CacheAligned<T>` places a `T` at the start of its own cache line,
+// so that two of them (say, per-thread counters in an array) never
+// share a line and never false-share. The alignment comes from a
+// zero-length array of a 64-byte SIMD type, which costs no space.
+//
+// For buffers rather than single values, see
+// `Kind::new_over_aligned` and `Alloc::alloc_array_aligned_to`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Size of a cache line on the targets we care about.
+pub const CACHE_LINE: usize = 64;
+
+#[repr(simd)]
+#[derive(Copy, Clone)]
+struct Line(u64, u64, u64, u64, u64, u64, u64, u64);
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheAligned<T> {
+    _align: [Line; 0],
+    value: T,
+}
+
+impl<T> CacheAligned<T> {
+    pub fn new(value: T) -> CacheAligned<T> {
+        CacheAligned { _align: [], value: value }
+    }
+
+    pub fn into_inner(self) -> T { self.value }
+}
+
+impl<T> Deref for CacheAligned<T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.value }
+}
+
+impl<T> DerefMut for CacheAligned<T> {
+    fn deref_mut(&mut self) -> &mut T { &mut self.value }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CacheAligned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.value, f)
+    }
+}
@@ -1,15 +1,65 @@
 use std::cmp;
+use std::fmt;
 use std::mem;
 use std::ptr::{self, Unique};
+use std::rc::Rc;
+use std::sync::Arc;
 
 use alloc_crate::heap;
 
+use boxed::Box;
+
 pub type Size = usize;
 pub type Capacity = usize;
 pub type Alignment = usize;
 
 pub type Address = *mut u8;
-pub struct Excess(Address, Capacity);
+
+/// An `Address` known not to be null, as returned by `alloc_nonnull`.
+///
+/// It becomes a raw pointer only through `as_ptr` and `cast`, which
+/// keep the pointer's provenance; there is deliberately no way to make
+/// one from an integer, nor to turn one into an integer other than to
+/// test its alignment.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NonNullAddress(Address);
+
+impl NonNullAddress {
+    pub fn new(p: Address) -> Option<NonNullAddress> {
+        if p.is_null() { None } else { Some(NonNullAddress(p)) }
+    }
+
+    pub unsafe fn new_unchecked(p: Address) -> NonNullAddress {
+        debug_assert!(!p.is_null());
+        NonNullAddress(p)
+    }
+
+    /// The block behind a container's `Unique` pointer, which is
+    /// never null.
+    pub fn from_unique<T>(p: &Unique<T>) -> NonNullAddress {
+        NonNullAddress(**p as Address)
+    }
+
+    pub fn as_ptr(self) -> Address { self.0 }
+
+    pub fn cast<T>(self) -> *mut T { self.0 as *mut T }
+
+    pub unsafe fn offset(self, count: isize) -> NonNullAddress {
+        NonNullAddress(self.0.offset(count))
+    }
+
+    pub fn is_aligned_to(self, align: Alignment) -> bool {
+        self.0 as usize & (align - 1) == 0
+    }
+}
+/// A block together with how many bytes it can really hold (at least
+/// the size asked for), as returned by `alloc_excess`/`realloc_excess`.
+pub struct Excess(pub Address, pub Capacity);
+
+impl Excess {
+    /// The block, or `None` if the request failed.
+    pub fn block(&self) -> Option<NonNullAddress> { NonNullAddress::new(self.0) }
+}
 
 /// Category for a memory record.
 ///
@@ -30,6 +80,30 @@ impl Kind {
     pub fn size(&self) -> usize { self.size }
 
     pub fn align(&self) -> usize { self.align }
+
+    /// Returns a non-null address aligned to `self.align()` that
+    /// points at no allocation. Allocators hand this out for
+    /// zero-sized kinds, and containers use it to mean "nothing
+    /// allocated yet".
+    pub fn dangling(&self) -> Address { self.align as Address }
+
+    pub fn is_zero_sized(&self) -> bool { self.size == 0 }
+
+    /// The largest size a kind with this alignment can have: rounded
+    /// up to the alignment it must still fit in an `isize`. Allocators
+    /// can compare against this to reject oversized requests before
+    /// doing any arithmetic on them.
+    pub fn max_size_for_align(&self) -> usize {
+        ::std::isize::MAX as usize - (self.align - 1)
+    }
+}
+
+// Renders as e.g. "24 bytes, align 8", for messages from wrapper
+// allocators.
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes, align {}", self.size, self.align)
+    }
 }
 
 
@@ -44,10 +118,6 @@ impl Kind {
         Kind { size: size, align: align }
     }
 
-    unsafe fn from_size_align(size: usize, align: usize) -> Kind {
-        Kind { size: size, align: align }
-    }
-
 }
 
 // public constructor methods
@@ -57,6 +127,28 @@ impl Kind {
         Kind::new_internal::<T>()
     }
 
+    /// Creates a `Kind` from a raw size and alignment.
+    ///
+    /// Unsafe because `align` must be a power of two and `size`,
+    /// rounded up to `align`, must not overflow.
+    pub unsafe fn from_size_align(size: usize, align: usize) -> Kind {
+        let kind = Kind { size: size, align: align };
+        check_kind(kind, "Kind::from_size_align");
+        kind
+    }
+
+    /// Creates a `Kind` for a single `T` aligned to at least `align`
+    /// bytes (e.g. 64 for a cache line, 4096 for a page).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn new_over_aligned<T>(align: usize) -> Kind {
+        assert!(align.is_power_of_two(), "alignment {} is not a power of two", align);
+        let (size, min_align) = size_align::<T>();
+        Kind { size: size, align: cmp::max(align, min_align) }
+    }
+
     pub unsafe fn for_value<T: ?Sized>(t: &T) -> Kind {
         Kind::from_size_align(mem::size_of_val(t), mem::align_of_val(t))
     }
@@ -98,6 +190,21 @@ impl Kind {
         }
     }
 
+    /// Creates a `Kind` whose size is that of `self` rounded up to a
+    /// multiple of `granule` (e.g. a page size from
+    /// `Granularity::page()`). The alignment is unchanged; combine
+    /// with `align_to` for blocks that must also start on a boundary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `granule` is not a power of two, or if the rounded
+    /// size overflows.
+    pub fn round_up_to(self, granule: usize) -> Kind {
+        assert!(granule.is_power_of_two(), "granule {} is not a power of two", granule);
+        let size = self.size.checked_add(granule - 1).expect("Kind::round_up_to: size overflow");
+        Kind { size: size & !(granule - 1), ..self }
+    }
+
     /// Returns the amount of padding we must insert after `self`
     /// to ensure that the following address will satisfy `align`.
     ///
@@ -138,6 +245,16 @@ impl Kind {
         Kind { size: padded_size * n, align: self.align }
     }
 
+    /// Like `array`, but returns `None` if the size overflows or
+    /// exceeds `max_size_for_align`.
+    pub fn checked_array(self, n: usize) -> Option<Kind> {
+        let padded_size = self.size + self.pad_to(self.align);
+        match padded_size.checked_mul(n) {
+            Some(size) if size <= self.max_size_for_align() => Some(Kind { size: size, align: self.align }),
+            _ => None,
+        }
+    }
+
     /// Creates a `Kind` describing the record for `n` instances of
     /// `self`, with no padding between each.
     pub fn array_packed(self, n: usize) -> Kind {
@@ -145,8 +262,62 @@ impl Kind {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct AllocError;
+/// Why an allocation request failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocError {
+    /// The allocator, or the memory beneath it, has run out.
+    Exhausted,
+    /// The allocator cannot serve this kind at all, e.g. because its
+    /// alignment is larger than the allocator supports.
+    Unsupported,
+    /// The requested size could not be computed without overflow.
+    CapacityOverflow,
+    /// A budget (see `quota::Alloc`) refused the request.
+    QuotaExceeded,
+    /// A rate limit (see `throttle::Alloc`) refused the request.
+    Throttled,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            AllocError::Exhausted => "memory exhausted",
+            AllocError::Unsupported => "allocation kind not supported",
+            AllocError::CapacityOverflow => "capacity overflow",
+            AllocError::QuotaExceeded => "allocation quota exceeded",
+            AllocError::Throttled => "allocation rate limit exceeded",
+        })
+    }
+}
+
+impl ::std::error::Error for AllocError {
+    fn description(&self) -> &str { "allocation failed" }
+}
+
+/// Marker for types whose values are plain data: an allocator can hand
+/// out uninitialized memory for them (via `alloc_one`/`alloc_array`)
+/// without any registration of the value with a tracing collector or
+/// drop machinery.
+///
+/// Every type is `Raw` by default. A type opts out by embedding a
+/// `NotRaw` field, or via `not_raw!(Type)`; such types can still be
+/// allocated, but only through `alloc_one_init`, which never exposes
+/// memory that does not already hold a value.
+pub unsafe trait Raw { }
+
+unsafe impl Raw for .. { }
+
+/// A zero-sized marker that makes the containing type `!Raw`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotRaw;
+
+impl !Raw for NotRaw { }
+
+/// Declares that the named (non-generic) type is not `Raw`.
+#[macro_export]
+macro_rules! not_raw {
+    ($($t:ty),*) => { $(impl !$crate::alloc::Raw for $t { })* }
+}
 
 // See https://github.com/pnkfelix/rfcs/blob/fsk-allocator-rfc/active/0000-allocator.md
 // for tons of documentation for the old API.
@@ -158,26 +329,146 @@ pub trait Alloc {
     unsafe fn alloc(&mut self, kind: Kind) -> Address;
     unsafe fn dealloc(&mut self, ptr: Address, kind: Kind);
 
+    /// Like `dealloc`, but hints that a block of the same `kind` is
+    /// likely to be requested again soon (e.g. a buffer shrunk to
+    /// nothing that is about to regrow). Caching allocators may keep
+    /// the block at the front of their free lists and skip poisoning
+    /// it; the default simply deallocates.
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.dealloc(ptr, kind)
+    }
+
+    /// Returns how many bytes a block allocated for `kind` can really
+    /// hold. A block may later be passed to `dealloc`/`realloc` with
+    /// any size between `kind.size()` and this value (same alignment).
     unsafe fn usable_size(&self, kind: Kind) -> Capacity {
         SuperAlloc::usable_size(self, kind)
     }
 
-    unsafe fn alloc_one<T>(&mut self) -> Result<Unique<T>, AllocError> {
+    /// Attempts to extend the block at `ptr` to `new_size` bytes
+    /// without moving it. On success the block may afterwards be
+    /// treated as allocated with `new_size`; on failure nothing has
+    /// changed. The default only succeeds within `usable_size`.
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        check_block(ptr, kind, "Alloc::grow_in_place");
+        check_contract(new_size >= kind.size(), "Alloc::grow_in_place", "the new size is smaller than the block");
+        if new_size <= self.usable_size(kind) { Ok(()) } else { Err(AllocError::Exhausted) }
+    }
+
+    /// Attempts to shrink the block at `ptr` to `new_size` bytes
+    /// (nonzero, at most `kind.size()`) without moving it, handing the
+    /// tail back where the allocator can, e.g. by unmapping whole
+    /// pages. On success the block may afterwards be treated as
+    /// allocated with `new_size`; on failure nothing has changed. The
+    /// default only succeeds when `new_size` still lands in the same
+    /// size class, so nothing is actually released.
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        check_block(ptr, kind, "Alloc::shrink_in_place");
+        check_contract(new_size != 0 && new_size <= kind.size(), "Alloc::shrink_in_place",
+              "the new size must be nonzero and no larger than the block");
+        let smaller = Kind::from_size_align(new_size, kind.align());
+        if self.usable_size(smaller) >= kind.size() { Ok(()) } else { Err(AllocError::Unsupported) }
+    }
+
+    /// Like `alloc`, but reports failure as an error (see
+    /// `alloc_error`) instead of a null pointer.
+    unsafe fn alloc_nonnull(&mut self, kind: Kind) -> Result<NonNullAddress, AllocError> {
+        let p = self.alloc(kind);
+        match NonNullAddress::new(p) {
+            Some(p) => Ok(p),
+            None => Err(self.alloc_error(kind)),
+        }
+    }
+
+    unsafe fn dealloc_nonnull(&mut self, ptr: NonNullAddress, kind: Kind) {
+        check_block(ptr.as_ptr(), kind, "Alloc::dealloc_nonnull");
+        self.dealloc(ptr.as_ptr(), kind)
+    }
+
+    /// Like `realloc`, but reports failure as an error instead of a
+    /// null pointer; the block is left as it was then.
+    unsafe fn realloc_nonnull(&mut self, ptr: NonNullAddress, kind: Kind, new_size: Size)
+                              -> Result<NonNullAddress, AllocError> {
+        let p = self.realloc(ptr.as_ptr(), kind, new_size);
+        match NonNullAddress::new(p) {
+            Some(p) => Ok(p),
+            None => Err(self.alloc_error(Kind { size: new_size, ..kind })),
+        }
+    }
+
+    /// Explains why the most recent `alloc`/`realloc` of `kind`
+    /// returned null. Wrappers that refuse requests themselves report
+    /// their own reason; the default assumes memory ran out.
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        let _ = kind;
+        AllocError::Exhausted
+    }
+
+    // The generic helpers below are `where Self: Sized` so that
+    // `Alloc` stays object safe: `&mut Alloc` and `Box<Alloc>` are
+    // allocators too, for choosing one at runtime.
+
+    /// Whether memory obtained from `self` may be returned through
+    /// `other`, as when one container takes over another's buffer.
+    /// The default answers `false`, which is always safe; stateless
+    /// allocators and handles to one shared instance override it.
+    fn compatible_with(&self, other: &Self) -> bool where Self: Sized {
+        let _ = other;
+        false
+    }
+
+    unsafe fn alloc_one<T: Raw>(&mut self) -> Result<Unique<T>, AllocError> where Self: Sized {
         SuperAlloc::alloc_one(self)
     }
 
-    unsafe fn dealloc_one<T>(&mut self, ptr: Unique<T>) {
+    fn alloc_one_init<T>(&mut self, value: T) -> Result<Box<T, &mut Self>, T> where Self: Sized {
+        SuperAlloc::alloc_one_init(self, value)
+    }
+
+    unsafe fn dealloc_one<T>(&mut self, ptr: Unique<T>) where Self: Sized {
         SuperAlloc::dealloc_one(self, ptr)
     }
 
-    unsafe fn alloc_array<T>(&mut self, n: usize) -> Result<Unique<T>, AllocError> {
+    unsafe fn alloc_array<T: Raw>(&mut self, n: usize) -> Result<Unique<T>, AllocError> where Self: Sized {
         SuperAlloc::alloc_array(self, n)
     }
 
+    /// Resizes an array from `alloc_array` from `n_old` to `n_new`
+    /// elements, moving it if need be. On failure the old array is
+    /// left as it was.
+    unsafe fn realloc_array<T: Raw>(&mut self, ptr: Unique<T>, n_old: usize, n_new: usize)
+                                    -> Result<Unique<T>, AllocError> where Self: Sized {
+        SuperAlloc::realloc_array(self, ptr, n_old, n_new)
+    }
+
+    /// Frees an array of `n` elements from `alloc_array` or
+    /// `realloc_array`.
+    unsafe fn dealloc_array<T>(&mut self, ptr: Unique<T>, n: usize) -> Result<(), AllocError> where Self: Sized {
+        SuperAlloc::dealloc_array(self, ptr, n)
+    }
+
+    /// Like `alloc_array`, but the array starts at an address that is
+    /// a multiple of `align` (which must be a power of two). Free it
+    /// with `Kind::new_over_aligned::<T>(align).array_packed(n)`.
+    unsafe fn alloc_array_aligned_to<T: Raw>(&mut self, n: usize, align: usize)
+                                             -> Result<Unique<T>, AllocError> where Self: Sized {
+        let kind = Kind::new_over_aligned::<T>(align).array_packed(n);
+        Ok(Unique::new(try!(self.alloc_nonnull(kind)).cast()))
+    }
+
     unsafe fn alloc_excess(&mut self, kind: Kind) -> Excess {
         SuperAlloc::alloc_excess(self, kind)
     }
 
+    /// Resizes the block at `ptr` to `new_size` bytes, moving it if
+    /// need be, and returns its address.
+    ///
+    /// On failure `realloc` returns null and the block at `ptr` is
+    /// left exactly as it was: still allocated with `kind`, contents
+    /// intact, to be freed by the caller. Implementations must never
+    /// release the old block before the new one is secured; callers
+    /// such as `RawVec::try_reserve` rely on this to keep their data
+    /// when growth is refused.
     unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
         SuperAlloc::realloc(self, ptr, kind, new_size)
     }
@@ -187,11 +478,68 @@ pub trait Alloc {
     }
 }
 
+// Contract checks. With the `checked-alloc` feature, the unsafe entry
+// points of this module, `RawVec` and `Box` assert the preconditions
+// they otherwise take on trust (a non-null pointer aligned for its
+// kind, a power-of-two alignment, a size of at most `isize::MAX`), so
+// a violation panics at the call that made it instead of corrupting
+// the heap later. Without the feature the checks compile to nothing.
+//
+// Whether a block is freed with the `Kind` it was allocated with can
+// only be checked against a table of live blocks; wrap the allocator
+// in `verify::Alloc` for that.
+
+/// Panics, under `checked-alloc`, if `kind` breaks the rules of
+/// `Kind::from_size_align`; `caller` names the entry point.
+#[cfg(feature = "checked-alloc")]
+pub fn check_kind(kind: Kind, caller: &str) {
+    assert!(kind.align.is_power_of_two(),
+            "{}: alignment {} is not a power of two", caller, kind.align);
+    assert!(kind.size <= ::std::isize::MAX as usize,
+            "{}: size {} exceeds isize::MAX", caller, kind.size);
+}
+
+#[cfg(not(feature = "checked-alloc"))]
+#[inline(always)]
+pub fn check_kind(_kind: Kind, _caller: &str) { }
+
+/// Panics, under `checked-alloc`, unless `ptr` could be a block of
+/// `kind`: non-null and, unless `kind` is zero-sized, aligned.
+#[cfg(feature = "checked-alloc")]
+pub fn check_block(ptr: Address, kind: Kind, caller: &str) {
+    check_kind(kind, caller);
+    assert!(!ptr.is_null(), "{}: null pointer for a block of {:?}", caller, kind);
+    assert!(kind.size == 0 || ptr as usize % kind.align == 0,
+            "{}: {:p} is not aligned to {} as {:?} requires", caller, ptr, kind.align, kind);
+}
+
+#[cfg(not(feature = "checked-alloc"))]
+#[inline(always)]
+pub fn check_block(_ptr: Address, _kind: Kind, _caller: &str) { }
+
+/// Panics, under `checked-alloc`, with "`caller`: `what`" unless `ok`.
+#[cfg(feature = "checked-alloc")]
+pub fn check_contract(ok: bool, caller: &str, what: &str) {
+    assert!(ok, "{}: {}", caller, what);
+}
+
+#[cfg(not(feature = "checked-alloc"))]
+#[inline(always)]
+pub fn check_contract(_ok: bool, _caller: &str, _what: &str) { }
+
+fn array_kind<T>(n: usize) -> Result<Kind, AllocError> {
+    Kind::new::<T>().checked_array(n).ok_or(AllocError::CapacityOverflow)
+}
+
 pub trait SuperAlloc {
     unsafe fn usable_size(&self, kind: Kind) -> Capacity;
-    unsafe fn alloc_one<T>(&mut self) -> Result<Unique<T>, AllocError>;
+    unsafe fn alloc_one<T: Raw>(&mut self) -> Result<Unique<T>, AllocError>;
+    fn alloc_one_init<T>(&mut self, value: T) -> Result<Box<T, &mut Self>, T>;
     unsafe fn dealloc_one<T>(&mut self, mut ptr: Unique<T>);
-    unsafe fn alloc_array<T>(&mut self, n: usize) -> Result<Unique<T>, AllocError>;
+    unsafe fn alloc_array<T: Raw>(&mut self, n: usize) -> Result<Unique<T>, AllocError>;
+    unsafe fn realloc_array<T: Raw>(&mut self, ptr: Unique<T>, n_old: usize, n_new: usize)
+                                    -> Result<Unique<T>, AllocError>;
+    unsafe fn dealloc_array<T>(&mut self, ptr: Unique<T>, n: usize) -> Result<(), AllocError>;
     unsafe fn alloc_excess(&mut self, kind: Kind) -> Excess;
     unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address;
     unsafe fn realloc_excess(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Excess;
@@ -202,18 +550,41 @@ impl<Self_:?Sized + Alloc> SuperAlloc for Self_ {
         kind.size
     }
 
-    unsafe fn alloc_one<T>(&mut self) -> Result<Unique<T>, AllocError> {
-        let p = self.alloc(Kind::new::<T>()) as *mut T;
-        if !p.is_null() { Ok(Unique::new(p)) } else { Err(AllocError) }
+    unsafe fn alloc_one<T: Raw>(&mut self) -> Result<Unique<T>, AllocError> {
+        Ok(Unique::new(try!(self.alloc_nonnull(Kind::new::<T>())).cast()))
     }
 
-    unsafe fn dealloc_one<T>(&mut self, mut ptr: Unique<T>) {
-        self.dealloc(ptr.get_mut() as *mut T as *mut u8, Kind::new::<T>());
+    fn alloc_one_init<T>(&mut self, value: T) -> Result<Box<T, &mut Self>, T> {
+        unsafe {
+            let p = self.alloc(Kind::new::<T>()) as *mut T;
+            if p.is_null() { return Err(value); }
+            ptr::write(p, value);
+            Ok(Box::from_raw_alloc(p, self))
+        }
     }
 
-    unsafe fn alloc_array<T>(&mut self, n: usize) -> Result<Unique<T>, AllocError> {
-        let p = self.alloc(Kind::new::<T>().array(n)) as *mut T;
-        if !p.is_null() { Ok(Unique::new(p)) } else { Err(AllocError) }
+    unsafe fn dealloc_one<T>(&mut self, ptr: Unique<T>) {
+        self.dealloc_nonnull(NonNullAddress::from_unique(&ptr), Kind::new::<T>());
+    }
+
+    unsafe fn alloc_array<T: Raw>(&mut self, n: usize) -> Result<Unique<T>, AllocError> {
+        let kind = try!(array_kind::<T>(n));
+        Ok(Unique::new(try!(self.alloc_nonnull(kind)).cast()))
+    }
+
+    unsafe fn realloc_array<T: Raw>(&mut self, ptr: Unique<T>, n_old: usize, n_new: usize)
+                                    -> Result<Unique<T>, AllocError> {
+        let old = try!(array_kind::<T>(n_old));
+        let new = try!(array_kind::<T>(n_new));
+        let p = NonNullAddress::from_unique(&ptr);
+        check_block(p.as_ptr(), old, "Alloc::realloc_array");
+        Ok(Unique::new(try!(self.realloc_nonnull(p, old, new.size)).cast()))
+    }
+
+    unsafe fn dealloc_array<T>(&mut self, ptr: Unique<T>, n: usize) -> Result<(), AllocError> {
+        let kind = try!(array_kind::<T>(n));
+        self.dealloc_nonnull(NonNullAddress::from_unique(&ptr), kind);
+        Ok(())
     }
 
     unsafe fn alloc_excess(&mut self, kind: Kind) -> Excess {
@@ -221,10 +592,15 @@ impl<Self_:?Sized + Alloc> SuperAlloc for Self_ {
     }
 
     unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        check_block(ptr, kind, "Alloc::realloc");
+        check_contract(new_size <= ::std::isize::MAX as usize, "Alloc::realloc",
+              "the new size exceeds isize::MAX");
         if new_size <= self.usable_size(kind) {
             return ptr;
         } else {
             let new_ptr = self.alloc(Kind { size: new_size, ..kind });
+            // The old block is released only once the copy is safe, so
+            // a refusal leaves it intact, as `Alloc::realloc` promises.
             if !new_ptr.is_null() {
                 ptr::copy(ptr as *const u8, new_ptr, cmp::min(kind.size, new_size));
                 self.dealloc(ptr, kind);
@@ -240,27 +616,214 @@ impl<Self_:?Sized + Alloc> SuperAlloc for Self_ {
 
 }
 
+// Lets a borrowed allocator back a container, e.g. the `Box` returned
+// by `alloc_one_init`.
+impl<'a, A: ?Sized + Alloc> Alloc for &'a mut A {
+    unsafe fn oom(&mut self) -> ! { (**self).oom() }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        (**self).alloc(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        (**self).dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        (**self).dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        (**self).usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        (**self).grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        (**self).shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        (**self).alloc_error(kind)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        (**self).realloc(ptr, kind, new_size)
+    }
+}
+
+// Lets an allocator chosen at runtime, e.g. `Box<Alloc>`, back a
+// container.
+impl<A: ?Sized + Alloc> Alloc for ::std::boxed::Box<A> {
+    unsafe fn oom(&mut self) -> ! { (**self).oom() }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        (**self).alloc(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        (**self).dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        (**self).dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        (**self).usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        (**self).grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        (**self).shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        (**self).alloc_error(kind)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        (**self).realloc(ptr, kind, new_size)
+    }
+}
+
+/// An allocator whose concrete type has been erased, for selecting
+/// one at runtime: `Vec<T, DynAlloc>`, or `&mut DynAlloc` to borrow.
+pub type DynAlloc = ::std::boxed::Box<Alloc>;
+
+/// An allocator that can serve requests through a shared reference,
+/// keeping whatever state it has behind interior mutability.
+///
+/// The handles `&A`, `Rc<A>` and `Arc<A>` implement `Alloc` for any
+/// `A: ShareAlloc`, so a single arena can back many containers by
+/// giving each one a handle, without the arena itself needing to be
+/// `Clone` (and so without a refcount inside every allocator).
+pub trait ShareAlloc {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address;
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind);
+
+    unsafe fn dealloc_hot_shared(&self, ptr: Address, kind: Kind) {
+        self.dealloc_shared(ptr, kind)
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        kind.size
+    }
+
+    unsafe fn alloc_error_shared(&self, kind: Kind) -> AllocError {
+        let _ = kind;
+        AllocError::Exhausted
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if new_size <= self.usable_size_shared(kind) {
+            return ptr;
+        }
+        let new_ptr = self.alloc_shared(Kind { size: new_size, ..kind });
+        if !new_ptr.is_null() {
+            ptr::copy(ptr as *const u8, new_ptr, cmp::min(kind.size, new_size));
+            self.dealloc_shared(ptr, kind);
+        }
+        new_ptr
+    }
+}
+
+macro_rules! share_alloc_handle {
+    ($($handle:ty),*) => { $(
+        impl<'a, A: ?Sized + ShareAlloc> Alloc for $handle {
+            unsafe fn alloc(&mut self, kind: Kind) -> Address {
+                (**self).alloc_shared(kind)
+            }
+
+            unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+                (**self).dealloc_shared(ptr, kind)
+            }
+
+            unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+                (**self).dealloc_hot_shared(ptr, kind)
+            }
+
+            unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+                (**self).usable_size_shared(kind)
+            }
+
+            unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+                (**self).alloc_error_shared(kind)
+            }
+
+            unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+                (**self).realloc_shared(ptr, kind, new_size)
+            }
+
+            fn compatible_with(&self, other: &Self) -> bool {
+                &**self as *const A as *const u8 == &**other as *const A as *const u8
+            }
+        }
+    )* }
+}
+
+share_alloc_handle!(&'a A, Rc<A>, Arc<A>);
+
+/// Allocators with no per-instance state: every value is
+/// interchangeable with every other, so memory obtained from one may
+/// be returned through another.
+pub unsafe trait StatelessAlloc: Alloc + Copy + Default { }
+
 #[derive(Copy, Clone, Debug)]
 pub struct DefaultAlloc;
 
+unsafe impl StatelessAlloc for DefaultAlloc { }
+
+impl ShareAlloc for DefaultAlloc {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        DefaultAlloc.alloc(kind)
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        DefaultAlloc.dealloc(ptr, kind)
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        DefaultAlloc.realloc(ptr, kind, new_size)
+    }
+}
+
 impl Default for DefaultAlloc {
     fn default() -> Self { DefaultAlloc }
 }
 
 impl Alloc for DefaultAlloc {
+    fn compatible_with(&self, _other: &Self) -> bool { true }
+
     unsafe fn alloc(&mut self, kind: Kind) -> Address {
         if kind.size == 0 {
-            heap::EMPTY as *mut u8
+            kind.dangling()
         } else {
             heap::allocate(kind.size, kind.align)
         }
     }
 
     unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
-        heap::reallocate(ptr, kind.size, new_size, kind.align)
+        // A dangling address is not a heap block, so transitions to or from
+        // a zero-sized kind cannot go through `heap::reallocate`.
+        if kind.size == 0 {
+            self.alloc(Kind { size: new_size, ..kind })
+        } else if new_size == 0 {
+            self.dealloc(ptr, kind);
+            kind.dangling()
+        } else {
+            heap::reallocate(ptr, kind.size, new_size, kind.align)
+        }
     }
 
     unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
-        heap::deallocate(ptr, kind.size, kind.align)
+        if kind.size != 0 {
+            heap::deallocate(ptr, kind.size, kind.align)
+        }
     }
 }
@@ -0,0 +1,111 @@
+// Tells external memory checkers about the state of memory that our
+// own allocators manage.
+//
+// To Valgrind's memcheck or AddressSanitizer, a region allocator is one
+// big block obtained from malloc (or mmap): a read of a freed bump
+// block or a quarantined block looks perfectly valid. The functions
+// here mark such ranges inaccessible, and mark them usable again when
+// they are handed back out, so the tools report misuse inside our
+// allocators as they would for malloc.
+//
+// With the `valgrind` feature the marks are memcheck client requests
+// (x86-64 only; they cost a few instructions and do nothing outside
+// Valgrind). With the `asan` feature they are ASan's manual poisoning
+// calls, for builds run under `-Z sanitizer=address`. Without either,
+// every function compiles to nothing.
+
+use alloc::Address;
+
+/// The range may not be touched until it is handed out again.
+#[inline]
+pub fn no_access(ptr: Address, len: usize) {
+    valgrind::request(valgrind::MAKE_MEM_NOACCESS, ptr, len);
+    asan::poison(ptr, len);
+}
+
+/// The range is usable but holds no meaningful value yet, as with
+/// fresh memory from an allocator.
+#[inline]
+pub fn undefined(ptr: Address, len: usize) {
+    valgrind::request(valgrind::MAKE_MEM_UNDEFINED, ptr, len);
+    asan::unpoison(ptr, len);
+}
+
+/// The range is usable and its contents may be read.
+#[inline]
+pub fn defined(ptr: Address, len: usize) {
+    valgrind::request(valgrind::MAKE_MEM_DEFINED, ptr, len);
+    asan::unpoison(ptr, len);
+}
+
+#[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+mod valgrind {
+    use alloc::Address;
+
+    // ('M' << 24) | ('C' << 16), plus the memcheck request number
+    pub const MAKE_MEM_NOACCESS: usize = 0x4d43_0000;
+    pub const MAKE_MEM_UNDEFINED: usize = 0x4d43_0001;
+    pub const MAKE_MEM_DEFINED: usize = 0x4d43_0002;
+
+    // The "magic sequence" from valgrind.h: a no-op on real hardware,
+    // recognized by Valgrind's JIT, which reads the request from the
+    // array in %rax.
+    #[inline]
+    pub fn request(code: usize, ptr: Address, len: usize) {
+        if len == 0 { return; }
+        let args: [usize; 6] = [code, ptr as usize, len, 0, 0, 0];
+        unsafe {
+            let _result: usize;
+            asm!("rolq $$3, %rdi; rolq $$13, %rdi; rolq $$61, %rdi; rolq $$51, %rdi; \
+                  xchgq %rbx, %rbx"
+                 : "={rdx}"(_result)
+                 : "{rax}"(args.as_ptr()), "{rdx}"(0usize)
+                 : "cc", "memory"
+                 : "volatile");
+        }
+    }
+}
+
+#[cfg(not(all(feature = "valgrind", target_arch = "x86_64")))]
+mod valgrind {
+    use alloc::Address;
+
+    pub const MAKE_MEM_NOACCESS: usize = 0;
+    pub const MAKE_MEM_UNDEFINED: usize = 1;
+    pub const MAKE_MEM_DEFINED: usize = 2;
+
+    #[inline(always)]
+    pub fn request(_code: usize, _ptr: Address, _len: usize) { }
+}
+
+#[cfg(feature = "asan")]
+mod asan {
+    use alloc::Address;
+    use libc::{c_void, size_t};
+
+    extern "C" {
+        fn __asan_poison_memory_region(addr: *const c_void, size: size_t);
+        fn __asan_unpoison_memory_region(addr: *const c_void, size: size_t);
+    }
+
+    #[inline]
+    pub fn poison(ptr: Address, len: usize) {
+        unsafe { __asan_poison_memory_region(ptr as *const c_void, len) }
+    }
+
+    #[inline]
+    pub fn unpoison(ptr: Address, len: usize) {
+        unsafe { __asan_unpoison_memory_region(ptr as *const c_void, len) }
+    }
+}
+
+#[cfg(not(feature = "asan"))]
+mod asan {
+    use alloc::Address;
+
+    #[inline(always)]
+    pub fn poison(_ptr: Address, _len: usize) { }
+
+    #[inline(always)]
+    pub fn unpoison(_ptr: Address, _len: usize) { }
+}
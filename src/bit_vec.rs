@@ -0,0 +1,142 @@
+// A growable bitset.
+//
+// Bits are packed into `usize` words in a `RawVec<usize, A>`, so a
+// per-frame bitset can live in the same arena as the rest of the
+// frame's data. `set` grows the vector on demand; bits past `len()`
+// read as unset.
+//
+// Invariant: the first `words(nbits)` words are initialized and every
+// bit at or past `nbits` in them is zero. This lets `count_ones` and
+// the set operations work a word at a time without masking.
+
+use alloc::{Alloc, DefaultAlloc};
+use raw_vec::RawVec;
+
+use std::cmp;
+use std::fmt;
+use std::ptr;
+use std::slice;
+use std::usize;
+
+const BITS: usize = usize::BITS;
+
+fn words(nbits: usize) -> usize {
+    (nbits + BITS - 1) / BITS
+}
+
+pub struct BitVec<A:Alloc = DefaultAlloc> {
+    buf: RawVec<usize, A>,
+    nbits: usize,
+}
+
+impl<A:Alloc> BitVec<A> {
+    pub fn new() -> Self where A: Default {
+        BitVec { buf: RawVec::new(), nbits: 0 }
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        BitVec { buf: RawVec::with_alloc(a), nbits: 0 }
+    }
+
+    /// Creates an empty bitset with room for `nbits` bits before it
+    /// needs to reallocate.
+    pub fn with_capacity_alloc(nbits: usize, a: A) -> Self {
+        BitVec { buf: RawVec::with_capacity_alloc(words(nbits), a), nbits: 0 }
+    }
+
+    /// Number of bits, set or not, in the vector.
+    pub fn len(&self) -> usize {
+        self.nbits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nbits == 0
+    }
+
+    fn storage(&self) -> &[usize] {
+        unsafe { slice::from_raw_parts(self.buf.ptr(), words(self.nbits)) }
+    }
+
+    fn storage_mut(&mut self) -> &mut [usize] {
+        unsafe { slice::from_raw_parts_mut(self.buf.ptr(), words(self.nbits)) }
+    }
+
+    /// Grows or shrinks to `nbits` bits; new bits are unset.
+    pub fn resize(&mut self, nbits: usize) {
+        let old_words = words(self.nbits);
+        let new_words = words(nbits);
+        if new_words > old_words {
+            self.buf.reserve(old_words, new_words - old_words);
+            unsafe {
+                ptr::write_bytes(self.buf.ptr().offset(old_words as isize), 0,
+                                 new_words - old_words);
+            }
+        }
+        if nbits < self.nbits {
+            // clear the bits we are dropping so the invariant holds if
+            // the vector grows again
+            let tail = nbits % BITS;
+            if tail != 0 {
+                self.storage_mut()[nbits / BITS] &= (1 << tail) - 1;
+            }
+        }
+        self.nbits = nbits;
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        if i >= self.nbits { return false; }
+        self.storage()[i / BITS] & (1 << (i % BITS)) != 0
+    }
+
+    /// Sets bit `i` to `value`, growing the vector to `i + 1` bits if
+    /// needed.
+    pub fn set(&mut self, i: usize, value: bool) {
+        if i >= self.nbits {
+            if !value { return; }
+            self.resize(i + 1);
+        }
+        let w = &mut self.storage_mut()[i / BITS];
+        if value { *w |= 1 << (i % BITS); } else { *w &= !(1 << (i % BITS)); }
+    }
+
+    /// Unsets every bit, keeping the length.
+    pub fn clear(&mut self) {
+        for w in self.storage_mut() { *w = 0; }
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.storage().iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Sets every bit that is set in `other`, growing to its length
+    /// if it is longer.
+    pub fn union<B:Alloc>(&mut self, other: &BitVec<B>) {
+        if other.nbits > self.nbits { self.resize(other.nbits); }
+        for (a, b) in self.storage_mut().iter_mut().zip(other.storage()) {
+            *a |= *b;
+        }
+    }
+
+    /// Unsets every bit that is not set in `other`.
+    pub fn intersection<B:Alloc>(&mut self, other: &BitVec<B>) {
+        let common = cmp::min(words(self.nbits), words(other.nbits));
+        let theirs = other.storage();
+        for (i, a) in self.storage_mut().iter_mut().enumerate() {
+            *a &= if i < common { theirs[i] } else { 0 };
+        }
+    }
+
+    /// Iterates over the indices of the set bits, in ascending order.
+    pub fn iter_ones<'a>(&'a self) -> Box<Iterator<Item=usize> + 'a> {
+        Box::new((0..self.nbits).filter(move |&i| self.get(i)))
+    }
+}
+
+impl<A:Alloc> fmt::Debug for BitVec<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..self.nbits {
+            try!(write!(f, "{}", if self.get(i) { 1 } else { 0 }));
+        }
+        Ok(())
+    }
+}
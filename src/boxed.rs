@@ -1,14 +1,14 @@
 use std::fmt;
 use std::intrinsics;
-use std::ops::{Deref, DerefMut};
+use std::marker::Unsize;
 use std::mem;
-use std::ptr::{Unique};
+use std::ops::{CoerceUnsized, Deref, DerefMut};
+use std::ptr::{self, Unique};
+use std::slice;
 
-use alloc::{Alloc, DefaultAlloc, Kind};
-
-// FIXME: Generalize to support `T: ?Sized`
-// (This is hard because I do not yet know how to call the
-// appropriate destructor for the underlying data.)
+use alloc::{self, Alloc, AllocError, DefaultAlloc, Kind, NonNullAddress, Raw};
+use alloc_crate::oom;
+use uninit::{Filled, ManuallyDrop, MaybeUninit, Slot};
 
 pub struct Box<T: ?Sized, A:Alloc = DefaultAlloc> {
     value: Unique<T>,
@@ -26,30 +26,220 @@ impl<T: ?Sized, A:Alloc> DerefMut for Box<T, A> {
 }
 
 impl<T: ?Sized, A:Alloc> Box<T, A> {
-    pub fn value_alloc(mut self) -> (Unique<T>, A) {
+    /// Takes the box apart without running its destructor.
+    pub fn value_alloc(self) -> (Unique<T>, A) {
+        // The fields are read out of a box that can no longer run its
+        // destructor, so neither is ever owned twice.
+        let this = ManuallyDrop::new(self);
+        unsafe { (ptr::read(&this.value), ptr::read(&this.alloc)) }
+    }
+    pub unsafe fn from_raw_alloc(raw: *mut T, alloc: A) -> Self {
+        if cfg!(feature = "checked-alloc") {
+            // The kind can only be read through a non-null pointer.
+            alloc::check_contract(!(raw as *const u8).is_null(), "Box::from_raw_alloc",
+                                  "null pointer");
+            alloc::check_block(raw as *mut u8, Kind::for_value(&*raw), "Box::from_raw_alloc");
+        }
+        Box { value: Unique::new(raw), alloc: alloc }
+    }
+}
+
+impl<T, A:Alloc> Box<T, A> {
+    /// Allocates memory from `alloc` and then places `value` into it.
+    ///
+    /// Aborts via `oom` if the allocator cannot satisfy the request.
+    pub fn new_in(value: T, alloc: A) -> Self {
+        match Box::try_new_in(value, alloc) {
+            Ok(b) => b,
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    /// Like `new_in`, but returns `Err` (dropping `value`) if the
+    /// allocator cannot satisfy the request.
+    pub fn try_new_in(value: T, alloc: A) -> Result<Self, AllocError> {
+        let mut b = try!(Box::try_new_uninit_in(alloc));
+        unsafe { ptr::write(b.as_mut_ptr(), value); }
+        Ok(unsafe { b.assume_init() })
+    }
+
+    /// Allocates memory from `alloc` and has `init` construct the value
+    /// directly in it, so a large `T` need not be built on the stack
+    /// and then moved:
+    ///
+    /// ```ignore
+    /// let b = Box::emplace_in(alloc, |slot| slot.write([0u8; 1 << 20]));
+    /// ```
+    ///
+    /// If `init` panics the memory is freed. Aborts via `oom` if the
+    /// allocator cannot satisfy the request.
+    pub fn emplace_in<F>(alloc: A, init: F) -> Self
+        where F: for<'a> FnOnce(Slot<'a, T>) -> Filled<'a>
+    {
+        let mut b = Box::new_uninit_in(alloc);
         unsafe {
-            let v = mem::replace(&mut self.value, mem::uninitialized());
-            let a = mem::replace(&mut self.alloc, mem::uninitialized());
-            mem::forget(self);
-            (v, a)
+            Slot::fill(b.as_mut_ptr(), init);
+            b.assume_init()
         }
     }
-    pub unsafe fn from_raw_alloc(raw: *mut T, alloc: A) -> Self {
-        Box { value: mem::transmute(raw), alloc: alloc }
+
+    /// Allocates memory for a `T` from `alloc` without initializing it.
+    pub fn new_uninit_in(alloc: A) -> UninitBox<T, A> {
+        match Box::try_new_uninit_in(alloc) {
+            Ok(b) => b,
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    pub fn try_new_uninit_in(mut alloc: A) -> Result<UninitBox<T, A>, AllocError> {
+        unsafe {
+            // Not `alloc_one`: the memory stays unobservable until
+            // `write`/`assume_init`, so `T` need not be `Raw`.
+            let p = try!(alloc.alloc_nonnull(Kind::new::<T>()));
+            Ok(Box::from_raw_alloc(p.cast::<MaybeUninit<T>>(), alloc))
+        }
+    }
+}
+
+impl<T, A:Alloc> Box<T, A> {
+    /// Moves the value into memory from `dest`, freeing the original
+    /// allocation with this box's allocator; e.g. to evacuate a value
+    /// that must outlive the arena it was built in.
+    pub fn rehome_in<B:Alloc>(self, dest: B) -> Box<T, B> {
+        let mut new = Box::new_uninit_in(dest);
+        let (v, mut a) = self.value_alloc();
+        unsafe {
+            ptr::copy_nonoverlapping(*v, new.as_mut_ptr(), 1);
+            a.dealloc_nonnull(NonNullAddress::from_unique(&v), Kind::new::<T>());
+            new.assume_init()
+        }
+    }
+}
+
+/// Memory for a single `T` obtained from an allocator, not yet
+/// holding a value.
+///
+/// Dropping an `UninitBox` returns the memory to its allocator
+/// without running any destructor for `T`.
+pub type UninitBox<T, A = DefaultAlloc> = Box<MaybeUninit<T>, A>;
+
+impl<T, A:Alloc> Box<MaybeUninit<T>, A> {
+    pub fn as_mut_ptr(&mut self) -> *mut T { (**self).as_mut_ptr() }
+
+    /// Moves `value` into the allocation, yielding an initialized box.
+    pub fn write(mut self, value: T) -> Box<T, A> {
+        unsafe {
+            ptr::write(self.as_mut_ptr(), value);
+            self.assume_init()
+        }
+    }
+
+    /// Converts to `Box<T, A>`; the caller must have initialized the
+    /// memory through `as_mut_ptr`.
+    pub unsafe fn assume_init(self) -> Box<T, A> {
+        let (v, a) = self.value_alloc();
+        Box::from_raw_alloc(*v as *mut T, a)
+    }
+}
+
+// Loading plain data straight from a file or socket buffer into
+// allocator memory, and writing it back out.
+//
+// The bytes are the values' in-memory representation, copied verbatim:
+// host byte order, host layout. Data meant for other machines should
+// be stored in a fixed order (e.g. `u32::to_le` before writing and
+// `u32::from_le` after loading), and `T` should be `#[repr(C)]` or a
+// primitive so its layout does not change between builds.
+impl<T: Raw + Copy, A:Alloc> Box<[T], A> {
+    /// Allocates a slice from `alloc`, aligned for `T`, and copies
+    /// `bytes` into it.
+    ///
+    /// Aborts via `oom` if the allocator cannot satisfy the request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is zero-sized, or if `bytes.len()` is not a
+    /// multiple of its size.
+    pub fn copy_from_bytes_in(bytes: &[u8], alloc: A) -> Self {
+        match Box::try_copy_from_bytes_in(bytes, alloc) {
+            Ok(b) => b,
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    /// Like `copy_from_bytes_in`, but returns `Err` if the allocator
+    /// cannot satisfy the request.
+    pub fn try_copy_from_bytes_in(bytes: &[u8], mut alloc: A) -> Result<Self, AllocError> {
+        let size = mem::size_of::<T>();
+        assert!(size != 0, "Box::copy_from_bytes_in: zero-sized element type");
+        assert!(bytes.len() % size == 0,
+                "Box::copy_from_bytes_in: {} bytes is not a whole number of {}-byte elements",
+                bytes.len(), size);
+        let n = bytes.len() / size;
+        let kind = Kind::new::<T>().array(n);
+        unsafe {
+            // Even an empty slice comes from `alloc`, since `drop`
+            // hands every box's block back to it.
+            let p = try!(alloc.alloc_nonnull(kind)).as_ptr();
+            ptr::copy_nonoverlapping(bytes.as_ptr(), p, bytes.len());
+            Ok(Box::from_raw_alloc(slice::from_raw_parts_mut(p as *mut T, n), alloc))
+        }
+    }
+
+    /// The slice's memory as bytes, e.g. for writing it to a file.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr() as *const u8, mem::size_of_val(&**self)) }
+    }
+}
+
+// `DefaultAlloc` draws from the same heap as the standard `Box`, so
+// ownership of the allocation can move across without copying.
+impl<T: ?Sized> Box<T, DefaultAlloc> {
+    pub fn into_std(self) -> ::std::boxed::Box<T> {
+        let (v, _) = self.value_alloc();
+        unsafe { ::std::boxed::Box::from_raw(*v) }
+    }
+
+    pub fn from_std(b: ::std::boxed::Box<T>) -> Self {
+        unsafe { Box::from_raw_alloc(::std::boxed::Box::into_raw(b), DefaultAlloc) }
+    }
+}
+
+// A box owns an allocation, so it is never plain data.
+impl<T: ?Sized, A:Alloc> !Raw for Box<T, A> { }
+
+// Allows `Box<[T; N], A>` to become `Box<[T], A>` and `Box<T, A>` to
+// become `Box<Trait, A>` exactly as with the standard `Box`.
+impl<T: ?Sized + Unsize<U>, U: ?Sized, A:Alloc> CoerceUnsized<Box<U, A>> for Box<T, A> { }
+
+// Returns a box's block to its allocator when dropped, so that the
+// block is freed even if the value's destructor panics.
+struct Free<'a, A: 'a + Alloc> {
+    alloc: &'a mut A,
+    ptr: NonNullAddress,
+    kind: Kind,
+}
+
+impl<'a, A: Alloc> Drop for Free<'a, A> {
+    fn drop(&mut self) {
+        unsafe { self.alloc.dealloc_nonnull(self.ptr, self.kind) }
     }
 }
 
 impl<T: ?Sized, A:Alloc> Drop for Box<T, A> {
     fn drop(&mut self) {
         unsafe {
-            println!("starting boxed::Box::drop for 0x{:x}", self as *mut _ as usize);
-            intrinsics::drop_in_place(&**self.value as *const T as *mut T);
+            // Compute the kind while the value is still intact; for
+            // trait objects the size and alignment come from the vtable.
             let k = Kind::for_value(self.value.get());
-            let mut a = mem::replace(&mut self.alloc, mem::dropped());
-            a.dealloc(*self.value as *mut u8, k);
-            drop(a);
-            println!("finished boxed::Box::drop");
+            alloc::check_block(*self.value as *mut u8, k, "Box::drop");
+            let ptr = NonNullAddress::new_unchecked(*self.value as *mut u8);
+            let _free = Free { alloc: &mut self.alloc, ptr: ptr, kind: k };
+            intrinsics::drop_in_place(&**self.value as *const T as *mut T);
         }
+        // `self.alloc` is dropped by the compiler after this returns,
+        // so an allocator whose destructor releases its memory (an
+        // arena, a mapping) is still intact while the block is freed.
     }
 }
 
@@ -65,7 +255,7 @@ impl<T: fmt::Debug + ?Sized, A:Alloc> fmt::Debug for Box<T, A> {
     }
 }
 
-impl<T, A:Alloc> fmt::Pointer for Box<T, A> {
+impl<T: ?Sized, A:Alloc> fmt::Pointer for Box<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // It's not possible to extract the inner Uniq directly from the Box,
         // instead we cast it to a *const which aliases the Unique
@@ -73,3 +263,61 @@ impl<T, A:Alloc> fmt::Pointer for Box<T, A> {
         fmt::Pointer::fmt(&ptr, f)
     }
 }
+
+// Calling through a box, so that `Box<Fn(..), A>` and `Box<FnMut(..), A>`
+// can be passed wherever a closure is expected. Calling a boxed
+// `FnOnce` needs the value moved out of the box, which cannot be
+// expressed for an unsized `F`; box an `FnBox` for that instead.
+
+impl<Args, F: Fn<Args> + ?Sized, A:Alloc> Fn<Args> for Box<F, A> {
+    extern "rust-call" fn call(&self, args: Args) -> F::Output {
+        (**self).call(args)
+    }
+}
+
+impl<Args, F: FnMut<Args> + ?Sized, A:Alloc> FnMut<Args> for Box<F, A> {
+    extern "rust-call" fn call_mut(&mut self, args: Args) -> F::Output {
+        (**self).call_mut(args)
+    }
+}
+
+impl<Args, F: FnMut<Args> + ?Sized, A:Alloc> FnOnce<Args> for Box<F, A> {
+    type Output = F::Output;
+
+    extern "rust-call" fn call_once(mut self, args: Args) -> F::Output {
+        (*self).call_mut(args)
+    }
+}
+
+/// A closure that can be called once from inside a box; see
+/// `Box::call_box`. Every `FnOnce` is one.
+pub trait FnBox<Args> {
+    type Output;
+
+    /// Moves the closure out of `*self` and calls it; `*self` must
+    /// not be used (or dropped) afterwards.
+    unsafe fn call_in_place(&mut self, args: Args) -> Self::Output;
+}
+
+impl<Args, F: FnOnce<Args>> FnBox<Args> for F {
+    type Output = F::Output;
+
+    unsafe fn call_in_place(&mut self, args: Args) -> F::Output {
+        ptr::read(self).call_once(args)
+    }
+}
+
+impl<'a, Args, R, A:Alloc> Box<FnBox<Args, Output=R> + 'a, A> {
+    /// Calls the boxed closure, consuming it, and frees the box.
+    pub fn call_box(self, args: Args) -> R {
+        let (v, mut a) = self.value_alloc();
+        unsafe {
+            let kind = Kind::for_value(&**v);
+            // Freed even if the call panics; by then the closure has
+            // been moved out, so it is not dropped here.
+            let _free = Free { alloc: &mut a, ptr: NonNullAddress::new_unchecked(*v as *mut u8),
+                               kind: kind };
+            (**v).call_in_place(args)
+        }
+    }
+}
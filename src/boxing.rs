@@ -1,46 +1,44 @@
-use alloc::{Alloc, Kind};
-use boxed::Box;
+// `in Boxing(alloc) { expr }` places `expr` into a `Box` from `alloc`.
+//
+// The placement protocol behind the `in` syntax is unstable and not
+// going anywhere, so this is only a thin adapter over the emplacement
+// API that works without it: the place is an `UninitBox`, which frees
+// its memory if `expr` panics, and finalizing is `assume_init`. New
+// code should call `Boxing::emplace` (that is, `Box::emplace_in`).
+
+use alloc::Alloc;
+use boxed::{Box, UninitBox};
+use uninit::{Filled, Slot};
 
-use std::mem;
 use std::ops::{Place, Placer, InPlace};
 
 pub struct Boxing<A:Alloc>(pub A);
 
+impl<A:Alloc> Boxing<A> {
+    /// `Box::emplace_in` with this allocator.
+    pub fn emplace<T, F>(self, init: F) -> Box<T, A>
+        where F: for<'a> FnOnce(Slot<'a, T>) -> Filled<'a>
+    {
+        Box::emplace_in(self.0, init)
+    }
+}
+
 impl<T, A:Alloc> Placer<T> for Boxing<A> {
     type Place = InterimBox<T, A>;
-    fn make_place(mut self) -> InterimBox<T, A> {
-        println!("start of <Boxing as Placer>::make_place");
-        let ret = unsafe {
-            InterimBox {
-                p: self.0.alloc(Kind::new::<T>()) as *mut T,
-                a: self.0
-            }
-        };
-        println!("at end of <Boxing as Placer>::make_place");
-        ret
+    fn make_place(self) -> InterimBox<T, A> {
+        InterimBox(Box::new_uninit_in(self.0))
     }
 }
 
-pub struct InterimBox<T, A> {
-    p: *mut T,
-    a: A,
-}
+pub struct InterimBox<T, A:Alloc>(UninitBox<T, A>);
 
-impl<T, A> Place<T> for InterimBox<T, A> {
-    fn pointer(&mut self) -> *mut T { self.p }
+impl<T, A:Alloc> Place<T> for InterimBox<T, A> {
+    fn pointer(&mut self) -> *mut T { self.0.as_mut_ptr() }
 }
 
-impl<T, A: Alloc> InPlace<T> for InterimBox<T, A> {
+impl<T, A:Alloc> InPlace<T> for InterimBox<T, A> {
     type Owner = Box<T, A>;
-    unsafe fn finalize(mut self) -> Box<T, A> {
-        println!("start of InterimBox::finalize");
-        // The use of mem::forget below makes it unnecessary
-        // to do the replace with mem::dropped here.
-        let p = mem::replace(&mut self.p, mem::uninitialized());
-        let a = mem::replace(&mut self.a, mem::uninitialized());
-        mem::forget(self);
-        let ret = Box::from_raw_alloc(p, a);
-        println!("at end of InterimBox::finalize");
-        ret
+    unsafe fn finalize(self) -> Box<T, A> {
+        self.0.assume_init()
     }
 }
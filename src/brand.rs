@@ -0,0 +1,76 @@
+// Confining allocations to a region with a branded lifetime.
+//
+// A container holding `&'a Arena` cannot outlive the arena, but that
+// is all the borrow checker promises: a handle that is `Clone` through
+// an `Rc`, or an arena that can be reset through `&self`, still lets a
+// box outlive the memory it points into. `scope(arena, |alloc| ...)`
+// closes that gap. It owns the arena for the duration of the call and
+// hands the closure a `Branded<'id, A>` handle, where `'id` is a fresh
+// lifetime chosen separately for every call (the closure is generic
+// over it). Nothing outside the closure can name `'id`, so no
+// container using the handle can be returned or stored anywhere that
+// outlives the call; the arena is dropped only after all of them.
+//
+// `'id` is invariant, so handles from two different scopes are
+// different types and cannot be mixed up either.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, ShareAlloc, Size};
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+/// An allocator handle that only exists inside one `scope` call.
+pub struct Branded<'id, A: 'id> {
+    alloc: &'id A,
+    _brand: PhantomData<Cell<&'id ()>>,
+}
+
+impl<'id, A> Clone for Branded<'id, A> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<'id, A> Copy for Branded<'id, A> { }
+
+impl<'id, A> Branded<'id, A> {
+    /// The arena, e.g. for its statistics.
+    pub fn get(&self) -> &'id A { self.alloc }
+}
+
+/// Runs `f` with a branded handle to `alloc`, then drops `alloc`.
+/// Containers built on the handle cannot escape `f`.
+pub fn scope<A, R, F>(alloc: A, f: F) -> R
+    where A: ShareAlloc, F: for<'id> FnOnce(Branded<'id, A>) -> R
+{
+    f(Branded { alloc: &alloc, _brand: PhantomData })
+}
+
+impl<'id, A: ShareAlloc> alloc::Alloc for Branded<'id, A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        self.alloc.alloc_shared(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.alloc.dealloc_shared(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.alloc.dealloc_hot_shared(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.alloc.usable_size_shared(kind)
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        self.alloc.alloc_error_shared(kind)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.alloc.realloc_shared(ptr, kind, new_size)
+    }
+
+    fn compatible_with(&self, _other: &Self) -> bool {
+        // Both handles carry the same brand, so the same arena.
+        true
+    }
+}
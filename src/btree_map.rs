@@ -0,0 +1,852 @@
+// Ordered maps and sets as B-trees over any allocator.
+//
+// Each node holds between `MIN` and `CAPACITY` sorted keys (the root
+// may hold fewer) with their values, and internal nodes one more child
+// than keys. A node is a `Box` from `A` whose key, value and child
+// arrays are `Vec`s from `A` allocated at full size up front, so a
+// node costs a fixed handful of blocks and never reallocates. Every
+// node needs its own copy of the allocator, so `A` must be `Clone`: a
+// stateless allocator or a handle (`&A`, `Rc<A>`) to an arena.
+//
+// For the sorted data common when filling an arena, `from_sorted_iter_in`
+// builds the tree bottom-up in one pass: entries are appended along
+// the right edge, every node left of that edge is allocated once and
+// filled completely, and only the nodes on the right edge are topped
+// up from their left neighbours at the end.
+//
+// `entry` and `CursorMut` keep the path their search took, as raw
+// node pointers under a `&mut` borrow of the map, so inserting at a
+// vacant entry or stepping and removing with a cursor never searches
+// again.
+//
+// `BTreeSet` is a `BTreeMap` with `()` values.
+
+use alloc::{Alloc, DefaultAlloc};
+use boxed::Box;
+use uninit::{Filled, Slot};
+use vec::Vec;
+
+use std::borrow::Borrow;
+use std::collections::Bound;
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+const B: usize = 6;
+const CAPACITY: usize = 2 * B - 1;
+const MIN: usize = B - 1;
+
+struct Node<K, V, A:Alloc + Clone> {
+    keys: Vec<K, A>,
+    vals: Vec<V, A>,
+    // empty in leaves
+    edges: Vec<Box<Node<K, V, A>, A>, A>,
+}
+
+impl<K, V, A:Alloc + Clone> Node<K, V, A> {
+    // Room for one entry over `CAPACITY`, which `insert` needs briefly
+    // before it splits the node.
+    fn leaf(a: &A) -> Box<Self, A> {
+        Box::new_in(Node { keys: Vec::with_capacity_alloc(CAPACITY + 1, a.clone()),
+                           vals: Vec::with_capacity_alloc(CAPACITY + 1, a.clone()),
+                           edges: Vec::with_alloc(a.clone()) },
+                    a.clone())
+    }
+
+    fn internal(a: &A) -> Box<Self, A> {
+        let mut node = Node::leaf(a);
+        node.edges = Vec::with_capacity_alloc(CAPACITY + 2, a.clone());
+        node
+    }
+
+    fn is_leaf(&self) -> bool { self.edges.is_empty() }
+
+    fn len(&self) -> usize { self.keys.len() }
+
+    fn search<Q: ?Sized>(&self, key: &Q) -> Result<usize, usize> where K: Borrow<Q>, Q: Ord {
+        self.keys.binary_search_by(|k| k.borrow().cmp(key))
+    }
+}
+
+// Moves `from[at..]` onto the end of `to`, which must have room.
+fn move_tail<T, A:Alloc>(from: &mut Vec<T, A>, at: usize, to: &mut Vec<T, A>) {
+    let n = from.len() - at;
+    debug_assert!(to.capacity() - to.len() >= n);
+    unsafe {
+        ptr::copy_nonoverlapping(from.as_ptr().offset(at as isize),
+                                 to.as_mut_ptr().offset(to.len() as isize), n);
+        from.set_len(at);
+        let len = to.len();
+        to.set_len(len + n);
+    }
+}
+
+// Splits an overfull node, returning the middle entry and the new
+// right half.
+fn split<K, V, A:Alloc + Clone>(node: &mut Node<K, V, A>, a: &A) -> (K, V, Box<Node<K, V, A>, A>) {
+    let mut right = if node.is_leaf() { Node::leaf(a) } else { Node::internal(a) };
+    move_tail(&mut node.keys, B + 1, &mut right.keys);
+    move_tail(&mut node.vals, B + 1, &mut right.vals);
+    if !node.is_leaf() { move_tail(&mut node.edges, B + 1, &mut right.edges); }
+    let k = node.keys.pop().unwrap();
+    let v = node.vals.pop().unwrap();
+    (k, v, right)
+}
+
+// The nodes from the root down to an entry or a gap between entries.
+// The last element holds the key index within its node; the others
+// hold the index of the edge taken to the next.
+type Path<K, V, A> = ::std::vec::Vec<(*mut Node<K, V, A>, usize)>;
+
+// Moves a path that ends past the last key of its node on to the next
+// entry: an ancestor's edge index is also the key index of the entry
+// after that subtree. Leaves `path` empty if there is none.
+unsafe fn settle<K, V, A:Alloc + Clone>(path: &mut Path<K, V, A>) {
+    loop {
+        let (n, i) = match path.last() { Some(&top) => top, None => return };
+        if i < (*n).len() { return; }
+        path.pop();
+    }
+}
+
+// Restores child `i` of `node` to at least `MIN` entries after a
+// removal, borrowing from a sibling or merging with one.
+fn fix_child<K, V, A:Alloc + Clone>(node: &mut Node<K, V, A>, i: usize) {
+    if node.edges[i].len() >= MIN { return; }
+    if i > 0 && node.edges[i - 1].len() > MIN {
+        rotate_right(node, i - 1);
+    } else if i < node.len() && node.edges[i + 1].len() > MIN {
+        rotate_left(node, i);
+    } else if i > 0 {
+        merge(node, i - 1);
+    } else {
+        merge(node, i);
+    }
+}
+
+// Moves the last entry of child `i` up into `node` and the separator
+// down to the front of child `i + 1`.
+fn rotate_right<K, V, A:Alloc + Clone>(node: &mut Node<K, V, A>, i: usize) {
+    let Node { ref mut keys, ref mut vals, ref mut edges } = *node;
+    let (l, r) = edges.split_at_mut(i + 1);
+    let (left, right) = (&mut l[i], &mut r[0]);
+    let k = mem::replace(&mut keys[i], left.keys.pop().unwrap());
+    let v = mem::replace(&mut vals[i], left.vals.pop().unwrap());
+    right.keys.insert(0, k);
+    right.vals.insert(0, v);
+    if !left.is_leaf() {
+        let e = left.edges.pop().unwrap();
+        right.edges.insert(0, e);
+    }
+}
+
+// The mirror image of `rotate_right`.
+fn rotate_left<K, V, A:Alloc + Clone>(node: &mut Node<K, V, A>, i: usize) {
+    let Node { ref mut keys, ref mut vals, ref mut edges } = *node;
+    let (l, r) = edges.split_at_mut(i + 1);
+    let (left, right) = (&mut l[i], &mut r[0]);
+    let k = mem::replace(&mut keys[i], right.keys.remove(0));
+    let v = mem::replace(&mut vals[i], right.vals.remove(0));
+    left.keys.push(k);
+    left.vals.push(v);
+    if !right.is_leaf() {
+        let e = right.edges.remove(0);
+        left.edges.push(e);
+    }
+}
+
+// Folds separator `i` and child `i + 1` into child `i`.
+fn merge<K, V, A:Alloc + Clone>(node: &mut Node<K, V, A>, i: usize) {
+    let mut right = node.edges.remove(i + 1);
+    let k = node.keys.remove(i);
+    let v = node.vals.remove(i);
+    let left = &mut node.edges[i];
+    left.keys.push(k);
+    left.vals.push(v);
+    move_tail(&mut right.keys, 0, &mut left.keys);
+    move_tail(&mut right.vals, 0, &mut left.vals);
+    if !right.is_leaf() { move_tail(&mut right.edges, 0, &mut left.edges); }
+}
+
+pub struct BTreeMap<K: Ord, V, A:Alloc + Clone = DefaultAlloc> {
+    root: Option<Box<Node<K, V, A>, A>>,
+    // levels below the root
+    height: usize,
+    length: usize,
+    alloc: A,
+}
+
+impl<K: Ord, V, A:Alloc + Clone> BTreeMap<K, V, A> {
+    pub fn new() -> Self where A: Default {
+        BTreeMap::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        BTreeMap { root: None, height: 0, length: 0, alloc: a }
+    }
+
+    /// Builds a map from an iterator that yields keys in strictly
+    /// ascending order, bottom-up in a single pass.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not strictly ascending.
+    pub fn from_sorted_iter_in<I>(iter: I, a: A) -> Self where I: IntoIterator<Item=(K, V)> {
+        let mut map = BTreeMap::with_alloc(a);
+        for (k, v) in iter {
+            map.push_last(k, v);
+        }
+        map.fix_right_edge();
+        map
+    }
+
+    // Appends an entry greater than every key in the map, filling the
+    // right edge of the tree; nodes there may be left underfull until
+    // `fix_right_edge`.
+    fn push_last(&mut self, key: K, value: V) {
+        if self.root.is_none() { self.root = Some(Node::leaf(&self.alloc)); }
+        // the deepest node on the right edge with room, and the depth of
+        // the deepest one with keys (whose last key is the greatest)
+        let mut open = None;
+        {
+            let mut node = &**self.root.as_ref().unwrap();
+            let mut greatest = None;
+            for depth in 0..self.height + 1 {
+                if node.len() < CAPACITY { open = Some(depth); }
+                if let Some(k) = node.keys.last() { greatest = Some(k); }
+                if depth < self.height { node = &**node.edges.last().unwrap(); }
+            }
+            if let Some(k) = greatest {
+                assert!(*k < key, "from_sorted_iter_in: keys are not strictly ascending");
+            }
+        }
+        self.length += 1;
+        let depth = match open {
+            Some(depth) => depth,
+            None => {
+                let mut root = Node::internal(&self.alloc);
+                root.edges.push(self.root.take().unwrap());
+                self.root = Some(root);
+                self.height += 1;
+                0
+            }
+        };
+        let below = self.height - depth;
+        let alloc = self.alloc.clone();
+        let mut node = &mut **self.root.as_mut().unwrap();
+        for _ in 0..depth {
+            let tmp = node;
+            node = &mut **tmp.edges.last_mut().unwrap();
+        }
+        node.keys.push(key);
+        node.vals.push(value);
+        if below > 0 {
+            // open a fresh right edge below the entry
+            let mut sub = Node::leaf(&alloc);
+            for _ in 1..below {
+                let mut parent = Node::internal(&alloc);
+                parent.edges.push(sub);
+                sub = parent;
+            }
+            node.edges.push(sub);
+        }
+    }
+
+    // Tops up underfull nodes on the right edge from their full left
+    // neighbours, top-down.
+    fn fix_right_edge(&mut self) {
+        if let Some(ref mut root) = self.root {
+            let mut node = &mut **root;
+            while !node.is_leaf() {
+                let last = node.len();
+                while node.edges[last].len() < MIN { rotate_right(node, last - 1); }
+                let tmp = node;
+                node = &mut **tmp.edges.last_mut().unwrap();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize { self.length }
+
+    pub fn is_empty(&self) -> bool { self.length == 0 }
+
+    pub fn alloc(&self) -> &A { &self.alloc }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V> where K: Borrow<Q>, Q: Ord {
+        let mut node = match self.root { Some(ref root) => &**root, None => return None };
+        loop {
+            match node.search(key) {
+                Ok(i) => return Some(&node.vals[i]),
+                Err(_) if node.is_leaf() => return None,
+                Err(i) => node = &*node.edges[i],
+            }
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q>, Q: Ord {
+        let mut node = match self.root { Some(ref mut root) => &mut **root, None => return None };
+        loop {
+            let tmp = node;
+            match tmp.search(key) {
+                Ok(i) => return Some(&mut tmp.vals[i]),
+                Err(_) if tmp.is_leaf() => return None,
+                Err(i) => node = &mut *tmp.edges[i],
+            }
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: Ord {
+        self.get(key).is_some()
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => Some(e.insert(value)),
+            Entry::Vacant(e) => { e.insert(value); None }
+        }
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: Ord {
+        if self.root.is_none() { return None; }
+        match self.path_to(key) {
+            (path, true) => Some(unsafe { self.remove_at(path) }.1),
+            (_, false) => None,
+        }
+    }
+
+    /// The entry for `key`, for inserting or updating it with a
+    /// single search.
+    pub fn entry(&mut self, key: K) -> Entry<K, V, A> {
+        if self.root.is_none() { self.root = Some(Node::leaf(&self.alloc)); }
+        let (path, found) = self.path_to(&key);
+        if found {
+            Entry::Occupied(OccupiedEntry { map: self, path: path })
+        } else {
+            Entry::Vacant(VacantEntry { map: self, path: path, key: key })
+        }
+    }
+
+    /// A cursor at the smallest key.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<K, V, A> {
+        let mut cursor = CursorMut { map: self, path: ::std::vec::Vec::new() };
+        cursor.move_next();
+        cursor
+    }
+
+    /// A cursor at the first key not below `bound`.
+    pub fn lower_bound_mut<Q: ?Sized>(&mut self, bound: Bound<&Q>) -> CursorMut<K, V, A>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let path = self.lower_bound_path(bound);
+        CursorMut { map: self, path: path }
+    }
+
+    // The path to `key` if present, otherwise to the leaf gap where it
+    // belongs. The root must exist.
+    fn path_to<Q: ?Sized>(&mut self, key: &Q) -> (Path<K, V, A>, bool) where K: Borrow<Q>, Q: Ord {
+        let mut path = ::std::vec::Vec::with_capacity(self.height + 1);
+        let mut node: *mut Node<K, V, A> = &mut **self.root.as_mut().unwrap();
+        unsafe {
+            loop {
+                match (*node).search(key) {
+                    Ok(i) => { path.push((node, i)); return (path, true); }
+                    Err(i) => {
+                        path.push((node, i));
+                        if (*node).is_leaf() { return (path, false); }
+                        node = &mut *(*node).edges[i];
+                    }
+                }
+            }
+        }
+    }
+
+    // The path to the first entry not below `bound`; empty if there is
+    // none.
+    fn lower_bound_path<Q: ?Sized>(&mut self, bound: Bound<&Q>) -> Path<K, V, A>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let mut path = ::std::vec::Vec::new();
+        let mut node: *mut Node<K, V, A> = match self.root {
+            Some(ref mut root) => &mut **root,
+            None => return path,
+        };
+        unsafe {
+            loop {
+                let (i, exact) = match bound {
+                    Bound::Unbounded => (0, false),
+                    Bound::Included(k) => match (*node).search(k) { Ok(i) => (i, true), Err(i) => (i, false) },
+                    Bound::Excluded(k) => match (*node).search(k) { Ok(i) => (i + 1, false), Err(i) => (i, false) },
+                };
+                path.push((node, i));
+                if (*node).is_leaf() || exact { break; }
+                node = &mut *(*node).edges[i];
+            }
+            settle(&mut path);
+        }
+        path
+    }
+
+    // Inserts at the leaf gap ending `path`, splitting full nodes on
+    // the way up; returns where the value ended up.
+    unsafe fn insert_at(&mut self, path: Path<K, V, A>, key: K, value: V) -> *mut V {
+        self.insert_at_with(path, key, |slot| slot.write(value))
+    }
+
+    // As `insert_at`, constructing the value in the leaf with `init`.
+    // The value goes in first, so a panicking `init` changes nothing.
+    unsafe fn insert_at_with<F>(&mut self, mut path: Path<K, V, A>, key: K, init: F) -> *mut V
+        where F: for<'s> FnOnce(Slot<'s, V>) -> Filled<'s>
+    {
+        let (leaf, i) = path.pop().unwrap();
+        (*leaf).vals.insert_with(i, init);
+        (*leaf).keys.insert(i, key);
+        self.length += 1;
+        // follow the new entry through the splits
+        let mut at = (leaf, i);
+        let mut node = leaf;
+        while (*node).len() > CAPACITY {
+            let (k, v, mut right) = split(&mut *node, &self.alloc);
+            let rp: *mut Node<K, V, A> = &mut *right;
+            let moved_up = at.0 == node && at.1 == B;
+            if at.0 == node && at.1 > B { at = (rp, at.1 - B - 1); }
+            match path.pop() {
+                Some((parent, e)) => {
+                    (*parent).keys.insert(e, k);
+                    (*parent).vals.insert(e, v);
+                    (*parent).edges.insert(e + 1, right);
+                    if moved_up { at = (parent, e); }
+                    node = parent;
+                }
+                None => {
+                    let mut root = Node::internal(&self.alloc);
+                    root.keys.push(k);
+                    root.vals.push(v);
+                    root.edges.push(self.root.take().unwrap());
+                    root.edges.push(right);
+                    if moved_up { at = (&mut *root, 0); }
+                    self.root = Some(root);
+                    self.height += 1;
+                    break;
+                }
+            }
+        }
+        &mut (*at.0).vals[at.1]
+    }
+
+    // Removes the entry ending `path`, rebalancing on the way up.
+    unsafe fn remove_at(&mut self, mut path: Path<K, V, A>) -> (K, V) {
+        let (node, i) = path.pop().unwrap();
+        let kv = if (*node).is_leaf() {
+            ((*node).keys.remove(i), (*node).vals.remove(i))
+        } else {
+            // swap in the predecessor, from the rightmost leaf on the left
+            path.push((node, i));
+            let mut n: *mut Node<K, V, A> = &mut *(*node).edges[i];
+            while !(*n).is_leaf() {
+                let last = (*n).len();
+                path.push((n, last));
+                n = &mut *(*n).edges[last];
+            }
+            let k = (*n).keys.pop().unwrap();
+            let v = (*n).vals.pop().unwrap();
+            (mem::replace(&mut (*node).keys[i], k), mem::replace(&mut (*node).vals[i], v))
+        };
+        while let Some((p, e)) = path.pop() {
+            fix_child(&mut *p, e);
+        }
+        self.length -= 1;
+        self.shrink_root();
+        kv
+    }
+
+    // Drops an emptied root, promoting its only child if it has one.
+    fn shrink_root(&mut self) {
+        let emptied = match self.root { Some(ref root) => root.len() == 0, None => false };
+        if emptied {
+            let mut root = self.root.take().unwrap();
+            if !root.is_leaf() {
+                self.root = root.edges.pop();
+                self.height -= 1;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.height = 0;
+        self.length = 0;
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = match self.root { Some(ref root) => &**root, None => return None };
+        while !node.is_leaf() { node = &*node.edges[0]; }
+        if node.len() == 0 { None } else { Some((&node.keys[0], &node.vals[0])) }
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = match self.root { Some(ref root) => &**root, None => return None };
+        while !node.is_leaf() { node = &**node.edges.last().unwrap(); }
+        match node.len() {
+            0 => None,
+            n => Some((&node.keys[n - 1], &node.vals[n - 1])),
+        }
+    }
+
+    /// Iterates over the entries in ascending key order.
+    pub fn iter(&self) -> Iter<K, V, A> {
+        self.range::<K, K>(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn keys<'a>(&'a self) -> ::std::boxed::Box<Iterator<Item=&'a K> + 'a> {
+        ::std::boxed::Box::new(self.iter().map(|e| e.0))
+    }
+
+    pub fn values<'a>(&'a self) -> ::std::boxed::Box<Iterator<Item=&'a V> + 'a> {
+        ::std::boxed::Box::new(self.iter().map(|e| e.1))
+    }
+
+    /// Iterates in ascending order over the entries whose keys lie
+    /// between `min` and `max`.
+    pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self, min: Bound<&'a Min>, max: Bound<&'a Max>)
+                                               -> Iter<'a, K, V, A>
+        where K: Borrow<Min> + Borrow<Max>, Min: Ord + 'a, Max: Ord + 'a
+    {
+        let mut stack = ::std::vec::Vec::new();
+        if let Some(ref root) = self.root {
+            // Descend to the first entry not below `min`, noting at
+            // each level where to carry on from. An exact match needs
+            // nothing from the child before it.
+            let mut node = &**root;
+            loop {
+                let (i, exact) = match min {
+                    Bound::Unbounded => (0, false),
+                    Bound::Included(k) => match node.search(k) { Ok(i) => (i, true), Err(i) => (i, false) },
+                    Bound::Excluded(k) => match node.search(k) { Ok(i) => (i + 1, false), Err(i) => (i, false) },
+                };
+                stack.push((node, i));
+                if node.is_leaf() || exact { break; }
+                node = &*node.edges[i];
+            }
+        }
+        let end = match max {
+            Bound::Unbounded => End::Unbounded,
+            Bound::Included(k) =>
+                End::Bounded(::std::boxed::Box::new(move |key: &K| Borrow::<Max>::borrow(key) <= k)),
+            Bound::Excluded(k) =>
+                End::Bounded(::std::boxed::Box::new(move |key: &K| Borrow::<Max>::borrow(key) < k)),
+        };
+        Iter { stack: stack, end: end }
+    }
+}
+
+enum End<'a, K: 'a> {
+    Unbounded,
+    // whether a key is still within the range
+    Bounded(::std::boxed::Box<Fn(&K) -> bool + 'a>),
+}
+
+pub struct Iter<'a, K: 'a, V: 'a, A:Alloc + Clone + 'a> {
+    // nodes on the path to the next entry, each with the index of its
+    // next key to yield
+    stack: ::std::vec::Vec<(&'a Node<K, V, A>, usize)>,
+    end: End<'a, K>,
+}
+
+impl<'a, K, V, A:Alloc + Clone> Iterator for Iter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            let (node, i) = match self.stack.last_mut() {
+                Some(top) => { let at = *top; top.1 += 1; at }
+                None => return None,
+            };
+            if i >= node.len() {
+                self.stack.pop();
+                continue;
+            }
+            if let End::Bounded(ref within) = self.end {
+                if !within(&node.keys[i]) {
+                    self.stack.clear();
+                    return None;
+                }
+            }
+            if !node.is_leaf() {
+                let mut child = &*node.edges[i + 1];
+                loop {
+                    self.stack.push((child, 0));
+                    if child.is_leaf() { break; }
+                    child = &*child.edges[0];
+                }
+            }
+            return Some((&node.keys[i], &node.vals[i]));
+        }
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug, A:Alloc + Clone> fmt::Debug for BTreeMap<K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{:?}: {:?}", k, v));
+        }
+        write!(f, "}}")
+    }
+}
+
+/// A view into a single entry of a `BTreeMap`; see `BTreeMap::entry`.
+pub enum Entry<'a, K: Ord + 'a, V: 'a, A:Alloc + Clone + 'a> {
+    Occupied(OccupiedEntry<'a, K, V, A>),
+    Vacant(VacantEntry<'a, K, V, A>),
+}
+
+impl<'a, K: Ord, V, A:Alloc + Clone> Entry<'a, K, V, A> {
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => e.key(),
+            Entry::Vacant(ref e) => e.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(f()),
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a, K: Ord + 'a, V: 'a, A:Alloc + Clone + 'a> {
+    map: &'a mut BTreeMap<K, V, A>,
+    path: Path<K, V, A>,
+}
+
+impl<'a, K: Ord, V, A:Alloc + Clone> OccupiedEntry<'a, K, V, A> {
+    fn slot(&self) -> (*mut Node<K, V, A>, usize) { *self.path.last().unwrap() }
+
+    pub fn key(&self) -> &K {
+        let (n, i) = self.slot();
+        unsafe { &(*n).keys[i] }
+    }
+
+    pub fn get(&self) -> &V {
+        let (n, i) = self.slot();
+        unsafe { &(*n).vals[i] }
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        let (n, i) = self.slot();
+        unsafe { &mut (*n).vals[i] }
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        let (n, i) = self.slot();
+        unsafe { &mut (*n).vals[i] }
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        unsafe { self.map.remove_at(self.path) }.1
+    }
+}
+
+pub struct VacantEntry<'a, K: Ord + 'a, V: 'a, A:Alloc + Clone + 'a> {
+    map: &'a mut BTreeMap<K, V, A>,
+    path: Path<K, V, A>,
+    key: K,
+}
+
+impl<'a, K: Ord, V, A:Alloc + Clone> VacantEntry<'a, K, V, A> {
+    pub fn key(&self) -> &K { &self.key }
+
+    pub fn into_key(self) -> K { self.key }
+
+    /// Inserts `value` where the search ended, without searching again.
+    pub fn insert(self, value: V) -> &'a mut V {
+        unsafe { &mut *self.map.insert_at(self.path, self.key, value) }
+    }
+
+    /// Like `insert`, but the value is constructed in place by `init`
+    /// (see `Box::emplace_in`), for values too large to build on the
+    /// stack and move. If `init` panics the map is left as it was.
+    pub fn insert_with<F>(self, init: F) -> &'a mut V
+        where F: for<'s> FnOnce(Slot<'s, V>) -> Filled<'s>
+    {
+        unsafe { &mut *self.map.insert_at_with(self.path, self.key, init) }
+    }
+}
+
+/// A position in a `BTreeMap` from which entries can be read, changed
+/// or removed while walking the map in order. Past the last entry the
+/// cursor sits on a "ghost" position with no entry; moving on from it
+/// wraps around to the other end.
+pub struct CursorMut<'a, K: Ord + 'a, V: 'a, A:Alloc + Clone + 'a> {
+    map: &'a mut BTreeMap<K, V, A>,
+    // empty at the ghost position
+    path: Path<K, V, A>,
+}
+
+impl<'a, K: Ord, V, A:Alloc + Clone> CursorMut<'a, K, V, A> {
+    pub fn key(&self) -> Option<&K> {
+        self.path.last().map(|&(n, i)| unsafe { &(*n).keys[i] })
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        self.path.last().map(|&(n, i)| unsafe { &(*n).vals[i] })
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        self.path.last().map(|&(n, i)| unsafe { &mut (*n).vals[i] })
+    }
+
+    pub fn move_next(&mut self) {
+        let top = self.path.last().cloned();
+        let (n, i) = match top {
+            Some(top) => top,
+            None => {
+                self.path = self.map.lower_bound_path::<K>(Bound::Unbounded);
+                return;
+            }
+        };
+        unsafe {
+            // the key index becomes the index of the edge after it
+            self.path.last_mut().unwrap().1 = i + 1;
+            if !(*n).is_leaf() {
+                let mut c: *mut Node<K, V, A> = &mut *(*n).edges[i + 1];
+                loop {
+                    self.path.push((c, 0));
+                    if (*c).is_leaf() { break; }
+                    c = &mut *(*c).edges[0];
+                }
+            }
+            settle(&mut self.path);
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        let top = self.path.last().cloned();
+        unsafe {
+            let mut c = match top {
+                Some((n, i)) if (*n).is_leaf() => {
+                    if i > 0 {
+                        self.path.last_mut().unwrap().1 = i - 1;
+                        return;
+                    }
+                    // up to the nearest ancestor entered by a later edge
+                    self.path.pop();
+                    loop {
+                        let (_, e) = match self.path.last() { Some(&top) => top, None => return };
+                        if e > 0 {
+                            self.path.last_mut().unwrap().1 = e - 1;
+                            return;
+                        }
+                        self.path.pop();
+                    }
+                }
+                // down to the last entry before key `i`, through edge `i`
+                Some((n, i)) => &mut *(*n).edges[i] as *mut Node<K, V, A>,
+                // from the ghost, wrap to the last entry
+                None => match self.map.root {
+                    Some(ref mut root) => &mut **root as *mut Node<K, V, A>,
+                    None => return,
+                },
+            };
+            loop {
+                let len = (*c).len();
+                if (*c).is_leaf() {
+                    if len > 0 { self.path.push((c, len - 1)); }
+                    return;
+                }
+                self.path.push((c, len));
+                c = &mut *(*c).edges[len];
+            }
+        }
+    }
+
+    /// Removes the entry at the cursor and moves on to the next one.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        if self.path.is_empty() { return None; }
+        let path = mem::replace(&mut self.path, ::std::vec::Vec::new());
+        let (k, v) = unsafe { self.map.remove_at(path) };
+        self.path = self.map.lower_bound_path(Bound::Excluded(&k));
+        Some((k, v))
+    }
+}
+
+pub struct BTreeSet<T: Ord, A:Alloc + Clone = DefaultAlloc> {
+    map: BTreeMap<T, (), A>,
+}
+
+impl<T: Ord, A:Alloc + Clone> BTreeSet<T, A> {
+    pub fn new() -> Self where A: Default {
+        BTreeSet { map: BTreeMap::new() }
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        BTreeSet { map: BTreeMap::with_alloc(a) }
+    }
+
+    /// Builds a set from strictly ascending elements; see
+    /// `BTreeMap::from_sorted_iter_in`.
+    pub fn from_sorted_iter_in<I>(iter: I, a: A) -> Self where I: IntoIterator<Item=T> {
+        BTreeSet { map: BTreeMap::from_sorted_iter_in(iter.into_iter().map(|t| (t, ())), a) }
+    }
+
+    pub fn len(&self) -> usize { self.map.len() }
+
+    pub fn is_empty(&self) -> bool { self.map.is_empty() }
+
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool where T: Borrow<Q>, Q: Ord {
+        self.map.contains_key(value)
+    }
+
+    /// Adds `value`, returning false if it was already present.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool where T: Borrow<Q>, Q: Ord {
+        self.map.remove(value).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.map.first_key_value().map(|e| e.0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.map.last_key_value().map(|e| e.0)
+    }
+
+    pub fn iter<'a>(&'a self) -> ::std::boxed::Box<Iterator<Item=&'a T> + 'a> {
+        self.map.keys()
+    }
+
+    /// Iterates in ascending order over the elements between `min`
+    /// and `max`.
+    pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self, min: Bound<&'a Min>, max: Bound<&'a Max>)
+                                               -> ::std::boxed::Box<Iterator<Item=&'a T> + 'a>
+        where T: Borrow<Min> + Borrow<Max>, Min: Ord + 'a, Max: Ord + 'a
+    {
+        ::std::boxed::Box::new(self.map.range(min, max).map(|e| e.0))
+    }
+}
+
+impl<T: Ord + fmt::Debug, A:Alloc + Clone> fmt::Debug for BTreeSet<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
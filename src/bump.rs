@@ -0,0 +1,214 @@
+// A chunked bump (region) allocator.
+//
+// Requests are carved off the front of the current chunk by advancing
+// a cursor. When a chunk runs out the next one is used, and new
+// chunks are obtained from the inner allocator as needed (a request
+// too large for the standard chunk size gets a chunk of its own).
+// `dealloc` is a no-op except for the most recent block, which rolls
+// the cursor back, so stack-like patterns reuse memory. `reset`
+// rewinds to the start of the first chunk while keeping every chunk
+// for reuse; chunks go back to the inner allocator on drop.
+//
+// To help size regions from real workloads the allocator reports
+// `used`, `remaining`, `high_water_mark` and `chunk_count`, and can
+// call back when `used` crosses configured thresholds.
+//
+// Memory not currently handed out is marked inaccessible for memory
+// checkers (see `annotate`).
+
+use alloc::{self, Address, AllocError, DefaultAlloc, Kind, Size};
+use annotate;
+
+use std::cmp;
+use std::ptr;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct Alloc<A: alloc::Alloc = DefaultAlloc> {
+    inner: A,
+    chunk_size: usize,
+    chunks: Vec<(Address, usize)>,
+    // index into `chunks` of the chunk being carved; equal to
+    // `chunks.len()` before the first allocation
+    cur: usize,
+    ptr: Address,
+    end: Address,
+    // bytes consumed in chunks before `cur`, so that `used` stays O(1)
+    used_before: usize,
+    high_water: usize,
+    thresholds: Vec<usize>,
+    on_threshold: Option<Box<FnMut(usize)>>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A) -> Alloc<A> {
+        Alloc::with_chunk_size(inner, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(inner: A, chunk_size: usize) -> Alloc<A> {
+        Alloc { inner: inner, chunk_size: chunk_size, chunks: Vec::new(), cur: 0,
+                ptr: ptr::null_mut(), end: ptr::null_mut(), used_before: 0,
+                high_water: 0, thresholds: Vec::new(), on_threshold: None }
+    }
+
+    /// Calls `f(t)` whenever `used()` grows from below `t` to at
+    /// least `t`, for each `t` in `thresholds`.
+    pub fn set_thresholds<F>(&mut self, thresholds: &[usize], f: F) where F: FnMut(usize) + 'static {
+        self.thresholds = thresholds.to_vec();
+        self.on_threshold = Some(Box::new(f));
+    }
+
+    /// Bytes handed out since creation or the last `reset`, including
+    /// alignment padding and space skipped at the end of chunks.
+    pub fn used(&self) -> usize {
+        match self.chunks.get(self.cur) {
+            Some(&(start, _)) => self.used_before + (self.ptr as usize - start as usize),
+            None => 0,
+        }
+    }
+
+    /// Bytes that can still be handed out without obtaining another
+    /// chunk from the inner allocator.
+    pub fn remaining(&self) -> usize {
+        let here = self.end as usize - self.ptr as usize;
+        let later: usize = self.chunks.iter().skip(self.cur + 1).map(|c| c.1).sum();
+        here + later
+    }
+
+    /// The largest value `used()` has reached, across resets.
+    pub fn high_water_mark(&self) -> usize { self.high_water }
+
+    /// Number of chunks obtained from the inner allocator.
+    pub fn chunk_count(&self) -> usize { self.chunks.len() }
+
+    /// Frees every block at once, keeping the chunks for reuse.
+    pub fn reset(&mut self) {
+        for &(start, size) in &self.chunks {
+            annotate::no_access(start, size);
+        }
+        self.cur = 0;
+        self.used_before = 0;
+        match self.chunks.first() {
+            Some(&(start, size)) => unsafe {
+                self.ptr = start;
+                self.end = start.offset(size as isize);
+            },
+            None => {
+                self.ptr = ptr::null_mut();
+                self.end = ptr::null_mut();
+            }
+        }
+    }
+
+    fn note_growth(&mut self, before: usize) {
+        let after = self.used();
+        if after > self.high_water { self.high_water = after; }
+        if let Some(ref mut f) = self.on_threshold {
+            for &t in &self.thresholds {
+                if before < t && t <= after { f(t); }
+            }
+        }
+    }
+
+    unsafe fn bump(&mut self, kind: Kind) -> Address {
+        let align = kind.align();
+        let start = (self.ptr as usize + align - 1) & !(align - 1);
+        if !self.ptr.is_null() && start + kind.size() <= self.end as usize {
+            self.ptr = (start + kind.size()) as Address;
+            return start as Address;
+        }
+        ptr::null_mut()
+    }
+
+    // Moves to the next chunk that can hold `kind`, obtaining one from
+    // the inner allocator if none of the retained ones can.
+    unsafe fn next_chunk(&mut self, kind: Kind) -> bool {
+        let needed = kind.size() + kind.align();
+        let mut next = if self.cur < self.chunks.len() { self.cur + 1 } else { 0 };
+        while next < self.chunks.len() && self.chunks[next].1 < needed {
+            next += 1;
+        }
+        if next == self.chunks.len() {
+            let size = cmp::max(self.chunk_size, needed);
+            let p = self.inner.alloc(Kind::from_size_align(size, 1));
+            if p.is_null() { return false; }
+            annotate::no_access(p, size);
+            self.chunks.push((p, size));
+        }
+        // The rest of the current chunk, and any retained chunks too
+        // small to use, count as consumed until the next reset.
+        if self.cur < next {
+            for &(_, size) in &self.chunks[self.cur..next] {
+                self.used_before += size;
+            }
+        }
+        let (start, size) = self.chunks[next];
+        self.cur = next;
+        self.ptr = start;
+        self.end = start.offset(size as isize);
+        true
+    }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        for &(p, size) in &self.chunks {
+            annotate::undefined(p, size);
+            unsafe { self.inner.dealloc(p, Kind::from_size_align(size, 1)); }
+        }
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        let before = self.used();
+        let mut p = self.bump(kind);
+        if p.is_null() {
+            if !self.next_chunk(kind) { return ptr::null_mut(); }
+            p = self.bump(kind);
+        }
+        self.note_growth(before);
+        annotate::undefined(p, kind.size());
+        p
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        annotate::no_access(ptr, kind.size());
+        // only the most recent block can be given back
+        if ptr.offset(kind.size() as isize) == self.ptr {
+            self.ptr = ptr;
+        }
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        if kind.size() != 0 && ptr.offset(kind.size() as isize) == self.ptr
+            && ptr as usize + new_size <= self.end as usize
+        {
+            let before = self.used();
+            if new_size > kind.size() {
+                annotate::undefined(ptr.offset(kind.size() as isize), new_size - kind.size());
+            }
+            self.ptr = ptr.offset(new_size as isize);
+            self.note_growth(before);
+            Ok(())
+        } else if new_size <= kind.size() {
+            Ok(())
+        } else {
+            Err(AllocError::Exhausted)
+        }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        // Pulls the bump pointer back if this is the newest block.
+        self.grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.size() != 0 && self.grow_in_place(ptr, kind, new_size).is_ok() {
+            return ptr;
+        }
+        alloc::SuperAlloc::realloc(self, ptr, kind, new_size)
+    }
+}
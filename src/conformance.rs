@@ -0,0 +1,189 @@
+// A reusable battery of checks for the `Alloc` contract.
+//
+// Allocator authors call `conformance::check(&mut my_alloc)` from
+// their own tests; it panics with a description of the first
+// violation found. The properties checked are:
+//
+//  * every non-null block is aligned to its `Kind`'s alignment;
+//  * `usable_size(k) >= k.size()`;
+//  * the whole usable size of a block can be written;
+//  * `realloc` preserves the first `min(old, new)` bytes, growing
+//    and shrinking, and keeps the alignment;
+//  * `dealloc` accepts a block with exactly the `Kind` it was
+//    allocated with, and with any size up to its usable size;
+//  * zero-sized kinds round-trip through alloc/realloc/dealloc;
+//  * a `realloc` that fails leaves the old block allocated and intact
+//    (and `check_failed_realloc` provokes such a failure on purpose).
+//
+// Null returns are allowed (an allocator may legitimately refuse a
+// request) and just skip the checks depending on them. Use
+// `testing::fuzz_alloc` for randomized interleavings on top of this.
+
+use alloc::{Alloc, Address, Kind};
+use testing::XorShift;
+
+fn kind(size: usize, align: usize) -> Kind {
+    unsafe { Kind::from_size_align(size, align) }
+}
+
+fn check_aligned(what: &str, p: Address, k: Kind) {
+    assert!(p as usize % k.align() == 0,
+            "conformance: {} returned {:p}, not aligned for {}", what, p, k);
+}
+
+unsafe fn fill(p: Address, len: usize, seed: u8) {
+    for i in 0..len { *p.offset(i as isize) = seed.wrapping_add(i as u8); }
+}
+
+unsafe fn verify_fill(what: &str, p: Address, len: usize, seed: u8) {
+    for i in 0..len {
+        let got = *p.offset(i as isize);
+        assert!(got == seed.wrapping_add(i as u8),
+                "conformance: {}: byte {} of {:p} changed (0x{:02x})", what, i, p, got);
+    }
+}
+
+/// Sizes and alignments exercised by `check`.
+pub const SIZES: &'static [usize] = &[1, 2, 3, 7, 8, 15, 16, 24, 63, 64, 100, 255, 4096, 10000];
+pub const ALIGNS: &'static [usize] = &[1, 2, 4, 8, 16, 32, 64];
+
+/// Runs the whole battery against `a`.
+pub fn check<A: Alloc>(a: &mut A) {
+    check_alloc_dealloc(a);
+    check_usable_size(a);
+    check_realloc(a);
+    check_zero_sized(a);
+    check_interleaved(a, 0xa110c);
+}
+
+pub fn check_alloc_dealloc<A: Alloc>(a: &mut A) {
+    for &align in ALIGNS {
+        for &size in SIZES {
+            let k = kind(size, align);
+            unsafe {
+                let p = a.alloc(k);
+                if p.is_null() { continue; }
+                check_aligned("alloc", p, k);
+                fill(p, size, size as u8);
+                verify_fill("alloc", p, size, size as u8);
+                a.dealloc(p, k);
+            }
+        }
+    }
+}
+
+pub fn check_usable_size<A: Alloc>(a: &mut A) {
+    for &align in ALIGNS {
+        for &size in SIZES {
+            let k = kind(size, align);
+            unsafe {
+                let usable = a.usable_size(k);
+                assert!(usable >= size,
+                        "conformance: usable_size({}) = {} is below the requested size",
+                        k, usable);
+                let p = a.alloc(k);
+                if p.is_null() { continue; }
+                fill(p, usable, 3);
+                verify_fill("usable_size", p, usable, 3);
+                // returning it with its usable size must be accepted
+                a.dealloc(p, kind(usable, align));
+            }
+        }
+    }
+}
+
+pub fn check_realloc<A: Alloc>(a: &mut A) {
+    for &align in ALIGNS {
+        for w in SIZES.windows(2) {
+            let (small, big) = (w[0], w[1]);
+            unsafe {
+                let k = kind(small, align);
+                let p = a.alloc(k);
+                if p.is_null() { continue; }
+                fill(p, small, 9);
+                let q = a.realloc(p, k, big);
+                if q.is_null() {
+                    verify_fill("failed realloc (grow)", p, small, 9);
+                    a.dealloc(p, k);
+                    continue;
+                }
+                check_aligned("realloc (grow)", q, kind(big, align));
+                verify_fill("realloc (grow)", q, small, 9);
+                fill(q, big, 11);
+                let r = a.realloc(q, kind(big, align), small);
+                if r.is_null() {
+                    verify_fill("failed realloc (shrink)", q, big, 11);
+                    a.dealloc(q, kind(big, align));
+                    continue;
+                }
+                check_aligned("realloc (shrink)", r, k);
+                verify_fill("realloc (shrink)", r, small, 11);
+                a.dealloc(r, k);
+            }
+        }
+    }
+}
+
+/// Asks `a` to grow a block to `huge` bytes, which it is expected to
+/// refuse, and checks that the block survives the refusal. Not part
+/// of `check`, since what is too large depends on the allocator.
+pub fn check_failed_realloc<A: Alloc>(a: &mut A, huge: usize) {
+    unsafe {
+        let k = kind(64, 8);
+        let p = a.alloc(k);
+        if p.is_null() { return; }
+        fill(p, 64, 13);
+        let q = a.realloc(p, k, huge);
+        assert!(q.is_null(), "conformance: realloc to {} bytes unexpectedly succeeded", huge);
+        verify_fill("failed realloc", p, 64, 13);
+        fill(p, 64, 17);
+        a.dealloc(p, k);
+    }
+}
+
+pub fn check_zero_sized<A: Alloc>(a: &mut A) {
+    for &align in ALIGNS {
+        let k = kind(0, align);
+        unsafe {
+            let p = a.alloc(k);
+            if p.is_null() { continue; }
+            check_aligned("alloc (zero-sized)", p, k);
+            let q = a.realloc(p, k, 32);
+            if q.is_null() { a.dealloc(p, k); continue; }
+            check_aligned("realloc (from zero-sized)", q, kind(32, align));
+            fill(q, 32, 5);
+            let r = a.realloc(q, kind(32, align), 0);
+            if r.is_null() { a.dealloc(q, kind(32, align)); continue; }
+            a.dealloc(r, k);
+        }
+    }
+}
+
+/// Keeps many blocks live at once and checks none of them overlap or
+/// get clobbered while others are allocated and freed.
+pub fn check_interleaved<A: Alloc>(a: &mut A, seed: u64) {
+    let mut rng = XorShift::new(seed);
+    let mut live: Vec<(Address, Kind, u8)> = Vec::new();
+    for round in 0..256usize {
+        let k = kind(SIZES[rng.below(SIZES.len())], ALIGNS[rng.below(ALIGNS.len())]);
+        unsafe {
+            let p = a.alloc(k);
+            if !p.is_null() {
+                check_aligned("alloc", p, k);
+                fill(p, k.size(), round as u8);
+                live.push((p, k, round as u8));
+            }
+            if round % 3 == 2 && !live.is_empty() {
+                let (p, k, tag) = live.swap_remove(rng.below(live.len()));
+                verify_fill("interleaved", p, k.size(), tag);
+                a.dealloc(p, k);
+            }
+        }
+    }
+    for (p, k, tag) in live {
+        unsafe {
+            verify_fill("interleaved", p, k.size(), tag);
+            a.dealloc(p, k);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Allocation tracking with callsites, enabled by the
+// `track-callsites` feature.
+//
+// `Track<A>` captures a backtrace for every allocation and keeps it
+// alongside the block's `Kind` until the block is freed. Freed blocks
+// keep both the allocating and the freeing backtrace, so that:
+//
+//  * `leaks()` reports who allocated each outstanding block, and
+//  * a double free panics with where the block was allocated and
+//    where it was first freed.
+//
+// Backtraces are captured as raw instruction pointers and only
+// symbolized when a report is rendered. Everything is kept in memory,
+// so this is for debugging sessions, not production.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use backtrace;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+/// A captured stack, innermost frame first.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Callsite {
+    frames: Vec<usize>,
+}
+
+impl Callsite {
+    /// Captures the current stack, omitting `skip` innermost frames.
+    pub fn capture(skip: usize) -> Callsite {
+        let mut frames = Vec::new();
+        let mut n = 0;
+        backtrace::trace(&mut |frame| {
+            if n >= skip { frames.push(frame.ip() as usize); }
+            n += 1;
+            true
+        });
+        Callsite { frames: frames }
+    }
+
+    /// One line per frame: `symbol (file:line)` where known.
+    pub fn resolve(&self) -> Vec<String> {
+        self.frames.iter().map(|&ip| {
+            let mut line = format!("0x{:x}", ip);
+            backtrace::resolve(ip as *mut c_void, &mut |sym| {
+                if let Some(name) = sym.name() {
+                    line = String::from_utf8_lossy(name).into_owned();
+                }
+                if let (Some(file), Some(no)) = (sym.filename(), sym.lineno()) {
+                    line.push_str(&format!(" ({}:{})", String::from_utf8_lossy(file), no));
+                }
+            });
+            line
+        }).collect()
+    }
+}
+
+impl fmt::Debug for Callsite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.resolve() {
+            try!(writeln!(f, "    {}", line));
+        }
+        Ok(())
+    }
+}
+
+/// An outstanding allocation and where it came from.
+#[derive(Clone, Debug)]
+pub struct TrackedLeak {
+    pub addr: usize,
+    pub kind: Kind,
+    pub allocated_at: Callsite,
+}
+
+struct State<A> {
+    inner: RefCell<A>,
+    live: RefCell<HashMap<usize, (Kind, Callsite)>>,
+    freed: RefCell<HashMap<usize, (Callsite, Callsite)>>,
+}
+
+#[derive(Clone)]
+pub struct Track<A> {
+    state: Rc<State<A>>,
+}
+
+// frames belonging to `Callsite::capture` and the `Alloc` method
+const SKIP: usize = 2;
+
+impl<A: alloc::Alloc> Track<A> {
+    pub fn new(inner: A) -> Track<A> {
+        Track {
+            state: Rc::new(State {
+                inner: RefCell::new(inner),
+                live: RefCell::new(HashMap::new()),
+                freed: RefCell::new(HashMap::new()),
+            })
+        }
+    }
+
+    pub fn leaks(&self) -> Vec<TrackedLeak> {
+        self.state.live.borrow().iter().map(|(&addr, &(kind, ref site))| {
+            TrackedLeak { addr: addr, kind: kind, allocated_at: site.clone() }
+        }).collect()
+    }
+
+    fn record(&self, p: Address, kind: Kind) {
+        if p.is_null() || kind.size() == 0 { return; }
+        self.state.freed.borrow_mut().remove(&(p as usize));
+        self.state.live.borrow_mut().insert(p as usize, (kind, Callsite::capture(SKIP + 1)));
+    }
+
+    // After a resize in place the block keeps its allocating callsite.
+    fn resize(&self, p: Address, new_kind: Kind) {
+        if let Some(entry) = self.state.live.borrow_mut().get_mut(&(p as usize)) {
+            entry.0 = new_kind;
+        }
+    }
+
+    fn release(&self, what: &str, p: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        let addr = p as usize;
+        match self.state.live.borrow_mut().remove(&addr) {
+            Some((_, allocated_at)) => {
+                let freed_at = Callsite::capture(SKIP + 1);
+                self.state.freed.borrow_mut().insert(addr, (allocated_at, freed_at));
+            }
+            None => match self.state.freed.borrow().get(&addr) {
+                Some(&(ref allocated_at, ref freed_at)) =>
+                    panic!("debug::Track: {} of 0x{:x} ({}) already freed\n\
+                            allocated at:\n{:?}first freed at:\n{:?}",
+                           what, addr, kind, allocated_at, freed_at),
+                None =>
+                    panic!("debug::Track: {} of 0x{:x} ({}), which was never allocated",
+                           what, addr, kind),
+            },
+        }
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Track<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        let p = self.state.inner.borrow_mut().alloc(kind);
+        self.record(p, kind);
+        p
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.release("dealloc", ptr, kind);
+        self.state.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.release("dealloc_hot", ptr, kind);
+        self.state.inner.borrow_mut().dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.state.inner.borrow().usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.state.inner.borrow_mut().grow_in_place(ptr, kind, new_size);
+        if r.is_ok() { self.resize(ptr, Kind::from_size_align(new_size, kind.align())); }
+        r
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.state.inner.borrow_mut().shrink_in_place(ptr, kind, new_size);
+        if r.is_ok() { self.resize(ptr, Kind::from_size_align(new_size, kind.align())); }
+        r
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let p = self.state.inner.borrow_mut().realloc(ptr, kind, new_size);
+        if !p.is_null() {
+            self.release("realloc", ptr, kind);
+            self.record(p, Kind::from_size_align(new_size, kind.align()));
+        }
+        p
+    }
+}
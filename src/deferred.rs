@@ -0,0 +1,128 @@
+// A wrapper that can put off deallocation until a safe point.
+//
+// Structures with optimistic readers (a reader may still be looking
+// at a node that a writer has just unlinked) cannot free memory the
+// moment it is unlinked. `dealloc_deferred` queues the block instead,
+// and `collect()` frees everything queued, to be called once no
+// reader can hold an old pointer (an epoch boundary, the end of a
+// frame). Ordinary `dealloc` still frees immediately.
+//
+// To keep a missing `collect` from growing the queue without bound,
+// the queue has a byte limit: `dealloc_deferred` refuses a block that
+// would exceed it, handing it back so the caller can collect first.
+
+use alloc::{self, Address, Capacity, Kind, ShareAlloc, Size};
+use pressure::{self, Trim};
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+
+/// Returned by `dealloc_deferred` when the queue is full; nothing was
+/// queued.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QueueFull {
+    pub ptr: Address,
+    pub kind: Kind,
+}
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deferred free queue is full")
+    }
+}
+
+pub struct Alloc<A> {
+    inner: RefCell<A>,
+    queue: RefCell<Vec<(Address, Kind)>>,
+    queued_bytes: Cell<usize>,
+    max_queued_bytes: usize,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A, max_queued_bytes: usize) -> Alloc<A> {
+        Alloc { inner: RefCell::new(inner), queue: RefCell::new(Vec::new()),
+                queued_bytes: Cell::new(0), max_queued_bytes: max_queued_bytes }
+    }
+
+    /// Queues the block for freeing at the next `collect`.
+    pub unsafe fn dealloc_deferred(&self, ptr: Address, kind: Kind) -> Result<(), QueueFull> {
+        let bytes = self.queued_bytes.get() + kind.size();
+        if bytes > self.max_queued_bytes {
+            return Err(QueueFull { ptr: ptr, kind: kind });
+        }
+        self.queue.borrow_mut().push((ptr, kind));
+        self.queued_bytes.set(bytes);
+        Ok(())
+    }
+
+    /// Frees every queued block, returning how many there were.
+    pub fn collect(&self) -> usize {
+        let queue = ::std::mem::replace(&mut *self.queue.borrow_mut(), Vec::new());
+        let mut inner = self.inner.borrow_mut();
+        for &(p, kind) in &queue {
+            unsafe { inner.dealloc(p, kind); }
+        }
+        self.queued_bytes.set(0);
+        queue.len()
+    }
+
+    pub fn queued_bytes(&self) -> usize { self.queued_bytes.get() }
+
+    pub fn queued_count(&self) -> usize { self.queue.borrow().len() }
+}
+
+// Trimming is a `collect`, so register the wrapper with `pressure`
+// only if `release` is called at points where collecting is safe. A
+// wrapper that is busy (its queue or allocator borrowed) is skipped.
+impl<A: alloc::Alloc> Trim for Alloc<A> {
+    fn trim(&self, _level: pressure::Level) -> usize {
+        let mut inner = match self.inner.try_borrow_mut() { Ok(i) => i, Err(_) => return 0 };
+        let queue = match self.queue.try_borrow_mut() {
+            Ok(mut q) => ::std::mem::replace(&mut *q, Vec::new()),
+            Err(_) => return 0,
+        };
+        for &(p, kind) in &queue {
+            unsafe { inner.dealloc(p, kind); }
+        }
+        let bytes = self.queued_bytes.get();
+        self.queued_bytes.set(0);
+        bytes
+    }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        self.collect();
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        self.inner.borrow_mut().alloc(kind)
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        self.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot_shared(&self, ptr: Address, kind: Kind) {
+        self.inner.borrow_mut().dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        self.inner.borrow().usable_size(kind)
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.inner.borrow_mut().realloc(ptr, kind, new_size)
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
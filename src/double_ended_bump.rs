@@ -0,0 +1,128 @@
+// A bump allocator that allocates from both ends of one region.
+//
+// The usual game-engine split: long-lived "level" data is allocated
+// from the top of the region downwards, per-frame temporaries from the
+// bottom upwards, and the region is full only when the two cursors
+// meet. Each end has its own marks, so the frame end can be rewound
+// every frame while the level end is rewound on level change.
+//
+// `front()` and `back()` give `Alloc` handles for the two ends. They
+// borrow the region, so rewinding (which takes `&mut self`) cannot
+// happen while any container built on a handle is still alive. As in
+// `bump`, freeing the most recent block on an end rolls that end
+// back; other frees are no-ops until the end is rewound.
+
+use alloc::{self, Address, DefaultAlloc, Kind};
+
+use std::cell::Cell;
+use std::ptr;
+
+/// A saved cursor position on one end of the region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mark(usize);
+
+pub struct Alloc<A: alloc::Alloc = DefaultAlloc> {
+    inner: A,
+    base: Address,
+    len: usize,
+    // offsets from `base`: [0, front) is in use from the front,
+    // [back, len) from the back
+    front: Cell<usize>,
+    back: Cell<usize>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Obtains a `len`-byte region from `inner`.
+    ///
+    /// Aborts via `oom` if `inner` cannot provide it.
+    pub fn new(mut inner: A, len: usize) -> Alloc<A> {
+        unsafe {
+            let base = inner.alloc(Kind::from_size_align(len, 16));
+            if base.is_null() { inner.oom() }
+            Alloc { inner: inner, base: base, len: len,
+                    front: Cell::new(0), back: Cell::new(len) }
+        }
+    }
+
+    /// An allocator serving requests from the bottom of the region.
+    pub fn front(&self) -> End<A> { End { region: self, front: true } }
+
+    /// An allocator serving requests from the top of the region.
+    pub fn back(&self) -> End<A> { End { region: self, front: false } }
+
+    /// Bytes not in use by either end.
+    pub fn free(&self) -> usize { self.back.get() - self.front.get() }
+
+    pub fn front_mark(&self) -> Mark { Mark(self.front.get()) }
+
+    pub fn back_mark(&self) -> Mark { Mark(self.back.get()) }
+
+    /// Frees everything allocated from the front since `mark`.
+    pub fn reset_front_to(&mut self, mark: Mark) {
+        assert!(mark.0 <= self.front.get(), "mark is ahead of the front cursor");
+        self.front.set(mark.0);
+    }
+
+    /// Frees everything allocated from the back since `mark`.
+    pub fn reset_back_to(&mut self, mark: Mark) {
+        assert!(mark.0 >= self.back.get() && mark.0 <= self.len,
+                "mark is ahead of the back cursor");
+        self.back.set(mark.0);
+    }
+
+    pub fn reset_front(&mut self) { self.front.set(0); }
+
+    pub fn reset_back(&mut self) { let len = self.len; self.back.set(len); }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        unsafe { self.inner.dealloc(self.base, Kind::from_size_align(self.len, 16)); }
+    }
+}
+
+/// One end of a double-ended region.
+pub struct End<'a, A: alloc::Alloc + 'a> {
+    region: &'a Alloc<A>,
+    front: bool,
+}
+
+impl<'a, A: alloc::Alloc> Clone for End<'a, A> {
+    fn clone(&self) -> Self { End { region: self.region, front: self.front } }
+}
+
+impl<'a, A: alloc::Alloc> alloc::Alloc for End<'a, A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        let r = self.region;
+        let base = r.base as usize;
+        let align = kind.align();
+        if self.front {
+            let start = (base + r.front.get() + align - 1) & !(align - 1);
+            let end = match start.checked_add(kind.size()) { Some(e) => e, None => return ptr::null_mut() };
+            if end > base + r.back.get() { return ptr::null_mut(); }
+            r.front.set(end - base);
+            start as Address
+        } else {
+            let top = base + r.back.get();
+            if top < base + kind.size() { return ptr::null_mut(); }
+            let start = (top - kind.size()) & !(align - 1);
+            if start < base + r.front.get() { return ptr::null_mut(); }
+            r.back.set(start - base);
+            start as Address
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        let r = self.region;
+        let off = ptr as usize - r.base as usize;
+        if self.front {
+            if off + kind.size() == r.front.get() { r.front.set(off); }
+        } else if off == r.back.get() {
+            // Any alignment padding above the block stays in use
+            // until the back end is rewound.
+            r.back.set(off + kind.size());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// Header-plus-tail records: C's flexible array members.
+//
+// `alloc_with_tail` allocates one block holding an `H` followed by
+// `n` values of `T`, with the layout computed by `Kind::extend` and
+// `Kind::array`, so callers never do the padding arithmetic by hand.
+// The block is identified by a pointer to its header; the tail is
+// found again with `tail`/`tail_mut`, which need the same `n`. The
+// header usually records `n` itself.
+//
+// These functions are unsafe to use correctly rather than to call:
+// `n` must be remembered, and `dealloc_with_tail` must be given the
+// same `H`, `T` and `n` as the allocation.
+
+use alloc::{Alloc, Kind};
+
+use std::intrinsics;
+use std::mem;
+use std::ptr::{self, Unique};
+use std::slice;
+
+/// The layout of an `H` followed by `n` values of `T`, and the offset
+/// of the first `T`.
+pub fn kind_with_tail<H, T>(n: usize) -> (Kind, usize) {
+    Kind::new::<H>().extend(Kind::new::<T>().array(n))
+}
+
+/// Allocates an `H` followed by `n` values of `T` from `alloc`,
+/// initializing the header with `init_header()` and element `i` with
+/// `init_elem(i)`.
+///
+/// Returns `None` if the allocator fails. If an initializer panics,
+/// what was built so far is dropped and the block freed.
+pub fn alloc_with_tail<H, T, A, FH, FE>(alloc: &mut A, n: usize,
+                                        init_header: FH, mut init_elem: FE) -> Option<Unique<H>>
+    where A: Alloc, FH: FnOnce() -> H, FE: FnMut(usize) -> T
+{
+    let (kind, offset) = kind_with_tail::<H, T>(n);
+    unsafe {
+        let block = if kind.size() == 0 { kind.dangling() } else { alloc.alloc(kind) };
+        if block.is_null() { return None; }
+
+        // Cleans up if an initializer unwinds: `header` says whether
+        // the header was written, `done` how many elements were.
+        struct Guard<'a, H, T, A: Alloc + 'a> {
+            alloc: &'a mut A,
+            block: *mut u8,
+            kind: Kind,
+            offset: usize,
+            header: bool,
+            done: usize,
+            _marker: ::std::marker::PhantomData<(H, T)>,
+        }
+        impl<'a, H, T, A: Alloc> Drop for Guard<'a, H, T, A> {
+            fn drop(&mut self) {
+                unsafe {
+                    let elems = self.block.offset(self.offset as isize) as *mut T;
+                    for i in 0..self.done { intrinsics::drop_in_place(elems.offset(i as isize)); }
+                    if self.header { intrinsics::drop_in_place(self.block as *mut H); }
+                    if self.kind.size() != 0 { self.alloc.dealloc(self.block, self.kind); }
+                }
+            }
+        }
+
+        let mut g: Guard<H, T, A> = Guard { alloc: alloc, block: block, kind: kind, offset: offset,
+                                            header: false, done: 0,
+                                            _marker: ::std::marker::PhantomData };
+        ptr::write(block as *mut H, init_header());
+        g.header = true;
+        let elems = block.offset(offset as isize) as *mut T;
+        while g.done < n {
+            ptr::write(elems.offset(g.done as isize), init_elem(g.done));
+            g.done += 1;
+        }
+        mem::forget(g);
+        Some(Unique::new(block as *mut H))
+    }
+}
+
+/// The tail of a block from `alloc_with_tail` with the same `n`.
+pub unsafe fn tail<'a, H, T>(header: *const H, n: usize) -> &'a [T] {
+    let (_, offset) = kind_with_tail::<H, T>(n);
+    slice::from_raw_parts((header as *const u8).offset(offset as isize) as *const T, n)
+}
+
+pub unsafe fn tail_mut<'a, H, T>(header: *mut H, n: usize) -> &'a mut [T] {
+    let (_, offset) = kind_with_tail::<H, T>(n);
+    slice::from_raw_parts_mut((header as *mut u8).offset(offset as isize) as *mut T, n)
+}
+
+/// Drops the header and the `n` tail elements, then returns the block
+/// to `alloc`.
+pub unsafe fn dealloc_with_tail<H, T, A: Alloc>(alloc: &mut A, header: Unique<H>, n: usize) {
+    let p = *header;
+    let (kind, _) = kind_with_tail::<H, T>(n);
+    for e in tail_mut::<H, T>(p, n) { intrinsics::drop_in_place(e); }
+    intrinsics::drop_in_place(p);
+    if kind.size() != 0 { alloc.dealloc(p as *mut u8, kind); }
+}
@@ -0,0 +1,132 @@
+// Exposing an `Alloc` to C code as malloc/free/realloc-style functions.
+//
+// C callers do not pass a size to `free`, so every block carries a
+// `HEADER`-byte prefix recording its payload size and alignment. The
+// header is as large as `MAX_ALIGN`, which keeps the payload aligned
+// as `malloc` guarantees. `aligned_malloc` serves larger alignments
+// (e.g. 64 for cache-line or AVX-512 data) by asking the allocator
+// for a block of that alignment and starting the payload a whole
+// alignment in, with the header just before it.
+//
+// The allocator must then really honour the alignment. A backend that
+// stops short of it (one over plain `malloc`, say) should be wrapped
+// in `align_cap::Alloc` in `Emulate` mode before being handed over.
+//
+// The functions take the allocator as an opaque context pointer,
+// which is what C libraries accepting custom allocators hand back on
+// every call (lua's `ud`, zlib's `opaque`, ...).
+
+use alloc::{Alloc, Kind};
+
+use std::cmp;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// Alignment guaranteed for every block returned through the shim.
+pub const MAX_ALIGN: usize = 16;
+const HEADER: usize = MAX_ALIGN;
+
+pub type MallocFn = extern "C" fn(ctx: *mut c_void, size: usize) -> *mut c_void;
+pub type FreeFn = extern "C" fn(ctx: *mut c_void, ptr: *mut c_void);
+pub type ReallocFn = extern "C" fn(ctx: *mut c_void, ptr: *mut c_void, size: usize) -> *mut c_void;
+pub type AlignedMallocFn = extern "C" fn(ctx: *mut c_void, align: usize, size: usize) -> *mut c_void;
+
+/// A set of C-callable entry points bound to one allocator.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CAllocVtable {
+    pub ctx: *mut c_void,
+    pub malloc: MallocFn,
+    pub free: FreeFn,
+    pub realloc: ReallocFn,
+    /// Like C11 `aligned_alloc`: `align` must be a power of two, and
+    /// the block is released with `free` and resized with `realloc`
+    /// (which keeps its alignment).
+    pub aligned_malloc: AlignedMallocFn,
+}
+
+/// Builds the C vtable for `alloc`, which must outlive every block
+/// the C side allocates through it.
+pub fn c_api_for<A: Alloc>(alloc: &'static mut A) -> CAllocVtable {
+    CAllocVtable {
+        ctx: alloc as *mut A as *mut c_void,
+        malloc: c_malloc::<A>,
+        free: c_free::<A>,
+        realloc: c_realloc::<A>,
+        aligned_malloc: c_aligned_malloc::<A>,
+    }
+}
+
+// Bytes from the start of a block to its payload.
+fn payload_offset(align: usize) -> usize {
+    cmp::max(align, HEADER)
+}
+
+fn block_kind(size: usize, align: usize) -> Option<Kind> {
+    size.checked_add(payload_offset(align))
+        .map(|total| unsafe { Kind::from_size_align(total, cmp::max(align, MAX_ALIGN)) })
+}
+
+// The payload's size and alignment, in the two words before it.
+unsafe fn header_of(payload: *mut c_void) -> *mut usize {
+    (payload as *mut usize).offset(-2)
+}
+
+unsafe fn block_of(payload: *mut c_void) -> (*mut u8, Kind) {
+    let h = header_of(payload);
+    let (size, align) = (*h, *h.offset(1));
+    ((payload as *mut u8).offset(-(payload_offset(align) as isize)),
+     block_kind(size, align).unwrap())
+}
+
+unsafe fn finish(block: *mut u8, size: usize, align: usize) -> *mut c_void {
+    let payload = block.offset(payload_offset(align) as isize) as *mut c_void;
+    let h = header_of(payload);
+    *h = size;
+    *h.offset(1) = align;
+    payload
+}
+
+unsafe fn malloc_aligned<A: Alloc>(ctx: *mut c_void, align: usize, size: usize) -> *mut c_void {
+    let a = &mut *(ctx as *mut A);
+    let kind = match block_kind(size, align) { Some(k) => k, None => return ptr::null_mut() };
+    let block = a.alloc(kind);
+    if block.is_null() { return ptr::null_mut(); }
+    finish(block, size, align)
+}
+
+extern "C" fn c_malloc<A: Alloc>(ctx: *mut c_void, size: usize) -> *mut c_void {
+    unsafe { malloc_aligned::<A>(ctx, MAX_ALIGN, size) }
+}
+
+extern "C" fn c_aligned_malloc<A: Alloc>(ctx: *mut c_void, align: usize, size: usize) -> *mut c_void {
+    if !align.is_power_of_two() { return ptr::null_mut(); }
+    unsafe { malloc_aligned::<A>(ctx, cmp::max(align, MAX_ALIGN), size) }
+}
+
+extern "C" fn c_free<A: Alloc>(ctx: *mut c_void, payload: *mut c_void) {
+    if payload.is_null() { return; }
+    unsafe {
+        let a = &mut *(ctx as *mut A);
+        let (block, kind) = block_of(payload);
+        a.dealloc(block, kind);
+    }
+}
+
+extern "C" fn c_realloc<A: Alloc>(ctx: *mut c_void, payload: *mut c_void,
+                                  size: usize) -> *mut c_void {
+    if payload.is_null() { return c_malloc::<A>(ctx, size); }
+    if size == 0 {
+        c_free::<A>(ctx, payload);
+        return ptr::null_mut();
+    }
+    unsafe {
+        let a = &mut *(ctx as *mut A);
+        let align = *header_of(payload).offset(1);
+        let (block, old_kind) = block_of(payload);
+        let new_total = match block_kind(size, align) { Some(k) => k.size(), None => return ptr::null_mut() };
+        let block = a.realloc(block, old_kind, new_total);
+        if block.is_null() { return ptr::null_mut(); }
+        finish(block, size, align)
+    }
+}
@@ -0,0 +1,115 @@
+// An arena addressed by generation-checked handles.
+//
+// `insert` returns a `Handle { index, generation }` instead of a
+// pointer. Each slot remembers its current generation, which is bumped
+// whenever the slot is freed; a handle whose generation no longer
+// matches is stale, and `get` returns `None` for it instead of
+// aliasing whatever now lives in the slot. This gives entity-style
+// code a safe API with no lifetimes tying handles to the arena.
+//
+// Slots live in a `Vec` from the arena's allocator; freed slots are
+// chained into a free list through the vacant entries and reused
+// before the vector grows.
+
+use alloc::{Alloc, DefaultAlloc};
+use vec::Vec;
+
+use std::mem;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Handle {
+    pub index: u32,
+    pub generation: u32,
+}
+
+enum Entry<T> {
+    Occupied { generation: u32, value: T },
+    // `next_free` is `!0` at the end of the free list
+    Vacant { generation: u32, next_free: u32 },
+}
+
+pub struct Arena<T, A:Alloc = DefaultAlloc> {
+    entries: Vec<Entry<T>, A>,
+    free_head: u32,
+    len: usize,
+}
+
+impl<T, A:Alloc> Arena<T, A> {
+    pub fn new() -> Self where A: Default {
+        Arena::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        Arena { entries: Vec::with_alloc(a), free_head: !0, len: 0 }
+    }
+
+    pub fn with_capacity_alloc(capacity: usize, a: A) -> Self {
+        Arena { entries: Vec::with_capacity_alloc(capacity, a), free_head: !0, len: 0 }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn insert(&mut self, value: T) -> Handle {
+        self.len += 1;
+        if self.free_head != !0 {
+            let index = self.free_head;
+            let slot = &mut self.entries[index as usize];
+            let (generation, next_free) = match *slot {
+                Entry::Vacant { generation, next_free } => (generation, next_free),
+                Entry::Occupied { .. } => unreachable!(),
+            };
+            *slot = Entry::Occupied { generation: generation, value: value };
+            self.free_head = next_free;
+            Handle { index: index, generation: generation }
+        } else {
+            let index = self.entries.len();
+            assert!(index < !0u32 as usize, "gen_arena: too many slots");
+            self.entries.push(Entry::Occupied { generation: 0, value: value });
+            Handle { index: index as u32, generation: 0 }
+        }
+    }
+
+    pub fn get(&self, h: Handle) -> Option<&T> {
+        match self.entries.get(h.index as usize) {
+            Some(&Entry::Occupied { generation, ref value }) if generation == h.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, h: Handle) -> Option<&mut T> {
+        match self.entries.get_mut(h.index as usize) {
+            Some(&mut Entry::Occupied { generation, ref mut value }) if generation == h.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, h: Handle) -> bool {
+        self.get(h).is_some()
+    }
+
+    /// Removes the value for `h`, invalidating `h` and every copy of
+    /// it. Returns `None` if `h` was already stale.
+    pub fn remove(&mut self, h: Handle) -> Option<T> {
+        if !self.contains(h) { return None; }
+        let vacant = Entry::Vacant { generation: h.generation.wrapping_add(1),
+                                     next_free: self.free_head };
+        let old = mem::replace(&mut self.entries[h.index as usize], vacant);
+        self.free_head = h.index;
+        self.len -= 1;
+        match old {
+            Entry::Occupied { value, .. } => Some(value),
+            Entry::Vacant { .. } => unreachable!(),
+        }
+    }
+
+    /// Iterates over the live values and their handles.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(Handle, &'a T)> + 'a> {
+        Box::new(self.entries.iter().enumerate().filter_map(|(i, e)| match *e {
+            Entry::Occupied { generation, ref value } =>
+                Some((Handle { index: i as u32, generation: generation }, value)),
+            Entry::Vacant { .. } => None,
+        }))
+    }
+}
@@ -0,0 +1,183 @@
+// Adapting an `Alloc` instance to serve as the process-wide heap.
+//
+// The standard library routes `alloc::heap` through five symbols
+// (`__rust_allocate`, `__rust_deallocate`, `__rust_reallocate`,
+// `__rust_reallocate_inplace` and `__rust_usable_size`) provided by
+// whichever crate is marked `#![allocator]`. The `as_global!` macro
+// defines a `GlobalAdapter` static and those symbols forwarding to it;
+// the crate invoking the macro must carry the `#![allocator]`
+// attribute itself, since a macro cannot add crate attributes.
+//
+// The adapter serializes access with a spin lock, so the wrapped
+// allocator need not be thread-safe. It must however never allocate
+// from the global heap itself, or it will deadlock on that lock.
+
+use alloc::{Alloc, AllocError, Kind};
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Counters maintained by a `GlobalAdapter` for every heap operation.
+///
+/// These take the place of a stats wrapper in the global position,
+/// where the `Rc`/`RefCell` based wrappers cannot be used.
+pub struct GlobalStats {
+    allocs: AtomicUsize,
+    deallocs: AtomicUsize,
+    reallocs: AtomicUsize,
+    live_bytes: AtomicUsize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlobalStatsSnapshot {
+    pub allocs: usize,
+    pub deallocs: usize,
+    pub reallocs: usize,
+    pub live_bytes: usize,
+}
+
+impl GlobalStats {
+    pub const fn new() -> GlobalStats {
+        GlobalStats {
+            allocs: AtomicUsize::new(0),
+            deallocs: AtomicUsize::new(0),
+            reallocs: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn snapshot(&self) -> GlobalStatsSnapshot {
+        GlobalStatsSnapshot {
+            allocs: self.allocs.load(Ordering::Relaxed),
+            deallocs: self.deallocs.load(Ordering::Relaxed),
+            reallocs: self.reallocs.load(Ordering::Relaxed),
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct GlobalAdapter<A> {
+    lock: AtomicBool,
+    inner: UnsafeCell<A>,
+    stats: GlobalStats,
+}
+
+unsafe impl<A: Send> Sync for GlobalAdapter<A> { }
+
+impl<A> GlobalAdapter<A> {
+    pub const fn new(inner: A) -> GlobalAdapter<A> {
+        GlobalAdapter {
+            lock: AtomicBool::new(false),
+            inner: UnsafeCell::new(inner),
+            stats: GlobalStats::new(),
+        }
+    }
+
+    pub fn stats(&self) -> &GlobalStats { &self.stats }
+}
+
+impl<A: Alloc> GlobalAdapter<A> {
+    fn with<R, F: FnOnce(&mut A) -> R>(&self, f: F) -> R {
+        while self.lock.compare_and_swap(false, true, Ordering::Acquire) { }
+        let ret = f(unsafe { &mut *self.inner.get() });
+        self.lock.store(false, Ordering::Release);
+        ret
+    }
+
+    pub unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        let p = self.with(|a| a.alloc(Kind::from_size_align(size, align)));
+        if !p.is_null() {
+            self.stats.allocs.fetch_add(1, Ordering::Relaxed);
+            self.stats.live_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+        p
+    }
+
+    pub unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        self.with(|a| a.dealloc(ptr, Kind::from_size_align(old_size, align)));
+        self.stats.deallocs.fetch_add(1, Ordering::Relaxed);
+        self.stats.live_bytes.fetch_sub(old_size, Ordering::Relaxed);
+    }
+
+    pub unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize,
+                             size: usize, align: usize) -> *mut u8 {
+        let p = self.with(|a| a.realloc(ptr, Kind::from_size_align(old_size, align), size));
+        if !p.is_null() {
+            self.stats.reallocs.fetch_add(1, Ordering::Relaxed);
+            self.stats.live_bytes.fetch_add(size, Ordering::Relaxed);
+            self.stats.live_bytes.fetch_sub(old_size, Ordering::Relaxed);
+        }
+        p
+    }
+
+    /// Resizes with `grow_in_place` or `shrink_in_place`. Per the heap
+    /// contract it returns the usable size of the block: that for
+    /// `size` if the resize succeeded, for `old_size` if not.
+    pub unsafe fn reallocate_inplace(&self, ptr: *mut u8, old_size: usize,
+                                     size: usize, align: usize) -> usize {
+        let kind = Kind::from_size_align(old_size, align);
+        let resized = self.with(|a| {
+            if size >= old_size {
+                a.grow_in_place(ptr, kind, size)
+            } else if size != 0 {
+                a.shrink_in_place(ptr, kind, size)
+            } else {
+                Err(AllocError::Unsupported)
+            }
+        });
+        match resized {
+            Ok(()) => {
+                self.stats.live_bytes.fetch_add(size, Ordering::Relaxed);
+                self.stats.live_bytes.fetch_sub(old_size, Ordering::Relaxed);
+                self.usable_size(size, align)
+            }
+            Err(_) => self.usable_size(old_size, align),
+        }
+    }
+
+    pub unsafe fn usable_size(&self, size: usize, align: usize) -> usize {
+        self.with(|a| a.usable_size(Kind::from_size_align(size, align)))
+    }
+}
+
+/// Installs `$init` (of type `$t`) as the process allocator, exposed
+/// as the static `$name`; e.g.
+///
+/// ```ignore
+/// #![allocator]
+/// as_global!(HEAP: MyAlloc = MyAlloc::new());
+/// ```
+#[macro_export]
+macro_rules! as_global {
+    ($name:ident : $t:ty = $init:expr) => {
+        pub static $name: $crate::global::GlobalAdapter<$t> =
+            $crate::global::GlobalAdapter::new($init);
+
+        #[no_mangle]
+        pub extern fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
+            unsafe { $name.allocate(size, align) }
+        }
+
+        #[no_mangle]
+        pub extern fn __rust_deallocate(ptr: *mut u8, old_size: usize, align: usize) {
+            unsafe { $name.deallocate(ptr, old_size, align) }
+        }
+
+        #[no_mangle]
+        pub extern fn __rust_reallocate(ptr: *mut u8, old_size: usize, size: usize,
+                                        align: usize) -> *mut u8 {
+            unsafe { $name.reallocate(ptr, old_size, size, align) }
+        }
+
+        #[no_mangle]
+        pub extern fn __rust_reallocate_inplace(ptr: *mut u8, old_size: usize, size: usize,
+                                                align: usize) -> usize {
+            unsafe { $name.reallocate_inplace(ptr, old_size, size, align) }
+        }
+
+        #[no_mangle]
+        pub extern fn __rust_usable_size(size: usize, align: usize) -> usize {
+            unsafe { $name.usable_size(size, align) }
+        }
+    }
+}
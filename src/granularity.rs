@@ -0,0 +1,84 @@
+// Page and cache-line arithmetic.
+//
+// The mmap-backed allocators, `VirtualRawVec`, `PageRounded` growth
+// and user code that sizes buffers to pages all need the page size,
+// and used to ask the OS (or assume 4096) separately. `Granularity` is
+// the one place that asks: the page size is queried on first use and
+// cached, and the rounding is done here rather than with bit tricks at
+// every call site.
+//
+// Platforms without `sysconf` are assumed to use 4KiB pages.
+
+use alloc::Kind;
+
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// A power-of-two unit that sizes and addresses get rounded to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Granularity(usize);
+
+static PAGE_SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[cfg(unix)]
+fn query_page_size() -> usize {
+    use libc;
+    let n = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if n > 0 { n as usize } else { 4096 }
+}
+
+#[cfg(not(unix))]
+fn query_page_size() -> usize { 4096 }
+
+impl Granularity {
+    /// # Panics
+    ///
+    /// Panics if `bytes` is not a power of two.
+    pub fn new(bytes: usize) -> Granularity {
+        assert!(bytes.is_power_of_two(), "granularity {} is not a power of two", bytes);
+        Granularity(bytes)
+    }
+
+    /// The OS page size, queried once per process.
+    pub fn page() -> Granularity {
+        let mut page = PAGE_SIZE.load(Ordering::Relaxed);
+        if page == 0 {
+            page = query_page_size();
+            PAGE_SIZE.store(page, Ordering::Relaxed);
+        }
+        Granularity(page)
+    }
+
+    /// The unit of cache coherence, as used for padding shared data.
+    /// 128 bytes on targets whose prefetcher pulls in line pairs.
+    pub fn cache_line() -> Granularity {
+        if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            Granularity(128)
+        } else {
+            Granularity(64)
+        }
+    }
+
+    pub fn bytes(&self) -> usize { self.0 }
+
+    /// `n` rounded up to a multiple of the granularity, saturating at
+    /// the largest multiple that fits.
+    pub fn round_up(&self, n: usize) -> usize {
+        n.saturating_add(self.0 - 1) & !(self.0 - 1)
+    }
+
+    /// `n` rounded down to a multiple of the granularity.
+    pub fn round_down(&self, n: usize) -> usize {
+        n & !(self.0 - 1)
+    }
+
+    pub fn is_aligned(&self, n: usize) -> bool {
+        n & (self.0 - 1) == 0
+    }
+
+    /// `kind` with its size rounded up to a multiple of the
+    /// granularity and its alignment raised to it, i.e. a whole
+    /// number of units starting on a unit boundary.
+    pub fn kind(&self, kind: Kind) -> Kind {
+        kind.round_up_to(self.0).align_to(self.0)
+    }
+}
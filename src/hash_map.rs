@@ -0,0 +1,380 @@
+// Hash maps and sets over any allocator.
+//
+// `HashMap` stores its `(K, V)` entries in a `RawTable` (see
+// `raw_table`), so the whole map is one block from `A`; it hashes with
+// a `BuildHasher`, by default std's randomly keyed `RandomState`.
+//
+// `HashSet` is a `HashMap` with `()` values, plus the set operations.
+// `union`, `intersection`, `difference` and `symmetric_difference` are
+// lazy iterators over borrowed elements, and the other set may live in
+// a different allocator, so e.g. a visited set in an arena can be
+// compared against one on the heap without copying either.
+
+use alloc::{Alloc, DefaultAlloc};
+use raw_table::{self, RawTable};
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter::Chain;
+use std::mem;
+
+fn make_hash<T: ?Sized + Hash, S: BuildHasher>(s: &S, t: &T) -> u64 {
+    let mut h = s.build_hasher();
+    t.hash(&mut h);
+    h.finish()
+}
+
+pub struct HashMap<K, V, S = RandomState, A:Alloc = DefaultAlloc> {
+    table: RawTable<(K, V), A>,
+    hash_builder: S,
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A:Alloc> HashMap<K, V, S, A> {
+    pub fn new() -> Self where S: Default, A: Default {
+        HashMap::with_hasher_in(S::default(), A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self where S: Default {
+        HashMap::with_hasher_in(S::default(), a)
+    }
+
+    pub fn with_hasher_in(hash_builder: S, a: A) -> Self {
+        HashMap { table: RawTable::new_in(a), hash_builder: hash_builder }
+    }
+
+    pub fn with_capacity_alloc(capacity: usize, a: A) -> Self where S: Default {
+        HashMap { table: RawTable::with_capacity_in(capacity, a), hash_builder: S::default() }
+    }
+
+    pub fn len(&self) -> usize { self.table.len() }
+
+    pub fn is_empty(&self) -> bool { self.table.is_empty() }
+
+    /// Entries the map can hold without reallocating.
+    pub fn capacity(&self) -> usize { self.table.capacity() }
+
+    pub fn hasher(&self) -> &S { &self.hash_builder }
+
+    pub fn alloc(&self) -> &A { self.table.alloc() }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let s = &self.hash_builder;
+        self.table.reserve(additional, |e| make_hash(s, &e.0))
+    }
+
+    fn find<Q: ?Sized>(&self, key: &Q) -> Option<usize> where K: Borrow<Q>, Q: Hash + Eq {
+        self.table.find(make_hash(&self.hash_builder, key), |e| e.0.borrow() == key)
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V> where K: Borrow<Q>, Q: Hash + Eq {
+        self.find(key).map(|pos| &self.table.get(pos).1)
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q>, Q: Hash + Eq {
+        match self.find(key) {
+            Some(pos) => Some(&mut self.table.get_mut(pos).1),
+            None => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: Hash + Eq {
+        self.find(key).is_some()
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => Some(e.insert(value)),
+            Entry::Vacant(e) => { e.insert(value); None }
+        }
+    }
+
+    /// The entry for `key`, for inserting or updating it with a
+    /// single lookup.
+    pub fn entry(&mut self, key: K) -> Entry<K, V, S, A> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let found = self.table.find(hash, |e| e.0 == key);
+        match found {
+            Some(pos) => Entry::Occupied(OccupiedEntry { table: &mut self.table, pos: pos }),
+            None => Entry::Vacant(VacantEntry { map: self, hash: hash, key: key }),
+        }
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: Hash + Eq {
+        match self.find(key) {
+            Some(pos) => Some(self.table.erase(pos).1),
+            None => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear()
+    }
+
+    /// Iterates over the entries in no particular order.
+    pub fn iter(&self) -> Iter<K, V, A> {
+        Iter { inner: self.table.iter() }
+    }
+
+    pub fn keys(&self) -> Keys<K, V, A> {
+        Keys { inner: self.table.iter() }
+    }
+
+    pub fn values<'a>(&'a self) -> Box<Iterator<Item=&'a V> + 'a> {
+        Box::new(self.table.iter().map(|e| &e.1))
+    }
+}
+
+pub struct Iter<'a, K: 'a, V: 'a, A:Alloc + 'a> {
+    inner: raw_table::Iter<'a, (K, V), A>,
+}
+
+impl<'a, K, V, A:Alloc> Iterator for Iter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next().map(|e| (&e.0, &e.1))
+    }
+}
+
+pub struct Keys<'a, K: 'a, V: 'a, A:Alloc + 'a> {
+    inner: raw_table::Iter<'a, (K, V), A>,
+}
+
+impl<'a, K, V, A:Alloc> Iterator for Keys<'a, K, V, A> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.inner.next().map(|e| &e.0)
+    }
+}
+
+impl<K, V, S, A> fmt::Debug for HashMap<K, V, S, A>
+    where K: Eq + Hash + fmt::Debug, V: fmt::Debug, S: BuildHasher, A:Alloc
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{:?}: {:?}", k, v));
+        }
+        write!(f, "}}")
+    }
+}
+
+/// A view into a single entry of a `HashMap`; see `HashMap::entry`.
+pub enum Entry<'a, K: 'a, V: 'a, S: 'a, A:Alloc + 'a> {
+    Occupied(OccupiedEntry<'a, K, V, A>),
+    Vacant(VacantEntry<'a, K, V, S, A>),
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher, A:Alloc> Entry<'a, K, V, S, A> {
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => e.key(),
+            Entry::Vacant(ref e) => e.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(f()),
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, A:Alloc + 'a> {
+    table: &'a mut RawTable<(K, V), A>,
+    pos: usize,
+}
+
+impl<'a, K, V, A:Alloc> OccupiedEntry<'a, K, V, A> {
+    pub fn key(&self) -> &K { &self.table.get(self.pos).0 }
+
+    pub fn get(&self) -> &V { &self.table.get(self.pos).1 }
+
+    pub fn get_mut(&mut self) -> &mut V { &mut self.table.get_mut(self.pos).1 }
+
+    pub fn into_mut(self) -> &'a mut V {
+        let OccupiedEntry { table, pos } = self;
+        &mut table.get_mut(pos).1
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.table.erase(self.pos).1
+    }
+}
+
+pub struct VacantEntry<'a, K: 'a, V: 'a, S: 'a, A:Alloc + 'a> {
+    map: &'a mut HashMap<K, V, S, A>,
+    hash: u64,
+    key: K,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher, A:Alloc> VacantEntry<'a, K, V, S, A> {
+    pub fn key(&self) -> &K { &self.key }
+
+    pub fn into_key(self) -> K { self.key }
+
+    /// Inserts `value` using the hash computed by `entry`.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let map = self.map;
+        let s = &map.hash_builder;
+        let pos = map.table.insert(self.hash, (self.key, value), |e| make_hash(s, &e.0));
+        &mut map.table.get_mut(pos).1
+    }
+}
+
+pub struct HashSet<T, S = RandomState, A:Alloc = DefaultAlloc> {
+    map: HashMap<T, (), S, A>,
+}
+
+impl<T: Eq + Hash, S: BuildHasher, A:Alloc> HashSet<T, S, A> {
+    pub fn new() -> Self where S: Default, A: Default {
+        HashSet { map: HashMap::new() }
+    }
+
+    pub fn with_alloc(a: A) -> Self where S: Default {
+        HashSet { map: HashMap::with_alloc(a) }
+    }
+
+    pub fn with_hasher_in(hash_builder: S, a: A) -> Self {
+        HashSet { map: HashMap::with_hasher_in(hash_builder, a) }
+    }
+
+    pub fn with_capacity_alloc(capacity: usize, a: A) -> Self where S: Default {
+        HashSet { map: HashMap::with_capacity_alloc(capacity, a) }
+    }
+
+    pub fn len(&self) -> usize { self.map.len() }
+
+    pub fn is_empty(&self) -> bool { self.map.is_empty() }
+
+    pub fn capacity(&self) -> usize { self.map.capacity() }
+
+    pub fn reserve(&mut self, additional: usize) { self.map.reserve(additional) }
+
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool where T: Borrow<Q>, Q: Hash + Eq {
+        self.map.contains_key(value)
+    }
+
+    /// Adds `value`, returning false if it was already present.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool where T: Borrow<Q>, Q: Hash + Eq {
+        self.map.remove(value).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn iter(&self) -> Keys<T, (), A> {
+        self.map.keys()
+    }
+
+    /// Elements of `self` that are not in `other`.
+    pub fn difference<'a, B:Alloc>(&'a self, other: &'a HashSet<T, S, B>) -> Difference<'a, T, S, A, B> {
+        Difference { iter: self.iter(), other: other }
+    }
+
+    /// Elements in both `self` and `other`.
+    pub fn intersection<'a, B:Alloc>(&'a self, other: &'a HashSet<T, S, B>) -> Intersection<'a, T, S, A, B> {
+        Intersection { iter: self.iter(), other: other }
+    }
+
+    /// Elements in either set, each yielded once.
+    pub fn union<'a, B:Alloc>(&'a self, other: &'a HashSet<T, S, B>) -> Union<'a, T, S, A, B> {
+        Union { iter: self.iter().chain(other.difference(self)) }
+    }
+
+    /// Elements in exactly one of the two sets.
+    pub fn symmetric_difference<'a, B:Alloc>(&'a self, other: &'a HashSet<T, S, B>)
+                                             -> SymmetricDifference<'a, T, S, A, B> {
+        SymmetricDifference { iter: self.difference(other).chain(other.difference(self)) }
+    }
+
+    pub fn is_subset<B:Alloc>(&self, other: &HashSet<T, S, B>) -> bool {
+        self.len() <= other.len() && self.iter().all(|t| other.contains(t))
+    }
+
+    pub fn is_disjoint<B:Alloc>(&self, other: &HashSet<T, S, B>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+pub struct Difference<'a, T: 'a, S: 'a, A:Alloc + 'a, B:Alloc + 'a> {
+    iter: Keys<'a, T, (), A>,
+    other: &'a HashSet<T, S, B>,
+}
+
+impl<'a, T: Eq + Hash, S: BuildHasher, A:Alloc, B:Alloc> Iterator for Difference<'a, T, S, A, B> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.iter.next() {
+                Some(t) => if !self.other.contains(t) { return Some(t) },
+                None => return None,
+            }
+        }
+    }
+}
+
+pub struct Intersection<'a, T: 'a, S: 'a, A:Alloc + 'a, B:Alloc + 'a> {
+    iter: Keys<'a, T, (), A>,
+    other: &'a HashSet<T, S, B>,
+}
+
+impl<'a, T: Eq + Hash, S: BuildHasher, A:Alloc, B:Alloc> Iterator for Intersection<'a, T, S, A, B> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.iter.next() {
+                Some(t) => if self.other.contains(t) { return Some(t) },
+                None => return None,
+            }
+        }
+    }
+}
+
+pub struct Union<'a, T: 'a, S: 'a, A:Alloc + 'a, B:Alloc + 'a> {
+    iter: Chain<Keys<'a, T, (), A>, Difference<'a, T, S, B, A>>,
+}
+
+impl<'a, T: Eq + Hash, S: BuildHasher, A:Alloc, B:Alloc> Iterator for Union<'a, T, S, A, B> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> { self.iter.next() }
+}
+
+pub struct SymmetricDifference<'a, T: 'a, S: 'a, A:Alloc + 'a, B:Alloc + 'a> {
+    iter: Chain<Difference<'a, T, S, A, B>, Difference<'a, T, S, B, A>>,
+}
+
+impl<'a, T: Eq + Hash, S: BuildHasher, A:Alloc, B:Alloc> Iterator for SymmetricDifference<'a, T, S, A, B> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> { self.iter.next() }
+}
+
+impl<T: Eq + Hash + fmt::Debug, S: BuildHasher, A:Alloc> fmt::Debug for HashSet<T, S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,161 @@
+// An allocator that backs large blocks with huge pages.
+//
+// Requests of at least `threshold` bytes are mapped directly: first
+// with MAP_HUGETLB (explicitly reserved huge pages, Linux only); if
+// that fails, with ordinary pages plus an madvise(MADV_HUGEPAGE) hint
+// so transparent huge pages can still kick in. Smaller requests go to
+// the inner allocator.
+//
+// Both paths map a length rounded up to the huge page size, so
+// `dealloc` can recompute the mapping length (and route the request)
+// from the `Kind` alone, without remembering which path was taken.
+// That only holds while no block changes sides in place: a small block
+// never grows in place to `threshold` or more (nor does its usable
+// size reach it), and a mapped one never shrinks below it.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+use granularity::Granularity;
+
+use libc;
+
+use std::cmp;
+use std::ptr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Huge2M,
+    Huge1G,
+}
+
+impl PageSize {
+    pub fn bytes(&self) -> usize {
+        match *self {
+            PageSize::Huge2M => 2 * 1024 * 1024,
+            PageSize::Huge1G => 1024 * 1024 * 1024,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn mmap_flags(&self) -> libc::c_int {
+        const MAP_HUGE_SHIFT: libc::c_int = 26;
+        let log2 = match *self { PageSize::Huge2M => 21, PageSize::Huge1G => 30 };
+        libc::MAP_HUGETLB | (log2 << MAP_HUGE_SHIFT)
+    }
+}
+
+pub struct Alloc<A> {
+    inner: A,
+    page: PageSize,
+    threshold: usize,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Serves requests of at least one huge page from huge pages.
+    pub fn new(inner: A, page: PageSize) -> Alloc<A> {
+        Alloc { inner: inner, page: page, threshold: page.bytes() }
+    }
+
+    pub fn with_threshold(inner: A, page: PageSize, threshold: usize) -> Alloc<A> {
+        Alloc { inner: inner, page: page, threshold: threshold }
+    }
+
+    fn is_large(&self, kind: Kind) -> bool { kind.size() >= self.threshold }
+
+    fn map_len(&self, size: usize) -> usize {
+        Granularity::new(self.page.bytes()).round_up(size)
+    }
+
+    unsafe fn map(&self, kind: Kind) -> Address {
+        if kind.align() > self.page.bytes() { return ptr::null_mut(); }
+        let len = self.map_len(kind.size());
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+
+        #[cfg(target_os = "linux")]
+        {
+            let p = libc::mmap(ptr::null_mut(), len, prot, flags | self.page.mmap_flags(), -1, 0);
+            if p != libc::MAP_FAILED { return p as Address; }
+        }
+
+        let p = libc::mmap(ptr::null_mut(), len, prot, flags, -1, 0);
+        if p == libc::MAP_FAILED { return ptr::null_mut(); }
+        #[cfg(target_os = "linux")]
+        {
+            libc::madvise(p, len, libc::MADV_HUGEPAGE);
+        }
+        p as Address
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if self.is_large(kind) { self.map(kind) } else { self.inner.alloc(kind) }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if self.is_large(kind) {
+            libc::munmap(ptr as *mut libc::c_void, self.map_len(kind.size()));
+        } else {
+            self.inner.dealloc(ptr, kind)
+        }
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        if self.is_large(kind) { self.dealloc(ptr, kind) } else { self.inner.dealloc_hot(ptr, kind) }
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        if self.is_large(kind) {
+            self.map_len(kind.size())
+        } else {
+            // Claiming more would route the block to `munmap` on free.
+            cmp::min(self.inner.usable_size(kind), self.threshold - 1)
+        }
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        match (self.is_large(kind), self.is_large(new_kind)) {
+            (false, false) => self.inner.grow_in_place(ptr, kind, new_size),
+            (true, true) if self.map_len(new_size) == self.map_len(kind.size()) => Ok(()),
+            // A mapping cannot be extended in place, and a small block
+            // crossing the threshold would have to become one.
+            _ => Err(AllocError::Unsupported),
+        }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        match (self.is_large(kind), self.is_large(new_kind)) {
+            (false, false) => self.inner.shrink_in_place(ptr, kind, new_size),
+            (true, true) => {
+                // Mappings are whole huge pages, so the tail can be
+                // unmapped without disturbing the head.
+                let (keep, had) = (self.map_len(new_size), self.map_len(kind.size()));
+                if keep < had {
+                    libc::munmap(ptr.offset(keep as isize) as *mut libc::c_void, had - keep);
+                }
+                Ok(())
+            }
+            // The smaller block would belong to the inner allocator.
+            _ => Err(AllocError::Unsupported),
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        match (self.is_large(kind), self.is_large(new_kind)) {
+            (false, false) => self.inner.realloc(ptr, kind, new_size),
+            (true, true) if self.map_len(new_size) == self.map_len(kind.size()) => ptr,
+            _ => {
+                let p = self.alloc(new_kind);
+                if !p.is_null() {
+                    let n = if kind.size() < new_size { kind.size() } else { new_size };
+                    ptr::copy_nonoverlapping(ptr as *const u8, p, n);
+                    self.dealloc(ptr, kind);
+                }
+                p
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// An allocator whose memory is a byte array inside the allocator
+// value itself, so the blocks it hands out live wherever the
+// allocator does: on the stack, in a static, or embedded in another
+// struct, with no heap traffic at all.
+//
+// Blocks are bumped off the front of the array. Freeing the newest
+// block (or reallocating it) works in place, and once every block has
+// been freed the whole array is reused, so LIFO patterns and
+// short-lived containers run indefinitely; anything else is reclaimed
+// when the last block goes. A request that does not fit returns null.
+//
+// The size is a type parameter, `Alloc<[u8; N]>`, for the sizes
+// implemented by the `Storage` impls below.
+//
+// Blocks point into the allocator, so it must not move while any are
+// live. Only handles (`&inline::Alloc`, and `Rc`/`Arc` of one) are
+// allocators, never the value itself: a `Box<T, &inline::Alloc<_>>`
+// borrows the allocator, which then stays put until the box is gone.
+
+use alloc::{Address, AllocError, Capacity, Kind, ShareAlloc, Size};
+use uninit::MaybeUninit;
+
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
+use std::mem;
+use std::ptr;
+
+/// Byte arrays that can serve as the storage of an `inline::Alloc`.
+pub unsafe trait Storage { }
+
+macro_rules! storage {
+    ($($n:expr),*) => { $( unsafe impl Storage for [u8; $n] { } )* }
+}
+
+storage!(16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536);
+
+/// Largest alignment an `inline::Alloc` can satisfy.
+pub fn max_align() -> usize { mem::align_of::<usize>() }
+
+pub struct Alloc<S: Storage = [u8; 64]> {
+    _align: [usize; 0],
+    buf: UnsafeCell<MaybeUninit<S>>,
+    // end of the newest block
+    top: Cell<usize>,
+    live: Cell<usize>,
+}
+
+impl<S: Storage> Alloc<S> {
+    pub fn new() -> Alloc<S> {
+        Alloc { _align: [], buf: UnsafeCell::new(MaybeUninit::uninit()),
+                top: Cell::new(0), live: Cell::new(0) }
+    }
+
+    /// Size of the storage in bytes.
+    pub fn capacity(&self) -> usize { mem::size_of::<S>() }
+
+    /// Bytes from the start of the storage to the end of the newest
+    /// block, including any holes left by out-of-order frees.
+    pub fn used(&self) -> usize { self.top.get() }
+
+    /// Number of blocks not yet freed.
+    pub fn live(&self) -> usize { self.live.get() }
+
+    fn base(&self) -> Address {
+        self.buf.get() as Address
+    }
+
+    fn is_newest(&self, ptr: Address, size: usize) -> bool {
+        ptr as usize + size == self.base() as usize + self.top.get()
+    }
+}
+
+impl<S: Storage> Default for Alloc<S> {
+    fn default() -> Alloc<S> { Alloc::new() }
+}
+
+impl<S: Storage> ShareAlloc for Alloc<S> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        if kind.align() > max_align() { return ptr::null_mut(); }
+        let start = (self.top.get() + kind.align() - 1) & !(kind.align() - 1);
+        if kind.size() > self.capacity() - cmp::min(start, self.capacity()) {
+            return ptr::null_mut();
+        }
+        self.top.set(start + kind.size());
+        self.live.set(self.live.get() + 1);
+        self.base().offset(start as isize)
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        self.live.set(self.live.get() - 1);
+        if self.live.get() == 0 {
+            self.top.set(0);
+        } else if self.is_newest(ptr, kind.size()) {
+            self.top.set(ptr as usize - self.base() as usize);
+        }
+    }
+
+    unsafe fn alloc_error_shared(&self, kind: Kind) -> AllocError {
+        if kind.align() > max_align() { AllocError::Unsupported } else { AllocError::Exhausted }
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.size() != 0 && new_size == 0 {
+            self.dealloc_shared(ptr, kind);
+            return kind.dangling();
+        }
+        if kind.size() != 0 && self.is_newest(ptr, kind.size()) {
+            let start = ptr as usize - self.base() as usize;
+            if new_size <= self.capacity() - start {
+                self.top.set(start + new_size);
+                return ptr;
+            }
+            return ptr::null_mut();
+        }
+        let new_ptr = self.alloc_shared(Kind::from_size_align(new_size, kind.align()));
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr as *const u8, new_ptr, cmp::min(kind.size(), new_size));
+            self.dealloc_shared(ptr, kind);
+        }
+        new_ptr
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        kind.size()
+    }
+}
@@ -0,0 +1,143 @@
+// String interning on top of an allocator.
+//
+// `Interner::intern` copies each distinct string once into chunks
+// obtained from `A` and returns a `Symbol`, a small index that is
+// cheap to copy, hash and compare. `resolve` maps it back to the
+// stored text. Strings are never freed individually; the chunks go
+// back to `A` when the interner is dropped, so an arena or bump
+// allocator is a natural backing store.
+//
+// The lookup table hashes the string contents; its keys point into
+// the chunks, which never move, so no string is stored twice.
+
+use alloc::{Address, Alloc, DefaultAlloc, Kind};
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::slice;
+use std::str;
+
+const CHUNK_SIZE: usize = 4096;
+
+/// A handle to an interned string. Symbols from different interners
+/// must not be mixed.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn as_u32(self) -> u32 { self.0 }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({})", self.0)
+    }
+}
+
+// A string stored in one of the interner's chunks.
+#[derive(Copy, Clone)]
+struct Key(*const u8, usize);
+
+impl Key {
+    fn as_str<'a>(&self) -> &'a str {
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.0, self.1)) }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool { self.as_str() == other.as_str() }
+}
+
+impl Eq for Key { }
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, h: &mut H) { self.as_str().hash(h) }
+}
+
+pub struct Interner<A:Alloc = DefaultAlloc> {
+    alloc: A,
+    chunks: Vec<(Address, usize)>,
+    cur: Address,
+    end: Address,
+    strs: Vec<Key>,
+    map: HashMap<Key, Symbol>,
+}
+
+impl<A:Alloc> Interner<A> {
+    pub fn new() -> Self where A: Default {
+        Interner::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        Interner { alloc: a, chunks: Vec::new(),
+                   cur: ptr::null_mut(), end: ptr::null_mut(),
+                   strs: Vec::new(), map: HashMap::new() }
+    }
+
+    /// Returns the symbol for `s`, storing a copy of it if this is
+    /// the first time it has been seen.
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(&sym) = self.map.get(&Key(s.as_ptr(), s.len())) {
+            return sym;
+        }
+        let p = self.store(s.as_bytes());
+        let key = Key(p, s.len());
+        let sym = Symbol(self.strs.len() as u32);
+        self.strs.push(key);
+        self.map.insert(key, sym);
+        sym
+    }
+
+    /// Returns the symbol for `s` if it has already been interned.
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.map.get(&Key(s.as_ptr(), s.len())).cloned()
+    }
+
+    /// Returns the text of `sym`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sym` did not come from this interner.
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        self.strs[sym.0 as usize].as_str()
+    }
+
+    /// Number of distinct strings interned.
+    pub fn len(&self) -> usize {
+        self.strs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strs.is_empty()
+    }
+
+    fn store(&mut self, bytes: &[u8]) -> *const u8 {
+        if bytes.is_empty() { return 1 as *const u8; }
+        unsafe {
+            if (self.end as usize) - (self.cur as usize) < bytes.len() {
+                let size = cmp::max(CHUNK_SIZE, bytes.len());
+                let kind = Kind::from_size_align(size, 1);
+                let chunk = self.alloc.alloc(kind);
+                if chunk.is_null() { self.alloc.oom() }
+                self.chunks.push((chunk, size));
+                self.cur = chunk;
+                self.end = chunk.offset(size as isize);
+            }
+            let p = self.cur;
+            ptr::copy_nonoverlapping(bytes.as_ptr(), p, bytes.len());
+            self.cur = p.offset(bytes.len() as isize);
+            p
+        }
+    }
+}
+
+impl<A:Alloc> Drop for Interner<A> {
+    fn drop(&mut self) {
+        for &(chunk, size) in &self.chunks {
+            unsafe { self.alloc.dealloc(chunk, Kind::from_size_align(size, 1)); }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Building blocks for allocators that keep freed blocks on lists
+// threaded through the blocks themselves, so that the lists cost no
+// memory of their own.
+//
+// `FreeList` is a LIFO stack of blocks of one kind: `push` writes the
+// link to the next block into the first word of the freed block and
+// `pop` reads it back. A block therefore has to be big enough and
+// aligned enough to hold a pointer; `FreeList::new` rounds the kind it
+// is given up to that minimum, and allocators should obtain blocks with
+// the rounded `kind()`.
+//
+// `SegList` groups free lists into power-of-two size classes. Class
+// `i` holds blocks of `min_size << i` bytes aligned to their own size,
+// so any request whose size and alignment both fit a class can be
+// served from it.
+//
+// Neither type owns the blocks or knows where they came from. Dropping
+// a list with blocks still on it simply forgets them; an allocator
+// built on these should `drain` its lists back to their source first.
+
+use alloc::{Address, Kind};
+
+use std::cmp;
+use std::mem;
+use std::ptr;
+
+pub struct FreeList {
+    head: Address,
+    len: usize,
+    kind: Kind,
+}
+
+impl FreeList {
+    /// An empty list for blocks of `kind`, rounded up as needed to
+    /// hold the embedded link.
+    pub fn new(kind: Kind) -> FreeList {
+        let link = Kind::new::<Address>();
+        let kind = unsafe {
+            Kind::from_size_align(cmp::max(kind.size(), link.size()),
+                                  cmp::max(kind.align(), link.align()))
+        };
+        FreeList { head: ptr::null_mut(), len: 0, kind: kind }
+    }
+
+    /// The kind of the blocks on this list.
+    pub fn kind(&self) -> Kind { self.kind }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Puts `block` on the list.
+    ///
+    /// Unsafe because `block` must be a live allocation of at least
+    /// `self.kind()` that nothing else uses until it is popped.
+    pub unsafe fn push(&mut self, block: Address) {
+        debug_assert!(!block.is_null());
+        debug_assert!(block as usize % self.kind.align() == 0,
+                      "intrusive: block {:p} is not aligned for {}", block, self.kind);
+        *(block as *mut Address) = self.head;
+        self.head = block;
+        self.len += 1;
+    }
+
+    /// Takes the most recently pushed block off the list.
+    pub fn pop(&mut self) -> Option<Address> {
+        if self.head.is_null() { return None; }
+        let block = self.head;
+        self.head = unsafe { *(block as *const Address) };
+        self.len -= 1;
+        Some(block)
+    }
+
+    /// Empties the list, handing every block to `f`.
+    pub fn drain<F: FnMut(Address)>(&mut self, mut f: F) {
+        while let Some(block) = self.pop() { f(block) }
+    }
+}
+
+pub struct SegList {
+    classes: Vec<FreeList>,
+    min_size: usize,
+}
+
+impl SegList {
+    /// Classes from `min_size` up to `max_size` bytes, both of which
+    /// must be powers of two; `min_size` is raised to pointer size.
+    pub fn new(min_size: usize, max_size: usize) -> SegList {
+        assert!(min_size.is_power_of_two() && max_size.is_power_of_two(),
+                "intrusive: class bounds must be powers of two");
+        let min_size = cmp::max(min_size, mem::size_of::<Address>());
+        let mut classes = Vec::new();
+        let mut size = min_size;
+        while size <= max_size {
+            classes.push(FreeList::new(unsafe { Kind::from_size_align(size, size) }));
+            size *= 2;
+        }
+        SegList { classes: classes, min_size: min_size }
+    }
+
+    pub fn class_count(&self) -> usize { self.classes.len() }
+
+    /// The class serving `kind`, or `None` if it is too large for all
+    /// of them.
+    pub fn class_of(&self, kind: Kind) -> Option<usize> {
+        let need = cmp::max(cmp::max(kind.size(), kind.align()), self.min_size);
+        let i = (need.next_power_of_two() / self.min_size).trailing_zeros() as usize;
+        if i < self.classes.len() { Some(i) } else { None }
+    }
+
+    /// The kind of the blocks in class `i`.
+    pub fn class_kind(&self, i: usize) -> Kind { self.classes[i].kind() }
+
+    pub fn class(&self, i: usize) -> &FreeList { &self.classes[i] }
+
+    pub fn class_mut(&mut self, i: usize) -> &mut FreeList { &mut self.classes[i] }
+
+    /// A cached block able to hold `kind`, if its class has one.
+    pub fn pop(&mut self, kind: Kind) -> Option<Address> {
+        match self.class_of(kind) {
+            Some(i) => self.classes[i].pop(),
+            None => None,
+        }
+    }
+
+    /// Puts `block`, allocated as `class_kind(class_of(kind))`, on its
+    /// class's list. Returns false, leaving the block alone, if no
+    /// class serves `kind`.
+    pub unsafe fn push(&mut self, block: Address, kind: Kind) -> bool {
+        match self.class_of(kind) {
+            Some(i) => { self.classes[i].push(block); true }
+            None => false,
+        }
+    }
+
+    /// Empties every class, handing each block and its class kind to `f`.
+    pub fn drain<F: FnMut(Address, Kind)>(&mut self, mut f: F) {
+        for list in &mut self.classes {
+            let kind = list.kind();
+            list.drain(|block| f(block, kind));
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use alloc::Alloc;
+use boxed::Box;
+use vec::Vec;
+
+/// Adapters for funneling an iterator into an allocator-backed
+/// collection.
+pub trait IteratorExt: Iterator + Sized {
+    /// Collects every element of `self` into a `Vec` whose buffer
+    /// comes from `alloc`.
+    fn collect_in<A:Alloc>(self, alloc: A) -> Vec<Self::Item, A> {
+        let (lower, _) = self.size_hint();
+        let mut v = Vec::with_capacity_alloc(lower, alloc);
+        v.extend(self);
+        v
+    }
+
+    /// Collects every element of `self` into a `Box<[T], A>` with no
+    /// excess capacity.
+    fn collect_box_slice_in<A:Alloc>(self, alloc: A) -> Box<[Self::Item], A> {
+        self.collect_in(alloc).into_boxed_slice()
+    }
+}
+
+impl<I: Iterator> IteratorExt for I { }
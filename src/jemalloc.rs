@@ -0,0 +1,81 @@
+// An allocator calling into jemalloc's non-standard API, enabled by
+// the `jemalloc` feature and linked against the system libjemalloc.
+//
+// jemalloc takes the alignment as a flag on every call, frees with the
+// size in hand (`sdallocx`, which skips a metadata lookup), can say
+// exactly how large a block of a given size really is (`nallocx`), and
+// can try to resize a block without moving it (`xallocx`), so each
+// `Alloc` method maps onto a single call.
+//
+// Zero-sized kinds never reach jemalloc; they get `Kind::dangling()`.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use libc::{c_int, c_void, size_t};
+
+#[link(name = "jemalloc")]
+extern "C" {
+    fn mallocx(size: size_t, flags: c_int) -> *mut c_void;
+    fn rallocx(ptr: *mut c_void, size: size_t, flags: c_int) -> *mut c_void;
+    fn xallocx(ptr: *mut c_void, size: size_t, extra: size_t, flags: c_int) -> size_t;
+    fn sdallocx(ptr: *mut c_void, size: size_t, flags: c_int);
+    fn nallocx(size: size_t, flags: c_int) -> size_t;
+}
+
+// MALLOCX_LG_ALIGN(la): the log2 of the alignment in the low bits.
+fn align_flags(align: usize) -> c_int {
+    align.trailing_zeros() as c_int
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Alloc;
+
+unsafe impl alloc::StatelessAlloc for Alloc { }
+
+impl alloc::Alloc for Alloc {
+    fn compatible_with(&self, _other: &Self) -> bool { true }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if kind.is_zero_sized() { return kind.dangling(); }
+        mallocx(kind.size(), align_flags(kind.align())) as Address
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if kind.is_zero_sized() { return; }
+        sdallocx(ptr as *mut c_void, kind.size(), align_flags(kind.align()))
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        if kind.is_zero_sized() { return 0; }
+        nallocx(kind.size(), align_flags(kind.align()))
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size)
+                            -> Result<(), AllocError> {
+        if kind.is_zero_sized() { return Err(AllocError::Exhausted); }
+        let got = xallocx(ptr as *mut c_void, new_size, 0, align_flags(kind.align()));
+        if got >= new_size { Ok(()) } else { Err(AllocError::Exhausted) }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size)
+                              -> Result<(), AllocError> {
+        if kind.is_zero_sized() { return Err(AllocError::Unsupported); }
+        // Never moves, but may leave the block in its old size class,
+        // and `sdallocx` then needs a size from that class: succeed
+        // only if the block now has exactly the class of `new_size`.
+        let flags = align_flags(kind.align());
+        let got = xallocx(ptr as *mut c_void, new_size, 0, flags);
+        if got == nallocx(new_size, flags) { Ok(()) } else { Err(AllocError::Unsupported) }
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.is_zero_sized() {
+            return self.alloc(Kind::from_size_align(new_size, kind.align()));
+        }
+        if new_size == 0 {
+            self.dealloc(ptr, kind);
+            return kind.dangling();
+        }
+        rallocx(ptr as *mut c_void, new_size, align_flags(kind.align())) as Address
+    }
+}
@@ -0,0 +1,172 @@
+// An allocator wrapper that remembers every outstanding allocation.
+//
+// Clones of a `leakcheck::Alloc` share one table, so a single
+// instance can be cloned into many containers and the table then
+// reflects all of them. Whatever is still in the table is a leak
+// (or a live allocation, if asked early); `leaks()` reports it on
+// demand, and the drop hook receives it when the last clone goes away.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// One allocation that was never returned to the allocator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LeakRecord {
+    pub addr: usize,
+    pub kind: Kind,
+    /// The label in effect when the block was allocated, if any.
+    pub label: Option<&'static str>,
+    /// Sequence number of the allocation (0 for the first one).
+    pub seq: u64,
+}
+
+struct State<A> {
+    inner: RefCell<A>,
+    live: RefCell<HashMap<usize, LeakRecord>>,
+    label: Cell<Option<&'static str>>,
+    next_seq: Cell<u64>,
+    on_drop: Cell<fn(&[LeakRecord])>,
+}
+
+#[derive(Clone)]
+pub struct Alloc<A> {
+    state: Rc<State<A>>,
+}
+
+/// The default drop hook: prints each leak to stderr.
+pub fn print_leaks(leaks: &[LeakRecord]) {
+    let mut err = io::stderr();
+    for l in leaks {
+        let _ = writeln!(err, "leakcheck: leaked {} at 0x{:x} (#{}, label: {:?})",
+                         l.kind, l.addr, l.seq, l.label);
+    }
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A) -> Alloc<A> {
+        Alloc {
+            state: Rc::new(State {
+                inner: RefCell::new(inner),
+                live: RefCell::new(HashMap::new()),
+                label: Cell::new(None),
+                next_seq: Cell::new(0),
+                on_drop: Cell::new(print_leaks),
+            })
+        }
+    }
+
+    /// Sets the label attached to subsequent allocations.
+    pub fn set_label(&self, label: Option<&'static str>) {
+        self.state.label.set(label);
+    }
+
+    /// Replaces the hook run with the outstanding allocations when
+    /// the last clone of this allocator is dropped.
+    pub fn set_drop_hook(&self, hook: fn(&[LeakRecord])) {
+        self.state.on_drop.set(hook);
+    }
+
+    /// Number of allocations not yet deallocated.
+    pub fn live_count(&self) -> usize {
+        self.state.live.borrow().len()
+    }
+
+    /// The allocations not yet deallocated, oldest first.
+    pub fn leaks(&self) -> Vec<LeakRecord> {
+        self.state.leaks()
+    }
+}
+
+impl<A> State<A> {
+    fn leaks(&self) -> Vec<LeakRecord> {
+        let mut v: Vec<LeakRecord> = self.live.borrow().values().cloned().collect();
+        v.sort_by(|a, b| a.seq.cmp(&b.seq));
+        v
+    }
+
+    fn record(&self, p: Address, kind: Kind) {
+        if p.is_null() { return; }
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        let rec = LeakRecord { addr: p as usize, kind: kind, label: self.label.get(), seq: seq };
+        self.live.borrow_mut().insert(p as usize, rec);
+    }
+
+    // After a resize in place, the block keeps its label and number.
+    fn resize(&self, p: Address, kind: Kind, new_size: Size) {
+        if let Some(rec) = self.live.borrow_mut().get_mut(&(p as usize)) {
+            rec.kind = unsafe { Kind::from_size_align(new_size, kind.align()) };
+        }
+    }
+
+    fn forget(&self, p: Address) -> Option<LeakRecord> {
+        self.live.borrow_mut().remove(&(p as usize))
+    }
+}
+
+impl<A> Drop for State<A> {
+    fn drop(&mut self) {
+        let leaks = self.leaks();
+        if !leaks.is_empty() {
+            (self.on_drop.get())(&leaks);
+        }
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    fn compatible_with(&self, other: &Self) -> bool {
+        &*self.state as *const State<A> == &*other.state as *const State<A>
+    }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        let p = self.state.inner.borrow_mut().alloc(kind);
+        self.state.record(p, kind);
+        p
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.state.forget(ptr);
+        self.state.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.state.forget(ptr);
+        self.state.inner.borrow_mut().dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.state.inner.borrow().usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.state.inner.borrow_mut().grow_in_place(ptr, kind, new_size);
+        if r.is_ok() { self.state.resize(ptr, kind, new_size); }
+        r
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.state.inner.borrow_mut().shrink_in_place(ptr, kind, new_size);
+        if r.is_ok() { self.state.resize(ptr, kind, new_size); }
+        r
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let p = self.state.inner.borrow_mut().realloc(ptr, kind, new_size);
+        if !p.is_null() {
+            let old = self.state.forget(ptr);
+            self.state.record(p, Kind::from_size_align(new_size, kind.align()));
+            // keep the original label and sequence number across a move
+            if let Some(old) = old {
+                let mut live = self.state.live.borrow_mut();
+                let rec = live.get_mut(&(p as usize)).unwrap();
+                rec.label = old.label;
+                rec.seq = old.seq;
+            }
+        }
+        p
+    }
+}
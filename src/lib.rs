@@ -1,21 +1,105 @@
 #![allow(unused_features)]
-#![feature(unique, unsafe_no_drop_flag, alloc)]
-#![feature(heap_api, oom, box_raw, filling_drop, num_bits_bytes)]
+#![feature(unique, alloc)]
+#![feature(heap_api, oom, box_raw, num_bits_bytes)]
 #![feature(core_intrinsics)]
+#![feature(coerce_unsized, unsize)]
+#![feature(const_fn)]
+#![feature(repr_simd)]
+#![feature(untagged_unions)]
+#![cfg_attr(feature = "valgrind", feature(asm))]
 
 #![feature(optin_builtin_traits)] // for `unsafe impl Raw for ..`
 
 #![feature(placement_new_protocol, placement_in_syntax)]
+#![feature(unboxed_closures, fn_traits)]
 
 
 extern crate alloc as alloc_crate;
+extern crate libc;
+#[cfg(feature = "track-callsites")]
+extern crate backtrace;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 // extern crate allocprint;
 
+#[macro_use]
 pub mod alloc;
+pub mod granularity;
+pub mod uninit;
+pub mod annotate;
 pub mod raw_vec;
 pub mod boxed;
 pub mod boxing;
+pub mod vec;
+pub mod string;
+pub mod iter;
+pub mod pinned;
+pub mod leakcheck;
+pub mod verify;
+pub mod segregated;
+#[macro_use]
+pub mod global;
+pub mod ffi;
+pub mod purgeable;
+pub mod objpool;
+#[cfg(unix)]
+pub mod mmap_file;
+pub mod offset_ptr;
+#[cfg(any(unix, windows))]
+pub mod shm;
+pub mod numa;
+#[cfg(unix)]
+pub mod hugepage;
+#[cfg(feature = "track-callsites")]
+pub mod debug;
+pub mod testing;
+pub mod conformance;
+pub mod quarantine;
+pub mod quota;
+pub mod throttle;
+pub mod vec_map;
+pub mod bit_vec;
+pub mod intern;
+pub mod typed_arena;
+pub mod observed;
+pub mod bump;
+pub mod aligned;
+pub mod double_ended_bump;
+pub mod ring;
+pub mod gen_arena;
+pub mod stats;
+pub mod dst;
+pub mod rc_lite;
+pub mod sync_bump;
+pub mod sharded;
+pub mod deferred;
+pub mod intrusive;
+pub mod scratch;
+pub mod persistent;
+pub mod rope;
+pub mod raw_table;
+pub mod hash_map;
+pub mod btree_map;
+pub mod pressure;
+pub mod inline;
+pub mod scoped;
+pub mod brand;
+pub mod raw_buf;
+pub mod timeline;
+pub mod align_cap;
+pub mod single_thread;
+pub mod remote_free;
+#[cfg(feature = "jemalloc")]
+pub mod jemalloc;
+#[cfg(feature = "mimalloc")]
+pub mod mimalloc;
+#[cfg(windows)]
+pub mod winheap;
+#[cfg(feature = "serde")]
+mod serde_impls;
 // pub mod btree { mod node; }
 
 #[cfg(test)]
@@ -0,0 +1,56 @@
+// An allocator calling into mimalloc, enabled by the `mimalloc`
+// feature and linked against the system libmimalloc.
+//
+// Every call goes through mimalloc's aligned entry points, frees pass
+// the size along (`mi_free_size_aligned`, which lets debug builds of
+// mimalloc check it), and `usable_size` reports the size class a
+// request would land in (`mi_good_size`).
+//
+// Zero-sized kinds never reach mimalloc; they get `Kind::dangling()`.
+
+use alloc::{self, Address, Capacity, Kind, Size};
+
+use libc::{c_void, size_t};
+
+#[link(name = "mimalloc")]
+extern "C" {
+    fn mi_malloc_aligned(size: size_t, alignment: size_t) -> *mut c_void;
+    fn mi_realloc_aligned(p: *mut c_void, newsize: size_t, alignment: size_t) -> *mut c_void;
+    fn mi_free_size_aligned(p: *mut c_void, size: size_t, alignment: size_t);
+    fn mi_good_size(size: size_t) -> size_t;
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Alloc;
+
+unsafe impl alloc::StatelessAlloc for Alloc { }
+
+impl alloc::Alloc for Alloc {
+    fn compatible_with(&self, _other: &Self) -> bool { true }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if kind.is_zero_sized() { return kind.dangling(); }
+        mi_malloc_aligned(kind.size(), kind.align()) as Address
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if kind.is_zero_sized() { return; }
+        mi_free_size_aligned(ptr as *mut c_void, kind.size(), kind.align())
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        if kind.is_zero_sized() { return 0; }
+        mi_good_size(kind.size())
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.is_zero_sized() {
+            return self.alloc(Kind::from_size_align(new_size, kind.align()));
+        }
+        if new_size == 0 {
+            self.dealloc(ptr, kind);
+            return kind.dangling();
+        }
+        mi_realloc_aligned(ptr as *mut c_void, new_size, kind.align()) as Address
+    }
+}
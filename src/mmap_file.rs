@@ -0,0 +1,158 @@
+// A bump allocator living inside a memory-mapped file.
+//
+// The file starts with a `Header` recording the high-water mark of
+// the bump cursor and a "root" offset, so that reopening the file
+// finds both the allocated data and the place to start reading it.
+// Only plain data (`Raw` types, holding no absolute pointers) survives
+// being written out and mapped back in at a different address; see
+// `offset_ptr` for pointers that do.
+//
+// Deallocation only reclaims space when it frees the most recent
+// allocation, as in the test bump allocator.
+
+use alloc::{self, Address, Kind, ShareAlloc, Size};
+
+use libc;
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+const MAGIC: u64 = 0x616c_6c6f_636f_6c6c; // "allocoll"
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+    high_water: u64,
+    root: u64,
+}
+
+pub struct Alloc {
+    base: *mut u8,
+    len: usize,
+    _file: File,
+}
+
+fn header_end() -> usize {
+    let h = mem::size_of::<Header>();
+    (h + 15) & !15
+}
+
+impl Alloc {
+    /// Maps `path`, creating it with `len` bytes (and an empty arena)
+    /// if it does not exist. An existing file keeps its length and
+    /// contents.
+    pub fn open<P: AsRef<Path>>(path: P, len: usize) -> io::Result<Alloc> {
+        let file = try!(OpenOptions::new().read(true).write(true).create(true).open(path));
+        let existing = try!(file.metadata()).len() as usize;
+        let fresh = existing == 0;
+        let len = if fresh {
+            if len < header_end() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "arena too small"));
+            }
+            try!(file.set_len(len as u64));
+            len
+        } else {
+            existing
+        };
+
+        let base = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let a = Alloc { base: base as *mut u8, len: len, _file: file };
+        unsafe {
+            let h = a.header();
+            if fresh {
+                *h = Header { magic: MAGIC, len: len as u64,
+                              high_water: header_end() as u64, root: 0 };
+            } else if (*h).magic != MAGIC || (*h).len as usize != len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "not an allocoll arena file"));
+            }
+        }
+        Ok(a)
+    }
+
+    fn header(&self) -> *mut Header { self.base as *mut Header }
+
+    pub fn base(&self) -> *mut u8 { self.base }
+
+    /// Bytes used so far, including the header.
+    pub fn high_water_mark(&self) -> usize {
+        unsafe { (*self.header()).high_water as usize }
+    }
+
+    pub fn remaining(&self) -> usize { self.len - self.high_water_mark() }
+
+    /// Offset of `p` from the start of the mapping.
+    pub fn offset_of<T>(&self, p: *const T) -> usize {
+        p as usize - self.base as usize
+    }
+
+    /// The address `offset` bytes into the mapping.
+    pub unsafe fn at_offset<T>(&self, offset: usize) -> *mut T {
+        self.base.offset(offset as isize) as *mut T
+    }
+
+    /// Records `p` as the entry point of the persisted data.
+    pub fn set_root<T>(&self, p: *const T) {
+        unsafe { (*self.header()).root = self.offset_of(p) as u64; }
+    }
+
+    /// The entry point recorded with `set_root`, if any.
+    pub fn root<T>(&self) -> Option<*mut T> {
+        match unsafe { (*self.header()).root } {
+            0 => None,
+            off => Some(unsafe { self.at_offset(off as usize) }),
+        }
+    }
+
+    /// Writes dirty pages back to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        let r = unsafe { libc::msync(self.base as *mut libc::c_void, self.len, libc::MS_SYNC) };
+        if r == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+}
+
+impl Drop for Alloc {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len); }
+    }
+}
+
+impl ShareAlloc for Alloc {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        let h = self.header();
+        let align = kind.align();
+        let start = ((*h).high_water as usize + align - 1) & !(align - 1);
+        let end = match start.checked_add(kind.size()) {
+            Some(end) if end <= self.len => end,
+            _ => return ptr::null_mut(),
+        };
+        (*h).high_water = end as u64;
+        self.base.offset(start as isize)
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        let h = self.header();
+        if self.offset_of(ptr) + kind.size() == (*h).high_water as usize {
+            (*h).high_water = self.offset_of(ptr) as u64;
+        }
+    }
+}
+
+impl alloc::Alloc for Alloc {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
@@ -0,0 +1,295 @@
+// A wrapper that applies a NUMA placement policy to the memory its
+// inner allocator hands out.
+//
+// The policy is attached (with `mbind`) to the whole pages lying
+// inside each block that is at least `min_bind_size` bytes. Partial
+// pages at either end are shared with neighbouring blocks and are left
+// alone, as are small blocks; give the wrapper a page-granular inner
+// allocator (e.g. mmap-backed) if every byte matters. Since the kernel
+// places a page when it is first touched, binding right after
+// allocation is enough; nothing is migrated.
+//
+// Nodes are numbered from 0 without limit; the mask handed to `mbind`
+// has as many words as the highest node needs. That is all
+// libnuma's `numa_alloc_onnode` does too (it maps pages and `mbind`s
+// them), so the wrapper does not link libnuma.
+//
+// Windows cannot change the placement of pages that are already
+// committed, so there blocks of at least `min_bind_size` bytes under a
+// node policy do not come from the inner allocator at all: they are
+// committed directly on the node with `VirtualAllocExNuma` and
+// released with `VirtualFree`. `Interleave` alternates whole blocks
+// (rather than pages) over the nodes of its mask, and smaller blocks,
+// over-aligned blocks and `LocalPreferred` are served by the inner
+// allocator as they are.
+//
+// On other platforms the policy is recorded but has no effect.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+use granularity::Granularity;
+
+use std::cmp;
+use std::ptr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Allocate on the given node only.
+    Bind(usize),
+    /// Prefer the given node, falling back to others when it is full.
+    Preferred(usize),
+    /// Spread pages round-robin over the nodes in the mask (bit `n`
+    /// set means node `n`, so only nodes 0 to 63 can be named).
+    Interleave(u64),
+    /// Prefer the node of the CPU that first touches the page.
+    LocalPreferred,
+}
+
+pub struct Alloc<A> {
+    inner: A,
+    policy: Policy,
+    min_bind_size: usize,
+    direct: sys::Direct,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A, policy: Policy) -> Alloc<A> {
+        Alloc { inner: inner, policy: policy, min_bind_size: Granularity::page().bytes(),
+                direct: sys::Direct::new() }
+    }
+
+    pub fn policy(&self) -> Policy { self.policy }
+
+    pub fn set_policy(&mut self, policy: Policy) { self.policy = policy; }
+
+    /// Blocks smaller than this are not bound (default: one page).
+    pub fn set_min_bind_size(&mut self, size: usize) { self.min_bind_size = size; }
+
+    fn bind(&self, p: Address, size: usize) {
+        if p.is_null() || size < self.min_bind_size { return; }
+        let page = Granularity::page();
+        let start = page.round_up(p as usize);
+        let end = page.round_down(p as usize + size);
+        if end > start {
+            sys::bind(start, end - start, self.policy);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::Policy;
+    use libc;
+
+    use std::mem;
+    use std::ptr;
+
+    pub use super::no_direct::Direct;
+
+    const MPOL_PREFERRED: libc::c_int = 1;
+    const MPOL_BIND: libc::c_int = 2;
+    const MPOL_INTERLEAVE: libc::c_int = 3;
+    const MPOL_LOCAL: libc::c_int = 4;
+
+    fn word_bits() -> usize { 8 * mem::size_of::<libc::c_ulong>() }
+
+    // The nodemask naming just `node`, as many words long as it needs.
+    fn single_node(node: usize) -> Vec<libc::c_ulong> {
+        let mut mask = vec![0; node / word_bits() + 1];
+        mask[node / word_bits()] = 1 << (node % word_bits());
+        mask
+    }
+
+    fn from_bits(bits: u64) -> Vec<libc::c_ulong> {
+        (0..64 / word_bits()).map(|i| (bits >> (i * word_bits())) as libc::c_ulong).collect()
+    }
+
+    pub fn bind(start: usize, len: usize, policy: Policy) {
+        let (mode, mask) = match policy {
+            Policy::Bind(n) => (MPOL_BIND, single_node(n)),
+            Policy::Preferred(n) => (MPOL_PREFERRED, single_node(n)),
+            Policy::Interleave(bits) => (MPOL_INTERLEAVE, from_bits(bits)),
+            Policy::LocalPreferred => (MPOL_LOCAL, vec![]),
+        };
+        // The kernel reads `maxnode - 1` bits.
+        let (maskp, maxnode) = if mask.is_empty() {
+            (ptr::null(), 0)
+        } else {
+            (mask.as_ptr(), (mask.len() * word_bits() + 1) as libc::c_ulong)
+        };
+        unsafe {
+            // Failure (no such node, no NUMA support) just leaves the
+            // default policy in place.
+            libc::syscall(libc::SYS_mbind, start, len, mode, maskp, maxnode, 0 as libc::c_uint);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::Policy;
+    use alloc::{Address, Kind};
+    use granularity::Granularity;
+
+    use libc::{c_void, size_t};
+
+    use std::collections::HashSet;
+    use std::ptr;
+
+    type Handle = *mut c_void;
+
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_RESERVE: u32 = 0x2000;
+    const MEM_RELEASE: u32 = 0x8000;
+    const PAGE_READWRITE: u32 = 0x04;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> Handle;
+        fn VirtualAllocExNuma(process: Handle, addr: *mut c_void, size: size_t,
+                              alloc_type: u32, protect: u32, node: u32) -> *mut c_void;
+        fn VirtualFree(addr: *mut c_void, size: size_t, free_type: u32) -> i32;
+    }
+
+    // Committed pages cannot be moved to another node.
+    pub fn bind(_start: usize, _len: usize, _policy: Policy) { }
+
+    // Blocks committed on a node with `VirtualAllocExNuma`.
+    pub struct Direct {
+        blocks: HashSet<usize>,
+        // index of the next mask bit `Interleave` places a block on
+        next_interleave: usize,
+    }
+
+    impl Direct {
+        pub fn new() -> Direct { Direct { blocks: HashSet::new(), next_interleave: 0 } }
+
+        pub fn eligible(&self, kind: Kind, policy: Policy, min_bind_size: usize) -> bool {
+            match policy {
+                Policy::LocalPreferred | Policy::Interleave(0) => false,
+                _ => kind.size() >= min_bind_size && kind.size() > 0
+                    && kind.align() <= Granularity::page().bytes(),
+            }
+        }
+
+        fn next_node(&mut self, bits: u64) -> usize {
+            loop {
+                let n = self.next_interleave % 64;
+                self.next_interleave = n + 1;
+                if bits & (1 << n) != 0 { return n; }
+            }
+        }
+
+        pub unsafe fn alloc(&mut self, kind: Kind, policy: Policy) -> Address {
+            let node = match policy {
+                Policy::Bind(n) | Policy::Preferred(n) => n,
+                Policy::Interleave(bits) => self.next_node(bits),
+                Policy::LocalPreferred => unreachable!(),
+            };
+            let len = Granularity::page().round_up(kind.size());
+            let p = VirtualAllocExNuma(GetCurrentProcess(), ptr::null_mut(), len,
+                                       MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE, node as u32);
+            if !p.is_null() { self.blocks.insert(p as usize); }
+            p as Address
+        }
+
+        pub fn owns(&self, ptr: Address) -> bool { self.blocks.contains(&(ptr as usize)) }
+
+        pub unsafe fn dealloc(&mut self, ptr: Address) {
+            self.blocks.remove(&(ptr as usize));
+            VirtualFree(ptr as *mut c_void, 0, MEM_RELEASE);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod sys {
+    use super::Policy;
+
+    pub use super::no_direct::Direct;
+
+    pub fn bind(_start: usize, _len: usize, _policy: Policy) { }
+}
+
+// Outside Windows every block comes from the inner allocator.
+#[cfg(not(windows))]
+mod no_direct {
+    use super::Policy;
+    use alloc::{Address, Kind};
+
+    use std::ptr;
+
+    pub struct Direct;
+
+    impl Direct {
+        pub fn new() -> Direct { Direct }
+
+        pub fn eligible(&self, _kind: Kind, _policy: Policy, _min_bind_size: usize) -> bool {
+            false
+        }
+
+        pub unsafe fn alloc(&mut self, _kind: Kind, _policy: Policy) -> Address {
+            ptr::null_mut()
+        }
+
+        pub fn owns(&self, _ptr: Address) -> bool { false }
+
+        pub unsafe fn dealloc(&mut self, _ptr: Address) { }
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if self.direct.eligible(kind, self.policy, self.min_bind_size) {
+            return self.direct.alloc(kind, self.policy);
+        }
+        let p = self.inner.alloc(kind);
+        self.bind(p, kind.size());
+        p
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if self.direct.owns(ptr) { return self.direct.dealloc(ptr); }
+        self.inner.dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        if self.direct.owns(ptr) { return self.direct.dealloc(ptr); }
+        self.inner.dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        // A block of this kind may have come from either side.
+        if self.direct.eligible(kind, self.policy, self.min_bind_size) { return kind.size(); }
+        self.inner.usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        if self.direct.owns(ptr) {
+            let page = Granularity::page();
+            return if new_size <= page.round_up(kind.size()) { Ok(()) } else { Err(AllocError::Unsupported) };
+        }
+        try!(self.inner.grow_in_place(ptr, kind, new_size));
+        self.bind(ptr, new_size);
+        Ok(())
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        if self.direct.owns(ptr) { return Ok(()); }
+        self.inner.shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        if self.direct.owns(ptr) || self.direct.eligible(new_kind, self.policy, self.min_bind_size) {
+            let new_ptr = self.alloc(new_kind);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(kind.size(), new_size));
+                self.dealloc(ptr, kind);
+            }
+            return new_ptr;
+        }
+        let p = self.inner.realloc(ptr, kind, new_size);
+        self.bind(p, new_size);
+        p
+    }
+}
@@ -0,0 +1,141 @@
+// A pool of reusable objects.
+//
+// `Pool::get` hands out a `Pooled` smart pointer. When it is dropped,
+// the object is not destroyed: the pool's reset hook runs on it and it
+// goes back on the free list, so the next `get` reuses it (buffers
+// keep their capacity, contexts keep their setup). Only when the free
+// list is empty does the pool construct a new object, boxed in memory
+// from its allocator. Registered with `pressure`, a pool destroys idle
+// objects when memory runs short.
+//
+// Idle objects are marked inaccessible to memory checkers (see
+// `annotate`) until they are handed out again, so a use of an object
+// after its `Pooled` was dropped is reported like a use after free.
+
+use alloc::{Address, Alloc, DefaultAlloc};
+use annotate;
+use boxed::Box;
+use pressure::{self, Trim};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+pub struct Pool<T, A:Alloc + Clone = DefaultAlloc> {
+    alloc: A,
+    free: RefCell<Vec<Box<T, A>>>,
+    construct: ::std::boxed::Box<Fn() -> T>,
+    reset: ::std::boxed::Box<Fn(&mut T)>,
+}
+
+impl<T, A:Alloc + Clone> Pool<T, A> {
+    /// Creates a pool that builds objects with `construct` and
+    /// prepares returned objects for reuse with `reset`.
+    pub fn new<C, R>(alloc: A, construct: C, reset: R) -> Self
+        where C: Fn() -> T + 'static, R: Fn(&mut T) + 'static
+    {
+        Pool { alloc: alloc, free: RefCell::new(Vec::new()),
+               construct: ::std::boxed::Box::new(construct),
+               reset: ::std::boxed::Box::new(reset) }
+    }
+
+    /// Takes an idle object, or constructs one if none is available.
+    pub fn get(&self) -> Pooled<T, A> {
+        // Not borrowed across `construct`, which may use the pool too.
+        let popped = self.free.borrow_mut().pop();
+        let obj = match popped {
+            Some(b) => { wake(&b); b }
+            None => Box::new_in((self.construct)(), self.alloc.clone()),
+        };
+        Pooled { obj: Some(obj), pool: self }
+    }
+
+    /// Number of objects waiting on the free list.
+    pub fn idle_count(&self) -> usize {
+        self.free.borrow().len()
+    }
+
+    /// Destroys idle objects until at most `keep` remain.
+    pub fn shrink_to(&self, keep: usize) {
+        let spare = mem::replace(&mut *self.free.borrow_mut(), Vec::new());
+        let mut spare = spare.into_iter();
+        self.free.borrow_mut().extend(spare.by_ref().take(keep));
+        for obj in spare { wake(&obj); }
+    }
+}
+
+impl<T, A:Alloc + Clone> Drop for Pool<T, A> {
+    fn drop(&mut self) {
+        for obj in self.free.borrow().iter() { wake(obj); }
+    }
+}
+
+fn object<T, A:Alloc>(obj: &Box<T, A>) -> (Address, usize) {
+    (&**obj as *const T as Address, mem::size_of::<T>())
+}
+
+// An object going onto the free list, where nothing may touch it.
+fn idle<T, A:Alloc>(obj: &Box<T, A>) {
+    let (p, size) = object(obj);
+    annotate::no_access(p, size);
+}
+
+// An idle object about to be used (or destroyed), still holding the
+// state `reset` left it in.
+fn wake<T, A:Alloc>(obj: &Box<T, A>) {
+    let (p, size) = object(obj);
+    annotate::defined(p, size);
+}
+
+// Under moderate pressure half the idle objects go, under critical
+// pressure all of them.
+impl<T, A:Alloc + Clone> Trim for Pool<T, A> {
+    fn trim(&self, level: pressure::Level) -> usize {
+        let idle = self.idle_count();
+        let keep = match level {
+            pressure::Level::Moderate => idle / 2,
+            pressure::Level::Critical => 0,
+        };
+        self.shrink_to(keep);
+        (idle - keep) * mem::size_of::<T>()
+    }
+}
+
+/// An object checked out of a `Pool`; returns itself on drop.
+pub struct Pooled<'a, T: 'a, A:Alloc + Clone + 'a = DefaultAlloc> {
+    obj: Option<Box<T, A>>,
+    pool: &'a Pool<T, A>,
+}
+
+impl<'a, T, A:Alloc + Clone> Pooled<'a, T, A> {
+    /// Removes the object from the pool's custody for good.
+    pub fn detach(mut self) -> Box<T, A> {
+        self.obj.take().unwrap()
+    }
+}
+
+impl<'a, T, A:Alloc + Clone> Deref for Pooled<'a, T, A> {
+    type Target = T;
+    fn deref(&self) -> &T { self.obj.as_ref().unwrap() }
+}
+
+impl<'a, T, A:Alloc + Clone> DerefMut for Pooled<'a, T, A> {
+    fn deref_mut(&mut self) -> &mut T { self.obj.as_mut().unwrap() }
+}
+
+impl<'a, T, A:Alloc + Clone> Drop for Pooled<'a, T, A> {
+    fn drop(&mut self) {
+        if let Some(mut obj) = self.obj.take() {
+            (self.pool.reset)(&mut obj);
+            idle(&obj);
+            self.pool.free.borrow_mut().push(obj);
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug, A:Alloc + Clone> fmt::Debug for Pooled<'a, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
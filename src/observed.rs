@@ -0,0 +1,175 @@
+// Allocation events for tools that only want to watch.
+//
+// `observed::Alloc<A, O>` forwards every request to `A` unchanged and
+// reports what happened to the observer `O` afterwards, so a logger
+// or a counter is a single `observe` method instead of a whole wrapper
+// allocator. Observers see the outcome (including the returned
+// address, and failures as null), never the request in flight, and
+// must not allocate from the allocator they are watching.
+//
+// Three observers come with the crate: `Stderr` prints each event,
+// `Recent` keeps the last N in a ring buffer for post-mortems, and
+// `Counters` aggregates them. A pair `(O1, O2)` observes with both.
+// `timeline::Timeline` records them with timestamps for export to
+// trace viewers.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// `ptr` is null if the allocation failed.
+    Alloc { ptr: Address, kind: Kind },
+    Dealloc { ptr: Address, kind: Kind },
+    /// `new_ptr` is null if the reallocation failed, in which case
+    /// the block at `ptr` is untouched.
+    Realloc { ptr: Address, kind: Kind, new_ptr: Address, new_size: Size },
+}
+
+pub trait AllocObserver {
+    fn observe(&mut self, event: Event);
+}
+
+impl<'a, O: AllocObserver + ?Sized> AllocObserver for &'a mut O {
+    fn observe(&mut self, event: Event) { (**self).observe(event) }
+}
+
+impl<O1: AllocObserver, O2: AllocObserver> AllocObserver for (O1, O2) {
+    fn observe(&mut self, event: Event) {
+        self.0.observe(event);
+        self.1.observe(event);
+    }
+}
+
+/// Writes one line per event to standard error.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stderr;
+
+impl AllocObserver for Stderr {
+    fn observe(&mut self, event: Event) {
+        let _ = writeln!(io::stderr(), "alloc event: {:?}", event);
+    }
+}
+
+/// Remembers the most recent events, oldest first.
+pub struct Recent {
+    events: VecDeque<Event>,
+    cap: usize,
+}
+
+impl Recent {
+    pub fn new(cap: usize) -> Recent {
+        Recent { events: VecDeque::with_capacity(cap), cap: cap }
+    }
+
+    pub fn events(&self) -> &VecDeque<Event> { &self.events }
+}
+
+impl AllocObserver for Recent {
+    fn observe(&mut self, event: Event) {
+        if self.cap == 0 { return; }
+        if self.events.len() == self.cap { self.events.pop_front(); }
+        self.events.push_back(event);
+    }
+}
+
+/// Aggregate counts of calls and bytes.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct Counters {
+    pub allocs: usize,
+    pub deallocs: usize,
+    pub reallocs: usize,
+    pub failures: usize,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+}
+
+impl AllocObserver for Counters {
+    fn observe(&mut self, event: Event) {
+        match event {
+            Event::Alloc { ptr, kind } => {
+                if ptr.is_null() { self.failures += 1; return; }
+                self.allocs += 1;
+                self.live_bytes += kind.size();
+            }
+            Event::Dealloc { kind, .. } => {
+                self.deallocs += 1;
+                self.live_bytes -= kind.size();
+            }
+            Event::Realloc { kind, new_ptr, new_size, .. } => {
+                if new_ptr.is_null() { self.failures += 1; return; }
+                self.reallocs += 1;
+                self.live_bytes = self.live_bytes - kind.size() + new_size;
+            }
+        }
+        if self.live_bytes > self.peak_bytes { self.peak_bytes = self.live_bytes; }
+    }
+}
+
+pub struct Alloc<A: alloc::Alloc, O: AllocObserver> {
+    inner: A,
+    observer: O,
+}
+
+impl<A: alloc::Alloc, O: AllocObserver> Alloc<A, O> {
+    pub fn new(inner: A, observer: O) -> Alloc<A, O> {
+        Alloc { inner: inner, observer: observer }
+    }
+
+    pub fn observer(&self) -> &O { &self.observer }
+
+    pub fn observer_mut(&mut self) -> &mut O { &mut self.observer }
+
+    pub fn into_inner(self) -> (A, O) { (self.inner, self.observer) }
+}
+
+impl<A: alloc::Alloc, O: AllocObserver> alloc::Alloc for Alloc<A, O> {
+    unsafe fn oom(&mut self) -> ! { self.inner.oom() }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        let ptr = self.inner.alloc(kind);
+        self.observer.observe(Event::Alloc { ptr: ptr, kind: kind });
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.inner.dealloc(ptr, kind);
+        self.observer.observe(Event::Dealloc { ptr: ptr, kind: kind });
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.inner.dealloc_hot(ptr, kind);
+        self.observer.observe(Event::Dealloc { ptr: ptr, kind: kind });
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.inner.usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.inner.grow_in_place(ptr, kind, new_size);
+        if r.is_ok() {
+            self.observer.observe(Event::Realloc { ptr: ptr, kind: kind,
+                                                   new_ptr: ptr, new_size: new_size });
+        }
+        r
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let r = self.inner.shrink_in_place(ptr, kind, new_size);
+        if r.is_ok() {
+            self.observer.observe(Event::Realloc { ptr: ptr, kind: kind,
+                                                   new_ptr: ptr, new_size: new_size });
+        }
+        r
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let new_ptr = self.inner.realloc(ptr, kind, new_size);
+        self.observer.observe(Event::Realloc { ptr: ptr, kind: kind,
+                                               new_ptr: new_ptr, new_size: new_size });
+        new_ptr
+    }
+}
@@ -0,0 +1,149 @@
+// Self-relative pointers, for data structures that must stay valid
+// when the memory holding them is mapped at a different address
+// (a `mmap_file` arena reopened later, a shared-memory segment mapped
+// into another process, ...).
+//
+// A `RelPtr<T>` stores the distance from its own address to its
+// target, so it is correct as long as it moves *together with* the
+// target; moving a `RelPtr` on its own (out of the arena) breaks it.
+// Hence it is neither `Copy` nor `Clone`, and the containers below are
+// meant to be constructed in place inside the arena.
+//
+// The containers cannot store their allocator (that would be an
+// absolute pointer), so operations that (de)allocate take it as an
+// argument and nothing is freed implicitly on drop.
+
+use alloc::{Alloc, Kind, Raw};
+
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+/// A pointer stored as an offset from its own address. The offset 0
+/// (pointing at itself) encodes null.
+pub struct RelPtr<T> {
+    offset: isize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> RelPtr<T> {
+    pub fn null() -> RelPtr<T> {
+        RelPtr { offset: 0, _marker: PhantomData }
+    }
+
+    pub fn is_null(&self) -> bool { self.offset == 0 }
+
+    pub fn get(&self) -> *mut T {
+        if self.offset == 0 {
+            ptr::null_mut()
+        } else {
+            (self as *const Self as isize).wrapping_add(self.offset) as *mut T
+        }
+    }
+
+    /// Points `self` at `target`, which must not be `self` itself.
+    pub fn set(&mut self, target: *mut T) {
+        self.offset = if target.is_null() {
+            0
+        } else {
+            (target as isize).wrapping_sub(self as *const Self as isize)
+        };
+        debug_assert!(target.is_null() || self.offset != 0);
+    }
+}
+
+/// A `Box`-like owner of one `T`, relocatable with its arena.
+pub struct RelBox<T: Raw> {
+    ptr: RelPtr<T>,
+}
+
+impl<T: Raw> RelBox<T> {
+    pub fn empty() -> RelBox<T> { RelBox { ptr: RelPtr::null() } }
+
+    /// Allocates `value` from `a` and points `self` at it, freeing
+    /// the previous value (from the same allocator) if any.
+    pub fn set_in<A: Alloc>(&mut self, value: T, a: &mut A) -> bool {
+        self.free_in(a);
+        unsafe {
+            match a.alloc_one::<T>() {
+                Ok(p) => { ptr::write(*p, value); self.ptr.set(*p); true }
+                Err(_) => false,
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.ptr.is_null() { None } else { Some(unsafe { &*self.ptr.get() }) }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.ptr.is_null() { None } else { Some(unsafe { &mut *self.ptr.get() }) }
+    }
+
+    /// Returns the value's memory to `a`, which must be the allocator
+    /// it came from.
+    pub fn free_in<A: Alloc>(&mut self, a: &mut A) {
+        if !self.ptr.is_null() {
+            unsafe { a.dealloc(self.ptr.get() as *mut u8, Kind::new::<T>()); }
+            self.ptr.set(ptr::null_mut());
+        }
+    }
+}
+
+/// A growable array of `T`, relocatable with its arena.
+pub struct RelVec<T: Raw> {
+    ptr: RelPtr<T>,
+    len: usize,
+    cap: usize,
+}
+
+impl<T: Raw> RelVec<T> {
+    pub fn empty() -> RelVec<T> { RelVec { ptr: RelPtr::null(), len: 0, cap: 0 } }
+
+    pub fn len(&self) -> usize { self.len }
+    pub fn capacity(&self) -> usize { self.cap }
+
+    /// Appends `value`, growing the buffer from `a` if necessary.
+    /// Returns `Err(value)` if the allocator cannot supply the room.
+    pub fn push_in<A: Alloc>(&mut self, value: T, a: &mut A) -> Result<(), T> {
+        assert!(mem::size_of::<T>() != 0, "RelVec does not support zero-sized types");
+        if self.len == self.cap {
+            let new_cap = cmp::max(4, self.cap * 2);
+            let p = unsafe {
+                if self.cap == 0 {
+                    a.alloc(Kind::new::<T>().array(new_cap))
+                } else {
+                    a.realloc(self.ptr.get() as *mut u8, Kind::new::<T>().array(self.cap),
+                              Kind::new::<T>().array(new_cap).size())
+                }
+            };
+            if p.is_null() { return Err(value); }
+            self.ptr.set(p as *mut T);
+            self.cap = new_cap;
+        }
+        unsafe { ptr::write(self.ptr.get().offset(self.len as isize), value); }
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        if self.len == 0 { return &[]; }
+        unsafe { slice::from_raw_parts(self.ptr.get(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        if self.len == 0 { return &mut []; }
+        unsafe { slice::from_raw_parts_mut(self.ptr.get(), self.len) }
+    }
+
+    /// Returns the buffer to `a`, which must be the allocator it came
+    /// from, leaving `self` empty.
+    pub fn free_in<A: Alloc>(&mut self, a: &mut A) {
+        if self.cap != 0 {
+            unsafe { a.dealloc(self.ptr.get() as *mut u8, Kind::new::<T>().array(self.cap)); }
+        }
+        *self = RelVec::empty();
+    }
+}
@@ -0,0 +1,170 @@
+// A persistent (immutable, structurally shared) vector.
+//
+// `PVec` is a 32-way trie in the style of Clojure's vectors: values
+// live in leaves of up to 32, and each level of branches above them
+// covers 32 times as many. `push_back` and `set` leave the original
+// untouched and return a new version that copies only the nodes on
+// the path to the changed slot, sharing everything else; both are
+// O(log32 n), which is a handful of node copies for any realistic n.
+//
+// Nodes are `RcLite`s whose blocks, like the child and value arrays
+// inside them, come from `A`. Every node needs its own copy of the
+// allocator, so `A` must be `Clone`: a stateless allocator, or a
+// handle (`&A`, `Rc<A>`) to an arena, in which case every version of
+// every vector lives in that arena.
+
+use alloc::{Alloc, DefaultAlloc};
+use rc_lite::RcLite;
+use vec::Vec;
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+enum Node<T, A:Alloc + Clone> {
+    Branch(Vec<RcLite<Node<T, A>, A>, A>),
+    Leaf(Vec<T, A>),
+}
+
+pub struct PVec<T, A:Alloc + Clone = DefaultAlloc> {
+    root: Option<RcLite<Node<T, A>, A>>,
+    len: usize,
+    // bit offset of the root's level; 0 when the root is a leaf
+    shift: usize,
+    alloc: A,
+}
+
+impl<T: Clone, A:Alloc + Clone> PVec<T, A> {
+    pub fn new() -> Self where A: Default {
+        PVec::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        PVec { root: None, len: 0, shift: 0, alloc: a }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        if i >= self.len { return None; }
+        let mut node = &**self.root.as_ref().unwrap();
+        let mut level = self.shift;
+        loop {
+            match *node {
+                Node::Branch(ref children) => {
+                    node = &*children[(i >> level) & MASK];
+                    level -= BITS;
+                }
+                Node::Leaf(ref values) => return Some(&values[i & MASK]),
+            }
+        }
+    }
+
+    /// A new version with `value` appended.
+    pub fn push_back(&self, value: T) -> Self {
+        let (root, shift) = match self.root {
+            None => (self.leaf(value), 0),
+            // the trie is full: grow a level above the old root
+            Some(ref root) if self.len == 1 << (self.shift + BITS) => {
+                let mut children = Vec::with_capacity_alloc(WIDTH, self.alloc.clone());
+                children.push(root.clone());
+                children.push(self.path(self.shift, value));
+                (self.node(Node::Branch(children)), self.shift + BITS)
+            }
+            Some(ref root) => (self.push_in(root, self.shift, value), self.shift),
+        };
+        PVec { root: Some(root), len: self.len + 1, shift: shift, alloc: self.alloc.clone() }
+    }
+
+    /// A new version with the value at `i` replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i >= len`.
+    pub fn set(&self, i: usize, value: T) -> Self {
+        assert!(i < self.len, "index out of bounds");
+        let root = self.set_in(self.root.as_ref().unwrap(), self.shift, i, value);
+        PVec { root: Some(root), len: self.len, shift: self.shift, alloc: self.alloc.clone() }
+    }
+
+    /// Iterates over the values in order.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=&'a T> + 'a> {
+        Box::new((0..self.len).map(move |i| self.get(i).unwrap()))
+    }
+
+    fn node(&self, n: Node<T, A>) -> RcLite<Node<T, A>, A> {
+        RcLite::new_in(n, self.alloc.clone())
+    }
+
+    fn leaf(&self, value: T) -> RcLite<Node<T, A>, A> {
+        let mut values = Vec::with_capacity_alloc(WIDTH, self.alloc.clone());
+        values.push(value);
+        self.node(Node::Leaf(values))
+    }
+
+    // A fresh chain of single-child branches from `level` down to a
+    // leaf holding `value`.
+    fn path(&self, level: usize, value: T) -> RcLite<Node<T, A>, A> {
+        if level == 0 { return self.leaf(value); }
+        let mut children = Vec::with_capacity_alloc(WIDTH, self.alloc.clone());
+        children.push(self.path(level - BITS, value));
+        self.node(Node::Branch(children))
+    }
+
+    // Copies of `node`'s arrays, for path copying.
+    fn copy(&self, node: &Node<T, A>) -> Node<T, A> {
+        match *node {
+            Node::Branch(ref children) => {
+                let mut c = Vec::with_capacity_alloc(WIDTH, self.alloc.clone());
+                for child in children.iter() { c.push(child.clone()); }
+                Node::Branch(c)
+            }
+            Node::Leaf(ref values) => {
+                let mut v = Vec::with_capacity_alloc(WIDTH, self.alloc.clone());
+                for x in values.iter() { v.push(x.clone()); }
+                Node::Leaf(v)
+            }
+        }
+    }
+
+    fn push_in(&self, node: &RcLite<Node<T, A>, A>, level: usize, value: T)
+               -> RcLite<Node<T, A>, A> {
+        let mut copy = self.copy(node);
+        match copy {
+            Node::Leaf(ref mut values) => values.push(value),
+            Node::Branch(ref mut children) => {
+                let i = (self.len >> level) & MASK;
+                if i < children.len() {
+                    let child = self.push_in(&children[i], level - BITS, value);
+                    children[i] = child;
+                } else {
+                    children.push(self.path(level - BITS, value));
+                }
+            }
+        }
+        self.node(copy)
+    }
+
+    fn set_in(&self, node: &RcLite<Node<T, A>, A>, level: usize, i: usize, value: T)
+              -> RcLite<Node<T, A>, A> {
+        let mut copy = self.copy(node);
+        match copy {
+            Node::Leaf(ref mut values) => values[i & MASK] = value,
+            Node::Branch(ref mut children) => {
+                let j = (i >> level) & MASK;
+                let child = self.set_in(&children[j], level - BITS, i, value);
+                children[j] = child;
+            }
+        }
+        self.node(copy)
+    }
+}
+
+impl<T, A:Alloc + Clone> Clone for PVec<T, A> {
+    fn clone(&self) -> Self {
+        PVec { root: self.root.clone(), len: self.len, shift: self.shift,
+               alloc: self.alloc.clone() }
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::{Alloc, DefaultAlloc};
+use boxed::Box;
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Types that can be safely moved even after being pinned.
+///
+/// This is implemented for every type by default; opt out by
+/// embedding a `PhantomPinned`.
+pub trait Unpin { }
+
+impl Unpin for .. { }
+
+/// A marker that makes the containing type `!Unpin`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhantomPinned;
+
+impl !Unpin for PhantomPinned { }
+
+/// Allocator-backed storage whose pointee is guaranteed never to move
+/// until it is dropped in place and returned to the allocator that
+/// produced it.
+///
+/// Shared access is always available. Mutable access is only safe
+/// for `T: Unpin`; otherwise it goes through `get_mut_unchecked`,
+/// whose caller promises not to move out of the reference.
+pub struct Pinned<T: ?Sized, A:Alloc = DefaultAlloc> {
+    boxed: Box<T, A>,
+}
+
+impl<T, A:Alloc> Pinned<T, A> {
+    pub fn new_in(value: T, alloc: A) -> Self {
+        Pinned { boxed: Box::new_in(value, alloc) }
+    }
+}
+
+impl<T, A:Alloc> Box<T, A> {
+    /// Allocates `value` from `alloc` and pins it there.
+    pub fn pin_in(value: T, alloc: A) -> Pinned<T, A> {
+        Pinned::new_in(value, alloc)
+    }
+}
+
+impl<T: ?Sized, A:Alloc> Pinned<T, A> {
+    /// Pins an existing box. The value may have moved while boxed
+    /// before this call, but never after.
+    pub fn from_box(boxed: Box<T, A>) -> Self {
+        Pinned { boxed: boxed }
+    }
+
+    /// Returns a mutable reference to the pinned value.
+    ///
+    /// Unsafe because the caller must not move the value out of the
+    /// reference (e.g. via `mem::swap` or `mem::replace`).
+    pub unsafe fn get_mut_unchecked(&mut self) -> &mut T {
+        &mut *self.boxed
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        &*self.boxed
+    }
+}
+
+impl<T: ?Sized + Unpin, A:Alloc> Pinned<T, A> {
+    /// Releases the pin; only possible when the value does not care
+    /// about its address.
+    pub fn into_box(self) -> Box<T, A> {
+        self.boxed
+    }
+}
+
+impl<T: ?Sized, A:Alloc> Deref for Pinned<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &*self.boxed }
+}
+
+impl<T: ?Sized + Unpin, A:Alloc> DerefMut for Pinned<T, A> {
+    fn deref_mut(&mut self) -> &mut T { &mut *self.boxed }
+}
+
+impl<T: fmt::Debug + ?Sized, A:Alloc> fmt::Debug for Pinned<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.boxed, f)
+    }
+}
@@ -0,0 +1,69 @@
+// Cooperative release of cached memory under memory pressure.
+//
+// Several wrappers hold on to memory they could give back: quarantine
+// parks freed blocks, object pools keep idle objects, the deferred
+// wrapper queues frees, purgeable blocks and scratch buffers linger
+// between uses. Each implements `Trim`, shedding more the higher the
+// `Level`.
+//
+// Caches to be trimmed are registered, as `Rc`s, with `register`; the
+// registry keeps only weak references, so a dropped cache simply
+// drops out of it. When the OS signals memory pressure the application
+// calls `release(level)`, which trims every registered cache that is
+// still alive. The caches are single-threaded (`Cell`/`RefCell`
+// inside), so the registry is per thread: `release` trims the caches
+// registered on the calling thread, and an application whose caches
+// live on several threads forwards the signal to each of them.
+//
+// A `RefCell`ed cache that is borrowed when `release` runs (because
+// `release` was called from inside it) is skipped.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+/// How urgently memory is needed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Drop what is cheap to rebuild; keep enough to stay fast.
+    Moderate,
+    /// Drop everything that is not in use.
+    Critical,
+}
+
+/// A cache that can give memory back on request.
+pub trait Trim {
+    /// Releases cached memory as `level` demands, returning the number
+    /// of bytes given back to the allocator beneath.
+    fn trim(&self, level: Level) -> usize;
+}
+
+impl<'a, T: Trim + ?Sized> Trim for &'a T {
+    fn trim(&self, level: Level) -> usize { (**self).trim(level) }
+}
+
+impl<T: Trim + ?Sized> Trim for Rc<T> {
+    fn trim(&self, level: Level) -> usize { (**self).trim(level) }
+}
+
+thread_local!(static REGISTRY: RefCell<Vec<Weak<Trim>>> = RefCell::new(Vec::new()));
+
+/// Adds `cache` to this thread's registry for as long as it lives.
+pub fn register<T: Trim + 'static>(cache: &Rc<T>) {
+    let weak: Weak<Trim> = Rc::downgrade(cache);
+    REGISTRY.with(|r| r.borrow_mut().push(weak));
+}
+
+/// Trims every live cache registered on this thread, returning the
+/// total bytes released.
+pub fn release(level: Level) -> usize {
+    // Work on a snapshot so that hooks may register further caches.
+    let hooks: Vec<Weak<Trim>> = REGISTRY.with(|r| r.borrow().clone());
+    let mut released = 0;
+    for hook in &hooks {
+        if let Some(cache) = hook.upgrade() {
+            released += cache.trim(level);
+        }
+    }
+    REGISTRY.with(|r| r.borrow_mut().retain(|w| w.upgrade().is_some()));
+    released
+}
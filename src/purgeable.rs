@@ -0,0 +1,205 @@
+// An allocator for cache-like data that may be thrown away under
+// memory pressure.
+//
+// Purgeable blocks are not addressed by pointer but by a `Token`;
+// their bytes are reached through `try_pin`, which returns `None` once
+// the block has been purged. Borrowing rules then ensure no block is
+// purged while pinned: `purge` and `try_pin` both need `&mut self`.
+//
+// Blocks are only purged while marked discardable (the default for
+// new blocks). `purge()`, and `pressure::release` if the allocator is
+// registered there, release every discardable block. A new purgeable
+// allocation that would take the total over the configured budget
+// instead releases discardable blocks oldest first, and only until the
+// new block fits.
+//
+// Ordinary `Alloc` requests are passed through to the inner allocator
+// and are never purged.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+use pressure::{self, Trim};
+
+use std::cell::RefCell;
+use std::slice;
+
+/// Names a purgeable block. Tokens are never reused, so a stale one
+/// simply stops resolving.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Token {
+    index: usize,
+    generation: u64,
+}
+
+struct Entry {
+    generation: u64,
+    block: Option<Address>,
+    kind: Kind,
+    discardable: bool,
+}
+
+pub struct Alloc<A: alloc::Alloc> {
+    inner: A,
+    entries: Vec<Entry>,
+    free_slots: Vec<usize>,
+    next_generation: u64,
+    live_bytes: usize,
+    budget: Option<usize>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A) -> Alloc<A> {
+        Alloc { inner: inner, entries: Vec::new(), free_slots: Vec::new(),
+                next_generation: 0, live_bytes: 0, budget: None }
+    }
+
+    /// Caps the bytes held in purgeable blocks; exceeding it purges
+    /// discardable blocks, oldest first, until the new block fits.
+    pub fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+    }
+
+    /// Bytes currently held in (unpurged) purgeable blocks.
+    pub fn purgeable_bytes(&self) -> usize { self.live_bytes }
+
+    pub fn alloc_purgeable(&mut self, kind: Kind) -> Option<Token> {
+        if let Some(budget) = self.budget {
+            if self.live_bytes + kind.size() > budget {
+                self.purge_oldest(budget.saturating_sub(kind.size()));
+            }
+        }
+        let block = unsafe { self.inner.alloc(kind) };
+        if block.is_null() { return None; }
+        self.live_bytes += kind.size();
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let entry = Entry { generation: generation, block: Some(block),
+                            kind: kind, discardable: true };
+        let index = match self.free_slots.pop() {
+            Some(i) => { self.entries[i] = entry; i }
+            None => { self.entries.push(entry); self.entries.len() - 1 }
+        };
+        Some(Token { index: index, generation: generation })
+    }
+
+    fn entry_mut(&mut self, t: Token) -> Option<&mut Entry> {
+        match self.entries.get_mut(t.index) {
+            Some(e) if e.generation == t.generation => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Controls whether `purge` may release the block.
+    pub fn set_discardable(&mut self, t: Token, discardable: bool) {
+        if let Some(e) = self.entry_mut(t) { e.discardable = discardable; }
+    }
+
+    /// Returns the block's bytes, or `None` if it has been purged (or
+    /// freed).
+    pub fn try_pin(&mut self, t: Token) -> Option<&mut [u8]> {
+        match self.entry_mut(t) {
+            Some(&mut Entry { block: Some(p), kind, .. }) =>
+                Some(unsafe { slice::from_raw_parts_mut(p, kind.size()) }),
+            _ => None,
+        }
+    }
+
+    pub fn is_purged(&self, t: Token) -> bool {
+        match self.entries.get(t.index) {
+            Some(e) if e.generation == t.generation => e.block.is_none(),
+            _ => true,
+        }
+    }
+
+    /// Releases the block (purged or not) and invalidates `t`.
+    pub fn free(&mut self, t: Token) {
+        let released = match self.entry_mut(t) {
+            Some(e) => { e.generation = !0; e.block.take().map(|p| (p, e.kind)) }
+            None => return,
+        };
+        if let Some((p, kind)) = released {
+            self.live_bytes -= kind.size();
+            unsafe { self.inner.dealloc(p, kind); }
+        }
+        self.free_slots.push(t.index);
+    }
+
+    /// Releases every discardable block, returning the bytes freed.
+    pub fn purge(&mut self) -> usize {
+        let mut freed = 0;
+        for e in &mut self.entries {
+            if !e.discardable { continue; }
+            if let Some(p) = e.block.take() {
+                unsafe { self.inner.dealloc(p, e.kind); }
+                freed += e.kind.size();
+            }
+        }
+        self.live_bytes -= freed;
+        freed
+    }
+
+    // Releases discardable blocks in allocation order until at most
+    // `target` bytes are held.
+    fn purge_oldest(&mut self, target: usize) {
+        let mut victims: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| self.entries[i].discardable && self.entries[i].block.is_some())
+            .collect();
+        victims.sort_by_key(|&i| self.entries[i].generation);
+        for i in victims {
+            if self.live_bytes <= target { break; }
+            let e = &mut self.entries[i];
+            let p = e.block.take().unwrap();
+            unsafe { self.inner.dealloc(p, e.kind); }
+            self.live_bytes -= e.kind.size();
+        }
+    }
+}
+
+// Purgeable blocks exist to be dropped under pressure, at any level.
+impl<A: alloc::Alloc> Trim for RefCell<Alloc<A>> {
+    fn trim(&self, _level: pressure::Level) -> usize {
+        match self.try_borrow_mut() {
+            Ok(mut p) => p.purge(),
+            Err(_) => 0,
+        }
+    }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        for e in &mut self.entries {
+            if let Some(p) = e.block.take() {
+                unsafe { self.inner.dealloc(p, e.kind); }
+            }
+        }
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        self.inner.alloc(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.inner.dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.inner.dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.inner.usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.inner.grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.inner.shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.inner.realloc(ptr, kind, new_size)
+    }
+}
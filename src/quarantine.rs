@@ -0,0 +1,139 @@
+// A debugging wrapper that delays the reuse of freed memory.
+//
+// Freed blocks are filled with a poison byte and parked in a FIFO
+// instead of being returned to the inner allocator. Only when the
+// parked blocks exceed the byte budget is the oldest one checked and
+// really freed. Meanwhile no new allocation can land on a parked
+// block, so a use-after-free reads poison (deterministically) rather
+// than someone else's live data, and a write-after-free is caught
+// when the block leaves quarantine and its poison turns out disturbed.
+//
+// Parked blocks are also marked inaccessible for memory checkers (see
+// `annotate`), so under Valgrind or ASan a use-after-free is reported
+// at the offending access rather than at release.
+//
+// Registered with `pressure`, it releases parked blocks early.
+//
+// `realloc` always moves the block, so stale pointers to the old
+// location are quarantined as well.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+use annotate;
+use pressure::{self, Trim};
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::ptr;
+
+pub const DEFAULT_POISON: u8 = 0xDE;
+
+pub struct Alloc<A: alloc::Alloc> {
+    inner: A,
+    parked: VecDeque<(Address, Kind)>,
+    parked_bytes: usize,
+    budget: usize,
+    poison: u8,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Quarantines up to `budget` bytes of freed blocks.
+    pub fn new(inner: A, budget: usize) -> Alloc<A> {
+        Alloc { inner: inner, parked: VecDeque::new(), parked_bytes: 0,
+                budget: budget, poison: DEFAULT_POISON }
+    }
+
+    pub fn set_poison(&mut self, poison: u8) { self.poison = poison; }
+
+    /// Bytes currently held in quarantine.
+    pub fn parked_bytes(&self) -> usize { self.parked_bytes }
+
+    /// Releases every quarantined block to the inner allocator.
+    pub fn flush(&mut self) {
+        while self.release_oldest() { }
+    }
+
+    fn release_oldest(&mut self) -> bool {
+        match self.parked.pop_front() {
+            Some((p, kind)) => {
+                unsafe {
+                    annotate::defined(p, kind.size());
+                    for i in 0..kind.size() {
+                        let b = *p.offset(i as isize);
+                        if b != self.poison {
+                            panic!("quarantine: freed block {:p} ({}) was written to after \
+                                    being freed (byte {} is 0x{:02x})", p, kind, i, b);
+                        }
+                    }
+                    self.inner.dealloc(p, kind);
+                }
+                self.parked_bytes -= kind.size();
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe fn park(&mut self, p: Address, kind: Kind) {
+        if kind.size() == 0 { return self.inner.dealloc(p, kind); }
+        ptr::write_bytes(p, self.poison, kind.size());
+        annotate::no_access(p, kind.size());
+        self.parked.push_back((p, kind));
+        self.parked_bytes += kind.size();
+        while self.parked_bytes > self.budget {
+            self.release_oldest();
+        }
+    }
+}
+
+// Quarantine only delays frees, so under pressure it shortens the
+// delay: to half the budget, or to nothing.
+impl<A: alloc::Alloc> Trim for RefCell<Alloc<A>> {
+    fn trim(&self, level: pressure::Level) -> usize {
+        let mut q = match self.try_borrow_mut() { Ok(q) => q, Err(_) => return 0 };
+        let before = q.parked_bytes;
+        let target = match level {
+            pressure::Level::Moderate => q.budget / 2,
+            pressure::Level::Critical => 0,
+        };
+        while q.parked_bytes > target { q.release_oldest(); }
+        before - q.parked_bytes
+    }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        self.inner.alloc(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.park(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.inner.usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.inner.grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.inner.shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let new_ptr = self.inner.alloc(Kind::from_size_align(new_size, kind.align()));
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr as *const u8, new_ptr, cmp::min(kind.size(), new_size));
+            self.park(ptr, kind);
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,180 @@
+// A wrapper enforcing a memory budget.
+//
+// Allocations that would take the live byte count or the number of
+// live blocks over its limit fail (return null) instead of reaching
+// the inner allocator. Allocations can additionally be tagged (see
+// `set_tag`); a tag may carry its own byte limit, checked on top of
+// the global ones.
+//
+// A refused request is reported by `alloc_error` as
+// `AllocError::QuotaExceeded`, so callers can tell it apart from the
+// inner allocator running out.
+//
+// The quota implements `ShareAlloc`, so several containers can draw
+// from one budget through `&quota` handles.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, ShareAlloc, Size};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: usize,
+    pub blocks: usize,
+}
+
+pub struct Alloc<A> {
+    inner: RefCell<A>,
+    max_bytes: Option<usize>,
+    max_blocks: Option<usize>,
+    usage: Cell<Usage>,
+    tag: Cell<Option<&'static str>>,
+    tag_limits: RefCell<HashMap<&'static str, usize>>,
+    tag_bytes: RefCell<HashMap<&'static str, usize>>,
+    // which tag each tagged live block was charged to
+    block_tags: RefCell<HashMap<usize, &'static str>>,
+    // whether the most recent request was refused by the quota itself
+    refused: Cell<bool>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A, max_bytes: Option<usize>, max_blocks: Option<usize>) -> Alloc<A> {
+        Alloc {
+            inner: RefCell::new(inner),
+            max_bytes: max_bytes,
+            max_blocks: max_blocks,
+            usage: Cell::new(Usage::default()),
+            tag: Cell::new(None),
+            tag_limits: RefCell::new(HashMap::new()),
+            tag_bytes: RefCell::new(HashMap::new()),
+            block_tags: RefCell::new(HashMap::new()),
+            refused: Cell::new(false),
+        }
+    }
+
+    pub fn usage(&self) -> Usage { self.usage.get() }
+
+    /// Charges subsequent allocations to `tag`.
+    pub fn set_tag(&self, tag: Option<&'static str>) { self.tag.set(tag); }
+
+    /// Limits the live bytes charged to `tag`.
+    pub fn set_tag_limit(&self, tag: &'static str, max_bytes: usize) {
+        self.tag_limits.borrow_mut().insert(tag, max_bytes);
+    }
+
+    pub fn tag_bytes(&self, tag: &'static str) -> usize {
+        self.tag_bytes.borrow().get(tag).cloned().unwrap_or(0)
+    }
+
+    /// Whether `extra_bytes` (and `extra_blocks`) more would still be
+    /// within every applicable limit.
+    fn admits(&self, extra_bytes: usize, extra_blocks: usize, tag: Option<&'static str>) -> bool {
+        let u = self.usage.get();
+        let within = |used: usize, extra: usize, max: Option<usize>| match max {
+            Some(max) => used.checked_add(extra).map_or(false, |n| n <= max),
+            None => true,
+        };
+        within(u.bytes, extra_bytes, self.max_bytes) &&
+        within(u.blocks, extra_blocks, self.max_blocks) &&
+        match tag {
+            Some(t) => within(self.tag_bytes(t), extra_bytes,
+                              self.tag_limits.borrow().get(t).cloned()),
+            None => true,
+        }
+    }
+
+    fn charge(&self, p: Address, bytes: isize, blocks: isize, tag: Option<&'static str>) {
+        let mut u = self.usage.get();
+        u.bytes = (u.bytes as isize + bytes) as usize;
+        u.blocks = (u.blocks as isize + blocks) as usize;
+        self.usage.set(u);
+        if let Some(t) = tag {
+            let mut tb = self.tag_bytes.borrow_mut();
+            let e = tb.entry(t).or_insert(0);
+            *e = (*e as isize + bytes) as usize;
+            if blocks > 0 { self.block_tags.borrow_mut().insert(p as usize, t); }
+        }
+    }
+
+    fn tag_of(&self, p: Address, remove: bool) -> Option<&'static str> {
+        if remove {
+            self.block_tags.borrow_mut().remove(&(p as usize))
+        } else {
+            self.block_tags.borrow().get(&(p as usize)).cloned()
+        }
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        let tag = self.tag.get();
+        self.refused.set(!self.admits(kind.size(), 1, tag));
+        if self.refused.get() { return ::std::ptr::null_mut(); }
+        let p = self.inner.borrow_mut().alloc(kind);
+        if !p.is_null() { self.charge(p, kind.size() as isize, 1, tag); }
+        p
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        let tag = self.tag_of(ptr, true);
+        self.charge(ptr, -(kind.size() as isize), -1, tag);
+        self.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    // Only `kind.size()` is charged, so that is all a block may be
+    // used for; the inner allocator's slack would otherwise be taken
+    // (and later freed) without ever being counted. Growth goes
+    // through `grow_in_place` or `realloc`, which charge it.
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        kind.size()
+    }
+
+    unsafe fn alloc_error_shared(&self, kind: Kind) -> AllocError {
+        if self.refused.get() {
+            AllocError::QuotaExceeded
+        } else {
+            self.inner.borrow().alloc_error(kind)
+        }
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let tag = self.tag_of(ptr, false);
+        let growth = new_size as isize - kind.size() as isize;
+        self.refused.set(growth > 0 && !self.admits(growth as usize, 0, tag));
+        if self.refused.get() { return ::std::ptr::null_mut(); }
+        let p = self.inner.borrow_mut().realloc(ptr, kind, new_size);
+        if !p.is_null() {
+            let tag = self.tag_of(ptr, true);
+            self.charge(p, growth, 0, tag);
+            if let Some(t) = tag { self.block_tags.borrow_mut().insert(p as usize, t); }
+        }
+        p
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError { self.alloc_error_shared(kind) }
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let tag = self.tag_of(ptr, false);
+        let growth = new_size - kind.size();
+        if !self.admits(growth, 0, tag) { return Err(AllocError::QuotaExceeded); }
+        try!(self.inner.borrow_mut().grow_in_place(ptr, kind, new_size));
+        self.charge(ptr, growth as isize, 0, tag);
+        Ok(())
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        try!(self.inner.borrow_mut().shrink_in_place(ptr, kind, new_size));
+        let tag = self.tag_of(ptr, false);
+        self.charge(ptr, -((kind.size() - new_size) as isize), 0, tag);
+        Ok(())
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
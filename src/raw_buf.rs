@@ -0,0 +1,121 @@
+// An untyped byte buffer hosting several typed regions.
+//
+// Serialization and network code often wants one allocation holding,
+// say, a header struct, an array of offsets and a byte payload, laid
+// out with `Kind::extend`. `RawBuf` is that allocation: it owns a
+// block of the combined `Kind` and hands out typed slices of it with
+// `view::<T>(offset, len)` and `view_mut`, checking bounds and
+// alignment on every call, so the offsets `Kind::extend` returned can
+// be used directly.
+//
+// The buffer is zero-filled when allocated and views are limited to
+// `Raw + Copy` types, so a view never exposes uninitialized memory and
+// never owns anything that would need dropping.
+
+use alloc::{Alloc, AllocError, DefaultAlloc, Kind, NonNullAddress, Raw};
+
+use alloc_crate::oom;
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+pub struct RawBuf<A:Alloc = DefaultAlloc> {
+    ptr: NonNullAddress,
+    kind: Kind,
+    alloc: A,
+}
+
+impl<A:Alloc + Default> RawBuf<A> {
+    /// A zeroed buffer of `kind`, from the default allocator.
+    pub fn new(kind: Kind) -> Self {
+        RawBuf::new_in(kind, A::default())
+    }
+}
+
+impl<A:Alloc> RawBuf<A> {
+    /// Allocates a zeroed buffer of `kind` from `alloc`.
+    ///
+    /// Aborts via `oom` if the allocator cannot satisfy the request.
+    pub fn new_in(kind: Kind, alloc: A) -> Self {
+        match RawBuf::try_new_in(kind, alloc) {
+            Ok(b) => b,
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    /// Like `new_in`, but returns `Err` if the allocator cannot
+    /// satisfy the request.
+    pub fn try_new_in(kind: Kind, mut alloc: A) -> Result<Self, AllocError> {
+        let ptr = unsafe {
+            if kind.is_zero_sized() {
+                NonNullAddress::new_unchecked(kind.dangling())
+            } else {
+                let p = try!(alloc.alloc_nonnull(kind));
+                ptr::write_bytes(p.as_ptr(), 0, kind.size());
+                p
+            }
+        };
+        Ok(RawBuf { ptr: ptr, kind: kind, alloc: alloc })
+    }
+
+    pub fn kind(&self) -> Kind { self.kind }
+
+    pub fn len(&self) -> usize { self.kind.size() }
+
+    pub fn alloc(&self) -> &A { &self.alloc }
+
+    pub fn as_ptr(&self) -> *const u8 { self.ptr.as_ptr() }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 { self.ptr.as_ptr() }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len()) }
+    }
+
+    // Start of `len` `T`s at byte `offset`, after the checks.
+    fn region<T>(&self, offset: usize, len: usize) -> *mut T {
+        let bytes = len.checked_mul(mem::size_of::<T>());
+        let end = bytes.and_then(|b| b.checked_add(offset));
+        assert!(end.map_or(false, |e| e <= self.len()),
+                "RawBuf: view of {} elements at offset {} exceeds {} bytes", len, offset, self.len());
+        let p = unsafe { self.ptr.offset(offset as isize) };
+        assert!(p.is_aligned_to(mem::align_of::<T>()),
+                "RawBuf: offset {} is not aligned to {}", offset, mem::align_of::<T>());
+        p.cast()
+    }
+
+    /// The `len` values of type `T` starting `offset` bytes in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region does not lie within the buffer, or if it
+    /// is not suitably aligned for `T`.
+    pub fn view<T: Raw + Copy>(&self, offset: usize, len: usize) -> &[T] {
+        unsafe { slice::from_raw_parts(self.region::<T>(offset, len), len) }
+    }
+
+    /// Mutable version of `view`.
+    pub fn view_mut<T: Raw + Copy>(&mut self, offset: usize, len: usize) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.region::<T>(offset, len), len) }
+    }
+}
+
+impl<A:Alloc> Drop for RawBuf<A> {
+    fn drop(&mut self) {
+        if !self.kind.is_zero_sized() {
+            unsafe { self.alloc.dealloc_nonnull(self.ptr, self.kind); }
+        }
+    }
+}
+
+impl<A:Alloc> fmt::Debug for RawBuf<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RawBuf({})", self.kind)
+    }
+}
@@ -0,0 +1,313 @@
+// The core of an open-addressing hash table, for building hashed
+// containers over any allocator.
+//
+// A table of `n` buckets is one block from `A`, laid out with
+// `Kind::extend` as `n` control bytes followed by `n` slots for `T`:
+//
+//     [ ctrl: [u8; n] | slots: [T; n] ]
+//
+// A control byte says whether its slot is EMPTY, DELETED (a tombstone
+// left by `erase`, which keeps probe sequences intact) or full, in
+// which case it holds the top seven bits of the value's hash, so most
+// mismatches are rejected without touching the slot. Lookups probe
+// linearly from `hash % n`, and the table is rebuilt once it is 7/8
+// full, counting tombstones.
+//
+// `n` need not be a power of two: the block is requested with
+// `alloc_excess`, and however many whole buckets the allocator's
+// slack can hold are used too.
+//
+// The table neither hashes nor compares: callers pass the hash with
+// each operation, plus an equality test for lookups and a hasher for
+// rebuilding. Positions are bucket indices, valid until the next
+// insertion or rebuild.
+
+use alloc::{Alloc, DefaultAlloc, Kind, NonNullAddress};
+
+use std::cmp;
+use std::intrinsics;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+
+const EMPTY: u8 = 0xFF;
+const DELETED: u8 = 0x80;
+
+fn h2(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+fn is_full(ctrl: u8) -> bool {
+    ctrl & 0x80 == 0
+}
+
+// Stores `value` in the first non-full bucket of its probe sequence;
+// returns the bucket and its previous control byte.
+unsafe fn place<T>(ctrl: *mut u8, slots: *mut T, buckets: usize, hash: u64, value: T) -> (usize, u8) {
+    let mut i = (hash % buckets as u64) as usize;
+    while is_full(*ctrl.offset(i as isize)) {
+        i += 1;
+        if i == buckets { i = 0; }
+    }
+    let was = *ctrl.offset(i as isize);
+    *ctrl.offset(i as isize) = h2(hash);
+    ptr::write(slots.offset(i as isize), value);
+    (i, was)
+}
+
+// Most values a table of `buckets` holds before it is rebuilt; at
+// least one bucket always stays empty so that probes terminate.
+fn capacity_of(buckets: usize) -> usize {
+    if buckets < 8 { buckets.saturating_sub(1) } else { buckets / 8 * 7 + buckets % 8 * 7 / 8 }
+}
+
+fn buckets_for(cap: usize) -> usize {
+    if cap < 7 { cap + 1 } else { cap.checked_mul(8).expect("capacity overflow") / 7 + 1 }
+}
+
+// The block for `buckets` buckets and the offset of the slots in it.
+fn layout<T>(buckets: usize) -> (Kind, usize) {
+    Kind::new::<u8>().array(buckets).extend(Kind::new::<T>().array(buckets))
+}
+
+pub struct RawTable<T, A:Alloc = DefaultAlloc> {
+    ctrl: *mut u8,
+    slots: *mut T,
+    buckets: usize,
+    items: usize,
+    // insertions left before a rebuild
+    growth_left: usize,
+    alloc: A,
+    _marker: PhantomData<T>,
+}
+
+impl<T, A:Alloc> RawTable<T, A> {
+    /// An empty table; nothing is allocated until the first insert.
+    pub fn new_in(a: A) -> Self {
+        RawTable { ctrl: ptr::null_mut(), slots: ptr::null_mut(), buckets: 0, items: 0,
+                   growth_left: 0, alloc: a, _marker: PhantomData }
+    }
+
+    pub fn with_capacity_in(cap: usize, a: A) -> Self {
+        let mut t = RawTable::new_in(a);
+        if cap > 0 { t.resize(cap, |_| unreachable!()); }
+        t
+    }
+
+    pub fn len(&self) -> usize { self.items }
+
+    pub fn is_empty(&self) -> bool { self.items == 0 }
+
+    /// Values the table can hold without being rebuilt.
+    pub fn capacity(&self) -> usize { self.items + self.growth_left }
+
+    /// Number of buckets, including the ones absorbed from the
+    /// allocator's slack.
+    pub fn buckets(&self) -> usize { self.buckets }
+
+    pub fn alloc(&self) -> &A { &self.alloc }
+
+    /// The position of a value with hash `hash` for which `eq` holds.
+    pub fn find<F>(&self, hash: u64, mut eq: F) -> Option<usize> where F: FnMut(&T) -> bool {
+        if self.buckets == 0 { return None; }
+        let tag = h2(hash);
+        let mut i = (hash % self.buckets as u64) as usize;
+        for _ in 0..self.buckets {
+            let c = unsafe { *self.ctrl.offset(i as isize) };
+            if c == EMPTY { return None; }
+            if c == tag && eq(unsafe { &*self.slots.offset(i as isize) }) { return Some(i); }
+            i += 1;
+            if i == self.buckets { i = 0; }
+        }
+        None
+    }
+
+    /// The value at `pos`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` does not hold a value.
+    pub fn get(&self, pos: usize) -> &T {
+        self.check(pos);
+        unsafe { &*self.slots.offset(pos as isize) }
+    }
+
+    pub fn get_mut(&mut self, pos: usize) -> &mut T {
+        self.check(pos);
+        unsafe { &mut *self.slots.offset(pos as isize) }
+    }
+
+    fn check(&self, pos: usize) {
+        assert!(pos < self.buckets && is_full(unsafe { *self.ctrl.offset(pos as isize) }),
+                "raw_table: bucket {} is not full", pos);
+    }
+
+    /// Adds `value`, which hashes to `hash`, without looking for an
+    /// equal one; returns its position. `hasher` rehashes the values
+    /// already present if the table must be rebuilt.
+    pub fn insert<H>(&mut self, hash: u64, value: T, hasher: H) -> usize where H: Fn(&T) -> u64 {
+        if self.growth_left == 0 {
+            let target = cmp::max(self.items + 1, self.items * 2);
+            self.resize(cmp::max(target, 4), hasher);
+        }
+        unsafe { self.insert_no_grow(hash, value) }
+    }
+
+    /// Makes room for `additional` more values without a rebuild.
+    pub fn reserve<H>(&mut self, additional: usize, hasher: H) where H: Fn(&T) -> u64 {
+        if additional > self.growth_left {
+            let need = self.items.checked_add(additional).expect("capacity overflow");
+            self.resize(need, hasher);
+        }
+    }
+
+    /// Removes and returns the value at `pos`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` does not hold a value.
+    pub fn erase(&mut self, pos: usize) -> T {
+        self.check(pos);
+        unsafe {
+            let next = if pos + 1 == self.buckets { 0 } else { pos + 1 };
+            // A probe reaching this bucket would stop at the next one
+            // anyway, so the bucket can become EMPTY, not a tombstone.
+            if *self.ctrl.offset(next as isize) == EMPTY {
+                *self.ctrl.offset(pos as isize) = EMPTY;
+                self.growth_left += 1;
+            } else {
+                *self.ctrl.offset(pos as isize) = DELETED;
+            }
+            self.items -= 1;
+            ptr::read(self.slots.offset(pos as isize))
+        }
+    }
+
+    /// Drops every value, keeping the allocation.
+    pub fn clear(&mut self) {
+        unsafe {
+            self.drop_values();
+            if self.buckets > 0 { ptr::write_bytes(self.ctrl, EMPTY, self.buckets); }
+        }
+        self.items = 0;
+        self.growth_left = capacity_of(self.buckets);
+    }
+
+    /// Iterates over the positions holding values.
+    pub fn positions(&self) -> Positions<T, A> {
+        Positions { table: self, next: 0 }
+    }
+
+    pub fn iter(&self) -> Iter<T, A> {
+        Iter { positions: self.positions() }
+    }
+
+    unsafe fn insert_no_grow(&mut self, hash: u64, value: T) -> usize {
+        let (pos, was) = place(self.ctrl, self.slots, self.buckets, hash, value);
+        if was == EMPTY { self.growth_left -= 1; }
+        self.items += 1;
+        pos
+    }
+
+    // Rebuilds the table with room for at least `cap` values, moving
+    // every value over (which also clears out the tombstones).
+    fn resize<H>(&mut self, cap: usize, hasher: H) where H: Fn(&T) -> u64 {
+        let mut buckets = buckets_for(cmp::max(cap, self.items));
+        let excess = unsafe { self.alloc.alloc_excess(layout::<T>(buckets).0) };
+        let block = match excess.block() {
+            Some(block) => block,
+            None => unsafe { self.alloc.oom() },
+        };
+        // Take whatever whole buckets the slack holds as well.
+        let mut more = cmp::max(buckets, excess.1 / (1 + mem::size_of::<T>()));
+        while more > buckets && layout::<T>(more).0.size() > excess.1 { more -= 1; }
+        buckets = more;
+
+        let ctrl = block.as_ptr();
+        let slots = unsafe { block.offset(layout::<T>(buckets).1 as isize).cast::<T>() };
+        let items = self.items;
+        unsafe {
+            ptr::write_bytes(ctrl, EMPTY, buckets);
+            // Each value is hashed in place and its old bucket emptied
+            // as it leaves, so if `hasher` panics every value still has
+            // exactly one owner: the old table keeps (and later drops)
+            // the ones not yet moved, and the moved ones leak with the
+            // new block.
+            for pos in 0..self.buckets {
+                if is_full(*self.ctrl.offset(pos as isize)) {
+                    let hash = hasher(&*self.slots.offset(pos as isize));
+                    *self.ctrl.offset(pos as isize) = EMPTY;
+                    self.items -= 1;
+                    let value = ptr::read(self.slots.offset(pos as isize));
+                    place(ctrl, slots, buckets, hash, value);
+                }
+            }
+            if self.buckets > 0 {
+                let old = self.block();
+                self.alloc.dealloc_nonnull(old, layout::<T>(self.buckets).0);
+            }
+        }
+        self.ctrl = ctrl;
+        self.slots = slots;
+        self.buckets = buckets;
+        self.items = items;
+        self.growth_left = capacity_of(buckets) - items;
+    }
+
+    // The table's block; only valid once something is allocated.
+    unsafe fn block(&self) -> NonNullAddress {
+        NonNullAddress::new_unchecked(self.ctrl)
+    }
+
+    unsafe fn drop_values(&mut self) {
+        for pos in 0..self.buckets {
+            if is_full(*self.ctrl.offset(pos as isize)) {
+                intrinsics::drop_in_place(self.slots.offset(pos as isize));
+            }
+        }
+    }
+}
+
+impl<T, A:Alloc> Drop for RawTable<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.drop_values();
+            if self.buckets > 0 {
+                let block = self.block();
+                self.alloc.dealloc_nonnull(block, layout::<T>(self.buckets).0);
+            }
+        }
+    }
+}
+
+/// The positions of a table's values; see `RawTable::positions`.
+pub struct Positions<'a, T: 'a, A:Alloc + 'a> {
+    table: &'a RawTable<T, A>,
+    next: usize,
+}
+
+impl<'a, T, A:Alloc> Iterator for Positions<'a, T, A> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.next < self.table.buckets {
+            let pos = self.next;
+            self.next += 1;
+            if is_full(unsafe { *self.table.ctrl.offset(pos as isize) }) { return Some(pos); }
+        }
+        None
+    }
+}
+
+pub struct Iter<'a, T: 'a, A:Alloc + 'a> {
+    positions: Positions<'a, T, A>,
+}
+
+impl<'a, T, A:Alloc> Iterator for Iter<'a, T, A> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let table = self.positions.table;
+        self.positions.next().map(|pos| unsafe { &*table.slots.offset(pos as isize) })
+    }
+}
@@ -1,37 +1,128 @@
-use alloc::{self, Alloc, DefaultAlloc};
+use alloc::{self, Alloc, AllocError, DefaultAlloc, Excess, NonNullAddress};
 use boxed::Box;
+use granularity::Granularity;
+use uninit::MaybeUninit;
 
-use alloc_crate::heap::EMPTY;
 use alloc_crate::oom;
+use alloc_crate::raw_vec::RawVec as StdRawVec;
 
+#[cfg(unix)]
+use libc;
+
+use std::cmp;
+use std::marker::PhantomData;
 use std::mem;
-use std::ptr::Unique;
+use std::ptr::{self, Unique};
 use std::slice::{self};
 use std::{isize, usize};
 
-#[unsafe_no_drop_flag]
-pub struct RawVec<T, A:Alloc = DefaultAlloc> {
+/// Decides how far a `RawVec` grows once it is full.
+///
+/// Policies are chosen with `RawVec`'s (and `Vec`'s) third type
+/// parameter. Whatever capacity a policy asks for, the vector also
+/// keeps any extra room the allocator reports via `usable_size`.
+pub trait GrowthPolicy {
+    /// Returns the capacity, in elements, to grow to from the full
+    /// capacity `cap` (which may be 0); must be greater than `cap`
+    /// unless that would overflow.
+    fn grow(cap: usize, elem_size: usize) -> usize;
+
+    /// Decides whether `shrink_to_fit` should reallocate, given the
+    /// usable size of the current block and of the block it would
+    /// shrink to. The default declines when nothing would be saved,
+    /// e.g. when both land in the same size class.
+    fn worth_shrinking(current_usable: usize, shrunk_usable: usize) -> bool {
+        shrunk_usable < current_usable
+    }
+}
+
+/// What `RawVec::shrink_to_fit` did.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shrink {
+    /// The capacity already matched.
+    Unchanged,
+    /// The buffer was reallocated to the requested capacity.
+    Shrunk,
+    /// The buffer was freed (shrinking to 0).
+    Freed,
+    /// The growth policy judged the savings not worth a realloc; the
+    /// capacity is unchanged.
+    Declined,
+}
+
+/// Doubles the capacity, starting at 4 elements. The default.
+pub struct Double;
+
+/// Grows by half the current capacity, starting at 4 elements.
+pub struct OneAndHalf;
+
+/// Grows by exactly one element; callers are expected to `reserve`.
+pub struct Exact;
+
+/// Doubles, then rounds the buffer up to a whole number of pages.
+pub struct PageRounded;
+
+fn initial_cap(elem_size: usize) -> usize {
+    // skip to 4 because tiny Vec's are dumb; but not if that would cause overflow
+    if elem_size > (!0) / 8 { 1 } else { 4 }
+}
+
+impl GrowthPolicy for Double {
+    fn grow(cap: usize, elem_size: usize) -> usize {
+        if cap == 0 { initial_cap(elem_size) } else { cap.saturating_mul(2) }
+    }
+}
+
+impl GrowthPolicy for OneAndHalf {
+    fn grow(cap: usize, elem_size: usize) -> usize {
+        if cap == 0 { initial_cap(elem_size) } else { cap.saturating_add(cmp::max(cap / 2, 1)) }
+    }
+}
+
+impl GrowthPolicy for Exact {
+    fn grow(cap: usize, _elem_size: usize) -> usize {
+        cap.saturating_add(1)
+    }
+}
+
+impl GrowthPolicy for PageRounded {
+    fn grow(cap: usize, elem_size: usize) -> usize {
+        let bytes = Double::grow(cap, elem_size).saturating_mul(elem_size);
+        let rounded = Granularity::page().round_up(bytes);
+        cmp::max(rounded / elem_size, cap.saturating_add(1))
+    }
+}
+
+pub struct RawVec<T, A:Alloc = DefaultAlloc, G:GrowthPolicy = Double> {
     ptr: Unique<T>,
     cap: usize,
     alloc: A,
+    _growth: PhantomData<G>,
 }
 
 fn empty<T>() -> (Unique<T>, usize) {
     // !0 is usize::MAX. This branch should be stripped at compile time.
     let cap = if mem::size_of::<T>() == 0 { !0 } else { 0 };
 
-    // heap::EMPTY doubles as "unallocated" and "zero-sized allocation"
-    unsafe { (Unique::new(EMPTY as *mut T), cap) }
+    // A dangling pointer aligned for `T` doubles as "unallocated" and
+    // "zero-sized allocation"; unlike `heap::EMPTY` it is a valid
+    // (if unusable) `*mut T` for every `T`.
+    unsafe { (Unique::new(mem::align_of::<T>() as *mut T), cap) }
 }
 
-impl<T, A:Alloc> RawVec<T, A> {
+impl<T, A:Alloc, G:GrowthPolicy> RawVec<T, A, G> {
     pub fn new() -> Self where A: Default {
         Self::with_alloc(Default::default())
     }
 
-    pub fn with_alloc(a: A) -> Self {
-        let (ptr, cap) = empty();
-        RawVec { ptr: ptr, cap: cap, alloc: a }
+    /// Creates an empty `RawVec` without allocating; usable in
+    /// constant expressions, e.g. `RawVec::with_alloc(DefaultAlloc)`.
+    pub const fn with_alloc(a: A) -> Self {
+        // Constant expressions cannot call `align_of`, so the pointer
+        // is a placeholder that `ptr()` never hands out: with no block
+        // it answers with the aligned sentinel instead.
+        RawVec { ptr: unsafe { Unique::new(1 as *mut T) }, cap: 0, alloc: a,
+                 _growth: PhantomData }
     }
 
     pub fn with_capacity(cap: usize) -> Self where A: Default {
@@ -46,24 +137,30 @@ impl<T, A:Alloc> RawVec<T, A> {
             alloc_guard(alloc_size);
 
             // handles ZSTs and `cap = 0` alike
-            let ptr = if alloc_size == 0 {
-                EMPTY as *mut u8
-            } else {
-                let ptr = a.alloc(alloc::Kind::new::<T>().array(cap));
-                if ptr.is_null() { oom() }
-                ptr
-            };
+            if alloc_size == 0 {
+                let ptr = alloc::Kind::new::<T>().dangling();
+                return RawVec { ptr: Unique::new(ptr as *mut T), cap: cap, alloc: a,
+                                _growth: PhantomData };
+            }
+            let excess = a.alloc_excess(alloc::Kind::new::<T>().array(cap));
+            if excess.block().is_none() { oom() }
 
-            RawVec { ptr: Unique::new(ptr as *mut _), cap: cap, alloc: a }
+            let mut v = RawVec::with_alloc(a);
+            v.adopt(excess, cap);
+            v
         }
     }
 
     pub unsafe fn from_raw_parts(ptr: *mut T, cap: usize) -> Self where A: Default {
-        RawVec { ptr: Unique::new(ptr), cap: cap, alloc: Default::default() }
+        Self::from_raw_parts_alloc(ptr, cap, Default::default())
     }
 
     pub unsafe fn from_raw_parts_alloc(ptr: *mut T, cap: usize, a: A) -> Self {
-        RawVec { ptr: Unique::new(ptr), cap: cap, alloc: a }
+        match alloc::Kind::new::<T>().checked_array(cap) {
+            Some(kind) => alloc::check_block(ptr as *mut u8, kind, "RawVec::from_raw_parts"),
+            None => alloc::check_contract(false, "RawVec::from_raw_parts", "capacity overflow"),
+        }
+        RawVec { ptr: Unique::new(ptr), cap: cap, alloc: a, _growth: PhantomData }
     }
 
     pub fn from_box(slice: Box<[T], A>) -> Self {
@@ -75,15 +172,29 @@ impl<T, A:Alloc> RawVec<T, A> {
     }
 }
 
-impl<T, A:Alloc> RawVec<T, A> {
+impl<T, A:Alloc, G:GrowthPolicy> RawVec<T, A, G> {
     pub fn ptr(&self) -> *mut T {
-        *self.ptr
+        // See `with_alloc` for why the stored pointer may not do.
+        if self.cap == 0 || mem::size_of::<T>() == 0 {
+            mem::align_of::<T>() as *mut T
+        } else {
+            *self.ptr
+        }
+    }
+
+    // The buffer as the allocator sees it.
+    fn block(&self) -> NonNullAddress {
+        NonNullAddress::from_unique(&self.ptr)
     }
 
     pub fn cap(&self) -> usize {
         if mem::size_of::<T>() == 0 { !0 } else { self.cap }
     }
 
+    pub fn alloc(&self) -> &A {
+        &self.alloc
+    }
+
     #[inline(never)]
     #[cold]
     pub fn double(&mut self) {
@@ -94,28 +205,23 @@ impl<T, A:Alloc> RawVec<T, A> {
             // 0, getting to here necessarily means the RawVec is overfull.
             assert!(elem_size != 0, "capacity overflow");
 
-            let (new_cap, ptr) = if self.cap == 0 {
-                // skip to 4 because tiny Vec's are dumb; but not if that would cause overflow
-                let new_cap = if elem_size > (!0) / 8 { 1 } else { 4 };
-                let ptr = self.alloc.alloc(alloc::Kind::new::<T>().array(new_cap));
-                (new_cap, ptr)
+            let new_cap = G::grow(self.cap, elem_size);
+            assert!(new_cap > self.cap, "capacity overflow");
+            let excess = if self.cap == 0 {
+                self.alloc.alloc_excess(alloc::Kind::new::<T>().array(new_cap))
             } else {
-                // Since we guarantee that we never allocate more than isize::MAX bytes,
-                // `elem_size * self.cap <= isize::MAX` as a precondition, so this can't overflow
-                let new_cap = 2 * self.cap;
-                let new_alloc_size = new_cap * elem_size;
+                let new_alloc_size = new_cap.checked_mul(elem_size).expect("capacity overflow");
                 alloc_guard(new_alloc_size);
-                let ptr = self.alloc.realloc(*self.ptr as *mut _,
-                                             alloc::Kind::new::<T>().array(self.cap),
-                                             new_alloc_size);
-                (new_cap, ptr)
+                let block = self.block();
+                self.alloc.realloc_excess(block.as_ptr(),
+                                          alloc::Kind::new::<T>().array(self.cap),
+                                          new_alloc_size)
             };
 
             // If allocate or reallocate fail, we'll get `null` back
-            if ptr.is_null() { oom() }
+            if excess.block().is_none() { oom() }
 
-            self.ptr = Unique::new(ptr as *mut _);
-            self.cap = new_cap;
+            self.adopt(excess, new_cap);
         }
     }
 
@@ -137,117 +243,484 @@ impl<T, A:Alloc> RawVec<T, A> {
             let new_alloc_size = new_cap.checked_mul(elem_size).expect("capacity overflow");
             alloc_guard(new_alloc_size);
 
-            let ptr = if self.cap == 0 {
-                self.alloc.alloc(alloc::Kind::new::<T>().array(new_cap))
+            let excess = if self.cap == 0 {
+                self.alloc.alloc_excess(alloc::Kind::new::<T>().array(new_cap))
             } else {
-                self.alloc.realloc(*self.ptr as *mut _,
-                                   alloc::Kind::new::<T>().array(self.cap),
-                                   new_alloc_size)
+                let block = self.block();
+                self.alloc.realloc_excess(block.as_ptr(),
+                                          alloc::Kind::new::<T>().array(self.cap),
+                                          new_alloc_size)
             };
 
             // If allocate or reallocate fail, we'll get `null` back
-            if ptr.is_null() { oom() }
+            if excess.block().is_none() { oom() }
 
-            self.ptr = Unique::new(ptr as *mut _);
-            self.cap = new_cap;
+            self.adopt(excess, new_cap);
         }
     }
 
     pub fn reserve(&mut self, used_cap: usize, needed_extra_cap: usize) {
+        match self.try_reserve(used_cap, needed_extra_cap) {
+            Ok(()) => {}
+            Err(AllocError::CapacityOverflow) => panic!("capacity overflow"),
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    /// Like `reserve`, but reports failure instead of panicking or
+    /// aborting. On failure the buffer is left as it was, which a
+    /// failed `realloc` guarantees (see `Alloc::realloc`).
+    pub fn try_reserve(&mut self, used_cap: usize, needed_extra_cap: usize)
+                       -> Result<(), AllocError> {
         unsafe {
             let elem_size = mem::size_of::<T>();
 
             // NOTE: we don't early branch on ZSTs here because we want this
             // to actually catch "asking for more than usize::MAX" in that case.
             // If we make it past the first branch then we are guaranteed to
-            // panic.
+            // fail.
 
             // Don't actually need any more capacity.
             // Wrapping in case they give a bas `used_cap`
-            if self.cap().wrapping_sub(used_cap) >= needed_extra_cap { return; }
-
-            // Nothing we can really do about these checks :(
-            let new_cap = used_cap.checked_add(needed_extra_cap)
-                                  .and_then(|cap| cap.checked_mul(2))
-                                  .expect("capacity overflow");
-            let new_alloc_size = new_cap.checked_mul(elem_size).expect("capacity overflow");
-            // FIXME: may crash and burn on over-reserve
-            alloc_guard(new_alloc_size);
+            if self.cap().wrapping_sub(used_cap) >= needed_extra_cap { return Ok(()); }
+
+            let required_cap = try!(used_cap.checked_add(needed_extra_cap)
+                                            .ok_or(AllocError::CapacityOverflow));
+            // Grow at least as much as the policy would, so that a
+            // sequence of small reserves stays amortized.
+            let new_cap = cmp::max(required_cap, G::grow(self.cap, elem_size));
+            let new_alloc_size = try!(new_cap.checked_mul(elem_size)
+                                             .ok_or(AllocError::CapacityOverflow));
+            if usize::BITS < 64 && new_alloc_size > isize::MAX as usize {
+                return Err(AllocError::CapacityOverflow);
+            }
 
-            let ptr = if self.cap == 0 {
-                self.alloc.alloc(alloc::Kind::new::<T>().array(new_cap))
+            let old_kind = alloc::Kind::new::<T>().array(self.cap);
+            let new_kind = alloc::Kind::new::<T>().array(new_cap);
+            let block = self.block();
+            let excess = if self.cap == 0 {
+                self.alloc.alloc_excess(new_kind)
+            } else if self.alloc.grow_in_place(block.as_ptr(), old_kind,
+                                               new_alloc_size).is_ok() {
+                // extended without copying anything
+                Excess(block.as_ptr(), self.alloc.usable_size(new_kind))
             } else {
-                self.alloc.realloc(*self.ptr as *mut _,
-                                   alloc::Kind::new::<T>().array(self.cap),
-                                   new_alloc_size)
+                self.alloc.realloc_excess(block.as_ptr(), old_kind, new_alloc_size)
             };
 
             // If allocate or reallocate fail, we'll get `null` back
-            if ptr.is_null() { oom() }
+            if excess.block().is_none() {
+                return Err(self.alloc.alloc_error(new_kind));
+            }
+
+            self.adopt(excess, new_cap);
+            Ok(())
+        }
+    }
+
+    /// Takes over the block in `excess`, allocated for `cap` elements,
+    /// raising `cap` to however many whole elements the allocator's
+    /// reported capacity holds, so that slack it handed out is not
+    /// wasted.
+    fn adopt(&mut self, excess: Excess, cap: usize) {
+        let block = excess.block().expect("adopting a failed allocation");
+        self.ptr = unsafe { Unique::new(block.cast()) };
+        self.cap = cmp::max(cap, excess.1 / mem::size_of::<T>());
+    }
 
-            self.ptr = Unique::new(ptr as *mut _);
-            self.cap = new_cap;
+    /// Shrinks the buffer to `amount` elements if the growth policy
+    /// thinks the memory saved is worth a reallocation; see
+    /// `GrowthPolicy::worth_shrinking`.
+    pub fn shrink_to_fit(&mut self, amount: usize) -> Shrink {
+        let elem_size = mem::size_of::<T>();
+        assert!(self.cap() >= amount, "Tried to shrink to a larger capacity");
+        if elem_size == 0 || amount == 0 || self.cap == amount {
+            return self.shrink_exact(amount);
+        }
+        unsafe {
+            let current = self.alloc.usable_size(alloc::Kind::new::<T>().array(self.cap));
+            let shrunk = self.alloc.usable_size(alloc::Kind::new::<T>().array(amount));
+            if !G::worth_shrinking(current, shrunk) { return Shrink::Declined; }
         }
+        self.shrink_exact(amount)
     }
 
-    pub fn shrink_to_fit(&mut self, amount: usize) {
+    /// Shrinks the buffer to exactly `amount` elements, regardless of
+    /// the growth policy (as needed before `into_box`).
+    pub fn shrink_exact(&mut self, amount: usize) -> Shrink {
         let elem_size = mem::size_of::<T>();
 
         // Set the `cap` because they might be about to promote to a `Box<[T]>`
         if elem_size == 0 {
             self.cap = amount;
-            return;
+            return Shrink::Unchanged;
         }
 
         // This check is my waterloo; it's the only thing Vec wouldn't have to do.
         assert!(self.cap >= amount, "Tried to shrink to a larger capacity");
 
         if amount == 0 {
+            if self.cap == 0 { return Shrink::Unchanged; }
+            unsafe {
+                // A buffer emptied out like this tends to be refilled.
+                let block = self.block();
+                self.alloc.dealloc_hot(block.as_ptr(),
+                                       alloc::Kind::new::<T>().array(self.cap));
+            }
             let (ptr, cap) = empty();
             self.ptr = ptr;
             self.cap = cap;
+            Shrink::Freed
         } else if self.cap != amount {
             unsafe {
                 // Overflow check is unnecessary as the vector is already at
                 // least this large.
-                let ptr = self.alloc.realloc(*self.ptr as *mut _,
-                                             alloc::Kind::new::<T>().array(self.cap),
-                                             amount * elem_size);
-                if ptr.is_null() { oom() }
-                self.ptr = Unique::new(ptr as *mut _);
+                let kind = alloc::Kind::new::<T>().array(self.cap);
+                let block = self.block();
+                match self.alloc.realloc_nonnull(block, kind, amount * elem_size) {
+                    Ok(block) => self.ptr = Unique::new(block.cast()),
+                    Err(_) => oom(),
+                }
             }
             self.cap = amount;
+            Shrink::Shrunk
+        } else {
+            Shrink::Unchanged
+        }
+    }
+
+    /// Shrinks the buffer to `new_cap` elements only if the allocator
+    /// can do it without moving the block (see `Alloc::shrink_in_place`);
+    /// otherwise returns `Declined` and leaves the buffer alone. Meant
+    /// for huge buffers from page-mapping allocators, where unmapping
+    /// the tail is cheap but copying the head is not; pointers into
+    /// the first `new_cap` elements stay valid either way.
+    pub fn shrink_keep_ptr(&mut self, new_cap: usize) -> Shrink {
+        let elem_size = mem::size_of::<T>();
+        assert!(self.cap() >= new_cap, "Tried to shrink to a larger capacity");
+        if elem_size == 0 || self.cap == new_cap { return Shrink::Unchanged; }
+        // Shrinking to nothing would mean freeing the block.
+        if new_cap == 0 { return Shrink::Declined; }
+        unsafe {
+            let kind = alloc::Kind::new::<T>().array(self.cap);
+            let block = self.block();
+            match self.alloc.shrink_in_place(block.as_ptr(), kind, new_cap * elem_size) {
+                Ok(()) => {
+                    self.cap = new_cap;
+                    Shrink::Shrunk
+                }
+                Err(_) => Shrink::Declined,
+            }
+        }
+    }
+
+    /// The slots past `used`, as uninitialized storage to be filled
+    /// before the owner claims them.
+    pub fn spare_capacity_mut(&mut self, used: usize) -> &mut [MaybeUninit<T>] {
+        assert!(used <= self.cap());
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr().offset(used as isize) as *mut MaybeUninit<T>,
+                                      self.cap() - used)
         }
     }
 
-    pub unsafe fn into_box(mut self) -> Box<[T], A> {
-        let alloc = mem::replace(&mut self.alloc, mem::uninitialized());
+    pub unsafe fn into_box(self) -> Box<[T], A> {
         // NOTE: not calling `cap()` here, actually using the real `cap` field!
         let slice = slice::from_raw_parts_mut(self.ptr(), self.cap);
-        let output: Box<[T], A> = Box::from_raw_alloc(slice, alloc);
+        let alloc = ptr::read(&self.alloc);
         mem::forget(self);
-        output
+        Box::from_raw_alloc(slice, alloc)
+    }
+}
+
+impl<T> RawVec<T, DefaultAlloc> {
+    /// Converts into the standard library's `RawVec` without copying;
+    /// valid because `DefaultAlloc` shares the standard heap.
+    pub fn into_std(self) -> StdRawVec<T> {
+        unsafe {
+            let v = StdRawVec::from_raw_parts(self.ptr(), self.cap());
+            mem::forget(self);
+            v
+        }
     }
 
-    pub fn unsafe_no_drop_flag_needs_drop(&self) -> bool {
-        self.cap != mem::POST_DROP_USIZE
+    pub fn from_std(v: StdRawVec<T>) -> Self {
+        unsafe {
+            let ret = RawVec::from_raw_parts(v.ptr(), v.cap());
+            mem::forget(v);
+            ret
+        }
     }
 }
 
-impl<T, A:Alloc> Drop for RawVec<T, A> {
+impl<T, A:Alloc, G:GrowthPolicy> Drop for RawVec<T, A, G> {
     /// Frees the memory owned by the RawVec *without* trying to Drop its contents.
     fn drop(&mut self) {
         let elem_size = mem::size_of::<T>();
-        if elem_size != 0 && self.cap != 0 && self.unsafe_no_drop_flag_needs_drop() {
+        if elem_size != 0 && self.cap != 0 {
+            let kind = alloc::Kind::new::<T>().array(self.cap);
+            let block = self.block();
+            alloc::check_block(block.as_ptr(), kind, "RawVec::drop");
             unsafe {
-                self.alloc.dealloc(*self.ptr as *mut _,
-                                   alloc::Kind::new::<T>().array(self.cap));
+                self.alloc.dealloc_nonnull(block, kind);
             }
         }
     }
 }
 
+/// A buffer whose address never changes as it grows.
+///
+/// The constructor reserves address space for `max_cap` elements
+/// without committing any memory; growing commits further pages of
+/// that range in place (with `mprotect`), so pointers into the buffer
+/// stay valid across growth, which a realloc-based `RawVec` cannot
+/// promise. Growing past `max_cap` panics. Only the committed pages
+/// use memory; the rest of the reservation is just address space.
+#[cfg(unix)]
+pub struct VirtualRawVec<T> {
+    ptr: Unique<T>,
+    // bytes of address space reserved / committed, page multiples
+    reserved: usize,
+    committed: usize,
+    cap: usize,
+}
+
+#[cfg(unix)]
+impl<T> VirtualRawVec<T> {
+    /// Reserves address space for up to `max_cap` elements.
+    ///
+    /// Aborts via `oom` if the address space cannot be reserved.
+    pub fn with_reservation(max_cap: usize) -> Self {
+        let elem_size = mem::size_of::<T>();
+        assert!(elem_size != 0, "VirtualRawVec does not support zero-sized types");
+        assert!(mem::align_of::<T>() <= Granularity::page().bytes(), "alignment exceeds the page size");
+        let bytes = max_cap.checked_mul(elem_size).expect("capacity overflow");
+        alloc_guard(bytes);
+        let reserved = Granularity::page().round_up(cmp::max(bytes, 1));
+        unsafe {
+            let p = libc::mmap(ptr::null_mut(), reserved, libc::PROT_NONE,
+                               libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+            if p == libc::MAP_FAILED { oom() }
+            VirtualRawVec { ptr: Unique::new(p as *mut T), reserved: reserved,
+                            committed: 0, cap: 0 }
+        }
+    }
+
+    pub fn ptr(&self) -> *mut T {
+        *self.ptr
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// The most elements this buffer can ever hold.
+    pub fn max_cap(&self) -> usize {
+        self.reserved / mem::size_of::<T>()
+    }
+
+    /// Ensures room for `used_cap + needed_extra_cap` elements,
+    /// committing pages in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if that exceeds `max_cap()`.
+    pub fn reserve(&mut self, used_cap: usize, needed_extra_cap: usize) {
+        if self.cap.wrapping_sub(used_cap) >= needed_extra_cap { return; }
+        let required = used_cap.checked_add(needed_extra_cap).expect("capacity overflow");
+        assert!(required <= self.max_cap(), "VirtualRawVec: reservation exhausted");
+        // Commit at least double what we have, to keep mprotect calls
+        // amortized, but never past the reservation.
+        let want = cmp::max(required * mem::size_of::<T>(), self.committed * 2);
+        self.commit(cmp::min(Granularity::page().round_up(want), self.reserved));
+    }
+
+    /// Grows to (roughly) double the capacity.
+    pub fn double(&mut self) {
+        let cap = self.cap;
+        self.reserve(cap, cmp::max(cap, 1));
+    }
+
+    fn commit(&mut self, bytes: usize) {
+        unsafe {
+            let start = (*self.ptr as *mut u8).offset(self.committed as isize);
+            let r = libc::mprotect(start as *mut libc::c_void, bytes - self.committed,
+                                   libc::PROT_READ | libc::PROT_WRITE);
+            if r != 0 { oom() }
+        }
+        self.committed = bytes;
+        self.cap = bytes / mem::size_of::<T>();
+    }
+}
+
+#[cfg(unix)]
+impl<T> Drop for VirtualRawVec<T> {
+    /// Releases the whole reservation *without* dropping the contents.
+    fn drop(&mut self) {
+        unsafe { libc::munmap(*self.ptr as *mut libc::c_void, self.reserved); }
+    }
+}
+
+/// A buffer that starts out in allocator `A` and moves to `B` the
+/// first time `A` cannot grow it; `SmallVec` generalized to any pair
+/// of allocators (an `&inline::Alloc` spilling to the heap, a
+/// per-frame arena spilling to a long-lived one).
+///
+/// Each growth is first tried in place or by reallocation in `A`; if
+/// that fails the contents are copied into a block from `B` and the
+/// old block is returned to `A`. The buffer remembers which allocator
+/// owns it, so it is always freed through the right one, and it stays
+/// in `B` from then on.
+pub struct SpillRawVec<T, A:Alloc, B:Alloc = DefaultAlloc, G:GrowthPolicy = Double> {
+    ptr: Unique<T>,
+    cap: usize,
+    spilled: bool,
+    primary: A,
+    secondary: B,
+    _growth: PhantomData<G>,
+}
+
+impl<T, A:Alloc, B:Alloc, G:GrowthPolicy> SpillRawVec<T, A, B, G> {
+    /// An empty buffer; nothing is allocated until it first grows.
+    pub fn new_in(primary: A, secondary: B) -> Self {
+        let (ptr, cap) = empty();
+        SpillRawVec { ptr: ptr, cap: cap, spilled: false, primary: primary,
+                      secondary: secondary, _growth: PhantomData }
+    }
+
+    /// Aborts via `oom` if neither allocator can provide `cap` elements.
+    pub fn with_capacity_in(cap: usize, primary: A, secondary: B) -> Self {
+        let mut v = SpillRawVec::new_in(primary, secondary);
+        if mem::size_of::<T>() != 0 && cap > 0 {
+            match unsafe { v.grow_to(cap) } {
+                Ok(()) => {}
+                Err(AllocError::CapacityOverflow) => panic!("capacity overflow"),
+                Err(_) => unsafe { oom() },
+            }
+        }
+        v
+    }
+
+    pub fn ptr(&self) -> *mut T {
+        *self.ptr
+    }
+
+    fn block(&self) -> NonNullAddress {
+        NonNullAddress::from_unique(&self.ptr)
+    }
+
+    pub fn cap(&self) -> usize {
+        if mem::size_of::<T>() == 0 { !0 } else { self.cap }
+    }
+
+    /// Whether the buffer has moved to the secondary allocator.
+    pub fn is_spilled(&self) -> bool {
+        self.spilled
+    }
+
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+
+    /// Grows to the capacity the growth policy picks.
+    pub fn double(&mut self) {
+        let cap = self.cap();
+        self.reserve(cap, 1);
+    }
+
+    pub fn reserve(&mut self, used_cap: usize, needed_extra_cap: usize) {
+        match self.try_reserve(used_cap, needed_extra_cap) {
+            Ok(()) => {}
+            Err(AllocError::CapacityOverflow) => panic!("capacity overflow"),
+            Err(_) => unsafe { oom() },
+        }
+    }
+
+    /// Like `reserve`, but reports failure instead of panicking or
+    /// aborting. On failure the buffer is left as it was.
+    pub fn try_reserve(&mut self, used_cap: usize, needed_extra_cap: usize)
+                       -> Result<(), AllocError> {
+        if self.cap().wrapping_sub(used_cap) >= needed_extra_cap { return Ok(()); }
+        let required_cap = try!(used_cap.checked_add(needed_extra_cap)
+                                        .ok_or(AllocError::CapacityOverflow));
+        let new_cap = cmp::max(required_cap, G::grow(self.cap, mem::size_of::<T>()));
+        unsafe { self.grow_to(new_cap) }
+    }
+
+    unsafe fn grow_to(&mut self, new_cap: usize) -> Result<(), AllocError> {
+        let elem_size = mem::size_of::<T>();
+        if elem_size == 0 { return Err(AllocError::CapacityOverflow); }
+        let new_alloc_size = try!(new_cap.checked_mul(elem_size)
+                                         .ok_or(AllocError::CapacityOverflow));
+        if usize::BITS < 64 && new_alloc_size > isize::MAX as usize {
+            return Err(AllocError::CapacityOverflow);
+        }
+        let old_kind = alloc::Kind::new::<T>().array(self.cap);
+        let new_kind = alloc::Kind::new::<T>().array(new_cap);
+
+        let block = self.block();
+        if self.spilled {
+            let excess = self.secondary.realloc_excess(block.as_ptr(), old_kind,
+                                                       new_alloc_size);
+            if excess.block().is_none() { return Err(self.secondary.alloc_error(new_kind)); }
+            self.adopt(excess, new_cap);
+            return Ok(());
+        }
+
+        let excess = if self.cap == 0 {
+            self.primary.alloc_excess(new_kind)
+        } else {
+            self.primary.realloc_excess(block.as_ptr(), old_kind, new_alloc_size)
+        };
+        if excess.block().is_some() {
+            self.adopt(excess, new_cap);
+            return Ok(());
+        }
+
+        // `A` is out of room (and still owns the old block): spill.
+        let excess = self.secondary.alloc_excess(new_kind);
+        if excess.block().is_none() { return Err(self.secondary.alloc_error(new_kind)); }
+        if self.cap != 0 {
+            ptr::copy_nonoverlapping(block.as_ptr(), excess.0, self.cap * elem_size);
+            self.primary.dealloc_nonnull(block, old_kind);
+        }
+        self.spilled = true;
+        self.adopt(excess, new_cap);
+        Ok(())
+    }
+
+    fn adopt(&mut self, excess: Excess, cap: usize) {
+        let block = excess.block().expect("adopting a failed allocation");
+        self.ptr = unsafe { Unique::new(block.cast()) };
+        self.cap = cmp::max(cap, excess.1 / mem::size_of::<T>());
+    }
+}
+
+impl<T, A:Alloc, B:Alloc, G:GrowthPolicy> Drop for SpillRawVec<T, A, B, G> {
+    /// Frees the buffer through whichever allocator owns it, *without*
+    /// dropping the contents.
+    fn drop(&mut self) {
+        if mem::size_of::<T>() == 0 || self.cap == 0 { return; }
+        let kind = alloc::Kind::new::<T>().array(self.cap);
+        let block = self.block();
+        unsafe {
+            if self.spilled {
+                self.secondary.dealloc_nonnull(block, kind);
+            } else {
+                self.primary.dealloc_nonnull(block, kind);
+            }
+        }
+    }
+}
 
+#[cfg(target_os = "linux")]
+const MAP_NORESERVE: libc::c_int = libc::MAP_NORESERVE;
+#[cfg(all(unix, not(target_os = "linux")))]
+const MAP_NORESERVE: libc::c_int = 0;
 
 // We need to guarantee the following:
 // * We don't ever allocate `> isize::MAX` byte-size objects
@@ -0,0 +1,127 @@
+// A reference-counted pointer without weak references.
+//
+// The standard `Rc` keeps a strong and a weak count and splits its
+// teardown into dropping the value (last strong) and freeing the
+// block (last weak). Graph-shaped arena data rarely needs `Weak`, so
+// `RcLite` keeps one count and does both at once, saving a word per
+// object.
+//
+// The control block is laid out with `Kind::extend`:
+//
+//     [ count: Cell<usize> | alloc: A | value: T ]
+//
+// with padding as `extend` inserts it. The allocator lives in the
+// block (zero bytes for a stateless one) so that the handle itself is
+// a single pointer; the last handle moves it out to free the block.
+
+use alloc::{Alloc, DefaultAlloc, Kind};
+
+use std::cell::Cell;
+use std::fmt;
+use std::intrinsics;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+use std::ptr;
+
+pub struct RcLite<T, A:Alloc = DefaultAlloc> {
+    block: *mut u8,
+    _marker: PhantomData<(T, A)>,
+}
+
+// Returns the block's kind and the offsets of `alloc` and `value`.
+fn layout<T, A>() -> (Kind, usize, usize) {
+    let (k, alloc_off) = Kind::new::<Cell<usize>>().extend(Kind::new::<A>());
+    let (k, value_off) = k.extend(Kind::new::<T>());
+    (k, alloc_off, value_off)
+}
+
+impl<T, A:Alloc> RcLite<T, A> {
+    /// Moves `value` into a new control block allocated from `alloc`.
+    ///
+    /// Aborts via `oom` if the allocation fails.
+    pub fn new_in(value: T, mut alloc: A) -> RcLite<T, A> {
+        let (kind, alloc_off, value_off) = layout::<T, A>();
+        unsafe {
+            let block = alloc.alloc(kind);
+            if block.is_null() { alloc.oom() }
+            ptr::write(block as *mut Cell<usize>, Cell::new(1));
+            ptr::write(block.offset(alloc_off as isize) as *mut A, alloc);
+            ptr::write(block.offset(value_off as isize) as *mut T, value);
+            RcLite { block: block, _marker: PhantomData }
+        }
+    }
+
+    fn count(&self) -> &Cell<usize> {
+        unsafe { &*(self.block as *const Cell<usize>) }
+    }
+
+    fn value_ptr(&self) -> *mut T {
+        let (_, _, value_off) = layout::<T, A>();
+        unsafe { self.block.offset(value_off as isize) as *mut T }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.count().get()
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.block == other.block
+    }
+
+    /// A mutable reference to the value, if this is the only handle.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.count().get() == 1 { Some(unsafe { &mut *this.value_ptr() }) } else { None }
+    }
+
+    /// Returns the value if this is the only handle, freeing the block.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this.count().get() != 1 { return Err(this); }
+        unsafe {
+            let value = ptr::read(this.value_ptr());
+            this.free();
+            mem::forget(this);
+            Ok(value)
+        }
+    }
+
+    // Frees the block; the value must already be dropped or moved out.
+    unsafe fn free(&self) {
+        let (kind, alloc_off, _) = layout::<T, A>();
+        let mut alloc = ptr::read(self.block.offset(alloc_off as isize) as *const A);
+        alloc.dealloc(self.block, kind);
+    }
+}
+
+impl<T, A:Alloc> Clone for RcLite<T, A> {
+    fn clone(&self) -> Self {
+        let c = self.count();
+        c.set(c.get().checked_add(1).expect("RcLite count overflow"));
+        RcLite { block: self.block, _marker: PhantomData }
+    }
+}
+
+impl<T, A:Alloc> Deref for RcLite<T, A> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.value_ptr() } }
+}
+
+impl<T, A:Alloc> Drop for RcLite<T, A> {
+    fn drop(&mut self) {
+        let c = self.count();
+        let n = c.get() - 1;
+        c.set(n);
+        if n == 0 {
+            unsafe {
+                intrinsics::drop_in_place(self.value_ptr());
+                self.free();
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug, A:Alloc> fmt::Debug for RcLite<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
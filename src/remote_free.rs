@@ -0,0 +1,62 @@
+// Handing blocks back to the thread that owns their allocator.
+//
+// A free-list pool or slab keeps its lists in plain memory that only
+// its own thread may touch, so a block dropped on another thread
+// cannot simply be pushed back. A `Queue` is the way home: any thread
+// `push`es the block and its `Kind`, and the owner `drain`s the queue
+// at its next allocator call, freeing each block on the right thread.
+// Many threads may push at once; only the owner drains.
+//
+// Checking an empty queue is a single atomic load, so the owner can
+// afford to look on every allocation. `single_thread::Pinned` drains
+// one this way; see `Pinned::with_remote_free` for making a pinned
+// pool safe to send boxes away from.
+
+use alloc::{Address, Kind};
+
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Queue {
+    pending: Mutex<Vec<(usize, Kind)>>,
+    len: AtomicUsize,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue { pending: Mutex::new(Vec::new()), len: AtomicUsize::new(0) }
+    }
+
+    /// Posts a block for its owner to free.
+    pub fn push(&self, ptr: Address, kind: Kind) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push((ptr as usize, kind));
+        self.len.store(pending.len(), Ordering::Release);
+    }
+
+    /// Number of blocks waiting.
+    pub fn len(&self) -> usize { self.len.load(Ordering::Acquire) }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Passes every waiting block to `free`, oldest first, and returns
+    /// how many there were. Blocks pushed meanwhile wait for the next
+    /// call.
+    pub fn drain<F: FnMut(Address, Kind)>(&self, mut free: F) -> usize {
+        if self.is_empty() { return 0; }
+        let taken = {
+            let mut pending = self.pending.lock().unwrap();
+            self.len.store(0, Ordering::Release);
+            mem::replace(&mut *pending, Vec::new())
+        };
+        for &(p, kind) in &taken {
+            free(p as Address, kind);
+        }
+        taken.len()
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Queue { Queue::new() }
+}
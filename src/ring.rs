@@ -0,0 +1,108 @@
+// A ring allocator with fence-based reclamation.
+//
+// Blocks are carved linearly from a circular buffer, the pattern used
+// for streaming uploads: each frame writes its data after the previous
+// frame's, and the space is reclaimed only once the consumer (a GPU,
+// a network thread) signals that it is done with that frame. The
+// producer calls `fence()` at the end of each frame and later
+// `retire(fence)` when the frame is consumed; everything allocated
+// before the fence becomes reusable. `dealloc` is a no-op.
+//
+// A block never straddles the end of the buffer: if it does not fit
+// in the remaining tail, the tail is skipped and the block starts
+// again at the front. When that would run into space not yet
+// retired, `alloc` returns null, so callers see back-pressure as an
+// ordinary allocation failure.
+//
+// `head` and `tail` count bytes ever handed out and reclaimed, so
+// `head - tail` is the space in flight and the offset into the buffer
+// is the count modulo the length.
+
+use alloc::{self, Address, Capacity, DefaultAlloc, Kind, ShareAlloc, Size};
+
+use std::cell::Cell;
+use std::ptr;
+
+/// Largest alignment the ring can satisfy; the buffer is aligned to it.
+pub const MAX_ALIGN: usize = 64;
+
+/// A point in the allocation stream; see `Alloc::fence`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fence(usize);
+
+pub struct Alloc<A: alloc::Alloc = DefaultAlloc> {
+    inner: A,
+    base: Address,
+    len: usize,
+    head: Cell<usize>,
+    tail: Cell<usize>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Obtains a `len`-byte ring from `inner`.
+    ///
+    /// Aborts via `oom` if `inner` cannot provide it.
+    pub fn new(mut inner: A, len: usize) -> Alloc<A> {
+        assert!(len % MAX_ALIGN == 0, "ring length must be a multiple of {}", MAX_ALIGN);
+        unsafe {
+            let base = inner.alloc(Kind::from_size_align(len, MAX_ALIGN));
+            if base.is_null() { inner.oom() }
+            Alloc { inner: inner, base: base, len: len, head: Cell::new(0), tail: Cell::new(0) }
+        }
+    }
+
+    /// Marks the end of the current frame. Retiring the returned
+    /// fence reclaims everything allocated before this call.
+    pub fn fence(&self) -> Fence { Fence(self.head.get()) }
+
+    /// Reclaims the space allocated before `fence`. Fences must be
+    /// retired in the order they were created; retiring an older
+    /// fence after a newer one has no effect.
+    pub fn retire(&self, fence: Fence) {
+        assert!(fence.0 <= self.head.get(), "fence from another ring");
+        if fence.0 > self.tail.get() { self.tail.set(fence.0); }
+    }
+
+    /// Bytes allocated but not yet retired (including skipped tails).
+    pub fn in_flight(&self) -> usize { self.head.get() - self.tail.get() }
+
+    pub fn capacity(&self) -> usize { self.len }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        unsafe { self.inner.dealloc(self.base, Kind::from_size_align(self.len, MAX_ALIGN)); }
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        if kind.align() > MAX_ALIGN || kind.size() > self.len { return ptr::null_mut(); }
+        let head = self.head.get();
+        let align = kind.align();
+        let off = head % self.len;
+        let mut start = (off + align - 1) & !(align - 1);
+        if start + kind.size() > self.len {
+            // skip the tail of the buffer and wrap to the front
+            start = 0;
+        }
+        let consumed = if start >= off { start - off } else { self.len - off } + kind.size();
+        if head - self.tail.get() + consumed > self.len {
+            return ptr::null_mut();
+        }
+        self.head.set(head + consumed);
+        self.base.offset(start as isize)
+    }
+
+    unsafe fn dealloc_shared(&self, _ptr: Address, _kind: Kind) { }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
@@ -0,0 +1,241 @@
+// A rope: text kept as a balanced tree of short chunks, so that
+// inserting or removing text anywhere costs O(log n) rather than
+// moving everything after the edit.
+//
+// The tree is a treap ordered by position: each node holds one chunk
+// of at most `CHUNK` bytes and the byte length of its whole subtree,
+// and random priorities keep it balanced in expectation. An edit
+// splits the tree at the edit's bounds (cutting at most one chunk in
+// two at each bound) and merges the pieces back around the new text.
+//
+// Nodes and their chunks come from `A`, which must be `Clone` so each
+// node can hold its own copy: a stateless allocator, or a handle to an
+// arena, so that all text fragments of a document can be released by
+// resetting the arena once the rope is dropped.
+
+use alloc::{Alloc, DefaultAlloc};
+use boxed::Box;
+use string::String;
+use vec::Vec;
+
+use std::cmp;
+use std::str;
+
+pub const CHUNK: usize = 512;
+
+type Link<A> = Option<Box<Node<A>, A>>;
+
+struct Node<A:Alloc> {
+    chunk: Vec<u8, A>,
+    // bytes in this subtree
+    len: usize,
+    prio: u32,
+    left: Link<A>,
+    right: Link<A>,
+}
+
+fn len_of<A:Alloc>(link: &Link<A>) -> usize {
+    link.as_ref().map_or(0, |n| n.len)
+}
+
+impl<A:Alloc> Node<A> {
+    fn update(&mut self) {
+        self.len = len_of(&self.left) + self.chunk.len() + len_of(&self.right);
+    }
+}
+
+fn merge<A:Alloc>(a: Link<A>, b: Link<A>) -> Link<A> {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut a), Some(mut b)) => {
+            if a.prio > b.prio {
+                let right = a.right.take();
+                a.right = merge(right, Some(b));
+                a.update();
+                Some(a)
+            } else {
+                let left = b.left.take();
+                b.left = merge(Some(a), left);
+                b.update();
+                Some(b)
+            }
+        }
+    }
+}
+
+pub struct Rope<A:Alloc + Clone = DefaultAlloc> {
+    root: Link<A>,
+    alloc: A,
+    seed: u32,
+}
+
+impl<A:Alloc + Clone> Rope<A> {
+    pub fn new() -> Self where A: Default {
+        Rope::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        Rope { root: None, alloc: a, seed: 0x9E37_79B9 }
+    }
+
+    pub fn from_str_in(s: &str, a: A) -> Self {
+        let mut rope = Rope::with_alloc(a);
+        rope.insert(0, s);
+        rope
+    }
+
+    /// Moves the text of `s` into a rope drawing from `s`'s allocator.
+    pub fn from_string(s: String<A>) -> Self {
+        Rope::from_str_in(&s, s.alloc().clone())
+    }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize { len_of(&self.root) }
+
+    pub fn is_empty(&self) -> bool { self.root.is_none() }
+
+    pub fn is_char_boundary(&self, mut pos: usize) -> bool {
+        if pos == 0 || pos == self.len() { return true; }
+        if pos > self.len() { return false; }
+        let mut link = &self.root;
+        while let Some(ref n) = *link {
+            let left = len_of(&n.left);
+            if pos < left {
+                link = &n.left;
+            } else if pos < left + n.chunk.len() {
+                // not a UTF-8 continuation byte
+                return n.chunk[pos - left] & 0xC0 != 0x80;
+            } else {
+                pos -= left + n.chunk.len();
+                link = &n.right;
+            }
+        }
+        unreachable!()
+    }
+
+    /// Inserts `text` at byte offset `pos`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` is not on a character boundary.
+    pub fn insert(&mut self, pos: usize, text: &str) {
+        assert!(self.is_char_boundary(pos), "rope: {} is not a char boundary", pos);
+        let root = self.root.take();
+        let (left, right) = self.split(root, pos);
+        let mut middle = None;
+        let mut rest = text;
+        while !rest.is_empty() {
+            let mut n = cmp::min(rest.len(), CHUNK);
+            while !rest.is_char_boundary(n) { n -= 1; }
+            let node = self.node(&rest.as_bytes()[..n]);
+            middle = merge(middle, Some(node));
+            rest = &rest[n..];
+        }
+        self.root = merge(merge(left, middle), right);
+    }
+
+    /// Removes the bytes in `start..end`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bounds are out of order, past the end, or not on
+    /// character boundaries.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.len(), "rope: bad range {}..{}", start, end);
+        assert!(self.is_char_boundary(start) && self.is_char_boundary(end),
+                "rope: range {}..{} is not on char boundaries", start, end);
+        let root = self.root.take();
+        let (left, rest) = self.split(root, start);
+        let (_, right) = self.split(rest, end - start);
+        self.root = merge(left, right);
+    }
+
+    /// Iterates over the text a chunk at a time, in order.
+    pub fn chunks(&self) -> Chunks<A> {
+        let mut it = Chunks { stack: ::std::vec::Vec::new() };
+        it.descend(&self.root);
+        it
+    }
+
+    /// Copies the text into a `String` drawing from `b`.
+    pub fn to_string_in<B:Alloc>(&self, b: B) -> String<B> {
+        let mut s = String::with_alloc(b);
+        for chunk in self.chunks() { s.push_str(chunk); }
+        s
+    }
+
+    fn next_prio(&mut self) -> u32 {
+        // xorshift32
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    fn node(&mut self, bytes: &[u8]) -> Box<Node<A>, A> {
+        let mut chunk = Vec::with_capacity_alloc(bytes.len(), self.alloc.clone());
+        chunk.extend_from_slice(bytes);
+        let prio = self.next_prio();
+        Box::new_in(Node { chunk: chunk, len: bytes.len(), prio: prio, left: None, right: None },
+                    self.alloc.clone())
+    }
+
+    // Splits `t` into the first `pos` bytes and the rest, cutting a
+    // chunk in two if `pos` falls inside it.
+    fn split(&mut self, t: Link<A>, pos: usize) -> (Link<A>, Link<A>) {
+        let mut n = match t {
+            None => return (None, None),
+            Some(n) => n,
+        };
+        let left_len = len_of(&n.left);
+        let chunk_len = n.chunk.len();
+        if pos <= left_len {
+            let left = n.left.take();
+            let (l, r) = self.split(left, pos);
+            n.left = r;
+            n.update();
+            (l, Some(n))
+        } else if pos >= left_len + chunk_len {
+            let right = n.right.take();
+            let (l, r) = self.split(right, pos - left_len - chunk_len);
+            n.right = l;
+            n.update();
+            (Some(n), r)
+        } else {
+            let cut = pos - left_len;
+            let tail = self.node(&n.chunk[cut..]);
+            n.chunk.truncate(cut);
+            let right = n.right.take();
+            n.update();
+            (Some(n), merge(Some(tail), right))
+        }
+    }
+}
+
+/// The chunks of a rope, in order; see `Rope::chunks`.
+pub struct Chunks<'a, A:Alloc + 'a> {
+    // nodes whose chunk and right subtree are still to come
+    stack: ::std::vec::Vec<&'a Node<A>>,
+}
+
+impl<'a, A:Alloc> Chunks<'a, A> {
+    fn descend(&mut self, mut link: &'a Link<A>) {
+        while let Some(ref n) = *link {
+            self.stack.push(&**n);
+            link = &n.left;
+        }
+    }
+}
+
+impl<'a, A:Alloc> Iterator for Chunks<'a, A> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let n = match self.stack.pop() { Some(n) => n, None => return None };
+        self.descend(&n.right);
+        Some(unsafe { str::from_utf8_unchecked(&n.chunk) })
+    }
+}
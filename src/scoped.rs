@@ -0,0 +1,194 @@
+// A default allocator chosen by the caller's dynamic scope.
+//
+// `with_default_alloc(&arena, || ...)` makes `arena` the current
+// thread's implicit allocator while the closure runs, and
+// `ImplicitAlloc`, a zero-sized `Alloc`, forwards every request to the
+// innermost such allocator (or to `DefaultAlloc` outside any scope).
+// Code deep in a call tree can then build `Vec<T, ImplicitAlloc>` and
+// friends in the caller's arena without an allocator parameter on
+// every function in between.
+//
+// An `ImplicitAlloc` does not remember which allocator it used, so the
+// thread records the scope id of every block a scope hands out, and a
+// free, `realloc` or in-place resize goes to the scope that made the
+// block, however deeply nested the caller is now; blocks in no scope
+// came from `DefaultAlloc`. A vector built in an outer scope can thus
+// grow or be dropped inside an inner one.
+//
+// A block must not outlive its scope, though: the allocator behind it
+// may be destroyed as soon as `with_default_alloc` returns, while the
+// escaped value can be reached from anywhere. Nothing can make a later
+// use of it safe, so a scope that ends with blocks still live (on
+// return or while unwinding) aborts the process, naming the count.
+
+use alloc::{self, Address, AllocError, Capacity, DefaultAlloc, Kind, ShareAlloc, Size};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem;
+
+struct Scope {
+    id: usize,
+    alloc: *const ShareAlloc,
+    live: Cell<usize>,
+}
+
+thread_local!(static SCOPES: RefCell<Vec<Scope>> = RefCell::new(Vec::new()));
+thread_local!(static NEXT_ID: Cell<usize> = Cell::new(0));
+// The id of the scope that allocated each live block, by address.
+thread_local!(static OWNERS: RefCell<HashMap<usize, usize>> = RefCell::new(HashMap::new()));
+
+/// Runs `f` with `alloc` as the thread's implicit allocator; see
+/// `ImplicitAlloc`.
+///
+/// # Aborts
+///
+/// Aborts the process if a block that `ImplicitAlloc` allocated during
+/// `f` has not been freed when `f` returns or unwinds.
+pub fn with_default_alloc<'a, S, R, F>(alloc: &'a S, f: F) -> R
+    where S: ShareAlloc + 'a, F: FnOnce() -> R
+{
+    let alloc = alloc as &(ShareAlloc + 'a) as *const (ShareAlloc + 'a);
+    // The pointer is only used while `f` runs, within `'a`.
+    let alloc: *const ShareAlloc = unsafe { mem::transmute(alloc) };
+    let id = NEXT_ID.with(|n| { let id = n.get(); n.set(id + 1); id });
+    SCOPES.with(|s| s.borrow_mut().push(Scope { id: id, alloc: alloc, live: Cell::new(0) }));
+    let _guard = PopScope;
+    f()
+}
+
+// Pops the scope when `f` returns or unwinds.
+struct PopScope;
+
+impl Drop for PopScope {
+    fn drop(&mut self) {
+        let live = SCOPES.with(|s| s.borrow_mut().pop().unwrap().live.get());
+        if live != 0 {
+            let _ = writeln!(io::stderr(),
+                             "{} block(s) allocated by ImplicitAlloc outlived their scope", live);
+            unsafe { ::std::intrinsics::abort() }
+        }
+    }
+}
+
+/// Allocates from the innermost `with_default_alloc` allocator of the
+/// current thread, or from `DefaultAlloc` outside any.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ImplicitAlloc {
+    // tied to the thread whose scopes it uses
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ImplicitAlloc {
+    pub fn new() -> ImplicitAlloc { ImplicitAlloc { _not_send: PhantomData } }
+}
+
+type Home = (usize, *const ShareAlloc, *const Cell<usize>);
+
+fn home_of(sc: &Scope) -> Home {
+    (sc.id, sc.alloc, &sc.live as *const Cell<usize>)
+}
+
+// The innermost scope, or `None`.
+fn innermost() -> Option<Home> {
+    SCOPES.with(|s| s.borrow().last().map(home_of))
+}
+
+// The scope that allocated the block at `ptr`, or `None` if it came
+// from `DefaultAlloc`.
+fn owner(ptr: Address) -> Option<Home> {
+    let id = match OWNERS.with(|o| o.borrow().get(&(ptr as usize)).cloned()) {
+        Some(id) => id,
+        None => return None,
+    };
+    SCOPES.with(|s| s.borrow().iter().rev().find(|sc| sc.id == id).map(home_of))
+}
+
+fn set_owner(ptr: Address, id: Option<usize>) {
+    OWNERS.with(|o| {
+        let mut o = o.borrow_mut();
+        match id {
+            Some(id) => { o.insert(ptr as usize, id); }
+            None => { o.remove(&(ptr as usize)); }
+        }
+    })
+}
+
+impl alloc::Alloc for ImplicitAlloc {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        match innermost() {
+            Some((id, a, live)) => {
+                let p = (*a).alloc_shared(kind);
+                if !p.is_null() {
+                    (*live).set((*live).get() + 1);
+                    set_owner(p, Some(id));
+                }
+                p
+            }
+            None => DefaultAlloc.alloc(kind),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        match owner(ptr) {
+            Some((_, a, live)) => {
+                set_owner(ptr, None);
+                (*live).set((*live).get() - 1);
+                (*a).dealloc_shared(ptr, kind)
+            }
+            None => DefaultAlloc.dealloc(ptr, kind),
+        }
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        match innermost() {
+            Some((_, a, _)) => (*a).usable_size_shared(kind),
+            None => DefaultAlloc.usable_size(kind),
+        }
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        match innermost() {
+            Some((_, a, _)) => (*a).alloc_error_shared(kind),
+            None => DefaultAlloc.alloc_error(kind),
+        }
+    }
+
+    // The default in-place resizes ask `usable_size`, which answers
+    // for the innermost scope; ask the block's own allocator instead.
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        match owner(ptr) {
+            Some((_, a, _)) if new_size <= (*a).usable_size_shared(kind) => Ok(()),
+            Some(_) => Err(AllocError::Exhausted),
+            None => DefaultAlloc.grow_in_place(ptr, kind, new_size),
+        }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        let smaller = Kind::from_size_align(new_size, kind.align());
+        match owner(ptr) {
+            Some((_, a, _)) if (*a).usable_size_shared(smaller) >= kind.size() => Ok(()),
+            Some(_) => Err(AllocError::Unsupported),
+            None => DefaultAlloc.shrink_in_place(ptr, kind, new_size),
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        match owner(ptr) {
+            Some((id, a, _)) => {
+                let p = (*a).realloc_shared(ptr, kind, new_size);
+                if !p.is_null() && p != ptr {
+                    set_owner(ptr, None);
+                    set_owner(p, Some(id));
+                }
+                p
+            }
+            None => DefaultAlloc.realloc(ptr, kind, new_size),
+        }
+    }
+
+    fn compatible_with(&self, _other: &Self) -> bool { true }
+}
@@ -0,0 +1,116 @@
+// A reusable scratch buffer for hot loops that need a temporary slice
+// per iteration.
+//
+// `Scratch` holds one block from `A`. `with_scratch::<T>(n, f)` fills
+// the front of it with `n` default `T`s, lends them to `f` as a slice,
+// and drops them again when `f` returns; the block stays for the next
+// call. The block is only replaced when a call needs more bytes or a
+// larger alignment than it has, and since its contents never outlive a
+// call it is freed and allocated afresh rather than reallocated, so
+// nothing is copied. After warming up, a loop calling `with_scratch`
+// allocates nothing.
+
+use alloc::{Alloc, DefaultAlloc, Kind};
+use pressure::{self, Trim};
+
+use std::cell::RefCell;
+use std::cmp;
+use std::intrinsics;
+use std::ptr;
+use std::slice;
+
+pub struct Scratch<A:Alloc = DefaultAlloc> {
+    alloc: A,
+    ptr: *mut u8,
+    // the kind `ptr` was allocated with; zero-sized when nothing is
+    kind: Kind,
+}
+
+impl<A:Alloc> Scratch<A> {
+    pub fn new() -> Self where A: Default {
+        Scratch::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        let kind = Kind::new::<()>();
+        Scratch { alloc: a, ptr: kind.dangling(), kind: kind }
+    }
+
+    /// Bytes available without replacing the block.
+    pub fn capacity(&self) -> usize { self.kind.size() }
+
+    /// Returns the block to the allocator; the next call allocates
+    /// again.
+    pub fn release(&mut self) {
+        if !self.kind.is_zero_sized() {
+            unsafe { self.alloc.dealloc(self.ptr, self.kind); }
+        }
+        self.kind = Kind::new::<()>();
+        self.ptr = self.kind.dangling();
+    }
+
+    /// Calls `f` with `n` default values of `T` placed in the scratch
+    /// block, returning what `f` returns.
+    pub fn with_scratch<T, R, F>(&mut self, n: usize, f: F) -> R
+        where T: Default, F: FnOnce(&mut [T]) -> R
+    {
+        let need = Kind::new::<T>().array(n);
+        if !need.is_zero_sized()
+            && (need.size() > self.kind.size() || need.align() > self.kind.align())
+        {
+            // never shrink, so alternating calls do not thrash
+            let old = self.kind;
+            self.release();
+            let kind = unsafe {
+                Kind::from_size_align(cmp::max(need.size(), old.size()),
+                                      cmp::max(need.align(), old.align()))
+            };
+            let p = unsafe { self.alloc.alloc(kind) };
+            if p.is_null() { unsafe { self.alloc.oom() } }
+            self.ptr = p;
+            self.kind = kind;
+        }
+
+        // Drops the values written so far, also when `T::default` or
+        // `f` panics.
+        struct Filled<T> { start: *mut T, len: usize }
+        impl<T> Drop for Filled<T> {
+            fn drop(&mut self) {
+                unsafe {
+                    let s = slice::from_raw_parts_mut(self.start, self.len);
+                    intrinsics::drop_in_place(s as *mut [T]);
+                }
+            }
+        }
+
+        let start = if need.is_zero_sized() {
+            Kind::new::<T>().dangling() as *mut T
+        } else {
+            self.ptr as *mut T
+        };
+        let mut filled = Filled { start: start, len: 0 };
+        while filled.len < n {
+            unsafe { ptr::write(start.offset(filled.len as isize), T::default()); }
+            filled.len += 1;
+        }
+        f(unsafe { slice::from_raw_parts_mut(start, n) })
+    }
+}
+
+// The block is cheap to get back on the next call, but keeping it is
+// the point, so it only goes under critical pressure.
+impl<A:Alloc> Trim for RefCell<Scratch<A>> {
+    fn trim(&self, level: pressure::Level) -> usize {
+        if level < pressure::Level::Critical { return 0; }
+        match self.try_borrow_mut() {
+            Ok(mut s) => { let bytes = s.capacity(); s.release(); bytes }
+            Err(_) => 0,
+        }
+    }
+}
+
+impl<A:Alloc> Drop for Scratch<A> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
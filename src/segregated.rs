@@ -0,0 +1,149 @@
+// A composite allocator that routes each request to one of three
+// tiers by size:
+//
+//   size <= small_max             => small tier  (e.g. a slab)
+//   small_max < size <= medium_max => medium tier (e.g. a free list)
+//   medium_max < size             => large tier  (e.g. mmap)
+//
+// Since every `dealloc` carries the `Kind` of the block, the tier that
+// owns a block is recomputed from its size; no per-block header or
+// address lookup is needed. For that to stay coherent `usable_size`
+// never reports more than the owning tier's upper bound, so a block
+// never silently grows into another tier's size range. A `realloc`
+// that crosses a threshold moves the block between tiers.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use std::cmp;
+use std::ptr;
+
+pub const DEFAULT_SMALL_MAX: usize = 256;
+pub const DEFAULT_MEDIUM_MAX: usize = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tier { Small, Medium, Large }
+
+#[derive(Clone, Debug)]
+pub struct Alloc<S, M, L> {
+    small: S,
+    medium: M,
+    large: L,
+    small_max: usize,
+    medium_max: usize,
+}
+
+impl<S: alloc::Alloc, M: alloc::Alloc, L: alloc::Alloc> Alloc<S, M, L> {
+    pub fn new(small: S, medium: M, large: L) -> Self {
+        Alloc::with_thresholds(small, medium, large, DEFAULT_SMALL_MAX, DEFAULT_MEDIUM_MAX)
+    }
+
+    pub fn with_thresholds(small: S, medium: M, large: L,
+                           small_max: usize, medium_max: usize) -> Self {
+        assert!(small_max <= medium_max, "segregated: small_max must not exceed medium_max");
+        Alloc { small: small, medium: medium, large: large,
+                small_max: small_max, medium_max: medium_max }
+    }
+
+    /// The tier responsible for blocks of the given kind.
+    pub fn tier_for(&self, kind: Kind) -> Tier {
+        self.tier_for_size(kind.size())
+    }
+
+    fn tier_for_size(&self, size: usize) -> Tier {
+        if size <= self.small_max {
+            Tier::Small
+        } else if size <= self.medium_max {
+            Tier::Medium
+        } else {
+            Tier::Large
+        }
+    }
+
+    pub fn small(&self) -> &S { &self.small }
+    pub fn medium(&self) -> &M { &self.medium }
+    pub fn large(&self) -> &L { &self.large }
+
+    unsafe fn alloc_in(&mut self, tier: Tier, kind: Kind) -> Address {
+        match tier {
+            Tier::Small => self.small.alloc(kind),
+            Tier::Medium => self.medium.alloc(kind),
+            Tier::Large => self.large.alloc(kind),
+        }
+    }
+
+    unsafe fn dealloc_in(&mut self, tier: Tier, ptr: Address, kind: Kind) {
+        match tier {
+            Tier::Small => self.small.dealloc(ptr, kind),
+            Tier::Medium => self.medium.dealloc(ptr, kind),
+            Tier::Large => self.large.dealloc(ptr, kind),
+        }
+    }
+}
+
+impl<S: alloc::Alloc, M: alloc::Alloc, L: alloc::Alloc> alloc::Alloc for Alloc<S, M, L> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        let tier = self.tier_for(kind);
+        self.alloc_in(tier, kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        let tier = self.tier_for(kind);
+        self.dealloc_in(tier, ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        match self.tier_for(kind) {
+            Tier::Small => self.small.dealloc_hot(ptr, kind),
+            Tier::Medium => self.medium.dealloc_hot(ptr, kind),
+            Tier::Large => self.large.dealloc_hot(ptr, kind),
+        }
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        match self.tier_for(kind) {
+            Tier::Small => cmp::min(self.small.usable_size(kind), self.small_max),
+            Tier::Medium => cmp::min(self.medium.usable_size(kind), self.medium_max),
+            Tier::Large => self.large.usable_size(kind),
+        }
+    }
+
+    // A block only resizes in place within its tier; crossing into
+    // another would leave it freed through the wrong allocator.
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        match self.tier_for(kind) {
+            t if t != self.tier_for_size(new_size) => Err(AllocError::Unsupported),
+            Tier::Small => self.small.grow_in_place(ptr, kind, new_size),
+            Tier::Medium => self.medium.grow_in_place(ptr, kind, new_size),
+            Tier::Large => self.large.grow_in_place(ptr, kind, new_size),
+        }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        match self.tier_for(kind) {
+            t if t != self.tier_for_size(new_size) => Err(AllocError::Unsupported),
+            Tier::Small => self.small.shrink_in_place(ptr, kind, new_size),
+            Tier::Medium => self.medium.shrink_in_place(ptr, kind, new_size),
+            Tier::Large => self.large.shrink_in_place(ptr, kind, new_size),
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let old_tier = self.tier_for(kind);
+        let new_tier = self.tier_for_size(new_size);
+        if old_tier == new_tier {
+            return match old_tier {
+                Tier::Small => self.small.realloc(ptr, kind, new_size),
+                Tier::Medium => self.medium.realloc(ptr, kind, new_size),
+                Tier::Large => self.large.realloc(ptr, kind, new_size),
+            };
+        }
+        let new_kind = Kind::from_size_align(new_size, kind.align());
+        let new_ptr = self.alloc_in(new_tier, new_kind);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr as *const u8, new_ptr, cmp::min(kind.size(), new_size));
+            self.dealloc_in(old_tier, ptr, kind);
+        }
+        new_ptr
+    }
+}
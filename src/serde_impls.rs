@@ -0,0 +1,174 @@
+// Serialization for the allocator-backed collections.
+//
+// `Serialize` is implemented as for the standard collections. The
+// standard `Deserialize` cannot say where the result should live, so
+// each collection also gets a `deserialize_in(deserializer, alloc)`
+// constructor that builds it directly in the given allocator (an
+// arena, say); the plain `Deserialize` impls use `A::default()`.
+
+use alloc::Alloc;
+use boxed::Box;
+use raw_vec::GrowthPolicy;
+use vec::Vec;
+use vec_map::{VecMap, VecSet};
+
+use serde::de::{self, Deserialize, Deserializer, MapVisitor, SeqVisitor, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use std::marker::PhantomData;
+
+impl<T: Serialize, A:Alloc, G:GrowthPolicy> Serialize for Vec<T, A, G> {
+    fn serialize<S: Serializer>(&self, s: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(s.serialize_seq(Some(self.len())));
+        for e in self.iter() {
+            try!(s.serialize_seq_elt(&mut state, e));
+        }
+        s.serialize_seq_end(state)
+    }
+}
+
+impl<T: Serialize + ?Sized, A:Alloc> Serialize for Box<T, A> {
+    fn serialize<S: Serializer>(&self, s: &mut S) -> Result<(), S::Error> {
+        (**self).serialize(s)
+    }
+}
+
+impl<K: Ord + Serialize, V: Serialize, A:Alloc> Serialize for VecMap<K, V, A> {
+    fn serialize<S: Serializer>(&self, s: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(s.serialize_map(Some(self.len())));
+        for (k, v) in self.iter() {
+            try!(s.serialize_map_key(&mut state, k));
+            try!(s.serialize_map_value(&mut state, v));
+        }
+        s.serialize_map_end(state)
+    }
+}
+
+impl<T: Ord + Serialize, A:Alloc> Serialize for VecSet<T, A> {
+    fn serialize<S: Serializer>(&self, s: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(s.serialize_seq(Some(self.len())));
+        for e in self.iter() {
+            try!(s.serialize_seq_elt(&mut state, e));
+        }
+        s.serialize_seq_end(state)
+    }
+}
+
+// Visitors carry the allocator to use until the first element
+// arrives; `Option` because `visit_*` takes `&mut self`.
+
+struct VecVisitor<T, A:Alloc, G:GrowthPolicy> {
+    alloc: Option<A>,
+    _marker: PhantomData<(T, G)>,
+}
+
+impl<T: Deserialize, A:Alloc, G:GrowthPolicy> Visitor for VecVisitor<T, A, G> {
+    type Value = Vec<T, A, G>;
+
+    fn visit_seq<V: SeqVisitor>(&mut self, mut visitor: V) -> Result<Vec<T, A, G>, V::Error> {
+        let a = self.alloc.take().expect("VecVisitor used twice");
+        let (lower, _) = visitor.size_hint();
+        let mut v = Vec::with_capacity_alloc(lower, a);
+        while let Some(e) = try!(visitor.visit()) {
+            v.push(e);
+        }
+        try!(visitor.end());
+        Ok(v)
+    }
+}
+
+impl<T: Deserialize, A:Alloc, G:GrowthPolicy> Vec<T, A, G> {
+    /// Deserializes a sequence into a vector whose buffer comes from
+    /// `alloc`.
+    pub fn deserialize_in<D: Deserializer>(d: &mut D, alloc: A) -> Result<Self, D::Error> {
+        d.deserialize_seq(VecVisitor { alloc: Some(alloc), _marker: PhantomData })
+    }
+}
+
+impl<T: Deserialize, A:Alloc + Default, G:GrowthPolicy> Deserialize for Vec<T, A, G> {
+    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+        Vec::deserialize_in(d, A::default())
+    }
+}
+
+impl<T: Deserialize, A:Alloc> Box<T, A> {
+    /// Deserializes a value into memory from `alloc`.
+    pub fn deserialize_in<D: Deserializer>(d: &mut D, alloc: A) -> Result<Self, D::Error> {
+        let value = try!(T::deserialize(d));
+        Ok(Box::new_in(value, alloc))
+    }
+}
+
+impl<T: Deserialize, A:Alloc + Default> Deserialize for Box<T, A> {
+    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+        Box::deserialize_in(d, A::default())
+    }
+}
+
+struct VecMapVisitor<K, V, A:Alloc> {
+    alloc: Option<A>,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: Ord + Deserialize, V: Deserialize, A:Alloc> Visitor for VecMapVisitor<K, V, A> {
+    type Value = VecMap<K, V, A>;
+
+    fn visit_map<M: MapVisitor>(&mut self, mut visitor: M) -> Result<VecMap<K, V, A>, M::Error> {
+        let a = self.alloc.take().expect("VecMapVisitor used twice");
+        let (lower, _) = visitor.size_hint();
+        let mut m = VecMap::with_capacity_alloc(lower, a);
+        while let Some((k, v)) = try!(visitor.visit()) {
+            if m.insert(k, v).is_some() {
+                return Err(de::Error::custom("duplicate key in map"));
+            }
+        }
+        try!(visitor.end());
+        Ok(m)
+    }
+}
+
+impl<K: Ord + Deserialize, V: Deserialize, A:Alloc> VecMap<K, V, A> {
+    /// Deserializes a map into one whose storage comes from `alloc`.
+    /// Entries may arrive in any order.
+    pub fn deserialize_in<D: Deserializer>(d: &mut D, alloc: A) -> Result<Self, D::Error> {
+        d.deserialize_map(VecMapVisitor { alloc: Some(alloc), _marker: PhantomData })
+    }
+}
+
+impl<K: Ord + Deserialize, V: Deserialize, A:Alloc + Default> Deserialize for VecMap<K, V, A> {
+    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+        VecMap::deserialize_in(d, A::default())
+    }
+}
+
+struct VecSetVisitor<T, A:Alloc> {
+    alloc: Option<A>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Ord + Deserialize, A:Alloc> Visitor for VecSetVisitor<T, A> {
+    type Value = VecSet<T, A>;
+
+    fn visit_seq<V: SeqVisitor>(&mut self, mut visitor: V) -> Result<VecSet<T, A>, V::Error> {
+        let a = self.alloc.take().expect("VecSetVisitor used twice");
+        let mut set = VecSet::with_alloc(a);
+        while let Some(e) = try!(visitor.visit()) {
+            set.insert(e);
+        }
+        try!(visitor.end());
+        Ok(set)
+    }
+}
+
+impl<T: Ord + Deserialize, A:Alloc> VecSet<T, A> {
+    /// Deserializes a set into one whose storage comes from `alloc`.
+    pub fn deserialize_in<D: Deserializer>(d: &mut D, alloc: A) -> Result<Self, D::Error> {
+        d.deserialize_seq(VecSetVisitor { alloc: Some(alloc), _marker: PhantomData })
+    }
+}
+
+impl<T: Ord + Deserialize, A:Alloc + Default> Deserialize for VecSet<T, A> {
+    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+        VecSet::deserialize_in(d, A::default())
+    }
+}
@@ -0,0 +1,123 @@
+// A thread-safe wrapper spreading requests over several allocators.
+//
+// A stateful allocator (a pool, a free list) shared between threads
+// behind one lock serializes them all. `sharded::Alloc` keeps N
+// independent shards, each behind its own mutex, and sends each
+// thread's allocations to "its" shard (threads are numbered as they
+// first allocate, and take shard `number % N`), so threads mostly
+// lock different shards.
+//
+// A block may be freed by a different thread than the one that
+// allocated it, so the wrapper remembers which shard each live block
+// came from. That map is itself split by address into the same number
+// of parts, each with its own lock, to keep it off the contended path.
+//
+// Share it with `&sharded::Alloc` or `Arc<sharded::Alloc>`; both are
+// allocators via `ShareAlloc`. The shards are assumed to be alike, so
+// `usable_size` asks the calling thread's shard.
+
+use alloc::{self, Address, Capacity, Kind, ShareAlloc, Size};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+static NEXT_THREAD: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local!(static THREAD_NUMBER: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed));
+
+pub struct Alloc<A> {
+    shards: Vec<Mutex<A>>,
+    owners: Vec<Mutex<HashMap<usize, usize>>>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Uses each element of `shards` as one shard.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is empty.
+    pub fn new(shards: Vec<A>) -> Alloc<A> {
+        assert!(!shards.is_empty(), "sharded::Alloc needs at least one shard");
+        let n = shards.len();
+        Alloc {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            owners: (0..n).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Builds `n` shards by calling `make` for each.
+    pub fn with_shards<F>(n: usize, mut make: F) -> Alloc<A> where F: FnMut() -> A {
+        Alloc::new((0..n).map(|_| make()).collect())
+    }
+
+    pub fn shard_count(&self) -> usize { self.shards.len() }
+
+    /// Runs `f` on shard `i` (e.g. to read its statistics), holding
+    /// that shard's lock.
+    pub fn with_shard<R, F>(&self, i: usize, f: F) -> R where F: FnOnce(&mut A) -> R {
+        f(&mut *self.shards[i].lock().unwrap())
+    }
+
+    /// The shard the calling thread allocates from.
+    pub fn current_shard(&self) -> usize {
+        THREAD_NUMBER.with(|n| *n) % self.shards.len()
+    }
+
+    fn owners_of(&self, p: Address) -> &Mutex<HashMap<usize, usize>> {
+        // drop the low bits, which alignment makes mostly zero
+        &self.owners[(p as usize >> 4) % self.owners.len()]
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        let i = self.current_shard();
+        let p = self.shards[i].lock().unwrap().alloc(kind);
+        if !p.is_null() && kind.size() != 0 {
+            self.owners_of(p).lock().unwrap().insert(p as usize, i);
+        }
+        p
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        if kind.size() == 0 {
+            let i = self.current_shard();
+            return self.shards[i].lock().unwrap().dealloc(ptr, kind);
+        }
+        let i = self.owners_of(ptr).lock().unwrap().remove(&(ptr as usize))
+            .expect("sharded: block was not allocated here");
+        self.shards[i].lock().unwrap().dealloc(ptr, kind)
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        self.shards[self.current_shard()].lock().unwrap().usable_size(kind)
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.size() == 0 {
+            return self.alloc_shared(Kind::from_size_align(new_size, kind.align()));
+        }
+        // The block stays in its shard, even if another thread grows it.
+        // Its entry goes before the realloc: once `ptr` is freed another
+        // thread may be handed the address and record itself as owner.
+        let i = self.owners_of(ptr).lock().unwrap().remove(&(ptr as usize))
+            .expect("sharded: block was not allocated here");
+        let p = self.shards[i].lock().unwrap().realloc(ptr, kind, new_size);
+        if p.is_null() {
+            self.owners_of(ptr).lock().unwrap().insert(ptr as usize, i);
+        } else if new_size != 0 {
+            self.owners_of(p).lock().unwrap().insert(p as usize, i);
+        }
+        p
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
@@ -0,0 +1,437 @@
+// An allocator over a shared-memory segment, so that several
+// processes can allocate from (and free into) one region. On Unix the
+// segment is a POSIX `shm_open` object; on Windows it is a named file
+// mapping backed by the paging file (`CreateFileMappingW`), and the
+// name `"/x"` becomes `Local\x`.
+//
+// Everything the allocator needs lives inside the segment: a header
+// holding the head of the free list, a root offset and (on Unix) a
+// process-shared robust mutex, followed by the heap proper. Windows
+// mutex handles cannot live in shared memory, so there the lock is a
+// named mutex beside the mapping. Each process may map the segment at
+// a different address, so all links are offsets from the segment
+// base; containers stored in the segment should use `offset_ptr` for
+// the same reason.
+//
+// The heap is a first-fit, address-ordered free list. Every block
+// starts with a `BLOCK_HEADER`-byte header holding its size; free
+// blocks also hold the offset of the next free block. Blocks are
+// split on allocation and coalesced with their neighbours on free.
+// Alignments above `MAX_ALIGN` are refused.
+//
+// If a process dies while holding the lock, the next locker takes the
+// lock over (a robust mutex, or an abandoned Windows mutex) so the
+// others do not hang. The free list is not journaled, though: a
+// process killed in the middle of a split or merge can leave it
+// leaking or, mid-merge, overlapping, so after such a death the
+// segment should be treated as suspect and rebuilt.
+
+use alloc::{self, Address, Kind, ShareAlloc, Size};
+
+use libc;
+
+#[cfg(unix)]
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr;
+
+pub const MAX_ALIGN: usize = 16;
+const BLOCK_HEADER: usize = 16;
+const MIN_BLOCK: usize = 2 * BLOCK_HEADER;
+const MAGIC: u64 = 0x616c_6c6f_6373_686d;
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+    free_head: u64,
+    root: u64,
+    #[cfg(unix)]
+    lock: libc::pthread_mutex_t,
+}
+
+#[repr(C)]
+struct Block {
+    size: u64,
+    next: u64,
+}
+
+pub struct Alloc {
+    base: *mut u8,
+    len: usize,
+    #[cfg(windows)]
+    mapping: win::Handle,
+    #[cfg(windows)]
+    lock: win::Handle,
+}
+
+unsafe impl Send for Alloc { }
+unsafe impl Sync for Alloc { }
+
+fn round_up(n: usize, align: usize) -> usize { (n + align - 1) & !(align - 1) }
+
+fn heap_start() -> usize { round_up(mem::size_of::<Header>(), MAX_ALIGN) }
+
+fn os_err<T>() -> io::Result<T> { Err(io::Error::last_os_error()) }
+
+#[cfg(unix)]
+unsafe fn map(fd: libc::c_int, len: usize) -> io::Result<*mut u8> {
+    let p = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED, fd, 0);
+    libc::close(fd);
+    if p == libc::MAP_FAILED { os_err() } else { Ok(p as *mut u8) }
+}
+
+#[cfg(unix)]
+impl Alloc {
+    /// Creates the segment `name` (e.g. `"/my-segment"`) with `len`
+    /// bytes and initializes an empty heap in it.
+    pub fn create(name: &str, len: usize) -> io::Result<Alloc> {
+        if len < heap_start() + MIN_BLOCK {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "segment too small"));
+        }
+        let cname = try!(CString::new(name));
+        unsafe {
+            let fd = libc::shm_open(cname.as_ptr(), libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                                    0o600);
+            if fd < 0 { return os_err(); }
+            if libc::ftruncate(fd, len as libc::off_t) != 0 {
+                libc::close(fd);
+                return os_err();
+            }
+            let base = try!(map(fd, len));
+            let a = Alloc { base: base, len: len };
+            a.init_heap();
+            let h = a.header();
+
+            let mut attr: libc::pthread_mutexattr_t = mem::zeroed();
+            libc::pthread_mutexattr_init(&mut attr);
+            libc::pthread_mutexattr_setpshared(&mut attr, libc::PTHREAD_PROCESS_SHARED);
+            libc::pthread_mutexattr_setrobust(&mut attr, libc::PTHREAD_MUTEX_ROBUST);
+            libc::pthread_mutex_init(&mut (*h).lock, &attr);
+            libc::pthread_mutexattr_destroy(&mut attr);
+            // Publish last: openers check the magic before trusting the rest.
+            ptr::write_volatile(&mut (*h).magic, MAGIC);
+            Ok(a)
+        }
+    }
+
+    /// Maps an existing segment created by `create`.
+    pub fn open(name: &str) -> io::Result<Alloc> {
+        let cname = try!(CString::new(name));
+        unsafe {
+            let fd = libc::shm_open(cname.as_ptr(), libc::O_RDWR, 0);
+            if fd < 0 { return os_err(); }
+            let mut st: libc::stat = mem::zeroed();
+            if libc::fstat(fd, &mut st) != 0 {
+                libc::close(fd);
+                return os_err();
+            }
+            let len = st.st_size as usize;
+            let base = try!(map(fd, len));
+            let a = Alloc { base: base, len: len };
+            if len < heap_start() || ptr::read_volatile(&(*a.header()).magic) != MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "not an allocoll shared segment"));
+            }
+            Ok(a)
+        }
+    }
+
+    /// Removes the segment name; mappings stay valid until dropped.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        let cname = try!(CString::new(name));
+        if unsafe { libc::shm_unlink(cname.as_ptr()) } == 0 { Ok(()) } else { os_err() }
+    }
+
+    fn with_lock<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        unsafe {
+            let lock = &mut (*self.header()).lock;
+            let r = libc::pthread_mutex_lock(lock);
+            if r == libc::EOWNERDEAD {
+                libc::pthread_mutex_consistent(lock);
+            }
+            let ret = f();
+            libc::pthread_mutex_unlock(lock);
+            ret
+        }
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use libc::{c_void, size_t};
+
+    pub type Handle = *mut c_void;
+
+    pub const INVALID_HANDLE_VALUE: Handle = !0usize as Handle;
+    pub const PAGE_READWRITE: u32 = 0x04;
+    pub const FILE_MAP_ALL_ACCESS: u32 = 0xF001F;
+    pub const INFINITE: u32 = !0;
+    pub const WAIT_OBJECT_0: u32 = 0;
+    pub const WAIT_ABANDONED: u32 = 0x80;
+    pub const ERROR_ALREADY_EXISTS: u32 = 183;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn CreateFileMappingW(file: Handle, attrs: *mut c_void, protect: u32,
+                                  size_high: u32, size_low: u32, name: *const u16) -> Handle;
+        pub fn OpenFileMappingW(access: u32, inherit: i32, name: *const u16) -> Handle;
+        pub fn MapViewOfFile(mapping: Handle, access: u32, offset_high: u32, offset_low: u32,
+                             bytes: size_t) -> *mut c_void;
+        pub fn UnmapViewOfFile(base: *const c_void) -> i32;
+        pub fn CreateMutexW(attrs: *mut c_void, initial_owner: i32, name: *const u16) -> Handle;
+        pub fn WaitForSingleObject(handle: Handle, millis: u32) -> u32;
+        pub fn ReleaseMutex(mutex: Handle) -> i32;
+        pub fn CloseHandle(handle: Handle) -> i32;
+        pub fn GetLastError() -> u32;
+    }
+
+    // `"/x"` as the session-local object name `Local\x` plus `suffix`,
+    // NUL-terminated UTF-16.
+    pub fn object_name(name: &str, suffix: &str) -> Vec<u16> {
+        let name = name.trim_left_matches('/');
+        "Local\\".encode_utf16()
+            .chain(name.encode_utf16())
+            .chain(suffix.encode_utf16())
+            .chain(Some(0))
+            .collect()
+    }
+}
+
+#[cfg(windows)]
+impl Alloc {
+    /// Creates the segment `name` (e.g. `"/my-segment"`) with `len`
+    /// bytes and initializes an empty heap in it.
+    pub fn create(name: &str, len: usize) -> io::Result<Alloc> {
+        if len < heap_start() + MIN_BLOCK {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "segment too small"));
+        }
+        unsafe {
+            let lock = try!(Alloc::open_lock(name));
+            let wname = win::object_name(name, "");
+            let len64 = len as u64;
+            let mapping = win::CreateFileMappingW(win::INVALID_HANDLE_VALUE, ptr::null_mut(),
+                                                  win::PAGE_READWRITE, (len64 >> 32) as u32,
+                                                  len64 as u32, wname.as_ptr());
+            if mapping.is_null() {
+                win::CloseHandle(lock);
+                return os_err();
+            }
+            if win::GetLastError() == win::ERROR_ALREADY_EXISTS {
+                win::CloseHandle(mapping);
+                win::CloseHandle(lock);
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                          "shared segment already exists"));
+            }
+            let a = try!(Alloc::map_view(mapping, lock, len));
+            a.init_heap();
+            // Publish last: openers check the magic before trusting the rest.
+            ptr::write_volatile(&mut (*a.header()).magic, MAGIC);
+            Ok(a)
+        }
+    }
+
+    /// Maps an existing segment created by `create`.
+    pub fn open(name: &str) -> io::Result<Alloc> {
+        unsafe {
+            let lock = try!(Alloc::open_lock(name));
+            let wname = win::object_name(name, "");
+            let mapping = win::OpenFileMappingW(win::FILE_MAP_ALL_ACCESS, 0, wname.as_ptr());
+            if mapping.is_null() {
+                win::CloseHandle(lock);
+                return os_err();
+            }
+            // Map the whole object; its length is recorded in the header.
+            let mut a = try!(Alloc::map_view(mapping, lock, 0));
+            if ptr::read_volatile(&(*a.header()).magic) != MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "not an allocoll shared segment"));
+            }
+            a.len = (*a.header()).len as usize;
+            Ok(a)
+        }
+    }
+
+    /// Does nothing: a Windows file mapping disappears by itself once
+    /// the last handle to it is closed.
+    pub fn unlink(_name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    unsafe fn open_lock(name: &str) -> io::Result<win::Handle> {
+        let wname = win::object_name(name, ".lock");
+        let lock = win::CreateMutexW(ptr::null_mut(), 0, wname.as_ptr());
+        if lock.is_null() { os_err() } else { Ok(lock) }
+    }
+
+    // Takes ownership of both handles, closing them on failure.
+    unsafe fn map_view(mapping: win::Handle, lock: win::Handle, len: usize) -> io::Result<Alloc> {
+        let base = win::MapViewOfFile(mapping, win::FILE_MAP_ALL_ACCESS, 0, 0, len);
+        if base.is_null() {
+            let err = io::Error::last_os_error();
+            win::CloseHandle(mapping);
+            win::CloseHandle(lock);
+            return Err(err);
+        }
+        Ok(Alloc { base: base as *mut u8, len: len, mapping: mapping, lock: lock })
+    }
+
+    fn with_lock<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        unsafe {
+            // An abandoned mutex is handed to us locked, as with a dead
+            // robust mutex on Unix.
+            match win::WaitForSingleObject(self.lock, win::INFINITE) {
+                win::WAIT_OBJECT_0 | win::WAIT_ABANDONED => {}
+                r => panic!("shm: waiting for the segment lock failed ({})", r),
+            }
+            let ret = f();
+            win::ReleaseMutex(self.lock);
+            ret
+        }
+    }
+}
+
+impl Alloc {
+    fn header(&self) -> *mut Header { self.base as *mut Header }
+
+    // Lays out an empty heap in a fresh segment (all but the lock and
+    // the magic number).
+    unsafe fn init_heap(&self) {
+        let h = self.header();
+        (*h).len = self.len as u64;
+        (*h).root = 0;
+        (*h).free_head = heap_start() as u64;
+        let first = self.block(heap_start());
+        (*first).size = (self.len - heap_start()) as u64 & !(MAX_ALIGN as u64 - 1);
+        (*first).next = 0;
+    }
+
+    unsafe fn block(&self, off: usize) -> *mut Block {
+        self.base.offset(off as isize) as *mut Block
+    }
+
+    pub fn base(&self) -> *mut u8 { self.base }
+
+    pub fn offset_of<T>(&self, p: *const T) -> usize { p as usize - self.base as usize }
+
+    pub unsafe fn at_offset<T>(&self, off: usize) -> *mut T {
+        self.base.offset(off as isize) as *mut T
+    }
+
+    pub fn set_root<T>(&self, p: *const T) {
+        unsafe { (*self.header()).root = self.offset_of(p) as u64; }
+    }
+
+    pub fn root<T>(&self) -> Option<*mut T> {
+        match unsafe { (*self.header()).root } {
+            0 => None,
+            off => Some(unsafe { self.at_offset(off as usize) }),
+        }
+    }
+
+    unsafe fn alloc_locked(&self, need: usize) -> Address {
+        let h = self.header();
+        let mut prev: usize = 0;
+        let mut cur = (*h).free_head as usize;
+        while cur != 0 {
+            let b = self.block(cur);
+            let size = (*b).size as usize;
+            if size >= need {
+                let next = if size - need >= MIN_BLOCK {
+                    let rest = self.block(cur + need);
+                    (*rest).size = (size - need) as u64;
+                    (*rest).next = (*b).next;
+                    (*b).size = need as u64;
+                    (cur + need) as u64
+                } else {
+                    (*b).next
+                };
+                if prev == 0 { (*h).free_head = next; } else { (*self.block(prev)).next = next; }
+                return self.base.offset((cur + BLOCK_HEADER) as isize);
+            }
+            prev = cur;
+            cur = (*b).next as usize;
+        }
+        ptr::null_mut()
+    }
+
+    unsafe fn free_locked(&self, off: usize) {
+        let h = self.header();
+        let b = self.block(off);
+        let mut prev: usize = 0;
+        let mut next = (*h).free_head as usize;
+        while next != 0 && next < off {
+            prev = next;
+            next = (*self.block(next)).next as usize;
+        }
+        // merge with the following free block
+        if next != 0 && off + (*b).size as usize == next {
+            let nb = self.block(next);
+            (*b).size += (*nb).size;
+            (*b).next = (*nb).next;
+        } else {
+            (*b).next = next as u64;
+        }
+        // merge into the preceding free block, or link after it
+        if prev == 0 {
+            (*h).free_head = off as u64;
+        } else {
+            let pb = self.block(prev);
+            if prev + (*pb).size as usize == off {
+                (*pb).size += (*b).size;
+                (*pb).next = (*b).next;
+            } else {
+                (*pb).next = off as u64;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Alloc {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len); }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Alloc {
+    fn drop(&mut self) {
+        unsafe {
+            win::UnmapViewOfFile(self.base as *const libc::c_void);
+            win::CloseHandle(self.mapping);
+            win::CloseHandle(self.lock);
+        }
+    }
+}
+
+impl ShareAlloc for Alloc {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        if kind.align() > MAX_ALIGN { return ptr::null_mut(); }
+        let need = match kind.size().checked_add(BLOCK_HEADER + MAX_ALIGN - 1) {
+            Some(n) => n & !(MAX_ALIGN - 1),
+            None => return ptr::null_mut(),
+        };
+        self.with_lock(|| self.alloc_locked(need))
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, _kind: Kind) {
+        let off = self.offset_of(ptr) - BLOCK_HEADER;
+        self.with_lock(|| self.free_locked(off))
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> usize {
+        round_up(kind.size() + BLOCK_HEADER, MAX_ALIGN) - BLOCK_HEADER
+    }
+}
+
+impl alloc::Alloc for Alloc {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> usize { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
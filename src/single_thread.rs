@@ -0,0 +1,187 @@
+// Catching frees from the wrong thread.
+//
+// The arena, bump and pool allocators keep their state in `Cell`s and
+// `Rc`s, which is only sound while every call comes from one thread.
+// Nothing stops a block from leaving, though: a handle made `Send` by
+// hand, or an unsafe transfer of a box, and the block is freed on
+// another thread in a data race with its owner.
+//
+// `Pinned<A>` records the thread that created it and is the only way
+// to reach `A`. Its handles can be sent anywhere, but only the owning
+// thread may allocate or reallocate through them; a `dealloc` from
+// another thread panics in debug builds, where the point is to find
+// the stray free, and in release builds is posted to a
+// `remote_free::Queue` that the owner empties on its next call (or
+// explicitly, with `drain_mailbox`). A pool made with
+// `with_remote_free` queues foreign frees in every build, for programs
+// that send boxes to worker threads on purpose. If the last handle is
+// dropped on a foreign thread the inner allocator is leaked rather
+// than destroyed there.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+use remote_free::Queue;
+use uninit::ManuallyDrop;
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+// An address unique to each live thread: that of a thread-local. A
+// thread started after the owner exits may reuse it, but by then no
+// one is left to race with.
+fn current_thread() -> usize {
+    thread_local!(static MARKER: u8 = 0);
+    MARKER.with(|m| m as *const u8 as usize)
+}
+
+struct Shared<A: alloc::Alloc> {
+    owner: usize,
+    inner: ManuallyDrop<RefCell<A>>,
+    mailbox: Queue,
+    // queue foreign frees even in debug builds
+    expect_remote: bool,
+}
+
+// `inner` is only touched on the owning thread; see `owned`.
+unsafe impl<A: alloc::Alloc> Send for Shared<A> { }
+unsafe impl<A: alloc::Alloc> Sync for Shared<A> { }
+
+impl<A: alloc::Alloc> Drop for Shared<A> {
+    fn drop(&mut self) {
+        if current_thread() == self.owner {
+            unsafe {
+                drain(&self.inner, &self.mailbox);
+                ManuallyDrop::drop(&mut self.inner);
+            }
+        }
+    }
+}
+
+unsafe fn drain<A: alloc::Alloc>(inner: &RefCell<A>, mailbox: &Queue) -> usize {
+    if mailbox.is_empty() { return 0; }
+    let mut inner = inner.borrow_mut();
+    mailbox.drain(|p, kind| inner.dealloc(p, kind))
+}
+
+/// A handle to an allocator that may only be used from the thread
+/// that created it.
+pub struct Pinned<A: alloc::Alloc> {
+    shared: Arc<Shared<A>>,
+}
+
+impl<A: alloc::Alloc> Clone for Pinned<A> {
+    fn clone(&self) -> Self { Pinned { shared: self.shared.clone() } }
+}
+
+impl<A: alloc::Alloc> Pinned<A> {
+    /// Pins `inner` to the current thread.
+    pub fn new(inner: A) -> Pinned<A> {
+        Pinned::with_policy(inner, false)
+    }
+
+    /// Pins `inner` to the current thread, queueing frees from other
+    /// threads for the owner in debug builds too. Use this for a pool
+    /// whose boxes are sent to other threads and dropped there.
+    pub fn with_remote_free(inner: A) -> Pinned<A> {
+        Pinned::with_policy(inner, true)
+    }
+
+    fn with_policy(inner: A, expect_remote: bool) -> Pinned<A> {
+        Pinned {
+            shared: Arc::new(Shared {
+                owner: current_thread(),
+                inner: ManuallyDrop::new(RefCell::new(inner)),
+                mailbox: Queue::new(),
+                expect_remote: expect_remote,
+            })
+        }
+    }
+
+    /// Whether the current thread owns the allocator.
+    pub fn is_owner(&self) -> bool {
+        current_thread() == self.shared.owner
+    }
+
+    /// Frees every block posted from other threads. Returns how many
+    /// there were.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from a thread other than the owner.
+    pub fn drain_mailbox(&self) -> usize {
+        unsafe { drain(self.owned("drain_mailbox"), &self.shared.mailbox) }
+    }
+
+    /// Number of foreign frees waiting for the owner.
+    pub fn pending_remote_frees(&self) -> usize {
+        self.shared.mailbox.len()
+    }
+
+    // The inner allocator, after checking that this is the owning thread.
+    fn owned(&self, what: &str) -> &RefCell<A> {
+        if !self.is_owner() {
+            panic!("single_thread::Pinned: {} called from a thread that does not own the allocator",
+                   what);
+        }
+        &self.shared.inner
+    }
+
+    unsafe fn owned_and_drained(&self, what: &str) -> &RefCell<A> {
+        let inner = self.owned(what);
+        drain(inner, &self.shared.mailbox);
+        inner
+    }
+
+    unsafe fn remote_dealloc(&self, ptr: Address, kind: Kind) {
+        if cfg!(debug_assertions) && !self.shared.expect_remote {
+            panic!("single_thread::Pinned: block at 0x{:x} ({}) freed on a thread that does \
+                    not own its allocator", ptr as usize, kind);
+        }
+        self.shared.mailbox.push(ptr, kind);
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Pinned<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        self.owned_and_drained("alloc").borrow_mut().alloc(kind)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if self.is_owner() {
+            self.owned_and_drained("dealloc").borrow_mut().dealloc(ptr, kind)
+        } else {
+            self.remote_dealloc(ptr, kind)
+        }
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        if self.is_owner() {
+            self.owned_and_drained("dealloc_hot").borrow_mut().dealloc_hot(ptr, kind)
+        } else {
+            self.remote_dealloc(ptr, kind)
+        }
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.owned("usable_size").borrow().usable_size(kind)
+    }
+
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError {
+        self.owned("alloc_error").borrow().alloc_error(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.owned("grow_in_place").borrow_mut().grow_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.owned("shrink_in_place").borrow_mut().shrink_in_place(ptr, kind, new_size)
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.owned_and_drained("realloc").borrow_mut().realloc(ptr, kind, new_size)
+    }
+
+    fn compatible_with(&self, other: &Self) -> bool {
+        &*self.shared as *const Shared<A> == &*other.shared as *const Shared<A>
+    }
+}
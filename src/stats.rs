@@ -0,0 +1,311 @@
+// A wrapper that keeps statistics about live blocks and reports them.
+//
+// Alongside the running totals it maintains a histogram of live
+// blocks by size class (powers of two), the bytes the inner allocator
+// really reserved for them (`usable_size`), and a breakdown by tag
+// (see `set_tag`, as in `quota`). `report()` snapshots all of it as a
+// `Report`, whose `Display` impl renders a table for dumping at exit
+// or from a debug endpoint; the struct itself is there for programs
+// that want to post-process the numbers.
+//
+// The fragmentation estimate is internal fragmentation only: the
+// fraction of reserved bytes that were not requested. The wrapper
+// cannot see the inner allocator's free lists.
+//
+// For adaptive pooling the wrapper also tracks how fast each size
+// class is being allocated. The program calls `tick()` at whatever
+// interval it sizes pools for (a frame, a request batch, a second);
+// each tick folds the allocations since the previous one into an
+// exponentially decaying average, so the rates follow the workload
+// without being thrown by one burst. `suggest_pool_sizes()` turns the
+// rates into a number of blocks per class worth keeping ready, for a
+// slab or magazine layer to pre-provision from.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, ShareAlloc, Size};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+
+const CLASSES: usize = 64;
+
+/// Live blocks whose size is in `(upper / 2, upper]` (`[0, 1]` for the
+/// first class).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SizeClass {
+    pub upper: usize,
+    pub blocks: usize,
+    pub bytes: usize,
+}
+
+/// The decayed allocation rate of one size class.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClassRate {
+    pub upper: usize,
+    /// Allocations per tick.
+    pub rate: f64,
+}
+
+/// How many blocks of a size class to keep ready for the next tick.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PoolSize {
+    pub upper: usize,
+    pub blocks: usize,
+}
+
+/// Weight of the latest tick in the rates unless `set_decay` says
+/// otherwise: a burst's influence halves with every tick.
+pub const DEFAULT_DECAY: f64 = 0.5;
+
+// Rates below this are the tail of an old burst, not a workload.
+const MIN_SUGGESTED_RATE: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagUsage {
+    pub tag: &'static str,
+    pub blocks: usize,
+    pub bytes: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub live_blocks: usize,
+    pub live_bytes: usize,
+    /// Bytes the inner allocator reserved for the live blocks.
+    pub usable_bytes: usize,
+    pub peak_bytes: usize,
+    pub total_allocs: usize,
+    /// Non-empty size classes, smallest first.
+    pub histogram: Vec<SizeClass>,
+    /// `1 - live_bytes / usable_bytes`, or 0 with nothing live.
+    pub fragmentation: f64,
+    /// Tagged usage, largest first; untagged blocks are not listed.
+    pub tags: Vec<TagUsage>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "live: {} blocks, {} bytes ({} reserved, {:.1}% fragmentation)",
+                      self.live_blocks, self.live_bytes, self.usable_bytes,
+                      self.fragmentation * 100.0));
+        try!(writeln!(f, "peak: {} bytes over {} allocations", self.peak_bytes, self.total_allocs));
+        if !self.histogram.is_empty() {
+            let widest = self.histogram.iter().map(|c| c.blocks).max().unwrap();
+            try!(writeln!(f, "{:>12} {:>8} {:>12}", "size <=", "blocks", "bytes"));
+            for c in &self.histogram {
+                let bar = (c.blocks * 40 + widest - 1) / widest;
+                try!(writeln!(f, "{:>12} {:>8} {:>12} {}", c.upper, c.blocks, c.bytes,
+                              ::std::iter::repeat('#').take(bar).collect::<String>()));
+            }
+        }
+        for t in &self.tags {
+            try!(writeln!(f, "tag {}: {} blocks, {} bytes", t.tag, t.blocks, t.bytes));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Counts {
+    blocks: usize,
+    bytes: usize,
+}
+
+pub struct Alloc<A> {
+    inner: RefCell<A>,
+    live: Cell<Counts>,
+    usable_bytes: Cell<usize>,
+    peak_bytes: Cell<usize>,
+    total_allocs: Cell<usize>,
+    classes: RefCell<[Counts; CLASSES]>,
+    tag: Cell<Option<&'static str>>,
+    tags: RefCell<HashMap<&'static str, Counts>>,
+    block_tags: RefCell<HashMap<usize, &'static str>>,
+    // allocations per class since the last tick, and the decayed rates
+    recent: RefCell<[usize; CLASSES]>,
+    rates: RefCell<[f64; CLASSES]>,
+    decay: Cell<f64>,
+}
+
+fn class_of(size: usize) -> usize {
+    let bits = 0usize.count_zeros() as usize;
+    bits - (size.saturating_sub(1)).leading_zeros() as usize
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A) -> Alloc<A> {
+        Alloc {
+            inner: RefCell::new(inner),
+            live: Cell::new(Counts::default()),
+            usable_bytes: Cell::new(0),
+            peak_bytes: Cell::new(0),
+            total_allocs: Cell::new(0),
+            classes: RefCell::new([Counts::default(); CLASSES]),
+            tag: Cell::new(None),
+            tags: RefCell::new(HashMap::new()),
+            block_tags: RefCell::new(HashMap::new()),
+            recent: RefCell::new([0; CLASSES]),
+            rates: RefCell::new([0.0; CLASSES]),
+            decay: Cell::new(DEFAULT_DECAY),
+        }
+    }
+
+    /// Attributes subsequent allocations to `tag`.
+    pub fn set_tag(&self, tag: Option<&'static str>) { self.tag.set(tag); }
+
+    /// Sets the weight (in `(0, 1]`) the latest tick gets in the rates;
+    /// 1 forgets everything before it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is out of range.
+    pub fn set_decay(&self, weight: f64) {
+        assert!(weight > 0.0 && weight <= 1.0, "stats: decay weight {} not in (0, 1]", weight);
+        self.decay.set(weight);
+    }
+
+    /// Ends the current interval, folding its allocations into the
+    /// per-class rates.
+    pub fn tick(&self) {
+        let w = self.decay.get();
+        let mut recent = self.recent.borrow_mut();
+        let mut rates = self.rates.borrow_mut();
+        for (rate, n) in rates.iter_mut().zip(recent.iter_mut()) {
+            *rate = *rate * (1.0 - w) + *n as f64 * w;
+            *n = 0;
+        }
+    }
+
+    /// Non-zero decayed rates, smallest class first.
+    pub fn class_rates(&self) -> Vec<ClassRate> {
+        self.rates.borrow().iter().enumerate()
+            .filter(|&(_, &r)| r > 0.0)
+            .map(|(i, &r)| ClassRate { upper: 1 << i, rate: r })
+            .collect()
+    }
+
+    /// Blocks per size class to provision for the next tick: the
+    /// decayed rate, rounded up. Classes whose rate has decayed to a
+    /// trickle are left out.
+    pub fn suggest_pool_sizes(&self) -> Vec<PoolSize> {
+        self.class_rates().into_iter()
+            .filter(|c| c.rate >= MIN_SUGGESTED_RATE)
+            .map(|c| PoolSize { upper: c.upper, blocks: c.rate.ceil() as usize })
+            .collect()
+    }
+
+    pub fn report(&self) -> Report {
+        let live = self.live.get();
+        let usable = self.usable_bytes.get();
+        let histogram = self.classes.borrow().iter().enumerate()
+            .filter(|&(_, c)| c.blocks != 0)
+            .map(|(i, c)| SizeClass { upper: 1 << i, blocks: c.blocks, bytes: c.bytes })
+            .collect();
+        let mut tags: Vec<TagUsage> = self.tags.borrow().iter()
+            .filter(|&(_, c)| c.blocks != 0)
+            .map(|(&t, c)| TagUsage { tag: t, blocks: c.blocks, bytes: c.bytes })
+            .collect();
+        tags.sort_by(|a, b| (b.bytes, a.tag).cmp(&(a.bytes, b.tag)));
+        Report {
+            live_blocks: live.blocks,
+            live_bytes: live.bytes,
+            usable_bytes: usable,
+            peak_bytes: self.peak_bytes.get(),
+            total_allocs: self.total_allocs.get(),
+            histogram: histogram,
+            fragmentation: if usable == 0 { 0.0 } else { 1.0 - live.bytes as f64 / usable as f64 },
+            tags: tags,
+        }
+    }
+
+    // Adds (`sign` 1) or removes (`sign` -1) one live block.
+    fn account(&self, p: Address, kind: Kind, sign: isize) {
+        let adjust = |c: &mut Counts, bytes: usize| {
+            c.blocks = (c.blocks as isize + sign) as usize;
+            c.bytes = (c.bytes as isize + sign * bytes as isize) as usize;
+        };
+        let size = kind.size();
+        let mut live = self.live.get();
+        adjust(&mut live, size);
+        self.live.set(live);
+        if live.bytes > self.peak_bytes.get() { self.peak_bytes.set(live.bytes); }
+        let usable = unsafe { self.inner.borrow().usable_size(kind) };
+        self.usable_bytes.set((self.usable_bytes.get() as isize + sign * usable as isize) as usize);
+        adjust(&mut self.classes.borrow_mut()[class_of(size)], size);
+
+        let tag = if sign > 0 {
+            let t = self.tag.get();
+            if let Some(t) = t { self.block_tags.borrow_mut().insert(p as usize, t); }
+            t
+        } else {
+            self.block_tags.borrow_mut().remove(&(p as usize))
+        };
+        if let Some(t) = tag {
+            adjust(self.tags.borrow_mut().entry(t).or_insert(Counts::default()), size);
+        }
+    }
+
+    // Re-accounts the block at `ptr` as one of `new_size` at `p`,
+    // keeping its tag.
+    fn resized(&self, ptr: Address, kind: Kind, p: Address, new_size: Size) {
+        let tag = self.block_tags.borrow().get(&(ptr as usize)).cloned();
+        self.account(ptr, kind, -1);
+        let current = self.tag.get();
+        self.tag.set(tag);
+        let new_kind = unsafe { Kind::from_size_align(new_size, kind.align()) };
+        self.account(p, new_kind, 1);
+        self.tag.set(current);
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        let p = self.inner.borrow_mut().alloc(kind);
+        if !p.is_null() {
+            self.total_allocs.set(self.total_allocs.get() + 1);
+            self.recent.borrow_mut()[class_of(kind.size())] += 1;
+            self.account(p, kind, 1);
+        }
+        p
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        self.account(ptr, kind, -1);
+        self.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    // Only `kind.size()` is accounted for, so that is all a caller may
+    // use; growing into the inner allocator's slack goes through
+    // `grow_in_place`, which re-accounts the block. (The report still
+    // asks the inner allocator for the bytes really reserved.)
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        kind.size()
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        let p = self.inner.borrow_mut().realloc(ptr, kind, new_size);
+        if !p.is_null() { self.resized(ptr, kind, p, new_size); }
+        p
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        try!(self.inner.borrow_mut().grow_in_place(ptr, kind, new_size));
+        self.resized(ptr, kind, ptr, new_size);
+        Ok(())
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        try!(self.inner.borrow_mut().shrink_in_place(ptr, kind, new_size));
+        self.resized(ptr, kind, ptr, new_size);
+        Ok(())
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
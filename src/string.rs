@@ -0,0 +1,78 @@
+// A UTF-8 string whose buffer comes from the allocator `A`: a `Vec<u8,
+// A>` known to hold valid UTF-8. Only what building and reading text
+// needs is provided; everything else comes from `Deref<Target=str>`.
+
+use alloc::{Alloc, DefaultAlloc};
+use vec::Vec;
+
+use std::fmt;
+use std::ops::Deref;
+use std::str;
+
+pub struct String<A:Alloc = DefaultAlloc> {
+    vec: Vec<u8, A>,
+}
+
+impl<A:Alloc> String<A> {
+    pub fn new() -> Self where A: Default {
+        String::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        String { vec: Vec::with_alloc(a) }
+    }
+
+    pub fn from_str_in(s: &str, a: A) -> Self {
+        let mut string = String::with_alloc(a);
+        string.push_str(s);
+        string
+    }
+
+    /// Takes over `bytes` if it is valid UTF-8, giving it back if not.
+    pub fn from_utf8(bytes: Vec<u8, A>) -> Result<Self, Vec<u8, A>> {
+        if str::from_utf8(&bytes).is_ok() { Ok(String { vec: bytes }) } else { Err(bytes) }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.vec) }
+    }
+
+    pub fn capacity(&self) -> usize { self.vec.capacity() }
+
+    pub fn clear(&mut self) { self.vec.clear() }
+
+    pub fn alloc(&self) -> &A { self.vec.alloc() }
+
+    pub fn into_bytes(self) -> Vec<u8, A> { self.vec }
+
+    /// Copies the text into a buffer from `b`; see `Vec::rehome_in`.
+    pub fn rehome_in<B:Alloc>(self, b: B) -> String<B> {
+        String { vec: self.vec.rehome_in(b) }
+    }
+}
+
+impl<A:Alloc> Deref for String<A> {
+    type Target = str;
+
+    fn deref(&self) -> &str { self.as_str() }
+}
+
+impl<A:Alloc> fmt::Display for String<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<A:Alloc> fmt::Debug for String<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a, A:Alloc> PartialEq<&'a str> for String<A> {
+    fn eq(&self, other: &&'a str) -> bool { self.as_str() == *other }
+}
@@ -0,0 +1,115 @@
+// A bump allocator that many threads can allocate from at once.
+//
+// The cursor is an `AtomicUsize`. Requests are rounded up to
+// `MIN_ALIGN` and claimed with a compare-and-swap loop that only moves
+// the cursor when the block fits, so a failed request leaves it where
+// it was (a blind `fetch_add` would keep pushing it on every failure
+// until it wrapped). `dealloc` does nothing: memory comes back only
+// through `reset`, which takes `&mut self` and so cannot race with
+// allocation (nor outlive any borrowed handle).
+//
+// Once the region is exhausted every request that does not fit in
+// what is left fails with null, until `reset`. As a
+// per-frame arena it is driven by `compact`, which resets after each
+// frame once the values that outlive it have been moved elsewhere.
+//
+// Share it with `&sync_bump::Alloc` or `Arc<sync_bump::Alloc>`; both
+// are allocators via `ShareAlloc`.
+
+use alloc::{self, Address, Capacity, DefaultAlloc, Kind, ShareAlloc, Size};
+
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MIN_ALIGN: usize = 16;
+
+pub struct Alloc<A: alloc::Alloc = DefaultAlloc> {
+    inner: A,
+    base: Address,
+    len: usize,
+    // offset of the next free byte; never past `len`
+    cursor: AtomicUsize,
+}
+
+unsafe impl<A: alloc::Alloc + Send> Send for Alloc<A> { }
+unsafe impl<A: alloc::Alloc + Send> Sync for Alloc<A> { }
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Obtains a `len`-byte region from `inner`.
+    ///
+    /// Aborts via `oom` if `inner` cannot provide it.
+    pub fn new(mut inner: A, len: usize) -> Alloc<A> {
+        unsafe {
+            let base = inner.alloc(Kind::from_size_align(len, MIN_ALIGN));
+            if base.is_null() { inner.oom() }
+            Alloc { inner: inner, base: base, len: len, cursor: AtomicUsize::new(0) }
+        }
+    }
+
+    /// Bytes handed out so far, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    /// Frees every block at once.
+    pub fn reset(&mut self) {
+        self.cursor.store(0, Ordering::Relaxed);
+    }
+
+    /// Runs one frame of work in the arena and then resets it. `f`
+    /// allocates through the handle it is given; whatever it returns
+    /// must not borrow that handle, so survivors have to be evacuated
+    /// first (with `rehome_in` on boxes, vectors and strings), and the
+    /// reset never pulls memory out from under a live value.
+    pub fn compact<R, F>(&mut self, f: F) -> R where F: for<'f> FnOnce(&'f Self) -> R {
+        let r = f(self);
+        self.reset();
+        r
+    }
+}
+
+impl<A: alloc::Alloc> Drop for Alloc<A> {
+    fn drop(&mut self) {
+        unsafe { self.inner.dealloc(self.base, Kind::from_size_align(self.len, MIN_ALIGN)); }
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        let size = match kind.size().checked_add(MIN_ALIGN - 1) {
+            Some(s) => s & !(MIN_ALIGN - 1),
+            None => return ptr::null_mut(),
+        };
+        if size > self.len { return ptr::null_mut(); }
+        // `base` and the cursor are multiples of `MIN_ALIGN`, so only
+        // over-aligned requests need padding.
+        let align = ::std::cmp::max(kind.align(), MIN_ALIGN);
+        let mut cur = self.cursor.load(Ordering::Relaxed);
+        loop {
+            let start = match (self.base as usize + cur).checked_add(align - 1) {
+                Some(s) => (s & !(align - 1)) - self.base as usize,
+                None => return ptr::null_mut(),
+            };
+            if start > self.len || self.len - start < size { return ptr::null_mut(); }
+            let prev = self.cursor.compare_and_swap(cur, start + size, Ordering::Relaxed);
+            if prev == cur { return self.base.offset(start as isize); }
+            cur = prev;
+        }
+    }
+
+    unsafe fn dealloc_shared(&self, _ptr: Address, _kind: Kind) { }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        (kind.size() + MIN_ALIGN - 1) & !(MIN_ALIGN - 1)
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
@@ -0,0 +1,209 @@
+// Tools for testing `Alloc` implementations.
+
+use alloc::{Alloc, Address, Kind};
+
+use std::cmp;
+
+/// A small xorshift generator, so fuzzing is reproducible from a seed
+/// without pulling in a dependency.
+#[derive(Clone, Debug)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        XorShift(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// A value in `0..n` (`n` must be nonzero).
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// What a fuzzing run did.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuzzStats {
+    pub allocs: usize,
+    pub reallocs: usize,
+    pub deallocs: usize,
+    pub failed: usize,
+    pub max_live: usize,
+}
+
+struct Block {
+    ptr: Address,
+    kind: Kind,
+    tag: u8,
+}
+
+fn pattern(tag: u8, i: usize) -> u8 { tag.wrapping_mul(31).wrapping_add(i as u8) }
+
+unsafe fn fill(b: &Block) {
+    for i in 0..b.kind.size() { *b.ptr.offset(i as isize) = pattern(b.tag, i); }
+}
+
+unsafe fn check(b: &Block, len: usize, op: usize, seed: u64) {
+    for i in 0..len {
+        let got = *b.ptr.offset(i as isize);
+        if got != pattern(b.tag, i) {
+            panic!("fuzz_alloc(seed={}): op {}: block at {:p} ({}) corrupted at byte {}: \
+                    expected 0x{:02x}, found 0x{:02x}",
+                   seed, op, b.ptr, b.kind, i, pattern(b.tag, i), got);
+        }
+    }
+}
+
+fn random_kind(rng: &mut XorShift) -> Kind {
+    let align = 1 << rng.below(6);
+    let size = match rng.below(4) {
+        0 => rng.below(16),
+        1 => rng.below(256),
+        2 => rng.below(4096),
+        _ => rng.below(64 * 1024),
+    };
+    unsafe { Kind::from_size_align(size, align) }
+}
+
+/// Runs `ops` random alloc/realloc/dealloc operations against `alloc`,
+/// keeping every live block filled with a per-block byte pattern and
+/// checking the pattern (and the block's alignment) before each
+/// realloc and dealloc. Panics, naming the seed, on any corruption.
+///
+/// All blocks still live at the end are checked and freed. Failed
+/// (null) allocations are tolerated and counted.
+pub fn fuzz_alloc<A: Alloc>(alloc: &mut A, ops: usize, seed: u64) -> FuzzStats {
+    let mut rng = XorShift::new(seed);
+    let mut live: Vec<Block> = Vec::new();
+    let mut stats = FuzzStats::default();
+    let mut next_tag = 0u8;
+
+    for op in 0..ops {
+        let choice = if live.is_empty() { 0 } else { rng.below(3) };
+        unsafe {
+            match choice {
+                0 => {
+                    let kind = random_kind(&mut rng);
+                    let p = alloc.alloc(kind);
+                    if p.is_null() { stats.failed += 1; continue; }
+                    assert!(p as usize % kind.align() == 0,
+                            "fuzz_alloc(seed={}): op {}: {:p} misaligned for {}",
+                            seed, op, p, kind);
+                    next_tag = next_tag.wrapping_add(1);
+                    let b = Block { ptr: p, kind: kind, tag: next_tag };
+                    fill(&b);
+                    live.push(b);
+                    stats.allocs += 1;
+                }
+                1 => {
+                    let i = rng.below(live.len());
+                    let new_size = random_kind(&mut rng).size();
+                    let (old_kind, tag) = (live[i].kind, live[i].tag);
+                    check(&live[i], old_kind.size(), op, seed);
+                    let p = alloc.realloc(live[i].ptr, old_kind, new_size);
+                    if p.is_null() { stats.failed += 1; continue; }
+                    let b = Block { ptr: p, kind: Kind::from_size_align(new_size, old_kind.align()),
+                                    tag: tag };
+                    check(&b, cmp::min(old_kind.size(), new_size), op, seed);
+                    fill(&b);
+                    live[i] = b;
+                    stats.reallocs += 1;
+                }
+                _ => {
+                    let i = rng.below(live.len());
+                    let b = live.swap_remove(i);
+                    check(&b, b.kind.size(), op, seed);
+                    alloc.dealloc(b.ptr, b.kind);
+                    stats.deallocs += 1;
+                }
+            }
+        }
+        stats.max_live = cmp::max(stats.max_live, live.len());
+    }
+
+    for b in live.drain(..) {
+        unsafe {
+            check(&b, b.kind.size(), ops, seed);
+            alloc.dealloc(b.ptr, b.kind);
+        }
+        stats.deallocs += 1;
+    }
+    stats
+}
+
+/// The first address `VirtualAlloc` hands out.
+pub const VIRTUAL_BASE: usize = 0x1000_0000;
+
+/// An allocator that hands out deterministic, fake addresses.
+///
+/// Addresses come from a counter starting at `VIRTUAL_BASE`, rounded
+/// up to each request's alignment, so a given sequence of requests
+/// yields the same addresses on every run and platform. That makes
+/// golden-output tests of code that only *records* addresses (the
+/// `observed` and `stats` wrappers, traces, placement logic) stable.
+///
+/// The addresses point at no memory and must never be dereferenced.
+/// Each block's contents live in a map inside the allocator instead,
+/// reachable through `read`/`write`; `realloc` carries them over.
+pub struct VirtualAlloc {
+    next: usize,
+    blocks: ::std::collections::BTreeMap<usize, ::std::vec::Vec<u8>>,
+}
+
+impl VirtualAlloc {
+    pub fn new() -> VirtualAlloc {
+        VirtualAlloc { next: VIRTUAL_BASE, blocks: ::std::collections::BTreeMap::new() }
+    }
+
+    /// Number of blocks currently allocated.
+    pub fn live_count(&self) -> usize { self.blocks.len() }
+
+    /// The contents of the block at `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no block starts at `addr`.
+    pub fn read(&self, addr: Address) -> &[u8] {
+        &self.blocks.get(&(addr as usize)).expect("VirtualAlloc: no block at address")[..]
+    }
+
+    pub fn write(&mut self, addr: Address, offset: usize, bytes: &[u8]) {
+        let b = self.blocks.get_mut(&(addr as usize)).expect("VirtualAlloc: no block at address");
+        b[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+impl Alloc for VirtualAlloc {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        if kind.size() == 0 { return kind.dangling(); }
+        let start = (self.next + kind.align() - 1) & !(kind.align() - 1);
+        self.next = start + kind.size();
+        self.blocks.insert(start, vec![0; kind.size()]);
+        start as Address
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        let b = self.blocks.remove(&(ptr as usize)).expect("VirtualAlloc: dealloc of unknown block");
+        assert_eq!(b.len(), kind.size(), "VirtualAlloc: dealloc with the wrong size");
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: usize) -> Address {
+        // A zero-sized block is only a dangling address; start afresh.
+        if kind.size() == 0 { return self.alloc(Kind::from_size_align(new_size, kind.align())); }
+        let mut contents = self.blocks.remove(&(ptr as usize))
+            .expect("VirtualAlloc: realloc of unknown block");
+        let new_ptr = self.alloc(Kind::from_size_align(new_size, kind.align()));
+        contents.resize(new_size, 0);
+        if new_size != 0 { self.blocks.insert(new_ptr as usize, contents); }
+        new_ptr
+    }
+}
@@ -27,6 +27,7 @@ use super::direct_alloc;
 use std::rc::Rc;
 use std::cell::Cell;
 use std::marker::PhantomData;
+use std::ptr::Unique;
 
 const MIN_ALIGN: u32 = 16;
 const MAX_LEN: u32 = 4 * 1024 * 1024;
@@ -43,21 +44,17 @@ pub struct Alloc<'a> {
     _a: PhantomData<Fn() -> &'a Alloc<'a>>,
 }
 
-impl<'a> Drop for Alloc<'a> {
-    fn drop(&mut self) {
-        println!("  bump_alloc::Alloc::drop: 0x{:x}", self as *mut Alloc as usize);
-    }
-}
-
 impl Drop for AllocState {
     fn drop(&mut self) {
-        println!("    bump_alloc::AllocState::drop: 0x{:x}", self as *mut _ as usize);
+        unsafe {
+            let len = self.limit as usize - self.block as usize;
+            direct_alloc::Alloc.dealloc_array(Unique::new(self.block), len).unwrap();
+        }
     }
 }
 
 impl<'a> Alloc<'a> {
     pub fn new(len: u32) -> Alloc<'a> {
-        println!("  bump_alloc::Alloc::new bump len: {:?}", len);
         if len > MAX_LEN {
             panic!("cannot make bump_alloc len={}; max is {}",
                    len, MAX_LEN);
@@ -83,7 +80,6 @@ fn roundup_size(size: i32) -> i32 {
 impl<'a> alloc::Alloc for Alloc<'a> {
     #[inline]
     unsafe fn alloc(&mut self, kind: alloc::Kind) -> alloc::Address {
-        println!("  bump_alloc::Alloc::alloc bump kind: {:?}", kind);
         if kind.align() <= MIN_ALIGN as usize {
             let size = roundup_size((kind.size() + 4) as i32);
             if self.state.cursor.get() < self.state.limit.offset(-size as isize) {
@@ -91,19 +87,15 @@ impl<'a> alloc::Alloc for Alloc<'a> {
                 let n = p.offset(size as isize);
                 self.state.cursor.set(n);
                 *(n.offset(-4) as *mut i32) = size;
-                println!("  alloc bump kind: {:?} => {:p}", kind, p);
                 return p;
             }
         }
-        let p = direct_alloc::Alloc.alloc(kind); 
-        println!("  alloc delg kind: {:?} => {:p}", kind, p);
-        return p;
+        direct_alloc::Alloc.alloc(kind)
     }
 
     #[inline]
     unsafe fn dealloc(&mut self, ptr: alloc::Address, kind: alloc::Kind) {
         if kind.align() <= MIN_ALIGN as usize {
-            println!("dealloc bump ptr {:p} kind: {:?}", ptr, kind);
             let size = roundup_size((kind.size() + 4) as i32);
             let next = ptr.offset(size as isize);
             let entry_size = next.offset(-4) as *mut i32;
@@ -128,11 +120,30 @@ impl<'a> alloc::Alloc for Alloc<'a> {
             self.state.cursor.set(back);
             return;
         } else {
-            println!("dealloc delg ptr {:p} kind: {:?}", ptr, kind);
             return direct_alloc::Alloc.dealloc(ptr, kind);
         }
     }
 
+    unsafe fn grow_in_place(&mut self,
+                            ptr: alloc::Address,
+                            kind: alloc::Kind,
+                            new_size: alloc::Size) -> Result<(), alloc::AllocError> {
+        // Only the most recent entry can grow, by pushing the cursor.
+        if kind.align() > MIN_ALIGN as usize { return Err(alloc::AllocError::Exhausted); }
+        let size = roundup_size((kind.size() + 4) as i32);
+        if ptr.offset(size as isize) != self.state.cursor.get() {
+            return Err(alloc::AllocError::Exhausted);
+        }
+        let new_entry = roundup_size((new_size + 4) as i32);
+        if ptr.offset(new_entry as isize) >= self.state.limit {
+            return Err(alloc::AllocError::Exhausted);
+        }
+        let n = ptr.offset(new_entry as isize);
+        self.state.cursor.set(n);
+        *(n.offset(-4) as *mut i32) = new_entry;
+        Ok(())
+    }
+
     unsafe fn realloc(&mut self,
                       ptr: alloc::Address,
                       kind: alloc::Kind,
@@ -3,22 +3,24 @@ use alloc_crate::raw_vec::RawVec;
 
 use std::mem;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Alloc;
 
+unsafe impl alloc::StatelessAlloc for Alloc { }
+
 impl alloc::Alloc for Alloc {
+    fn compatible_with(&self, _other: &Self) -> bool { true }
+
     #[inline]
     unsafe fn alloc(&mut self, kind: alloc::Kind) -> alloc::Address {
         // TODO: ensure alignment too
         let data: RawVec<u8> = RawVec::with_capacity(kind.size());
         let p = data.ptr();
-        // println!("  alloc kind: {:?} => {:p}", kind, p);
         mem::forget(data);
         p
     }
     #[inline]
     unsafe fn dealloc(&mut self, ptr: alloc::Address, kind: alloc::Kind) {
-        // println!("dealloc ptr {:p} kind: {:?}", ptr, kind);
         drop(RawVec::from_raw_parts(ptr, kind.size()))
     }
 }
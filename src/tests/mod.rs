@@ -1,3 +1,11 @@
+// The suite also runs under MIRI (`cargo miri test`). Tests that call
+// into the OS or a foreign allocator, which MIRI cannot execute, are
+// marked `#[cfg_attr(miri, ignore)]`. For a quick CI run, the `miri_`
+// tests cover the unsafe core (RawVec, Box, Boxing, RawBuf, RawTable) on
+// their own:
+//
+//     cargo miri test miri_
+
 use alloc::Alloc as AllocTrait;
 
 mod direct_alloc;
@@ -6,34 +14,2620 @@ mod bump_alloc;
 
 use boxing::Boxing;
 
+// The demos log their allocator calls to standard error through
+// `observed::Stderr`.
+
 #[test]
 fn demo_direct_in_place() {
-    let std = direct_alloc::Alloc;
+    use observed;
+    let std = observed::Alloc::new(direct_alloc::Alloc, observed::Stderr);
     let b = in Boxing(std) { 3 };
-    println!("at end of demo_direct b: {:?}", b);}
+    assert_eq!(*b, 3);
+}
 
 #[test]
 fn demo_bump_calls() {
+    use observed;
     use std::ptr::Unique;
-    let mut bmp = bump_alloc::Alloc::new(4*1024*1024);
+    let mut bmp = observed::Alloc::new(bump_alloc::Alloc::new(4*1024*1024), observed::Stderr);
     let p: Unique<u32>;
     unsafe {
         p = bmp.alloc_one().unwrap();
         **p = 3;
-        println!("at end of demo_bump_calls *p: {:?}", *p);
+        assert_eq!(**p, 3);
         bmp.dealloc_one(p);
     }
 }
 
 #[test]
 fn demo_bump_in_place() {
+    use observed;
+    let bmp = observed::Alloc::new(bump_alloc::Alloc::new(4*1024*1024), observed::Stderr);
+    let b = in Boxing(bmp) { 3 };
+    assert_eq!(*b, 3);
+}
+
+#[test]
+fn demo_collect_in() {
+    use iter::IteratorExt;
+    let v = (0..10).map(|x| x * 2).collect_in(direct_alloc::Alloc);
+    assert_eq!(&v[..], &[0, 2, 4, 6, 8, 10, 12, 14, 16, 18]);
+    let b = v.iter().cloned().collect_box_slice_in(direct_alloc::Alloc);
+    assert_eq!(b.len(), 10);
+    assert_eq!(b[9], 18);
+}
+
+#[test]
+fn demo_box_new_in() {
+    use boxed::Box;
+    let b = Box::new_in(17u64, direct_alloc::Alloc);
+    assert_eq!(*b, 17);
+    let u = Box::<u32, _>::new_uninit_in(bump_alloc::Alloc::new(1024));
+    let b = u.write(5);
+    assert_eq!(*b, 5);
+}
+
+#[test]
+fn demo_pin_in() {
+    use boxed::Box;
+    use pinned::PhantomPinned;
+    struct SelfRef { data: u32, ptr: *const u32, _pin: PhantomPinned }
+    let mut p = Box::pin_in(SelfRef { data: 7, ptr: 0 as *const u32, _pin: PhantomPinned },
+                            direct_alloc::Alloc);
+    unsafe {
+        let r = p.get_mut_unchecked();
+        r.ptr = &r.data;
+    }
+    assert_eq!(unsafe { *p.ptr }, 7);
+}
+
+#[test]
+fn demo_unsize_coercions() {
+    use boxed::Box;
+    use std::fmt::Debug;
+    let b: Box<[u16], _> = Box::new_in([1u16, 2, 3, 4], direct_alloc::Alloc);
+    assert_eq!(b.len(), 4);
+    let d: Box<Debug, _> = Box::new_in(String::from("dyn"), bump_alloc::Alloc::new(1024));
+    assert_eq!(format!("{:?}", d), "\"dyn\"");
+}
+
+#[test]
+fn demo_alloc_one_init() {
+    let mut a = direct_alloc::Alloc;
+    let b = a.alloc_one_init(String::from("owned")).ok().unwrap();
+    assert_eq!(&**b, "owned");
+}
+
+#[test]
+fn zero_size_round_trips() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use raw_vec::RawVec;
+    let mut v: RawVec<u32, DefaultAlloc> = RawVec::with_capacity(0);
+    v.reserve(0, 8);
+    assert!(v.cap() >= 8);
+    v.shrink_to_fit(0);
+    assert_eq!(v.cap(), 0);
+    v.reserve_exact(0, 3);
+    assert_eq!(v.cap(), 3);
+
+    let zst: RawVec<(), DefaultAlloc> = RawVec::with_capacity(10);
+    drop(zst);
+
+    unsafe {
+        let mut a = DefaultAlloc;
+        let k = Kind::new::<u64>().array(0);
+        let p = a.alloc(k);
+        let p = a.realloc(p, k, 64);
+        assert!(!p.is_null());
+        let p = a.realloc(p, Kind::new::<u64>().array(8), 0);
+        a.dealloc(p, k);
+    }
+}
+
+#[test]
+fn leakcheck_reports_outstanding() {
+    use boxed::Box;
+    use leakcheck;
+    fn ignore(_: &[leakcheck::LeakRecord]) { }
+
+    let lc = leakcheck::Alloc::new(direct_alloc::Alloc);
+    lc.set_drop_hook(ignore);
+    {
+        let b = Box::new_in(1u32, lc.clone());
+        assert_eq!(lc.live_count(), 1);
+        drop(b);
+    }
+    assert!(lc.leaks().is_empty());
+
+    lc.set_label(Some("forgotten"));
+    ::std::mem::forget(Box::new_in([0u8; 24], lc.clone()));
+    let leaks = lc.leaks();
+    assert_eq!(leaks.len(), 1);
+    assert_eq!(leaks[0].kind.size(), 24);
+    assert_eq!(leaks[0].label, Some("forgotten"));
+}
+
+#[test]
+#[should_panic(expected = "already freed")]
+fn verify_catches_double_free() {
+    use alloc::{Alloc, Kind};
+    use verify;
+    let mut v = verify::Alloc::new(direct_alloc::Alloc);
+    unsafe {
+        let k = Kind::new::<[u64; 4]>();
+        let p = v.alloc(k);
+        v.dealloc(p, k);
+        v.dealloc(p, k);
+    }
+}
+
+#[test]
+#[should_panic(expected = "but it was allocated with")]
+fn verify_catches_wrong_kind() {
+    use alloc::{Alloc, Kind};
+    use verify;
+    let mut v = verify::Alloc::new(direct_alloc::Alloc);
+    unsafe {
+        let p = v.alloc(Kind::new::<[u64; 4]>());
+        v.dealloc(p, Kind::new::<[u64; 2]>());
+    }
+}
+
+#[test]
+fn segregated_routes_by_size() {
+    use alloc::{Alloc, Kind};
+    use segregated::{self, Tier};
+    use verify;
+    let (s, m, l) = (verify::Alloc::new(direct_alloc::Alloc),
+                     verify::Alloc::new(direct_alloc::Alloc),
+                     verify::Alloc::new(direct_alloc::Alloc));
+    let mut seg = segregated::Alloc::with_thresholds(s.clone(), m.clone(), l.clone(), 16, 128);
+    unsafe {
+        let k = Kind::new::<[u8; 8]>();
+        assert_eq!(seg.tier_for(k), Tier::Small);
+        let p = seg.alloc(k);
+        assert_eq!(s.live_count(), 1);
+        let p = seg.realloc(p, k, 100);
+        assert_eq!((s.live_count(), m.live_count()), (0, 1));
+        let p = seg.realloc(p, Kind::from_size_align(100, 1), 1000);
+        assert_eq!((m.live_count(), l.live_count()), (0, 1));
+        seg.dealloc(p, Kind::from_size_align(1000, 1));
+        assert_eq!(l.live_count(), 0);
+    }
+}
+
+#[test]
+fn global_adapter_counts() {
+    use alloc::DefaultAlloc;
+    use global::GlobalAdapter;
+    static G: GlobalAdapter<DefaultAlloc> = GlobalAdapter::new(DefaultAlloc);
+    unsafe {
+        let p = G.allocate(32, 8);
+        let p = G.reallocate(p, 32, 64, 8);
+        assert_eq!(G.stats().snapshot().live_bytes, 64);
+        assert!(G.reallocate_inplace(p, 64, 64, 8) >= 64);
+        assert_eq!(G.stats().snapshot().live_bytes, 64);
+        G.deallocate(p, 64, 8);
+    }
+    let s = G.stats().snapshot();
+    assert_eq!((s.allocs, s.reallocs, s.deallocs, s.live_bytes), (1, 1, 1, 0));
+}
+
+#[test]
+fn ffi_shim_round_trip() {
+    use ffi;
+    use alloc::DefaultAlloc;
+    static mut HEAP: DefaultAlloc = DefaultAlloc;
+    let vt = ffi::c_api_for(unsafe { &mut HEAP });
+    unsafe {
+        let p = (vt.malloc)(vt.ctx, 10) as *mut u8;
+        assert_eq!(p as usize % ffi::MAX_ALIGN, 0);
+        for i in 0..10 { *p.offset(i) = i as u8; }
+        let p = (vt.realloc)(vt.ctx, p as *mut _, 1000) as *mut u8;
+        assert_eq!(*p.offset(9), 9);
+        (vt.free)(vt.ctx, p as *mut _);
+    }
+}
+
+#[test]
+fn std_interop() {
+    use boxed::Box;
+    use vec::Vec;
+    let b = Box::from_std(::std::boxed::Box::new(41u32));
+    assert_eq!(*b.into_std(), 41);
+
+    let v = Vec::from_std(vec![1, 2, 3]);
+    let mut s = v.into_std();
+    s.push(4);
+    assert_eq!(s, [1, 2, 3, 4]);
+}
+
+#[test]
+fn shared_handles_back_many_boxes() {
+    use alloc::{self, Address, DefaultAlloc, Kind, ShareAlloc};
+    use boxed::Box;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Counting { live: Cell<usize> }
+    impl ShareAlloc for Counting {
+        unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+            self.live.set(self.live.get() + 1);
+            alloc::Alloc::alloc(&mut DefaultAlloc, kind)
+        }
+        unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+            self.live.set(self.live.get() - 1);
+            alloc::Alloc::dealloc(&mut DefaultAlloc, ptr, kind)
+        }
+    }
+
+    let arena = Counting { live: Cell::new(0) };
+    {
+        let a = Box::new_in(1u64, &arena);
+        let b = Box::new_in(2u64, &arena);
+        assert_eq!((*a + *b, arena.live.get()), (3, 2));
+    }
+    assert_eq!(arena.live.get(), 0);
+
+    let shared = Rc::new(arena);
+    let c = Box::new_in([0u8; 16], shared.clone());
+    assert_eq!(shared.live.get(), 1);
+    drop(c);
+    assert_eq!(shared.live.get(), 0);
+}
+
+#[test]
+fn empty_raw_vec_is_aligned() {
+    use alloc::DefaultAlloc;
+    use raw_vec::RawVec;
+    let v: RawVec<u64, DefaultAlloc> = RawVec::with_alloc(DefaultAlloc);
+    assert_eq!(v.ptr() as usize % ::std::mem::align_of::<u64>(), 0);
+    let z: RawVec<[u64; 0], DefaultAlloc> = RawVec::with_capacity(4);
+    assert!(!z.ptr().is_null());
+    let mut z: RawVec<[u64; 0], DefaultAlloc> = RawVec::with_alloc(DefaultAlloc);
+    z.shrink_exact(3);
+    assert_eq!(z.ptr() as usize % ::std::mem::align_of::<u64>(), 0);
+}
+
+#[test]
+fn vec_spare_capacity() {
+    use vec::Vec;
+    let mut v: Vec<u8, _> = Vec::with_capacity_alloc(16, direct_alloc::Alloc);
+    v.push(1);
+    {
+        let spare = v.spare_capacity_mut();
+        assert!(spare.len() >= 15);
+        spare[0].write(2);
+        spare[1].write(3);
+    }
+    unsafe { let n = v.len(); v.set_len(n + 2); }
+    assert_eq!(&v[..], &[1, 2, 3]);
+}
+
+#[test]
+fn purgeable_blocks() {
+    use alloc::Kind;
+    use purgeable;
+    let mut p = purgeable::Alloc::new(direct_alloc::Alloc);
+    let k = Kind::new::<[u8; 64]>();
+    let a = p.alloc_purgeable(k).unwrap();
+    let b = p.alloc_purgeable(k).unwrap();
+    p.set_discardable(b, false);
+    p.try_pin(a).unwrap()[0] = 7;
+    assert_eq!(p.purge(), 64);
+    assert!(p.try_pin(a).is_none());
+    assert_eq!(p.try_pin(b).unwrap().len(), 64);
+
+    p.set_budget(Some(100));
+    p.set_discardable(b, true);
+    let c = p.alloc_purgeable(k).unwrap();
+    assert!(p.is_purged(b));
+    p.free(c);
+    assert!(p.try_pin(c).is_none());
+}
+
+#[test]
+fn purgeable_budget_purges_oldest_first() {
+    use alloc::Kind;
+    use purgeable;
+    let mut p = purgeable::Alloc::new(direct_alloc::Alloc);
+    let k = Kind::new::<[u8; 64]>();
+    p.set_budget(Some(200));
+    let a = p.alloc_purgeable(k).unwrap();
+    let b = p.alloc_purgeable(k).unwrap();
+    let c = p.alloc_purgeable(k).unwrap();
+    // `a`'s slot is reused by `d`, so slot order is not age order.
+    p.free(a);
+    let d = p.alloc_purgeable(k).unwrap();
+    let e = p.alloc_purgeable(k).unwrap();
+    assert!(p.is_purged(b));
+    assert!(!p.is_purged(c) && !p.is_purged(d) && !p.is_purged(e));
+    assert_eq!(p.purgeable_bytes(), 192);
+}
+
+#[test]
+fn objpool_recycles() {
+    use objpool::Pool;
+    let pool = Pool::new(direct_alloc::Alloc, || Vec::<u8>::with_capacity(32), |v| v.clear());
+    let first_ptr = {
+        let mut buf = pool.get();
+        buf.extend_from_slice(b"hello");
+        buf.as_ptr()
+    };
+    assert_eq!(pool.idle_count(), 1);
+    let buf = pool.get();
+    assert!(buf.is_empty());
+    assert_eq!(buf.as_ptr(), first_ptr);
+    let kept = buf.detach();
+    assert_eq!(kept.capacity(), 32);
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[test]
+fn objpool_constructor_may_trigger_a_trim() {
+    use alloc::DefaultAlloc;
+    use objpool::Pool;
+    use pressure::{self, Level};
+    use std::rc::Rc;
+
+    // The trim reaches this very pool while it is constructing.
+    let pool = Rc::new(Pool::new(DefaultAlloc, || { pressure::release(Level::Critical); 7u32 },
+                                 |_| ()));
+    pressure::register(&pool);
+    let a = pool.get();
+    let b = pool.get();
+    assert_eq!((*a, *b), (7, 7));
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn mmap_file_persists() {
+    use alloc::Alloc;
+    use mmap_file;
+    use std::env;
+    use std::fs;
+
+    let path = env::temp_dir().join("allocoll-mmap-file-test.arena");
+    let _ = fs::remove_file(&path);
+    {
+        let mut arena = mmap_file::Alloc::open(&path, 4096).unwrap();
+        unsafe {
+            let p = *arena.alloc_array::<u32>(4).unwrap();
+            for i in 0..4 { *p.offset(i) = 10 * i as u32; }
+            arena.set_root(p);
+        }
+        arena.flush().unwrap();
+    }
+    {
+        let arena = mmap_file::Alloc::open(&path, 0).unwrap();
+        let p: *mut u32 = arena.root().unwrap();
+        assert_eq!(unsafe { *p.offset(3) }, 30);
+        assert!(arena.high_water_mark() >= 16);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rel_ptr_survives_relocation() {
+    use offset_ptr::{RelPtr, RelVec};
+    use std::ptr;
+
+    #[repr(C)]
+    struct Node { value: u32, next: RelPtr<u32> }
+
+    let mut region = [0u64; 8];
+    let mut moved = [0u64; 8];
+    unsafe {
+        let node = region.as_mut_ptr() as *mut Node;
+        let target = region.as_mut_ptr().offset(4) as *mut u32;
+        *target = 99;
+        ptr::write(node, Node { value: 1, next: RelPtr::null() });
+        (*node).next.set(target);
+        ptr::copy_nonoverlapping(region.as_ptr(), moved.as_mut_ptr(), 8);
+        let node = moved.as_ptr() as *const Node;
+        assert_eq!(*(*node).next.get(), 99);
+    }
+
+    let mut a = direct_alloc::Alloc;
+    let mut v = RelVec::empty();
+    for i in 0..10u16 { v.push_in(i, &mut a).ok().unwrap(); }
+    assert_eq!(v.as_slice()[9], 9);
+    v.free_in(&mut a);
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn shm_two_mappings_share_heap() {
+    use alloc::{Alloc, Kind};
+    use shm;
+    let name = "/allocoll-shm-test";
+    let _ = shm::Alloc::unlink(name);
+    let mut a = shm::Alloc::create(name, 64 * 1024).unwrap();
+    let mut b = shm::Alloc::open(name).unwrap();
+    unsafe {
+        let k = Kind::new::<[u64; 8]>();
+        let p = a.alloc(k) as *mut u64;
+        *p = 1234;
+        a.set_root(p);
+        let q: *mut u64 = b.root().unwrap();
+        assert_eq!(*q, 1234);
+        let r = b.alloc(k);
+        assert!(r as usize != q as usize);
+        b.dealloc(q as *mut u8, k);
+        a.dealloc(a.at_offset::<u8>(b.offset_of(r)), k);
+        // everything coalesced back into one block
+        let big = a.alloc(Kind::from_size_align(60 * 1024, 8));
+        assert!(!big.is_null());
+    }
+    shm::Alloc::unlink(name).unwrap();
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn numa_wrapper_passes_through() {
+    use numa::{self, Policy};
+    use vec::Vec;
+    let a = numa::Alloc::new(direct_alloc::Alloc, Policy::Bind(0));
+    let mut v: Vec<u64, _> = Vec::with_alloc(a);
+    for i in 0..10000 { v.push(i); }
+    assert_eq!(v[9999], 9999);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn numa_accepts_nodes_past_the_first_mask_word() {
+    use numa::{self, Policy};
+    use vec::Vec;
+    // No such node here: the bind fails and the memory stays usable.
+    let a = numa::Alloc::new(direct_alloc::Alloc, Policy::Preferred(200));
+    let mut v: Vec<u64, _> = Vec::with_alloc(a);
+    for i in 0..10000 { v.push(i); }
+    assert_eq!(v[9999], 9999);
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn hugepage_falls_back() {
+    use hugepage::{self, PageSize};
+    use raw_vec::RawVec;
+    let a = hugepage::Alloc::with_threshold(direct_alloc::Alloc, PageSize::Huge2M, 4096);
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_alloc(16, a);
+    v.reserve(16, 8192);
+    assert!(v.cap() >= 2 * 1024 * 1024);
+    unsafe { *v.ptr().offset(8000) = 1; }
+
+    // A small block never grows in place into the mapped range, where
+    // it would be freed with `munmap`.
+    unsafe {
+        use alloc::{Alloc, DefaultAlloc, Kind};
+        use bump;
+        let mut h = hugepage::Alloc::with_threshold(bump::Alloc::new(DefaultAlloc), PageSize::Huge2M, 4096);
+        let k = Kind::from_size_align(1024, 8);
+        let p = h.alloc(k);
+        assert!(h.grow_in_place(p, k, 2048).is_ok());
+        assert!(h.grow_in_place(p, Kind::from_size_align(2048, 8), 8192).is_err());
+        assert!(h.usable_size(Kind::from_size_align(4000, 8)) < 4096);
+        h.dealloc(p, Kind::from_size_align(2048, 8));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn shrink_keep_ptr_unmaps_tail() {
+    use alloc::DefaultAlloc;
+    use hugepage::{self, PageSize};
+    use raw_vec::{RawVec, Shrink};
+    let a = hugepage::Alloc::with_threshold(direct_alloc::Alloc, PageSize::Huge2M, 4096);
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_alloc(5 << 20, a);
+    let p = v.ptr();
+    assert_eq!(v.shrink_keep_ptr(1 << 20), Shrink::Shrunk);
+    assert_eq!(v.ptr(), p);
+    assert_eq!(v.cap(), 1 << 20);
+    unsafe { *v.ptr().offset((1 << 20) - 1) = 1; }
+
+    // The heap cannot release part of a block, so it declines.
+    let mut w: RawVec<u8, DefaultAlloc> = RawVec::with_capacity(1000);
+    assert_eq!(w.shrink_keep_ptr(10), Shrink::Declined);
+    assert_eq!(w.cap(), 1000);
+}
+
+#[test]
+fn shrink_to_zero_uses_hot_dealloc() {
+    use alloc::{self, Address, Kind};
+    use raw_vec::RawVec;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct HotCounter(Rc<Cell<usize>>);
+    impl alloc::Alloc for HotCounter {
+        unsafe fn alloc(&mut self, kind: Kind) -> Address { direct_alloc::Alloc.alloc(kind) }
+        unsafe fn dealloc(&mut self, p: Address, kind: Kind) { direct_alloc::Alloc.dealloc(p, kind) }
+        unsafe fn dealloc_hot(&mut self, p: Address, kind: Kind) {
+            self.0.set(self.0.get() + 1);
+            self.dealloc(p, kind)
+        }
+    }
+
+    let hot = Rc::new(Cell::new(0));
+    let mut v: RawVec<u32, _> = RawVec::with_capacity_alloc(8, HotCounter(hot.clone()));
+    v.shrink_to_fit(0);
+    assert_eq!(hot.get(), 1);
+}
+
+#[test]
+fn growth_policies() {
+    use raw_vec::{Exact, OneAndHalf, PageRounded, RawVec};
+    use vec::Vec;
+
+    let mut v: RawVec<u32, _, OneAndHalf> = RawVec::with_alloc(direct_alloc::Alloc);
+    let mut caps = vec![];
+    for _ in 0..4 { v.double(); caps.push(v.cap()); }
+    assert_eq!(caps, [4, 6, 9, 13]);
+
+    let mut e: Vec<u8, _, Exact> = Vec::with_alloc(direct_alloc::Alloc);
+    for i in 0..3 { e.push(i); }
+    assert_eq!(e.capacity(), 3);
+
+    let mut p: RawVec<u64, _, PageRounded> = RawVec::with_alloc(direct_alloc::Alloc);
+    p.double();
+    assert_eq!(p.cap(), 512);
+}
+
+#[cfg(feature = "track-callsites")]
+#[test]
+fn track_reports_leak_callsite() {
+    use boxed::Box;
+    use debug;
+    let t = debug::Track::new(direct_alloc::Alloc);
+    ::std::mem::forget(Box::new_in(5u32, t.clone()));
+    let leaks = t.leaks();
+    assert_eq!(leaks.len(), 1);
+    assert!(!leaks[0].allocated_at.resolve().is_empty());
+}
+
+#[test]
+fn fuzz_default_and_segregated() {
+    use alloc::DefaultAlloc;
+    use segregated;
+    use testing::fuzz_alloc;
+    use verify;
+    let s = fuzz_alloc(&mut DefaultAlloc, 2000, 1);
+    assert_eq!(s.allocs, s.deallocs);
+    let mut seg = segregated::Alloc::new(verify::Alloc::new(DefaultAlloc),
+                                         verify::Alloc::new(DefaultAlloc),
+                                         verify::Alloc::new(DefaultAlloc));
+    fuzz_alloc(&mut seg, 2000, 42);
+}
+
+#[test]
+fn conformance_of_builtin_allocators() {
+    use alloc::DefaultAlloc;
+    use conformance;
+    use verify;
+    conformance::check(&mut DefaultAlloc);
+    conformance::check(&mut verify::Alloc::new(DefaultAlloc));
+}
+
+#[test]
+#[should_panic(expected = "written to after being freed")]
+fn quarantine_catches_write_after_free() {
+    use alloc::{Alloc, Kind};
+    use quarantine;
+    let mut q = quarantine::Alloc::new(direct_alloc::Alloc, 1024);
+    unsafe {
+        let k = Kind::new::<[u8; 32]>();
+        let p = q.alloc(k);
+        q.dealloc(p, k);
+        assert_eq!(*p, quarantine::DEFAULT_POISON);
+        *p.offset(5) = 1;
+        q.flush();
+    }
+}
+
+#[test]
+fn quota_refuses_over_budget() {
+    use boxed::Box;
+    use quota;
+    let q = quota::Alloc::new(direct_alloc::Alloc, Some(64), Some(3));
+    let a = Box::try_new_in([0u8; 40], &q).ok().unwrap();
+    assert!(Box::try_new_in([0u8; 40], &q).is_err());
+    let b = Box::try_new_in(1u8, &q).ok().unwrap();
+    let c = Box::try_new_in(2u8, &q).ok().unwrap();
+    assert!(Box::try_new_in(3u8, &q).is_err());
+    drop((a, b, c));
+    assert_eq!(q.usage(), quota::Usage { bytes: 0, blocks: 0 });
+
+    q.set_tag_limit("plugin", 16);
+    q.set_tag(Some("plugin"));
+    assert!(Box::try_new_in([0u8; 32], &q).is_err());
+    let d = Box::try_new_in([0u8; 8], &q).ok().unwrap();
+    assert_eq!(q.tag_bytes("plugin"), 8);
+    drop(d);
+    assert_eq!(q.tag_bytes("plugin"), 0);
+}
+
+#[test]
+fn throttle_per_frame() {
+    use boxed::Box;
+    use throttle::{self, OnExceed, Window};
+    let t = throttle::Alloc::new(direct_alloc::Alloc, 32, 48, Window::Frames, OnExceed::Fail);
+    let a = Box::try_new_in([0u8; 24], &t).ok().unwrap();
+    assert!(Box::try_new_in([0u8; 16], &t).is_err());
+    t.end_frame();
+    t.end_frame();
+    assert_eq!(t.available(), 48);
+    drop(a);
+    assert_eq!(t.exceeded_count(), 1);
+}
+
+#[test]
+fn vec_retain_drain_filter_dedup() {
+    use iter::IteratorExt;
+    let mut v = (0..20).collect_in(direct_alloc::Alloc);
+    v.retain(|&x| x % 3 != 0);
+    assert_eq!(&v[..], &[1, 2, 4, 5, 7, 8, 10, 11, 13, 14, 16, 17, 19]);
+
+    let evens: ::std::vec::Vec<i32> = v.drain_filter(|x| *x % 2 == 0).collect();
+    assert_eq!(evens, [2, 4, 8, 10, 14, 16]);
+    assert_eq!(&v[..], &[1, 5, 7, 11, 13, 17, 19]);
+
+    let mut w = vec![1, 1, 2, 3, 3, 3, 1].into_iter().collect_in(direct_alloc::Alloc);
+    w.dedup_by(|a, b| a == b);
+    assert_eq!(&w[..], &[1, 2, 3, 1]);
+}
+
+#[test]
+fn vec_retain_survives_a_panicking_predicate() {
+    use iter::IteratorExt;
+    use std::panic::{self, AssertUnwindSafe};
+    let mut v = (0..6).collect_in(direct_alloc::Alloc);
+    let mut calls = 0;
+    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+        v.retain(|&x| {
+            calls += 1;
+            if x == 3 { panic!("predicate"); }
+            x % 2 == 1
+        })
+    }));
+    assert!(r.is_err());
+    // called once per element up to the panic, and never again
+    assert_eq!(calls, 4);
+    assert_eq!(&v[..], &[1, 3, 4, 5]);
+}
+
+#[test]
+fn vec_map_and_set() {
+    use vec_map::{VecMap, VecSet};
+    let mut m = VecMap::with_alloc(direct_alloc::Alloc);
+    assert_eq!(m.insert(3, "c"), None);
+    assert_eq!(m.insert(1, "a"), None);
+    assert_eq!(m.insert(2, "b"), None);
+    assert_eq!(m.insert(2, "B"), Some("b"));
+    assert_eq!(m.get(&2), Some(&"B"));
+    assert_eq!(m.remove(&1), Some("a"));
+    assert_eq!(m.keys().cloned().collect::<::std::vec::Vec<_>>(), [2, 3]);
+
+    let s = VecSet::from_sorted_iter_in(0..10, direct_alloc::Alloc);
+    assert!(s.contains(&9) && !s.contains(&10));
+    assert_eq!(s.len(), 10);
+}
+
+#[test]
+fn bit_vec_ops() {
+    use bit_vec::BitVec;
+    let mut a = BitVec::with_alloc(direct_alloc::Alloc);
+    a.set(3, true);
+    a.set(200, true);
+    assert_eq!(a.len(), 201);
+    assert!(a.get(3) && a.get(200) && !a.get(4) && !a.get(1000));
+
+    let mut b = BitVec::with_alloc(direct_alloc::Alloc);
+    b.set(3, true);
+    b.set(7, true);
+    let mut u = BitVec::with_alloc(direct_alloc::Alloc);
+    u.union(&a);
+    u.union(&b);
+    assert_eq!(u.count_ones(), 3);
+    a.intersection(&b);
+    assert_eq!(a.iter_ones().collect::<::std::vec::Vec<_>>(), [3]);
+}
+
+#[test]
+fn interner_dedups() {
+    use intern::Interner;
+    let mut i = Interner::with_alloc(direct_alloc::Alloc);
+    let a = i.intern("alpha");
+    let b = i.intern("beta");
+    let big = ::std::iter::repeat('x').take(10000).collect::<String>();
+    let c = i.intern(&big);
+    assert_eq!(i.intern(&String::from("alpha")), a);
+    assert!(a != b);
+    assert_eq!(i.len(), 3);
+    assert_eq!(i.resolve(b), "beta");
+    assert_eq!(i.resolve(c).len(), 10000);
+    assert_eq!(i.get("gamma"), None);
+}
+
+#[test]
+fn typed_arena_stable_refs() {
+    use std::cell::Cell;
+    use typed_arena::Arena;
+
+    struct Noisy<'a>(&'a Cell<usize>, u64);
+    impl<'a> Drop for Noisy<'a> {
+        fn drop(&mut self) { self.0.set(self.0.get() + 1); }
+    }
+
+    let drops = Cell::new(0);
+    {
+        let arena = Arena::with_alloc(direct_alloc::Alloc);
+        let first = arena.alloc(Noisy(&drops, 0));
+        let mut last = &mut *arena.alloc(Noisy(&drops, 1));
+        for i in 2..2000 {
+            last = arena.alloc(Noisy(&drops, i));
+        }
+        first.1 = 42;
+        assert_eq!(first.1, 42);
+        assert_eq!(last.1, 1999);
+        assert_eq!(arena.len(), 2000);
+    }
+    assert_eq!(drops.get(), 2000);
+}
+
+#[test]
+fn into_iter_by_value() {
+    use iter::IteratorExt;
+    use std::rc::Rc;
+
+    let tracker = Rc::new(());
+    let v = (0..5).map(|i| (i, tracker.clone())).collect_in(direct_alloc::Alloc);
+    assert_eq!(Rc::strong_count(&tracker), 6);
+    let mut it = v.into_iter();
+    assert_eq!(it.next().map(|e| e.0), Some(0));
+    assert_eq!(it.next_back().map(|e| e.0), Some(4));
+    assert_eq!(it.len(), 3);
+    drop(it);
+    assert_eq!(Rc::strong_count(&tracker), 1);
+
+    let b = vec![String::from("a"), String::from("b")].into_iter()
+        .collect_box_slice_in(direct_alloc::Alloc);
+    let joined: String = b.into_iter().collect();
+    assert_eq!(joined, "ab");
+}
+
+#[test]
+fn move_across_allocators() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use iter::IteratorExt;
+    use vec::Vec;
+
+    let mut bmp = bump_alloc::Alloc::new(4096);
+    let mut long_lived: Vec<String> = Vec::with_alloc(DefaultAlloc);
+    long_lived.push(String::from("kept"));
+    {
+        let temps = (0..3).map(|i| i.to_string()).collect_in(&mut bmp);
+        long_lived.append_from(temps);
+    }
+    assert_eq!(&long_lived[..], &["kept", "0", "1", "2"]);
+
+    let b = Box::new_in(String::from("moved"), direct_alloc::Alloc);
+    let b: Box<String, DefaultAlloc> = b.rehome_in(DefaultAlloc);
+    assert_eq!(&**b, "moved");
+}
+
+#[test]
+fn extend_from_slice_grows_in_place() {
+    use vec::Vec;
+    let mut bmp = bump_alloc::Alloc::new(64 * 1024);
+    let mut v: Vec<u32, _> = Vec::with_capacity_alloc(4, &mut bmp);
+    v.extend_from_slice(&[1, 2, 3]);
+    let before = v.as_ptr();
+    let chunk = [7u32; 1000];
+    v.extend_from_slice(&chunk);
+    // the vector is the bump allocator's last block, so it grew in place
+    assert_eq!(v.as_ptr(), before);
+    assert_eq!(v.len(), 1003);
+    assert_eq!(&v[..4], &[1, 2, 3, 7]);
+}
+
+#[test]
+fn observed_counts_and_recent() {
+    use boxed::Box;
+    use observed::{self, Counters, Event, Recent};
+    use vec::Vec;
+
+    let mut o = observed::Alloc::new(direct_alloc::Alloc, (Counters::default(), Recent::new(2)));
+    {
+        let _b = Box::new_in(7u64, &mut o);
+    }
+    {
+        let mut v: Vec<u32, _> = Vec::with_alloc(&mut o);
+        for i in 0..100 { v.push(i); }
+    }
+    let &(counters, ref recent) = o.observer();
+    assert_eq!(counters.allocs, 2);
+    assert_eq!(counters.deallocs, 2);
+    assert!(counters.reallocs > 0);
+    assert_eq!(counters.live_bytes, 0);
+    assert!(counters.peak_bytes >= 400);
+    match recent.events().back() {
+        Some(&Event::Dealloc { .. }) => {}
+        other => panic!("unexpected last event {:?}", other),
+    }
+}
+
+#[test]
+fn bump_watermarks() {
+    use bump;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use vec::Vec;
+
+    let crossed = Rc::new(RefCell::new(::std::vec::Vec::new()));
+    let mut b = bump::Alloc::with_chunk_size(direct_alloc::Alloc, 1024);
+    let c = crossed.clone();
+    b.set_thresholds(&[512, 2048], move |t| c.borrow_mut().push(t));
+    {
+        let mut v: Vec<u8, _> = Vec::with_alloc(&mut b);
+        v.extend_from_slice(&[0; 600]);
+    }
+    assert_eq!(b.chunk_count(), 1);
+    unsafe {
+        b.alloc(alloc::Kind::from_size_align(1500, 8));
+    }
+    assert_eq!(b.chunk_count(), 2);
+    assert!(b.used() >= 2048);
+    let hw = b.high_water_mark();
+    b.reset();
+    assert_eq!(b.used(), 0);
+    assert_eq!(b.high_water_mark(), hw);
+    assert!(b.remaining() >= 1024 + 1500);
+    // the rolled-back vector took `used` over 512 once already
+    assert_eq!(*crossed.borrow(), [512, 512, 2048]);
+}
+
+#[test]
+fn runtime_selected_allocator() {
+    use alloc::{DefaultAlloc, DynAlloc};
+    use boxed::Box;
+    use raw_vec::RawVec;
+
+    fn pick(direct: bool) -> DynAlloc {
+        if direct { ::std::boxed::Box::new(direct_alloc::Alloc) }
+        else { ::std::boxed::Box::new(DefaultAlloc) }
+    }
+
+    for &direct in &[true, false] {
+        let b: Box<u32, DynAlloc> = Box::new_in(5, pick(direct));
+        assert_eq!(*b, 5);
+
+        let mut a = pick(direct);
+        let mut rv: RawVec<u64, &mut AllocTrait> = RawVec::with_capacity_alloc(4, &mut *a);
+        rv.double();
+        assert!(rv.cap() >= 8);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deserialize_into_allocator() {
+    use serde_json;
+    use vec::Vec;
+    use vec_map::VecMap;
+
+    let mut bmp = bump_alloc::Alloc::new(64 * 1024);
+    {
+        let mut d = serde_json::Deserializer::new(b"[1, 2, 3]".iter().map(|&b| Ok(b)));
+        let v: Vec<u32, _> = Vec::deserialize_in(&mut d, &mut bmp).unwrap();
+        assert_eq!(&v[..], &[1, 2, 3]);
+        assert_eq!(serde_json::to_string(&v).unwrap(), "[1,2,3]");
+    }
+    let mut d = serde_json::Deserializer::new(br#"{"b": 2, "a": 1}"#.iter().map(|&b| Ok(b)));
+    let m: VecMap<String, u32, _> = VecMap::deserialize_in(&mut d, direct_alloc::Alloc).unwrap();
+    assert_eq!(serde_json::to_string(&m).unwrap(), r#"{"a":1,"b":2}"#);
+}
+
+#[test]
+fn over_aligned_allocations() {
+    use aligned::{CacheAligned, CACHE_LINE};
+    use alloc::{DefaultAlloc, Kind};
+    use std::mem;
+    use verify;
+
+    assert_eq!(mem::align_of::<CacheAligned<u8>>(), CACHE_LINE);
+    let pair = [CacheAligned::new(1u32), CacheAligned::new(2u32)];
+    assert_eq!(&pair[1] as *const _ as usize - &pair[0] as *const _ as usize, CACHE_LINE);
+
+    let mut v = verify::Alloc::new(DefaultAlloc);
+    unsafe {
+        let p = v.alloc_array_aligned_to::<u8>(100, 4096).unwrap();
+        assert_eq!(*p as usize % 4096, 0);
+        v.dealloc(*p, Kind::new_over_aligned::<u8>(4096).array_packed(100));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn virtual_raw_vec_never_moves() {
+    use raw_vec::VirtualRawVec;
+    let mut v: VirtualRawVec<u64> = VirtualRawVec::with_reservation(1 << 20);
+    v.reserve(0, 1);
+    let p = v.ptr();
+    for i in 0..(1 << 20) {
+        if i == v.cap() { v.double(); }
+        unsafe { *v.ptr().offset(i as isize) = i as u64; }
+    }
+    assert_eq!(v.ptr(), p);
+    assert_eq!(v.cap(), v.max_cap());
+    assert_eq!(unsafe { *p.offset(12345) }, 12345);
+}
+
+#[test]
+fn double_ended_bump_ends() {
+    use double_ended_bump;
+    use vec::Vec;
+
+    let mut region = double_ended_bump::Alloc::new(direct_alloc::Alloc, 4096);
+    let level_start = region.back_mark();
     {
-        println!("start of demo_bump_in_place");
-        let bmp = bump_alloc::Alloc::new(4*1024*1024);
-        let b = in Boxing(bmp) { 3 };
-        println!("at end of dump_bump_in_place alloc scope b: {:?} 0x{:x}",
-                 b, &*b as *const _ as usize);
-        // ::std::mem::forget(b);
+        let mut level: Vec<u64, _> = Vec::with_capacity_alloc(64, region.back());
+        level.extend_from_slice(&[1; 64]);
+        ::std::mem::forget(level);
+    }
+    for _frame in 0..3 {
+        let frame_start = region.front_mark();
+        {
+            let mut temps: Vec<u32, _> = Vec::with_capacity_alloc(100, region.front());
+            temps.extend_from_slice(&[2; 100]);
+            ::std::mem::forget(temps);
+            assert_eq!(region.free(), 4096 - 512 - 400);
+            let mut big = region.front();
+            assert!(unsafe { big.alloc(alloc::Kind::from_size_align(4000, 8)) }.is_null());
+        }
+        region.reset_front_to(frame_start);
+    }
+    region.reset_back_to(level_start);
+    assert_eq!(region.free(), 4096);
+}
+
+#[test]
+fn ring_reclaims_at_fences() {
+    use ring;
+    let r = ring::Alloc::new(direct_alloc::Alloc, 1024);
+    let mut h = &r;
+    let k = unsafe { alloc::Kind::from_size_align(400, 8) };
+    unsafe {
+        let a = h.alloc(k);
+        let f1 = r.fence();
+        let b = h.alloc(k);
+        let f2 = r.fence();
+        assert!(!a.is_null() && !b.is_null());
+        // 224 bytes left at the end, and frame 1 is still in flight
+        assert!(h.alloc(k).is_null());
+        r.retire(f1);
+        let c = h.alloc(k);
+        assert_eq!(c, a);
+        assert_eq!(r.in_flight(), 400 + 224 + 400);
+        r.retire(f2);
+        assert_eq!(r.in_flight(), 400 + 224);
+    }
+}
+
+#[test]
+fn gen_arena_stale_handles() {
+    use gen_arena::Arena;
+    let mut a = Arena::with_alloc(direct_alloc::Alloc);
+    let x = a.insert("x");
+    let y = a.insert("y");
+    assert_eq!(a.remove(x), Some("x"));
+    let z = a.insert("z");
+    // z reuses x's slot, but x's handle stays dead
+    assert_eq!(z.index, x.index);
+    assert_eq!(a.get(x), None);
+    assert_eq!(a.get(z), Some(&"z"));
+    assert_eq!(a.remove(x), None);
+    *a.get_mut(y).unwrap() = "Y";
+    assert_eq!(a.iter().map(|(_, v)| *v).collect::<::std::vec::Vec<_>>(), ["z", "Y"]);
+    assert_eq!(a.len(), 2);
+}
+
+#[test]
+fn stats_report() {
+    use boxed::Box;
+    use stats;
+
+    let s = stats::Alloc::new(direct_alloc::Alloc);
+    s.set_tag(Some("small"));
+    let a = Box::new_in([0u8; 3], &s);
+    let b = Box::new_in([0u8; 4], &s);
+    s.set_tag(Some("big"));
+    let c = Box::new_in([0u8; 1000], &s);
+    s.set_tag(None);
+    let d = Box::new_in([0u8; 100], &s);
+
+    let r = s.report();
+    assert_eq!(r.live_blocks, 4);
+    assert_eq!(r.live_bytes, 1107);
+    assert_eq!(r.histogram.iter().map(|c| (c.upper, c.blocks)).collect::<::std::vec::Vec<_>>(),
+               [(4, 2), (128, 1), (1024, 1)]);
+    assert_eq!(r.tags[0].tag, "big");
+    assert_eq!(r.tags[1].bytes, 7);
+    assert_eq!(r.fragmentation, 0.0);
+    assert!(format!("{}", r).contains("tag small: 2 blocks, 7 bytes"));
+    drop((a, b, c, d));
+    assert_eq!(s.report().live_blocks, 0);
+}
+
+#[test]
+fn virtual_alloc_is_deterministic() {
+    use alloc::Kind;
+    use observed::{self, Event, Recent};
+    use testing::{VirtualAlloc, VIRTUAL_BASE};
+
+    let run = || {
+        let mut o = observed::Alloc::new(VirtualAlloc::new(), Recent::new(8));
+        unsafe {
+            let a = o.alloc(Kind::from_size_align(3, 1));
+            let b = o.alloc(Kind::from_size_align(8, 8));
+            let b = o.realloc(b, Kind::from_size_align(8, 8), 16);
+            o.dealloc(a, Kind::from_size_align(3, 1));
+            o.dealloc(b, Kind::from_size_align(16, 8));
+        }
+        o.observer().events().iter().cloned().collect::<::std::vec::Vec<Event>>()
+    };
+    let first = run();
+    assert_eq!(first, run());
+    match first[1] {
+        Event::Alloc { ptr, .. } => assert_eq!(ptr as usize, VIRTUAL_BASE + 8),
+        ref e => panic!("unexpected {:?}", e),
+    }
+
+    let mut v = VirtualAlloc::new();
+    unsafe {
+        let p = v.alloc(Kind::from_size_align(4, 1));
+        v.write(p, 1, &[7, 8]);
+        let q = v.realloc(p, Kind::from_size_align(4, 1), 6);
+        assert_eq!(v.read(q), &[0, 7, 8, 0, 0, 0]);
+
+        // Growing out of a zero-sized block allocates a fresh one.
+        let empty = Kind::from_size_align(0, 4);
+        let e = v.alloc(empty);
+        let r = v.realloc(e, empty, 8);
+        assert_eq!(v.read(r), &[0; 8]);
+        assert_eq!(v.live_count(), 2);
+    }
+}
+
+#[test]
+fn fill_then_assume_init() {
+    use boxed::Box;
+    use std::io::Read;
+    use uninit::MaybeUninit;
+    use vec::Vec;
+
+    let mut b = Box::<[u8; 4], _>::new_uninit_in(direct_alloc::Alloc);
+    unsafe { (&b"abcd"[..]).read_exact(&mut *b.as_mut_ptr()).unwrap(); }
+    let b = unsafe { b.assume_init() };
+    assert_eq!(&*b, b"abcd");
+
+    // a non-`Copy` element type is fine now
+    let mut v: Vec<String, _> = Vec::with_capacity_alloc(2, direct_alloc::Alloc);
+    for (slot, s) in v.spare_capacity_mut().iter_mut().zip(&["x", "y"]) {
+        *slot = MaybeUninit::new(String::from(*s));
+    }
+    unsafe { v.set_len(2); }
+    assert_eq!(&v[..], &["x", "y"]);
+}
+
+#[test]
+fn dst_header_and_tail() {
+    use dst;
+    use verify;
+
+    struct Header { len: usize, tag: u8 }
+    let mut v = verify::Alloc::new(direct_alloc::Alloc);
+    unsafe {
+        let h = dst::alloc_with_tail::<Header, String, _, _, _>(
+            &mut v, 3, || Header { len: 3, tag: 9 }, |i| i.to_string()).unwrap();
+        let (kind, offset) = dst::kind_with_tail::<Header, String>(3);
+        assert_eq!(offset % ::std::mem::align_of::<String>(), 0);
+        assert_eq!(kind.size(), offset + 3 * ::std::mem::size_of::<String>());
+        let n = (**h).len;
+        assert_eq!((**h).tag, 9);
+        assert_eq!(dst::tail::<Header, String>(*h, n), &["0", "1", "2"]);
+        dst::dealloc_with_tail::<Header, String, _>(&mut v, h, n);
+    }
+    assert_eq!(v.live_count(), 0);
+}
+
+#[test]
+fn rc_lite_single_count() {
+    use rc_lite::RcLite;
+    use std::mem;
+    use verify;
+
+    assert_eq!(mem::size_of::<RcLite<u64, direct_alloc::Alloc>>(), mem::size_of::<usize>());
+    let v = verify::Alloc::new(direct_alloc::Alloc);
+    let a = RcLite::new_in(String::from("shared"), v.clone());
+    let mut b = a.clone();
+    assert_eq!(RcLite::strong_count(&a), 2);
+    assert!(RcLite::get_mut(&mut b).is_none());
+    drop(a);
+    RcLite::get_mut(&mut b).unwrap().push('!');
+    assert_eq!(RcLite::try_unwrap(b).ok().unwrap(), "shared!");
+    assert_eq!(v.live_count(), 0);
+}
+
+#[test]
+fn shrink_declined_within_size_class() {
+    use alloc::{self, Address, Capacity, Kind};
+    use raw_vec::Shrink;
+    use vec::Vec;
+
+    // Rounds every block up to a power of two, like a slab allocator.
+    struct Classes;
+    impl alloc::Alloc for Classes {
+        unsafe fn alloc(&mut self, kind: Kind) -> Address {
+            direct_alloc::Alloc.alloc(Kind::from_size_align(self.usable_size(kind), kind.align()))
+        }
+        unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+            direct_alloc::Alloc.dealloc(ptr, Kind::from_size_align(self.usable_size(kind), kind.align()))
+        }
+        unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+            kind.size().next_power_of_two()
+        }
+        unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: usize) -> Address {
+            let new_class = Kind::from_size_align(new_size, kind.align());
+            direct_alloc::Alloc.realloc(ptr, Kind::from_size_align(self.usable_size(kind), kind.align()),
+                                        self.usable_size(new_class))
+        }
+    }
+
+    let mut v: Vec<u8, _> = Vec::with_capacity_alloc(64, Classes);
+    v.extend_from_slice(&[1; 40]);
+    assert_eq!(v.shrink_to_fit(), Shrink::Declined);
+    assert_eq!(v.capacity(), 64);
+    v.truncate(10);
+    assert_eq!(v.shrink_to_fit(), Shrink::Shrunk);
+    assert_eq!(v.capacity(), 10);
+    v.clear();
+    assert_eq!(v.shrink_to_fit(), Shrink::Freed);
+}
+
+#[test]
+fn frozen_arena_shared_across_threads() {
+    use alloc::DefaultAlloc;
+    use std::thread;
+    use typed_arena::Arena;
+
+    let arena = Arena::with_alloc(DefaultAlloc);
+    for i in 0..5000u64 { arena.alloc(i); }
+    let frozen = arena.freeze();
+    let handles: ::std::vec::Vec<_> = (0..4).map(|_| {
+        let f = frozen.clone();
+        thread::spawn(move || f.iter().sum::<u64>())
+    }).collect();
+    for h in handles { assert_eq!(h.join().unwrap(), 4999 * 5000 / 2); }
+    assert_eq!(frozen.len(), 5000);
+    assert_eq!(frozen.get(4321), Some(&4321));
+    assert_eq!(frozen.get(5000), None);
+}
+
+#[test]
+fn sync_bump_from_many_threads() {
+    use alloc::DefaultAlloc;
+    use std::sync::Arc;
+    use std::thread;
+    use sync_bump;
+
+    let region = Arc::new(sync_bump::Alloc::new(DefaultAlloc, 1 << 20));
+    let threads: ::std::vec::Vec<_> = (0..4).map(|t| {
+        let mut a = region.clone();
+        thread::spawn(move || unsafe {
+            let mut addrs = ::std::vec::Vec::new();
+            for _ in 0..1000 {
+                let p = a.alloc(alloc::Kind::from_size_align(24, 8));
+                assert!(!p.is_null());
+                *(p as *mut u64) = t;
+                addrs.push(p as usize);
+            }
+            addrs
+        })
+    }).collect();
+    let mut all: ::std::vec::Vec<usize> = threads.into_iter()
+        .flat_map(|h| h.join().unwrap()).collect();
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), 4000);
+    assert_eq!(region.used(), 4000 * 32);
+
+    // A request that does not fit leaves the cursor where it was.
+    let mut small = sync_bump::Alloc::new(DefaultAlloc, 64);
+    unsafe {
+        assert!(!small.alloc(alloc::Kind::from_size_align(48, 8)).is_null());
+        assert!(small.alloc(alloc::Kind::from_size_align(32, 8)).is_null());
+        assert_eq!(small.used(), 48);
+        assert!(!small.alloc(alloc::Kind::from_size_align(16, 8)).is_null());
+    }
+}
+
+#[test]
+fn sharded_cross_thread_free() {
+    use sharded;
+    use stats;
+    use std::sync::Arc;
+    use std::thread;
+
+    let s = Arc::new(sharded::Alloc::with_shards(4, || stats::Alloc::new(direct_alloc::Alloc)));
+    let k = unsafe { alloc::Kind::from_size_align(64, 8) };
+    let blocks: ::std::vec::Vec<usize> = (0..4).map(|_| {
+        let mut a = s.clone();
+        thread::spawn(move || unsafe { a.alloc(k) as usize }).join().unwrap()
+    }).collect();
+    // grow and free everything from this thread
+    let mut a = s.clone();
+    for p in blocks {
+        unsafe {
+            let p = a.realloc(p as alloc::Address, k, 128);
+            a.dealloc(p, alloc::Kind::from_size_align(128, 8));
+        }
+    }
+    let mut total = 0;
+    for i in 0..4 {
+        let r = s.with_shard(i, |shard| shard.report());
+        assert_eq!(r.live_blocks, 0);
+        total += r.total_allocs;
+    }
+    assert_eq!(total, 4);
+}
+
+#[test]
+fn deferred_frees_at_collect() {
+    use deferred;
+    use verify;
+
+    let v = verify::Alloc::new(direct_alloc::Alloc);
+    let d = deferred::Alloc::new(v.clone(), 100);
+    let k = unsafe { alloc::Kind::from_size_align(40, 8) };
+    let mut h = &d;
+    unsafe {
+        let a = h.alloc(k);
+        let b = h.alloc(k);
+        let c = h.alloc(k);
+        d.dealloc_deferred(a, k).unwrap();
+        d.dealloc_deferred(b, k).unwrap();
+        // still readable until collected
+        *(a as *mut u64) = 1;
+        assert_eq!(d.dealloc_deferred(c, k).unwrap_err().ptr, c);
+        assert_eq!(v.live_count(), 3);
+        assert_eq!(d.collect(), 2);
+        assert_eq!(v.live_count(), 1);
+        d.dealloc_deferred(c, k).unwrap();
+    }
+    drop(d);
+    assert_eq!(v.live_count(), 0);
+}
+
+#[test]
+fn panicking_drop_frees_and_drops_rest() {
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+    use verify;
+    use vec::Vec;
+
+    struct Bomb<'a>(&'a Cell<usize>, bool);
+    impl<'a> Drop for Bomb<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+            if self.1 { panic!("bomb"); }
+        }
+    }
+
+    let drops = Cell::new(0);
+    let v = verify::Alloc::new(direct_alloc::Alloc);
+    let make = || {
+        let mut xs = Vec::with_alloc(v.clone());
+        for i in 0..5 { xs.push(Bomb(&drops, i == 2)); }
+        xs
+    };
+
+    let xs = make();
+    assert!(panic::catch_unwind(AssertUnwindSafe(move || drop(xs))).is_err());
+    assert_eq!(drops.get(), 5);
+    assert_eq!(v.live_count(), 0);
+
+    drops.set(0);
+    let mut it = make().into_iter();
+    drop(it.next());
+    assert!(panic::catch_unwind(AssertUnwindSafe(move || drop(it))).is_err());
+    assert_eq!(drops.get(), 5);
+    assert_eq!(v.live_count(), 0);
+}
+
+#[test]
+fn append_takes_buffer_only_from_compatible_alloc() {
+    use verify;
+    use vec::Vec;
+
+    let shared = verify::Alloc::new(direct_alloc::Alloc);
+    let mut a: Vec<u32, _> = Vec::with_alloc(shared.clone());
+    let mut b = Vec::with_alloc(shared.clone());
+    b.push(1); b.push(2);
+    let p = b.as_ptr();
+    a.append(&mut b);
+    assert_eq!(a.as_ptr(), p);
+    assert!(b.is_empty());
+
+    // a separate verifier's memory must not be adopted
+    let mut c = Vec::with_alloc(verify::Alloc::new(direct_alloc::Alloc));
+    let mut d = Vec::with_alloc(verify::Alloc::new(direct_alloc::Alloc));
+    d.push(3);
+    let p = d.as_ptr();
+    c.append(&mut d);
+    assert!(c.as_ptr() != p);
+    assert_eq!(&c[..], &[3]);
+    assert!(d.is_empty());
+}
+
+#[test]
+fn kind_introspection() {
+    let k = alloc::Kind::new::<[u64; 3]>();
+    assert_eq!(k.to_string(), "24 bytes, align 8");
+    assert!(!k.is_zero_sized());
+    assert!(alloc::Kind::new::<()>().is_zero_sized());
+    assert_eq!(k.max_size_for_align() % 8, 0);
+    assert!(k.max_size_for_align() <= ::std::isize::MAX as usize);
+}
+
+#[test]
+fn alloc_errors_say_why() {
+    use alloc::AllocError;
+    use boxed::Box;
+    use quota;
+    use throttle::{self, OnExceed, Window};
+    use vec::Vec;
+
+    let q = quota::Alloc::new(direct_alloc::Alloc, Some(64), None);
+    assert_eq!(Box::try_new_in([0u8; 80], &q).err(), Some(AllocError::QuotaExceeded));
+    let mut v: Vec<u8, _> = Vec::with_alloc(&q);
+    v.push(1);
+    assert_eq!(v.try_reserve(100), Err(AllocError::QuotaExceeded));
+    assert_eq!(&v[..], &[1]);
+    assert_eq!(v.try_reserve(!0), Err(AllocError::CapacityOverflow));
+
+    let t = throttle::Alloc::new(direct_alloc::Alloc, 16, 16, Window::Frames, OnExceed::Fail);
+    assert_eq!(Box::try_new_in([0u8; 32], &t).err(), Some(AllocError::Throttled));
+    assert_eq!(AllocError::Throttled.to_string(), "allocation rate limit exceeded");
+}
+
+#[test]
+fn growth_keeps_reported_excess() {
+    use alloc::{self, Address, Capacity, Excess, Kind};
+    use std::cell::Cell;
+    use vec::Vec;
+
+    // Hands out whole 256-byte pages and says so through `*_excess`.
+    struct Pages<'a> { excess_calls: &'a Cell<usize> }
+    fn round(size: usize) -> usize { (size + 255) & !255 }
+    impl<'a> alloc::Alloc for Pages<'a> {
+        unsafe fn alloc(&mut self, kind: Kind) -> Address {
+            direct_alloc::Alloc.alloc(Kind::from_size_align(round(kind.size()), kind.align()))
+        }
+        unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+            direct_alloc::Alloc.dealloc(ptr, Kind::from_size_align(round(kind.size()), kind.align()))
+        }
+        unsafe fn usable_size(&self, kind: Kind) -> Capacity { round(kind.size()) }
+        unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: usize) -> Address {
+            direct_alloc::Alloc.realloc(ptr, Kind::from_size_align(round(kind.size()), kind.align()),
+                                        round(new_size))
+        }
+        unsafe fn alloc_excess(&mut self, kind: Kind) -> Excess {
+            self.excess_calls.set(self.excess_calls.get() + 1);
+            Excess(self.alloc(kind), round(kind.size()))
+        }
+        unsafe fn realloc_excess(&mut self, ptr: Address, kind: Kind, new_size: usize) -> Excess {
+            self.excess_calls.set(self.excess_calls.get() + 1);
+            Excess(self.realloc(ptr, kind, new_size), round(new_size))
+        }
+    }
+
+    let calls = Cell::new(0);
+    let mut v: Vec<u32, _> = Vec::with_alloc(Pages { excess_calls: &calls });
+    v.push(1);
+    assert_eq!(v.capacity(), 64);
+    for i in 0..64 { v.push(i); }
+    assert_eq!(v.capacity(), 128);
+    assert_eq!(calls.get(), 2);
+
+    // 256 is not a multiple of 3: only whole elements count
+    let mut w: Vec<[u8; 3], _> = Vec::with_alloc(Pages { excess_calls: &calls });
+    w.reserve(10);
+    assert_eq!(w.capacity(), 85);
+}
+
+#[cfg(unix)]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn box_frees_before_dropping_its_allocator() {
+    use alloc::{self, Address, Kind};
+    use boxed::Box;
+    use libc;
+    use std::cell::RefCell;
+    use std::ptr;
+
+    // Owns one mapped page and unmaps it when dropped; `dealloc`
+    // scribbles over the freed block, so freeing after the unmap
+    // would fault.
+    struct Page<'a> { base: Address, log: &'a RefCell<Vec<&'static str>> }
+    impl<'a> alloc::Alloc for Page<'a> {
+        unsafe fn alloc(&mut self, kind: Kind) -> Address {
+            assert!(kind.size() <= 4096 && kind.align() <= 4096);
+            self.base
+        }
+        unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+            ptr::write_bytes(ptr, 0xdd, kind.size());
+            self.log.borrow_mut().push("dealloc");
+        }
+    }
+    impl<'a> Drop for Page<'a> {
+        fn drop(&mut self) {
+            unsafe { libc::munmap(self.base as *mut libc::c_void, 4096); }
+            self.log.borrow_mut().push("unmap");
+        }
+    }
+
+    let log = RefCell::new(Vec::new());
+    let base = unsafe {
+        libc::mmap(ptr::null_mut(), 4096, libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_PRIVATE | libc::MAP_ANON, -1, 0)
+    };
+    assert!(base != libc::MAP_FAILED);
+    let b = Box::new_in([7u64; 4], Page { base: base as Address, log: &log });
+    assert_eq!(b[3], 7);
+    drop(b);
+    assert_eq!(*log.borrow(), ["dealloc", "unmap"]);
+}
+
+#[test]
+fn seg_list_caches_by_class() {
+    use alloc::{DefaultAlloc, Kind};
+    use intrusive::{FreeList, SegList};
+
+    let mut one = FreeList::new(Kind::new::<u8>());
+    assert_eq!(one.kind(), Kind::new::<*mut u8>());
+    let mut segs = SegList::new(16, 256);
+    assert_eq!(segs.class_count(), 5);
+    let k24 = Kind::new::<[u64; 3]>();
+    let class = segs.class_of(k24).unwrap();
+    assert_eq!(segs.class_kind(class).size(), 32);
+    assert_eq!(segs.class_of(Kind::new::<[u8; 512]>()), None);
+
+    unsafe {
+        let a = DefaultAlloc.alloc(one.kind());
+        let b = DefaultAlloc.alloc(segs.class_kind(class));
+        one.push(a);
+        assert!(segs.push(b, k24));
+        assert_eq!(segs.pop(Kind::new::<[u64; 4]>()), Some(b));
+        assert_eq!(segs.pop(k24), None);
+        assert!(segs.push(b, k24));
+
+        let mut freed = 0;
+        one.drain(|p| { DefaultAlloc.dealloc(p, Kind::new::<*mut u8>()); freed += 1; });
+        segs.drain(|p, k| { DefaultAlloc.dealloc(p, k); freed += 1; });
+        assert_eq!(freed, 2);
+        assert!(one.is_empty());
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn jemalloc_conformance() {
+    use conformance;
+    use jemalloc;
+    conformance::check(&mut jemalloc::Alloc);
+}
+
+#[cfg(feature = "mimalloc")]
+#[test]
+#[cfg_attr(miri, ignore)]
+fn mimalloc_conformance() {
+    use conformance;
+    use mimalloc;
+    conformance::check(&mut mimalloc::Alloc);
+}
+
+#[cfg(windows)]
+#[test]
+fn winheap_private_heap() {
+    use alloc::Kind;
+    use conformance;
+    use winheap;
+
+    let mut heap = winheap::Alloc::new().unwrap();
+    conformance::check(&mut heap);
+    unsafe {
+        let k = Kind::from_size_align(100, 64);
+        let p = heap.alloc(k);
+        assert_eq!(p as usize % 64, 0);
+    }
+    // leaked on purpose: destroying the heap releases it
+    drop(heap);
+}
+
+#[test]
+fn scratch_reuses_its_block() {
+    use leakcheck;
+    use scratch::Scratch;
+
+    let lc = leakcheck::Alloc::new(direct_alloc::Alloc);
+    let mut s = Scratch::with_alloc(lc.clone());
+    let sum = s.with_scratch::<u32, _, _>(100, |xs| {
+        for (i, x) in xs.iter_mut().enumerate() { *x = i as u32; }
+        xs.iter().sum::<u32>()
+    });
+    assert_eq!(sum, 4950);
+    let cap = s.capacity();
+    for n in 0..50 {
+        s.with_scratch::<u16, _, _>(n, |xs| assert!(xs.iter().all(|&x| x == 0)));
+    }
+    assert_eq!(s.capacity(), cap);
+    assert_eq!(lc.live_count(), 1);
+    s.with_scratch::<String, _, _>(3, |xs| xs[0].push_str("temp"));
+    drop(s);
+    assert_eq!(lc.live_count(), 0);
+}
+
+#[test]
+fn split_off_shares_the_allocator() {
+    use quota;
+    use vec::Vec;
+
+    let q = quota::Alloc::new(direct_alloc::Alloc, None, None);
+    let mut v: Vec<u32, _> = Vec::with_alloc(&q);
+    v.extend_from_slice(&[1, 2, 3, 4, 5]);
+    let tail = v.split_off(3);
+    assert_eq!(&v[..], &[1, 2, 3]);
+    assert_eq!(&tail[..], &[4, 5]);
+    assert_eq!(q.usage().blocks, 2);
+
+    let (init, spare) = v.split_at_spare_mut();
+    spare[0].write(init[0] + init[2]);
+    unsafe { v.set_len(4); }
+    assert_eq!(v[3], 4);
+}
+
+#[test]
+fn persistent_vec_shares_structure() {
+    use persistent::PVec;
+    use quota;
+
+    let q = quota::Alloc::new(direct_alloc::Alloc, None, None);
+    let mut v = PVec::with_alloc(&q);
+    for i in 0..2000u32 { v = v.push_back(i); }
+    assert_eq!(v.len(), 2000);
+    assert_eq!(v.get(1234), Some(&1234));
+
+    let blocks = q.usage().blocks;
+    let w = v.set(5, 99);
+    // a root, a branch and a leaf, each a node block plus its array
+    assert_eq!(q.usage().blocks - blocks, 6);
+    assert_eq!(w.get(5), Some(&99));
+    assert_eq!(v.get(5), Some(&5));
+    assert_eq!(w.iter().zip(v.iter()).filter(|&(a, b)| a != b).count(), 1);
+
+    drop((v, w));
+    assert_eq!(q.usage().blocks, 0);
+}
+
+#[test]
+fn rope_edits() {
+    use rope::{self, Rope};
+    use string::String;
+
+    let text: ::std::string::String = (0..300).map(|i| format!("line {}\n", i)).collect();
+    let mut r = Rope::from_string(String::from_str_in(&text, direct_alloc::Alloc));
+    assert_eq!(r.len(), text.len());
+    assert!(r.chunks().count() > 1);
+    assert!(r.chunks().all(|c| c.len() <= rope::CHUNK));
+
+    r.insert(1000, "héllo");
+    assert!(!r.is_char_boundary(1002));
+    r.remove(10, 500);
+    let expected = format!("{}{}héllo{}", &text[..10], &text[500..1000], &text[1000..]);
+    let s = r.to_string_in(direct_alloc::Alloc);
+    assert_eq!(s.as_str(), &expected[..]);
+}
+
+#[test]
+fn raw_table_find_insert_erase() {
+    use raw_table::RawTable;
+
+    fn hash(x: &u64) -> u64 { x.wrapping_mul(0x9E37_79B9_7F4A_7C15) }
+
+    let mut t = RawTable::new_in(direct_alloc::Alloc);
+    for i in 0..1000u64 {
+        t.insert(hash(&i), i, hash);
+    }
+    assert_eq!(t.len(), 1000);
+    assert!(t.capacity() >= 1000 && t.capacity() < t.buckets());
+    for i in (0..1000u64).filter(|i| i % 3 == 0) {
+        let pos = t.find(hash(&i), |&x| x == i).unwrap();
+        assert_eq!(t.erase(pos), i);
+    }
+    assert_eq!(t.len(), 666);
+    assert_eq!(t.find(hash(&300), |&x| x == 300), None);
+    assert_eq!(*t.get(t.find(hash(&301), |&x| x == 301).unwrap()), 301);
+    assert_eq!(t.iter().map(|&x| x).sum::<u64>(),
+               (0..1000u64).filter(|i| i % 3 != 0).sum::<u64>());
+}
+
+#[test]
+fn raw_table_rebuild_survives_a_panicking_hasher() {
+    use raw_table::RawTable;
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    let token = Rc::new(());
+    let mut t = RawTable::new_in(direct_alloc::Alloc);
+    for i in 0..5u64 { t.insert(i, token.clone(), |_| 0); }
+    let calls = Cell::new(0);
+    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+        t.reserve(100, |_| {
+            calls.set(calls.get() + 1);
+            if calls.get() == 3 { panic!("hasher"); }
+            0
+        })
+    }));
+    assert!(r.is_err());
+    // The two values moved before the panic leak; the rest stay put.
+    assert_eq!(t.len(), 3);
+    drop(t);
+    assert_eq!(Rc::strong_count(&token), 1 + 2);
+}
+
+#[test]
+fn hash_set_operations_across_allocators() {
+    use hash_map::{HashMap, HashSet};
+    use quota;
+    use std::collections::hash_map::RandomState;
+
+    let q = quota::Alloc::new(direct_alloc::Alloc, None, None);
+    let mut evens: HashSet<u32, RandomState, _> = HashSet::with_alloc(&q);
+    let mut threes: HashSet<u32> = HashSet::new();
+    for i in 0..30 {
+        if i % 2 == 0 { evens.insert(i); }
+        if i % 3 == 0 { threes.insert(i); }
+    }
+    assert!(!evens.insert(4));
+    assert_eq!(evens.len(), 15);
+
+    let mut both: ::std::vec::Vec<u32> = evens.intersection(&threes).cloned().collect();
+    both.sort();
+    assert_eq!(both, vec![0, 6, 12, 18, 24]);
+    let mut odd_threes: ::std::vec::Vec<u32> = threes.difference(&evens).cloned().collect();
+    odd_threes.sort();
+    assert_eq!(odd_threes, vec![3, 9, 15, 21, 27]);
+    assert_eq!(evens.union(&threes).count(), 20);
+    assert_eq!(evens.symmetric_difference(&threes).count(), 15);
+
+    let mut m: HashMap<&str, u32> = HashMap::new();
+    assert_eq!(m.insert("a", 1), None);
+    assert_eq!(m.insert("a", 2), Some(1));
+    assert_eq!(m.get("a"), Some(&2));
+    assert_eq!(m.remove("a"), Some(2));
+    assert!(m.is_empty());
+
+    drop(evens);
+    assert_eq!(q.usage().blocks, 0);
+}
+
+#[test]
+fn btree_set_range_and_bulk_build() {
+    use btree_map::{BTreeMap, BTreeSet};
+    use quota;
+    use std::collections::Bound::{Excluded, Included, Unbounded};
+
+    let q = quota::Alloc::new(direct_alloc::Alloc, None, None);
+    let set = BTreeSet::from_sorted_iter_in((0..1000u32).map(|i| i * 2), &q);
+    assert_eq!(set.len(), 1000);
+    assert_eq!(set.first(), Some(&0));
+    assert_eq!(set.last(), Some(&1998));
+    assert!(set.contains(&500) && !set.contains(&501));
+    let r: ::std::vec::Vec<u32> = set.range(Included(&101), Excluded(&110)).cloned().collect();
+    assert_eq!(r, vec![102, 104, 106, 108]);
+    assert_eq!(set.range::<u32, u32>(Excluded(&1994), Unbounded).count(), 2);
+    drop(set);
+    assert_eq!(q.usage().blocks, 0);
+
+    // mixed inserts and removals stay ordered and balanced
+    let mut m = BTreeMap::with_alloc(&q);
+    for i in 0..2000u32 { m.insert((i * 7919) % 2000, i); }
+    for i in (0..2000u32).filter(|i| i % 3 != 0) { assert!(m.remove(&i).is_some()); }
+    assert_eq!(m.len(), 667);
+    let keys: ::std::vec::Vec<u32> = m.keys().cloned().collect();
+    assert_eq!(keys, (0..2000u32).filter(|i| i % 3 == 0).collect::<::std::vec::Vec<_>>());
+    assert_eq!(m.get(&999).map(|&i| (i * 7919) % 2000), Some(999));
+}
+
+#[test]
+fn map_entries_and_cursor() {
+    use alloc::DefaultAlloc;
+    use btree_map::{self, BTreeMap};
+    use hash_map::{self, HashMap};
+    use std::collections::Bound::Included;
+
+    let words = "a rose is a rose is a rose".split(' ');
+    let mut h: HashMap<&str, u32> = HashMap::new();
+    let mut b: BTreeMap<&str, u32> = BTreeMap::new();
+    for w in words {
+        *h.entry(w).or_insert(0) += 1;
+        *b.entry(w).or_insert(0) += 1;
+    }
+    assert_eq!(h.get("rose"), Some(&3));
+    assert_eq!(b.iter().collect::<::std::vec::Vec<_>>(), vec![(&"a", &3), (&"is", &2), (&"rose", &3)]);
+    match h.entry("is") {
+        hash_map::Entry::Occupied(e) => assert_eq!(e.remove(), 2),
+        hash_map::Entry::Vacant(_) => panic!("\"is\" was counted"),
+    }
+    match b.entry("the") {
+        btree_map::Entry::Vacant(e) => { e.insert(0); }
+        btree_map::Entry::Occupied(_) => panic!("\"the\" was not counted"),
+    }
+
+    // double every value and drop the odd keys in one ordered pass
+    let mut m = BTreeMap::from_sorted_iter_in((0..500u32).map(|i| (i, i)), DefaultAlloc);
+    {
+        let mut c = m.lower_bound_mut(Included(&100));
+        loop {
+            let k = match c.key() { Some(&k) => k, None => break };
+            if k % 2 == 1 {
+                c.remove_current();
+            } else {
+                *c.value_mut().unwrap() *= 2;
+                c.move_next();
+            }
+        }
+        c.move_prev();
+        assert_eq!(c.key(), Some(&498));
+    }
+    assert_eq!(m.len(), 100 + 200);
+    assert_eq!(m.get(&99), Some(&99));
+    assert_eq!(m.get(&300), Some(&600));
+    assert_eq!(m.get(&301), None);
+}
+
+#[test]
+fn pressure_trims_registered_caches() {
+    use alloc::{DefaultAlloc, Kind};
+    use objpool::Pool;
+    use pressure::{self, Level};
+    use quarantine;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let q = Rc::new(RefCell::new(quarantine::Alloc::new(direct_alloc::Alloc, 4000)));
+    {
+        let mut q = q.borrow_mut();
+        let kind = unsafe { Kind::from_size_align(1000, 8) };
+        for _ in 0..4 {
+            unsafe { let p = q.alloc(kind); q.dealloc(p, kind); }
+        }
+        assert_eq!(q.parked_bytes(), 4000);
+    }
+    let pool = Rc::new(Pool::new(DefaultAlloc, || [0u8; 64], |_| ()));
+    drop((0..4).map(|_| pool.get()).collect::<::std::vec::Vec<_>>());
+    pressure::register(&q);
+    pressure::register(&pool);
+
+    assert_eq!(pressure::release(Level::Moderate), 2000 + 2 * 64);
+    assert_eq!(pool.idle_count(), 2);
+    assert_eq!(pressure::release(Level::Critical), 2000 + 2 * 64);
+    assert_eq!(q.borrow().parked_bytes(), 0);
+
+    // a dropped cache leaves the registry
+    drop(pool);
+    assert_eq!(pressure::release(Level::Critical), 0);
+}
+
+#[test]
+fn array_helpers_check_overflow() {
+    use alloc::AllocError;
+    use std::ptr::Unique;
+
+    let mut a = direct_alloc::Alloc;
+    unsafe {
+        let p = a.alloc_array::<u64>(4).unwrap();
+        for i in 0..4 { *p.offset(i) = i as u64; }
+        let p = a.realloc_array(p, 4, 1000).unwrap();
+        assert_eq!(*p.offset(3), 3);
+        a.dealloc_array(p, 1000).unwrap();
+
+        let huge = ::std::usize::MAX / 4;
+        assert_eq!(a.alloc_array::<u64>(huge).err(), Some(AllocError::CapacityOverflow));
+        let p = a.alloc_array::<u64>(1).unwrap();
+        let q = Unique::new(*p);
+        assert_eq!(a.realloc_array(p, 1, huge).err(), Some(AllocError::CapacityOverflow));
+        a.dealloc_array(q, 1).unwrap();
+    }
+}
+
+#[test]
+fn alloc_nonnull_reports_errors() {
+    use alloc::{AllocError, Kind, NonNullAddress};
+    use quota;
+    use std::ptr;
+
+    assert_eq!(NonNullAddress::new(ptr::null_mut()), None);
+    let q = quota::Alloc::new(direct_alloc::Alloc, Some(64), None);
+    let mut a = &q;
+    let kind = Kind::new::<[u64; 6]>();
+    unsafe {
+        let p = a.alloc_nonnull(kind).unwrap();
+        *p.cast::<[u64; 6]>() = [1, 2, 3, 4, 5, 6];
+        assert_eq!(a.alloc_nonnull(kind).err(), Some(AllocError::QuotaExceeded));
+        a.dealloc_nonnull(p, kind);
+    }
+    assert_eq!(q.usage().blocks, 0);
+}
+
+#[test]
+fn miri_owned_values_drop_exactly_once() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use raw_vec::RawVec;
+    use std::cell::Cell;
+    use vec::Vec;
+
+    struct Counted<'a>(&'a Cell<usize>);
+    impl<'a> Drop for Counted<'a> {
+        fn drop(&mut self) { self.0.set(self.0.get() + 1); }
+    }
+
+    let drops = Cell::new(0);
+    {
+        let mut v: Vec<Counted, DefaultAlloc> = Vec::new();
+        for _ in 0..20 { v.push(Counted(&drops)); }
+        v.truncate(5);
+        assert_eq!(drops.get(), 15);
+    }
+    assert_eq!(drops.get(), 20);
+
+    // Growing and shrinking moves values without running destructors.
+    let mut raw: RawVec<u64, DefaultAlloc> = RawVec::with_capacity(1);
+    unsafe { *raw.ptr() = 7; }
+    raw.double();
+    raw.reserve(1, 10);
+    assert_eq!(unsafe { *raw.ptr() }, 7);
+    let zst: RawVec<(), DefaultAlloc> = RawVec::with_capacity(3);
+    drop(zst);
+    drop(raw);
+
+    let b = Box::new_in(Counted(&drops), DefaultAlloc);
+    let (p, a) = b.value_alloc();
+    assert_eq!(drops.get(), 20);
+    drop(unsafe { Box::from_raw_alloc(*p, a) });
+    assert_eq!(drops.get(), 21);
+
+    let placed = in Boxing(DefaultAlloc) { Counted(&drops) };
+    drop(placed);
+    assert_eq!(drops.get(), 22);
+}
+
+#[test]
+fn miri_raw_buf_and_raw_table_keep_provenance() {
+    use alloc::{DefaultAlloc, Kind};
+    use raw_buf::RawBuf;
+    use raw_table::RawTable;
+
+    let (kind, offsets) = Kind::new::<u32>().extend(Kind::new::<u16>().array(4));
+    let mut buf: RawBuf<DefaultAlloc> = RawBuf::new(kind);
+    buf.view_mut::<u32>(0, 1)[0] = 9;
+    buf.view_mut::<u16>(offsets, 4).copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!((buf.view::<u32>(0, 1)[0], buf.view::<u16>(offsets, 4)[3]), (9, 4));
+    drop(buf);
+
+    // Rebuilds move every value into a fresh block.
+    let mut t = RawTable::new_in(DefaultAlloc);
+    for i in 0..50u64 { t.insert(i, i, |&x| x); }
+    let pos = t.find(7, |&x| x == 7).unwrap();
+    assert_eq!(t.erase(pos), 7);
+    assert_eq!(t.iter().map(|&x| x).sum::<u64>(), (0..50).sum::<u64>() - 7);
+}
+
+#[test]
+fn emplace_constructs_in_allocator_memory() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use vec::Vec;
+
+    let b: Box<[u64; 512], _> = Box::emplace_in(DefaultAlloc, |slot| slot.write([7; 512]));
+    assert!(b.iter().all(|&x| x == 7));
+
+    // Piecewise initialization through the raw pointer.
+    let b: Box<(u32, u32), _> = Boxing(DefaultAlloc).emplace(|mut slot| unsafe {
+        let p = slot.as_mut_ptr();
+        (*p).0 = 1;
+        (*p).1 = 2;
+        slot.assume_init()
+    });
+    assert_eq!(*b, (1, 2));
+
+    let mut v: Vec<String, DefaultAlloc> = Vec::new();
+    for i in 0..10 { v.push_with(|slot| slot.write(i.to_string())); }
+    assert_eq!(v.len(), 10);
+    assert_eq!(v[9], "9");
+
+    // A panicking initializer leaves the vector as it was.
+    let r = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        v.push_with(|_| panic!("no value"));
+    }));
+    assert!(r.is_err());
+    assert_eq!(v.len(), 10);
+}
+
+#[test]
+fn inline_alloc_keeps_blocks_in_the_allocator() {
+    use alloc::AllocError;
+    use boxed::Box;
+    use inline;
+    use vec::Vec;
+
+    let a: inline::Alloc<[u8; 64]> = inline::Alloc::new();
+    let lo = &a as *const _ as usize;
+    {
+        let b = Box::new_in(7u32, &a);
+        let p = &*b as *const u32 as usize;
+        assert!(p >= lo && p < lo + 64);
+
+        let mut v: Vec<u8, _> = Vec::with_capacity_alloc(8, &a);
+        for i in 0..30 { v.push(i); }
+        assert_eq!(v[29], 29);
+        assert_eq!(v.try_reserve(64), Err(AllocError::Exhausted));
+        assert_eq!(a.live(), 2);
+    }
+    assert_eq!(a.live(), 0);
+    assert_eq!(a.used(), 0);
+
+    // Freed in LIFO order, the storage is reused indefinitely.
+    for i in 0..1000u64 {
+        let b = Box::new_in(i, &a);
+        assert_eq!(*b, i);
+    }
+
+    // Reallocating the newest block to nothing frees it.
+    unsafe {
+        use alloc::{Kind, ShareAlloc};
+        let k = Kind::from_size_align(16, 8);
+        let p = a.alloc_shared(k);
+        assert_eq!(a.live(), 1);
+        a.realloc_shared(p, k, 0);
+        assert_eq!(a.live(), 0);
+        assert_eq!(a.used(), 0);
+    }
+}
+
+#[test]
+fn spill_raw_vec_moves_to_secondary_allocator() {
+    use alloc::DefaultAlloc;
+    use inline;
+    use raw_vec::SpillRawVec;
+
+    let a: inline::Alloc<[u8; 64]> = inline::Alloc::new();
+    let mut v: SpillRawVec<u32, _, DefaultAlloc> = SpillRawVec::with_capacity_in(4, &a, DefaultAlloc);
+    assert!(!v.is_spilled());
+    assert_eq!(a.live(), 1);
+    for i in 0..100 {
+        if i == v.cap() { v.double(); }
+        unsafe { *v.ptr().offset(i as isize) = i as u32; }
+    }
+    assert!(v.is_spilled());
+    // The primary block went back to the inline allocator on spilling.
+    assert_eq!(a.live(), 0);
+    for i in 0..100 {
+        assert_eq!(unsafe { *v.ptr().offset(i as isize) }, i as u32);
+    }
+}
+
+#[cfg(feature = "checked-alloc")]
+#[test]
+#[should_panic(expected = "RawVec::from_raw_parts: 0x3 is not aligned")]
+fn checked_alloc_rejects_misaligned_raw_parts() {
+    use alloc::DefaultAlloc;
+    use raw_vec::RawVec;
+    let _v: RawVec<u32, DefaultAlloc> = unsafe { RawVec::from_raw_parts(3 as *mut u32, 4) };
+}
+
+#[test]
+fn typed_arena_alloc_extend_packs_slices() {
+    use typed_arena::Arena;
+
+    let arena: Arena<String> = Arena::new();
+    {
+        let one = arena.alloc("head".to_string());
+        let args = arena.alloc_extend((0..5).map(|i| i.to_string()));
+        assert_eq!(args.len(), 5);
+        assert_eq!(args[4], "4");
+        // No size hint and longer than the first chunk: the slice moves to
+        // a new chunk partway through, and stays contiguous.
+        let long = arena.alloc_extend((0..1000).filter(|_| true).map(|i| format!("n{}", i)));
+        assert_eq!(long.len(), 1000);
+        assert!(long.iter().enumerate().all(|(i, s)| *s == format!("n{}", i)));
+        assert_eq!(*one, "head");
+        assert_eq!(arena.len(), 1006);
+        let empty = arena.alloc_extend(Vec::new());
+        assert!(empty.is_empty());
+    }
+
+    let frozen = arena.freeze();
+    assert_eq!(frozen.len(), 1006);
+    assert_eq!(frozen.get(6).map(|s| &s[..]), Some("n0"));
+}
+
+#[test]
+fn boxed_closures_are_callable() {
+    use boxed::{Box, FnBox};
+    use quota;
+    use alloc::DefaultAlloc;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn apply<F: Fn(u32) -> u32>(f: F, x: u32) -> u32 { f(x) }
+    fn twice<F: FnMut()>(mut f: F) { f(); f(); }
+
+    let q = quota::Alloc::new(DefaultAlloc, None, None);
+    let k = 3;
+    let add: Box<Fn(u32) -> u32, _> = Box::new_in(move |x| x + k, &q);
+    assert_eq!(apply(&*add, 1), 4);
+    assert_eq!(apply(add, 2), 5);
+
+    let hits = Rc::new(Cell::new(0));
+    let mut queue: Vec<Box<FnMut(), _>> = Vec::new();
+    for &n in &[1, 10] {
+        let hits = hits.clone();
+        queue.push(Box::new_in(move || hits.set(hits.get() + n), &q));
+    }
+    for task in queue { twice(task); }
+    assert_eq!(hits.get(), 22);
+
+    let name = "once".to_string();
+    let once: Box<FnBox<(), Output=String>, _> = Box::new_in(move || name, &q);
+    assert_eq!(once.call_box(()), "once");
+    assert_eq!(q.usage().blocks, 0);
+}
+
+#[test]
+fn scoped_default_alloc_reaches_deep_callees() {
+    use alloc::DefaultAlloc;
+    use quota;
+    use scoped::{with_default_alloc, ImplicitAlloc};
+    use vec::Vec;
+
+    // Knows nothing about the arena its caller chose.
+    fn squares(n: u64) -> u64 {
+        let mut v: Vec<u64, ImplicitAlloc> = Vec::new();
+        for i in 0..n { v.push(i * i); }
+        v.iter().sum()
+    }
+
+    let outer = quota::Alloc::new(DefaultAlloc, None, None);
+    let inner = quota::Alloc::new(DefaultAlloc, None, None);
+    with_default_alloc(&outer, || {
+        let _held: Vec<u8, ImplicitAlloc> = vec_of(3);
+        let sum = with_default_alloc(&inner, || {
+            let v = vec_of(2);
+            assert_eq!(inner.usage().blocks, 1);
+            drop(v);
+            squares(10)
+        });
+        assert_eq!(sum, 285);
+        assert_eq!(outer.usage().blocks, 1);
+    });
+    assert_eq!(outer.usage().blocks, 0);
+    // Outside any scope it falls back to the heap.
+    assert_eq!(squares(4), 14);
+
+    fn vec_of(n: usize) -> Vec<u8, ImplicitAlloc> { Vec::with_capacity(n) }
+
+    // A block grows and is freed through the scope that made it, even
+    // from inside a nested one.
+    with_default_alloc(&outer, || {
+        let mut held = vec_of(1);
+        with_default_alloc(&inner, || {
+            held.extend(0..100);
+            assert_eq!(inner.usage().blocks, 0);
+            assert_eq!(outer.usage().blocks, 1);
+            drop(held);
+            assert_eq!(outer.usage().blocks, 0);
+        });
+    });
+}
+
+#[test]
+fn branded_scope_confines_allocations() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use brand;
+    use sync_bump;
+    use vec::Vec;
+
+    let total = brand::scope(sync_bump::Alloc::new(DefaultAlloc, 1 << 16), |region| {
+        let b = Box::new_in(40u64, region);
+        let mut v: Vec<u64, _> = Vec::with_capacity_alloc(4, region);
+        v.push(2);
+        assert!(region.get().used() > 0);
+        // Returning `b` or `v` here would not compile: their type
+        // names the scope's brand.
+        *b + v[0]
+    });
+    assert_eq!(total, 42);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn refused_growth_keeps_the_old_buffer() {
+    use alloc::{AllocError, DefaultAlloc};
+    use conformance;
+    use quota;
+    use vec::Vec;
+
+    conformance::check_failed_realloc(&mut DefaultAlloc, ::std::isize::MAX as usize / 2);
+
+    let q = quota::Alloc::new(DefaultAlloc, Some(256), None);
+    let mut v: Vec<u64, _> = Vec::with_capacity_alloc(8, &q);
+    for i in 0..8 { v.push(i); }
+    assert_eq!(v.try_reserve(100), Err(AllocError::QuotaExceeded));
+    assert_eq!(v.len(), 8);
+    assert!(v.iter().cloned().eq(0..8));
+    // Still usable within the budget.
+    assert_eq!(v.try_reserve(8), Ok(()));
+    v.push(8);
+    assert_eq!(v[8], 8);
+}
+
+#[test]
+fn place_back_and_vacant_entry_emplacement() {
+    use alloc::DefaultAlloc;
+    use btree_map::{BTreeMap, Entry};
+    use vec::Vec;
+
+    let mut v: Vec<[u8; 4096], DefaultAlloc> = Vec::new();
+    for i in 0..3 {
+        let r = in v.place_back() { [i as u8; 4096] };
+        r[0] = 9;
+    }
+    assert_eq!(v.len(), 3);
+    assert_eq!((v[2][0], v[2][1]), (9, 2));
+
+    let mut w: Vec<String, DefaultAlloc> = Vec::new();
+    for s in &["a", "c"] { w.push(s.to_string()); }
+    w.insert_with(1, |slot| slot.write("b".to_string()));
+    assert_eq!(&w[..], &["a", "b", "c"]);
+    let r = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        w.insert_with(0, |_| panic!("no value"));
+    }));
+    assert!(r.is_err());
+    assert_eq!(&w[..], &["a", "b", "c"]);
+
+    let mut m: BTreeMap<u32, [u64; 256], DefaultAlloc> = BTreeMap::new();
+    for k in 0..100 {
+        match m.entry(k) {
+            Entry::Vacant(e) => { e.insert_with(|slot| slot.write([k as u64; 256])); }
+            Entry::Occupied(_) => unreachable!(),
+        }
+    }
+    assert_eq!(m.len(), 100);
+    assert_eq!(m.get(&42).unwrap()[255], 42);
+}
+
+#[test]
+fn kinds_round_to_page_and_cache_line_granularity() {
+    use alloc::Kind;
+    use granularity::Granularity;
+
+    let page = Granularity::page();
+    assert!(page.bytes().is_power_of_two());
+    assert_eq!(page, Granularity::page());
+    assert_eq!(page.round_up(1), page.bytes());
+    assert_eq!(page.round_up(page.bytes()), page.bytes());
+    assert_eq!(page.round_down(page.bytes() + 1), page.bytes());
+
+    let k = Kind::new::<[u8; 100]>().round_up_to(64);
+    assert_eq!((k.size(), k.align()), (128, 1));
+    let k = page.kind(Kind::new::<u64>());
+    assert_eq!((k.size(), k.align()), (page.bytes(), page.bytes()));
+
+    let line = Granularity::cache_line();
+    assert!(line.is_aligned(line.round_up(65)));
+}
+
+#[test]
+fn raw_buf_hosts_typed_regions() {
+    use alloc::{DefaultAlloc, Kind};
+    use raw_buf::RawBuf;
+
+    let (k, offsets) = Kind::new::<u64>().extend(Kind::new::<u32>().array(4));
+    let (k, payload) = k.extend(Kind::new::<u8>().array(10));
+    let mut buf: RawBuf<DefaultAlloc> = RawBuf::new(k);
+    assert!(buf.as_bytes().iter().all(|&b| b == 0));
+
+    buf.view_mut::<u64>(0, 1)[0] = 0xfeed;
+    buf.view_mut::<u32>(offsets, 4).copy_from_slice(&[1, 2, 3, 4]);
+    buf.view_mut::<u8>(payload, 10)[9] = 7;
+    assert_eq!(buf.view::<u64>(0, 1), &[0xfeed]);
+    assert_eq!(buf.view::<u32>(offsets, 4), &[1, 2, 3, 4]);
+    assert_eq!(buf.as_bytes()[payload + 9], 7);
+
+    let past_end = ::std::panic::catch_unwind(|| { buf.view::<u8>(payload, 11).len() });
+    assert!(past_end.is_err());
+    let misaligned = ::std::panic::catch_unwind(|| { buf.view::<u32>(1, 1).len() });
+    assert!(misaligned.is_err());
+}
+
+#[test]
+fn boxed_slices_round_trip_through_bytes() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use bump;
+    use leakcheck;
+
+    // Little-endian on the wire, whatever the host.
+    let bytes: Vec<u8> = [1u32, 0xdead_beef, 7].iter().flat_map(|&w| {
+        (0..4).map(move |i| (w >> (8 * i)) as u8)
+    }).collect();
+
+    let mut arena = bump::Alloc::new(DefaultAlloc);
+    // Offset by one so the source is misaligned for `u32`.
+    let mut src = vec![0u8];
+    src.extend_from_slice(&bytes);
+    let b: Box<[u32], _> = Box::copy_from_bytes_in(&src[1..], &mut arena);
+    assert_eq!(b.as_ptr() as usize % 4, 0);
+    let host: Vec<u32> = b.iter().map(|&w| u32::from_le(w)).collect();
+    assert_eq!(host, [1, 0xdead_beef, 7]);
+    assert_eq!(b.as_bytes(), &bytes[..]);
+
+    let empty: Box<[u64], DefaultAlloc> = Box::copy_from_bytes_in(&[], DefaultAlloc);
+    assert!(empty.is_empty());
+    // The empty block is allocated like any other, to match its free.
+    let checked = leakcheck::Alloc::new(DefaultAlloc);
+    let empty: Box<[u64], _> = Box::copy_from_bytes_in(&[], checked.clone());
+    assert_eq!(checked.live_count(), 1);
+    drop(empty);
+    assert_eq!(checked.live_count(), 0);
+    let ragged = ::std::panic::catch_unwind(|| {
+        Box::<[u32], DefaultAlloc>::copy_from_bytes_in(&[0; 5], DefaultAlloc).len()
+    });
+    assert!(ragged.is_err());
+}
+
+#[test]
+fn timeline_exports_chrome_trace_tracks() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use observed;
+    use timeline::Timeline;
+
+    let mut a = observed::Alloc::new(DefaultAlloc, Timeline::new());
+    unsafe {
+        a.observer_mut().set_tag(Some("parser"));
+        let p = a.alloc(Kind::new::<[u8; 100]>());
+        a.observer_mut().set_tag(None);
+        let q = a.alloc(Kind::new::<[u8; 8]>());
+        // Freed under a different tag, still charged to "parser".
+        a.dealloc(p, Kind::new::<[u8; 100]>());
+        a.dealloc(q, Kind::new::<[u8; 8]>());
+    }
+    assert_eq!(a.observer().len(), 4);
+
+    let mut out = Vec::new();
+    a.observer().write_chrome_trace(&mut out).unwrap();
+    let json = String::from_utf8(out).unwrap();
+    assert!(json.starts_with("{\"traceEvents\":["));
+    assert!(json.contains("\"args\":{\"name\":\"parser\"}"));
+    assert!(json.contains("\"name\":\"live bytes (parser)\",\"ph\":\"C\""));
+    assert!(json.contains("\"args\":{\"bytes\":100}"));
+    assert_eq!(json.matches("\"name\":\"dealloc\"").count(), 2);
+    assert!(json.ends_with("],\"displayTimeUnit\":\"ms\"}"));
+}
+
+#[test]
+fn verify_fills_fresh_and_freed_memory() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use bump;
+    use verify;
+
+    let mut arena = bump::Alloc::new(DefaultAlloc);
+    {
+        let mut v = verify::Alloc::new(&mut arena);
+        v.enable_default_fills();
+        unsafe {
+            let k = Kind::new::<[u8; 32]>();
+            let p = v.alloc(k);
+            assert!((0..32).all(|i| *p.offset(i) == verify::DEFAULT_JUNK));
+            *p = 1;
+            let p = v.realloc(p, k, 64);
+            assert_eq!(*p, 1);
+            assert!((32..64).all(|i| *p.offset(i) == verify::DEFAULT_JUNK));
+            // The arena keeps the memory mapped, so the poison can be
+            // inspected after the free.
+            v.dealloc(p, Kind::new::<[u8; 64]>());
+            assert!((0..64).all(|i| *p.offset(i) == verify::DEFAULT_POISON));
+
+            v.set_fills(None, None);
+            let q = v.alloc(k);
+            assert_eq!(*q, verify::DEFAULT_POISON);
+            v.dealloc(q, k);
+        }
+    }
+}
+
+#[test]
+fn align_cap_rejects_or_emulates_over_aligned_kinds() {
+    use alloc::{Alloc, AllocError, Kind};
+    use align_cap::{self, Mode};
+    use ffi;
+
+    // `direct_alloc` is malloc underneath: 16-byte alignment at most.
+    let line = Kind::new_over_aligned::<[u8; 100]>(64);
+
+    let mut strict = align_cap::Alloc::new(direct_alloc::Alloc, 16, Mode::Reject);
+    unsafe {
+        assert!(strict.alloc(line).is_null());
+        assert_eq!(strict.alloc_error(line), AllocError::Unsupported);
+        let p = strict.alloc(Kind::new::<u64>());
+        assert!(!p.is_null());
+        strict.dealloc(p, Kind::new::<u64>());
+    }
+
+    let mut emulated = align_cap::Alloc::new(direct_alloc::Alloc, 16, Mode::Emulate);
+    unsafe {
+        let p = emulated.alloc(line);
+        assert_eq!(p as usize % 64, 0);
+        for i in 0..100 { *p.offset(i) = i as u8; }
+        let p = emulated.realloc(p, line, 300);
+        assert_eq!(p as usize % 64, 0);
+        assert_eq!(*p.offset(99), 99);
+        emulated.dealloc(p, Kind::from_size_align(300, 64));
+
+        // Growing from nothing still lands in an emulated block.
+        let empty = Kind::from_size_align(0, 64);
+        let p = emulated.alloc(empty);
+        let p = emulated.realloc(p, empty, 32);
+        assert_eq!(p as usize % 64, 0);
+        emulated.dealloc(p, Kind::from_size_align(32, 64));
+    }
+
+    let heap = unsafe { &mut *::std::boxed::Box::into_raw(::std::boxed::Box::new(emulated)) };
+    let vt = ffi::c_api_for(heap);
+    unsafe {
+        let p = (vt.aligned_malloc)(vt.ctx, 64, 10) as *mut u8;
+        assert_eq!(p as usize % 64, 0);
+        *p.offset(9) = 9;
+        let p = (vt.realloc)(vt.ctx, p as *mut _, 1000) as *mut u8;
+        assert_eq!(p as usize % 64, 0);
+        assert_eq!(*p.offset(9), 9);
+        (vt.free)(vt.ctx, p as *mut _);
+        assert!((vt.aligned_malloc)(vt.ctx, 48, 10).is_null());
+    }
+}
+
+#[test]
+fn stats_decayed_rates_suggest_pool_sizes() {
+    use alloc::{Kind, ShareAlloc};
+    use stats::{self, PoolSize};
+
+    let s = stats::Alloc::new(direct_alloc::Alloc);
+    let churn = |n: usize, kind: Kind| unsafe {
+        for _ in 0..n {
+            let p = s.alloc_shared(kind);
+            s.dealloc_shared(p, kind);
+        }
+    };
+
+    // Steady traffic of 8 small blocks per tick, one burst of large.
+    for t in 0..10 {
+        churn(8, Kind::new::<[u8; 24]>());
+        if t == 0 { churn(100, Kind::new::<[u8; 1000]>()); }
+        s.tick();
+    }
+    let rates = s.class_rates();
+    assert_eq!(rates.len(), 2);
+    assert!((rates[0].rate - 8.0).abs() < 0.1);
+    // The burst has halved every tick since.
+    assert!(rates[1].rate < 0.5);
+    assert_eq!(s.suggest_pool_sizes(), vec![PoolSize { upper: 32, blocks: 8 }]);
+
+    s.set_decay(1.0);
+    churn(3, Kind::new::<[u8; 1000]>());
+    s.tick();
+    assert_eq!(s.suggest_pool_sizes(), vec![PoolSize { upper: 1024, blocks: 3 }]);
+}
+
+#[test]
+fn pinned_allocator_catches_foreign_frees() {
+    use alloc::{Alloc, Kind};
+    use single_thread::Pinned;
+    use std::thread;
+
+    let mut a = Pinned::new(direct_alloc::Alloc);
+    let k = Kind::new::<[u64; 4]>();
+    let p = unsafe { a.alloc(k) } as usize;
+    assert!(a.is_owner());
+
+    let mut remote = a.clone();
+    let r = thread::spawn(move || {
+        assert!(!remote.is_owner());
+        unsafe { remote.dealloc(p as *mut u8, k); }
+    }).join();
+    if cfg!(debug_assertions) {
+        assert!(r.is_err());
+        unsafe { a.dealloc(p as *mut u8, k); }
+    } else {
+        assert!(r.is_ok());
+        assert_eq!(a.drain_mailbox(), 1);
+    }
+
+    let mut remote = a.clone();
+    let r = thread::spawn(move || unsafe { remote.alloc(k) as usize }).join();
+    assert!(r.is_err());
+}
+
+#[test]
+fn remote_frees_are_returned_to_the_owning_thread() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use boxed::Box;
+    use remote_free::Queue;
+    use single_thread::Pinned;
+    use std::sync::Arc;
+    use std::thread;
+    use verify;
+
+    let q = Arc::new(Queue::new());
+    let workers: Vec<_> = (0..4).map(|i| {
+        let q = q.clone();
+        thread::spawn(move || q.push((16 * (i + 1)) as *mut u8, Kind::new::<u64>()))
+    }).collect();
+    for w in workers { w.join().unwrap(); }
+    assert_eq!(q.len(), 4);
+    let mut seen = Vec::new();
+    assert_eq!(q.drain(|p, _| seen.push(p as usize)), 4);
+    seen.sort();
+    assert_eq!(seen, [16, 32, 48, 64]);
+    assert!(q.is_empty());
+
+    // A verifying pool, so a block freed twice or on the wrong thread
+    // would panic.
+    let v = verify::Alloc::new(DefaultAlloc);
+    let mut pool = Pinned::with_remote_free(v.clone());
+    let b = Box::new_in([7u64; 8], pool.clone());
+    let sum = thread::spawn(move || b.iter().sum::<u64>()).join().unwrap();
+    assert_eq!(sum, 56);
+    assert_eq!(pool.pending_remote_frees(), 1);
+    assert_eq!(v.live_count(), 1);
+    unsafe {
+        let k = Kind::new::<u32>();
+        let p = pool.alloc(k);
+        assert_eq!(pool.pending_remote_frees(), 0);
+        assert_eq!(v.live_count(), 1);
+        pool.dealloc(p, k);
+    }
+    assert_eq!(v.live_count(), 0);
+}
+
+#[test]
+fn survivors_are_rehomed_out_of_a_frame_arena() {
+    use alloc::DefaultAlloc;
+    use boxed::Box;
+    use string::String;
+    use sync_bump;
+    use vec::Vec;
+
+    let mut frame = sync_bump::Alloc::new(DefaultAlloc, 1 << 16);
+    let mut kept: Vec<String, DefaultAlloc> = Vec::new();
+    for i in 0..3 {
+        let (name, total) = frame.compact(|a| {
+            let mut scratch: Vec<u64, _> = Vec::with_capacity_alloc(100, a);
+            for j in 0..100 { scratch.push(j * i); }
+            let mut name = String::with_alloc(a);
+            name.push_str("frame ");
+            name.push_str(&i.to_string());
+            let total = Box::new_in(scratch.iter().sum::<u64>(), a);
+            assert!(a.used() > 0);
+            (name.rehome_in(DefaultAlloc), total.rehome_in(DefaultAlloc))
+        });
+        assert_eq!(frame.used(), 0);
+        assert_eq!(*total, 4950 * i);
+        kept.push(name);
+    }
+    assert_eq!(kept.iter().map(|s| s.as_str()).collect::<::std::vec::Vec<_>>(),
+               ["frame 0", "frame 1", "frame 2"]);
+
+    let mut v: Vec<u32, _> = Vec::with_capacity_alloc(64, &frame);
+    v.extend_from_slice(&[1, 2, 3]);
+    let v: Vec<u32, DefaultAlloc> = v.rehome_in(DefaultAlloc);
+    assert_eq!((v.len(), v.capacity()), (3, 3));
+}
+
+#[test]
+fn wrappers_forward_in_place_resizes() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use bump;
+    use quota;
+    use verify;
+
+    // The newest block of a bump arena grows and shrinks in place.
+    let mut q = quota::Alloc::new(verify::Alloc::new(bump::Alloc::new(DefaultAlloc)),
+                                  Some(256), None);
+    unsafe {
+        let k = Kind::from_size_align(32, 8);
+        let p = q.alloc(k);
+        assert!(q.grow_in_place(p, k, 128).is_ok());
+        assert_eq!(q.usage().bytes, 128);
+        assert!(q.grow_in_place(p, Kind::from_size_align(128, 8), 512).is_err());
+        assert!(q.shrink_in_place(p, Kind::from_size_align(128, 8), 64).is_ok());
+        assert_eq!(q.usage().bytes, 64);
+        // `verify` panics unless it recorded the new size.
+        q.dealloc(p, Kind::from_size_align(64, 8));
+    }
+    assert_eq!(q.usage(), quota::Usage { bytes: 0, blocks: 0 });
+}
+
+#[test]
+fn quota_charges_growth_into_slack() {
+    use alloc::{self, Address, Alloc, Capacity, DefaultAlloc, Kind};
+    use quota;
+
+    // Rounds every block up to 64 bytes and says so.
+    struct Slack;
+    unsafe fn rounded(kind: Kind) -> Kind { Kind::from_size_align((kind.size() + 63) & !63, kind.align()) }
+    impl alloc::Alloc for Slack {
+        unsafe fn alloc(&mut self, kind: Kind) -> Address { DefaultAlloc.alloc(rounded(kind)) }
+        unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { DefaultAlloc.dealloc(ptr, rounded(kind)) }
+        unsafe fn usable_size(&self, kind: Kind) -> Capacity { rounded(kind).size() }
+    }
+
+    let mut q = quota::Alloc::new(Slack, Some(48), None);
+    unsafe {
+        let k = Kind::from_size_align(10, 1);
+        assert_eq!(q.usable_size(k), 10);
+        let p = q.alloc(k);
+        assert!(q.grow_in_place(p, k, 40).is_ok());
+        assert_eq!(q.usage().bytes, 40);
+        // room in the block, but not in the quota
+        assert!(q.grow_in_place(p, Kind::from_size_align(40, 1), 60).is_err());
+        q.dealloc(p, Kind::from_size_align(40, 1));
+    }
+    assert_eq!(q.usage().bytes, 0);
+}
+
+#[test]
+fn stats_reaccount_blocks_resized_in_place() {
+    use alloc::{Alloc, DefaultAlloc, Kind};
+    use bump;
+    use stats;
+
+    let mut s = stats::Alloc::new(bump::Alloc::new(DefaultAlloc));
+    s.set_tag(Some("grown"));
+    unsafe {
+        let k = Kind::from_size_align(16, 8);
+        assert_eq!(s.usable_size(k), 16);
+        let p = s.alloc(k);
+        s.set_tag(None);
+        assert!(s.grow_in_place(p, k, 64).is_ok());
+        let r = s.report();
+        assert_eq!((r.live_blocks, r.live_bytes), (1, 64));
+        assert_eq!((r.tags[0].tag, r.tags[0].bytes), ("grown", 64));
+        assert!(s.shrink_in_place(p, Kind::from_size_align(64, 8), 24).is_ok());
+        assert_eq!(s.report().live_bytes, 24);
+        s.dealloc(p, Kind::from_size_align(24, 8));
     }
-    println!("at end of demo_bump_in_place");
+    let r = s.report();
+    assert_eq!((r.live_blocks, r.live_bytes), (0, 0));
 }
@@ -0,0 +1,154 @@
+// A wrapper that limits how many bytes may be allocated per time
+// window, for code (game loops, audio callbacks) that treats heap
+// allocation in its hot path as a bug to be surfaced.
+//
+// The limit is a token bucket: each window adds `rate` bytes of
+// allowance, up to at most `burst` saved up, and each allocation (or
+// realloc growth) spends its size. Windows advance either explicitly,
+// by calling `end_frame()`, or from a caller-supplied clock.
+//
+// When an allocation exceeds the allowance the wrapper either fails
+// it (returns null, with `alloc_error` reporting
+// `AllocError::Throttled`) or reports it to a callback and lets it
+// through.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, ShareAlloc, Size};
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::ptr;
+
+/// An allocation that went over the allowance.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Exceeded {
+    pub requested: usize,
+    pub available: usize,
+    pub window: u64,
+}
+
+pub enum OnExceed {
+    /// Make the allocation fail.
+    Fail,
+    /// Report it and allow it anyway.
+    Report(Box<Fn(Exceeded)>),
+}
+
+pub enum Window {
+    /// Windows end when `end_frame` is called.
+    Frames,
+    /// Windows last `period` ticks of `now()`.
+    Clock { now: fn() -> u64, period: u64 },
+}
+
+pub struct Alloc<A> {
+    inner: RefCell<A>,
+    rate: usize,
+    burst: usize,
+    window: Window,
+    on_exceed: OnExceed,
+    tokens: Cell<usize>,
+    current: Cell<u64>,
+    exceeded: Cell<usize>,
+    // whether the most recent request was refused by the throttle
+    refused: Cell<bool>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    /// Allows `rate` bytes per window, saving up at most `burst`
+    /// (which should be at least `rate`).
+    pub fn new(inner: A, rate: usize, burst: usize, window: Window, on_exceed: OnExceed)
+               -> Alloc<A> {
+        let start = match window {
+            Window::Frames => 0,
+            Window::Clock { now, period } => now() / cmp::max(period, 1),
+        };
+        Alloc { inner: RefCell::new(inner), rate: rate, burst: cmp::max(burst, rate),
+                window: window, on_exceed: on_exceed, tokens: Cell::new(rate),
+                current: Cell::new(start), exceeded: Cell::new(0), refused: Cell::new(false) }
+    }
+
+    /// Closes the current frame (for `Window::Frames`).
+    pub fn end_frame(&self) {
+        self.advance_to(self.current.get() + 1);
+    }
+
+    /// Bytes that may still be allocated in the current window.
+    pub fn available(&self) -> usize {
+        self.refresh();
+        self.tokens.get()
+    }
+
+    /// How many allocations have exceeded the allowance so far.
+    pub fn exceeded_count(&self) -> usize { self.exceeded.get() }
+
+    fn advance_to(&self, window: u64) {
+        let elapsed = window.saturating_sub(self.current.get());
+        if elapsed == 0 { return; }
+        let refill = (self.rate as u64).saturating_mul(elapsed);
+        let tokens = cmp::min(self.tokens.get() as u64 + refill, self.burst as u64);
+        self.tokens.set(tokens as usize);
+        self.current.set(window);
+    }
+
+    fn refresh(&self) {
+        if let Window::Clock { now, period } = self.window {
+            self.advance_to(now() / cmp::max(period, 1));
+        }
+    }
+
+    /// Spends `bytes`; returns false if the allocation must fail.
+    fn spend(&self, bytes: usize) -> bool {
+        self.refresh();
+        let tokens = self.tokens.get();
+        if bytes <= tokens {
+            self.tokens.set(tokens - bytes);
+            return true;
+        }
+        self.exceeded.set(self.exceeded.get() + 1);
+        let e = Exceeded { requested: bytes, available: tokens, window: self.current.get() };
+        match self.on_exceed {
+            OnExceed::Fail => false,
+            OnExceed::Report(ref f) => { f(e); self.tokens.set(0); true }
+        }
+    }
+}
+
+impl<A: alloc::Alloc> ShareAlloc for Alloc<A> {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        self.refused.set(!self.spend(kind.size()));
+        if self.refused.get() { return ptr::null_mut(); }
+        self.inner.borrow_mut().alloc(kind)
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        self.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    unsafe fn usable_size_shared(&self, kind: Kind) -> Capacity {
+        self.inner.borrow().usable_size(kind)
+    }
+
+    unsafe fn alloc_error_shared(&self, kind: Kind) -> AllocError {
+        if self.refused.get() {
+            AllocError::Throttled
+        } else {
+            self.inner.borrow().alloc_error(kind)
+        }
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.refused.set(new_size > kind.size() && !self.spend(new_size - kind.size()));
+        if self.refused.get() { return ptr::null_mut(); }
+        self.inner.borrow_mut().realloc(ptr, kind, new_size)
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn alloc_error(&self, kind: Kind) -> AllocError { self.alloc_error_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
@@ -0,0 +1,151 @@
+// Allocation timelines for viewing next to a CPU profile.
+//
+// `Timeline` is an `observed` observer that timestamps every event and
+// attributes it to the current tag (see `set_tag`, as in `stats`);
+// `write_chrome_trace` then writes the whole run in the Chrome Trace
+// Event format, which chrome://tracing, Perfetto and speedscope all
+// open. Each tag becomes its own track (a "thread" in the viewer)
+// holding an instant event per call, and a "live bytes" counter per
+// tag plots its footprint over time, so a spike can be lined up with
+// whatever the profiler says was running.
+//
+// A block keeps the tag it was allocated under, so its free lands on
+// the same track and counter even if the tag changed in between.
+// Timestamps are microseconds since the `Timeline` was created.
+
+use alloc::Address;
+use observed::{AllocObserver, Event};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Instant;
+
+const UNTAGGED: &'static str = "untagged";
+
+struct Record {
+    micros: u64,
+    event: Event,
+    tag: &'static str,
+}
+
+pub struct Timeline {
+    start: Instant,
+    tag: Option<&'static str>,
+    block_tags: HashMap<usize, &'static str>,
+    records: Vec<Record>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline { start: Instant::now(), tag: None,
+                   block_tags: HashMap::new(), records: Vec::new() }
+    }
+
+    /// Attributes subsequent allocations to `tag`.
+    pub fn set_tag(&mut self, tag: Option<&'static str>) { self.tag = tag; }
+
+    /// Number of events recorded so far.
+    pub fn len(&self) -> usize { self.records.len() }
+
+    fn now(&self) -> u64 {
+        let d = self.start.elapsed();
+        d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1000) as u64
+    }
+
+    fn take_tag(&mut self, ptr: Address) -> &'static str {
+        self.block_tags.remove(&(ptr as usize)).unwrap_or(UNTAGGED)
+    }
+
+    fn give_tag(&mut self, ptr: Address, tag: &'static str) {
+        if !ptr.is_null() && tag != UNTAGGED { self.block_tags.insert(ptr as usize, tag); }
+    }
+
+    /// Writes the recorded events as Chrome Trace Event JSON.
+    pub fn write_chrome_trace<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut tracks: Vec<&'static str> = vec![UNTAGGED];
+        let mut live: HashMap<&'static str, usize> = HashMap::new();
+        let mut sep = "";
+        try!(write!(w, "{{\"traceEvents\":["));
+        for r in &self.records {
+            let tid = match tracks.iter().position(|&t| t == r.tag) {
+                Some(i) => i,
+                None => {
+                    tracks.push(r.tag);
+                    tracks.len() - 1
+                }
+            };
+            let (name, ptr, size, delta) = match r.event {
+                Event::Alloc { ptr, kind } if ptr.is_null() => ("alloc failed", ptr, kind.size(), 0),
+                Event::Alloc { ptr, kind } => ("alloc", ptr, kind.size(), kind.size() as isize),
+                Event::Dealloc { ptr, kind } => ("dealloc", ptr, kind.size(), -(kind.size() as isize)),
+                Event::Realloc { ptr, new_ptr, new_size, .. } if new_ptr.is_null() =>
+                    ("realloc failed", ptr, new_size, 0),
+                Event::Realloc { kind, new_ptr, new_size, .. } =>
+                    ("realloc", new_ptr, new_size, new_size as isize - kind.size() as isize),
+            };
+            try!(write!(w, "{}{{\"name\":\"{}\",\"cat\":\"alloc\",\"ph\":\"i\",\"s\":\"t\",\
+                            \"ts\":{},\"pid\":1,\"tid\":{},\"args\":{{\"ptr\":\"{:p}\",\"size\":{}}}}}",
+                        sep, name, r.micros, tid, ptr, size));
+            sep = ",";
+            if delta != 0 {
+                let bytes = live.entry(r.tag).or_insert(0);
+                *bytes = (*bytes as isize + delta) as usize;
+                try!(write!(w, ",{{\"name\":\"live bytes ({})\",\"ph\":\"C\",\"ts\":{},\"pid\":1,\
+                                \"args\":{{\"bytes\":{}}}}}",
+                            Escaped(r.tag), r.micros, *bytes));
+            }
+        }
+        for (tid, &tag) in tracks.iter().enumerate() {
+            try!(write!(w, "{}{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\
+                            \"args\":{{\"name\":\"{}\"}}}}",
+                        sep, tid, Escaped(tag)));
+            sep = ",";
+        }
+        write!(w, "],\"displayTimeUnit\":\"ms\"}}")
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Timeline { Timeline::new() }
+}
+
+impl AllocObserver for Timeline {
+    fn observe(&mut self, event: Event) {
+        let tag = match event {
+            Event::Alloc { ptr, .. } => {
+                let tag = self.tag.unwrap_or(UNTAGGED);
+                self.give_tag(ptr, tag);
+                tag
+            }
+            Event::Dealloc { ptr, .. } => self.take_tag(ptr),
+            Event::Realloc { ptr, new_ptr, .. } if new_ptr.is_null() => {
+                self.block_tags.get(&(ptr as usize)).cloned().unwrap_or(UNTAGGED)
+            }
+            Event::Realloc { ptr, new_ptr, .. } => {
+                let tag = self.take_tag(ptr);
+                self.give_tag(new_ptr, tag);
+                tag
+            }
+        };
+        let micros = self.now();
+        self.records.push(Record { micros: micros, event: event, tag: tag });
+    }
+}
+
+// A tag as the contents of a JSON string.
+struct Escaped<'a>(&'a str);
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => try!(f.write_str("\\\"")),
+                '\\' => try!(f.write_str("\\\\")),
+                c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+                c => try!(write!(f, "{}", c)),
+            }
+        }
+        Ok(())
+    }
+}
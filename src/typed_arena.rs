@@ -0,0 +1,226 @@
+// A typed arena: allocate many values of one type through a shared
+// reference and get `&mut T` back.
+//
+// Values are placed in chunks obtained from `A`. A chunk is never
+// reallocated, so a reference handed out by `alloc` stays valid for as
+// long as the arena is borrowed. When a chunk fills up a new one,
+// twice as large, is started. Nothing is freed until the arena itself
+// is dropped, at which point every value's destructor runs and the
+// chunks go back to `A`.
+//
+// `alloc_extend` places a whole sequence contiguously, as one slice.
+// If the sequence outgrows the current chunk, what has been placed so
+// far moves to a new chunk large enough for it, and the old chunk's
+// tail goes unused; so chunks other than the current one may not be
+// full, and each records how many values it holds.
+//
+// This differs from a bump allocator implementing `Alloc`: that hands
+// out raw memory of any kind, whereas this owns typed values and is
+// what most callers reaching for "an arena" actually want.
+//
+// The unused tail of the current chunk is marked inaccessible for
+// memory checkers (see `annotate`).
+//
+// Once built, an arena can be `freeze`d into a `Frozen` handle: it
+// can no longer grow, but it can be cloned and shared across threads
+// for reading, and the values are dropped when the last handle goes.
+
+use alloc::{Alloc, DefaultAlloc, Kind};
+use annotate;
+
+use std::cell::RefCell;
+use std::cmp;
+use std::intrinsics;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+const INITIAL_BYTES: usize = 4096;
+
+struct Chunk<T> {
+    start: *mut T,
+    cap: usize,
+    // number of values in the chunk
+    len: usize,
+}
+
+struct Inner<T, A:Alloc> {
+    alloc: A,
+    // the last chunk is the current one
+    chunks: Vec<Chunk<T>>,
+}
+
+pub struct Arena<T, A:Alloc = DefaultAlloc> {
+    inner: RefCell<Inner<T, A>>,
+}
+
+impl<T, A:Alloc> Arena<T, A> {
+    pub fn new() -> Self where A: Default {
+        Arena::with_alloc(A::default())
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        Arena { inner: RefCell::new(Inner { alloc: a, chunks: Vec::new() }) }
+    }
+
+    /// Moves `value` into the arena and returns a reference to it.
+    pub fn alloc(&self, value: T) -> &mut T {
+        let mut inner = self.inner.borrow_mut();
+        if inner.room() == 0 { inner.grow(1); }
+        unsafe {
+            let c = inner.chunks.last_mut().unwrap();
+            let p = c.start.offset(c.len as isize);
+            annotate::undefined(p as *mut u8, mem::size_of::<T>());
+            ptr::write(p, value);
+            c.len += 1;
+            &mut *p
+        }
+    }
+
+    /// Moves every item of `iter` into the arena, contiguously, and
+    /// returns them as one slice.
+    ///
+    /// The iterator must not allocate from this arena itself (that
+    /// panics, as the arena is busy). If it panics, the items already
+    /// taken from it stay in the arena and are dropped with it.
+    pub fn alloc_extend<I>(&self, iter: I) -> &mut [T] where I: IntoIterator<Item=T> {
+        let mut iter = iter.into_iter();
+        let mut inner = self.inner.borrow_mut();
+        let hint = iter.size_hint().0;
+        if inner.room() < cmp::max(hint, 1) { inner.grow(cmp::max(hint, 1)); }
+        // the slice is the `n` values from `first` in the current chunk
+        let mut first = inner.chunks.last().unwrap().len;
+        let mut n = 0;
+        while let Some(value) = iter.next() {
+            if inner.room() == 0 {
+                let more = cmp::max(iter.size_hint().0, 1);
+                inner.rechunk(first, n, more);
+                first = 0;
+            }
+            unsafe {
+                let c = inner.chunks.last_mut().unwrap();
+                let p = c.start.offset(c.len as isize);
+                annotate::undefined(p as *mut u8, mem::size_of::<T>());
+                ptr::write(p, value);
+                c.len += 1;
+            }
+            n += 1;
+        }
+        unsafe {
+            let start = inner.chunks.last().unwrap().start.offset(first as isize);
+            slice::from_raw_parts_mut(start, n)
+        }
+    }
+
+    /// Number of values allocated so far.
+    pub fn len(&self) -> usize {
+        self.inner.borrow().chunks.iter().map(|c| c.len).sum()
+    }
+}
+
+impl<T, A:Alloc> Arena<T, A> {
+    /// Ends the building phase, turning the arena into a shareable,
+    /// read-only handle. The values keep their addresses.
+    pub fn freeze(self) -> Frozen<T, A> {
+        Frozen { inner: Arc::new(self.inner.into_inner()) }
+    }
+}
+
+/// A read-only, shareable view of a finished arena; see `freeze`.
+pub struct Frozen<T, A:Alloc = DefaultAlloc> {
+    inner: Arc<Inner<T, A>>,
+}
+
+// The values are only ever read through a `Frozen`, and the last
+// handle (on whichever thread) drops them and frees the chunks.
+unsafe impl<T: Send + Sync, A:Alloc + Send> Send for Frozen<T, A> { }
+unsafe impl<T: Send + Sync, A:Alloc + Send> Sync for Frozen<T, A> { }
+
+impl<T, A:Alloc> Clone for Frozen<T, A> {
+    fn clone(&self) -> Self { Frozen { inner: self.inner.clone() } }
+}
+
+impl<T, A:Alloc> Frozen<T, A> {
+    pub fn len(&self) -> usize {
+        self.inner.chunks.iter().map(|c| c.len).sum()
+    }
+
+    /// The `i`th value allocated, counting from 0.
+    pub fn get(&self, mut i: usize) -> Option<&T> {
+        for chunk in &self.inner.chunks {
+            if i < chunk.len { return Some(unsafe { &*chunk.start.offset(i as isize) }); }
+            i -= chunk.len;
+        }
+        None
+    }
+
+    /// Iterates over the values in allocation order.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=&'a T> + 'a> {
+        Box::new(self.inner.chunks.iter().flat_map(|chunk| {
+            (0..chunk.len).map(move |j| unsafe { &*chunk.start.offset(j as isize) })
+        }))
+    }
+}
+
+impl<T, A:Alloc> Inner<T, A> {
+    // free slots in the current chunk
+    fn room(&self) -> usize {
+        self.chunks.last().map_or(0, |c| c.cap - c.len)
+    }
+
+    // Starts a new current chunk with room for at least `min` values.
+    fn grow(&mut self, min: usize) {
+        let elem_size = mem::size_of::<T>();
+        let cap = match self.chunks.last() {
+            Some(c) => c.cap.checked_mul(2).expect("capacity overflow"),
+            None if elem_size == 0 => !0,
+            None => cmp::max(1, INITIAL_BYTES / elem_size),
+        };
+        let cap = cmp::max(cap, min);
+        let start = if elem_size == 0 {
+            Kind::new::<T>().dangling() as *mut T
+        } else {
+            unsafe {
+                let kind = Kind::new::<T>().array(cap);
+                let p = self.alloc.alloc(kind);
+                if p.is_null() { self.alloc.oom() }
+                annotate::no_access(p, kind.size());
+                p as *mut T
+            }
+        };
+        self.chunks.push(Chunk { start: start, cap: cap, len: 0 });
+    }
+
+    // Moves the `n` values from `first` at the end of the current
+    // chunk into a new chunk with room for `more` after them.
+    fn rechunk(&mut self, first: usize, n: usize, more: usize) {
+        self.grow(n.checked_add(more).expect("capacity overflow"));
+        let last = self.chunks.len() - 1;
+        let (from, to) = (self.chunks[last - 1].start, self.chunks[last].start);
+        unsafe {
+            let bytes = mem::size_of::<T>() * n;
+            annotate::undefined(to as *mut u8, bytes);
+            ptr::copy_nonoverlapping(from.offset(first as isize), to, n);
+            annotate::no_access(from.offset(first as isize) as *mut u8, bytes);
+        }
+        self.chunks[last - 1].len -= n;
+        self.chunks[last].len = n;
+    }
+}
+
+impl<T, A:Alloc> Drop for Inner<T, A> {
+    fn drop(&mut self) {
+        for c in &self.chunks {
+            unsafe {
+                for j in 0..c.len {
+                    intrinsics::drop_in_place(c.start.offset(j as isize));
+                }
+                if mem::size_of::<T>() != 0 {
+                    annotate::undefined(c.start as *mut u8, mem::size_of::<T>() * c.cap);
+                    self.alloc.dealloc(c.start as *mut u8, Kind::new::<T>().array(c.cap));
+                }
+            }
+        }
+    }
+}
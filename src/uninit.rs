@@ -0,0 +1,149 @@
+// Storage for a value that may not be initialized yet.
+//
+// `MaybeUninit<T>` has the size and alignment of `T` but never runs
+// `T`'s destructor and carries no validity requirements, so buffers of
+// them can be handed out before anything has been written (for I/O to
+// fill, say) and claimed with `assume_init` afterwards. This replaces
+// `mem::uninitialized` plus `forget`/`transmute` juggling: the type
+// says which memory is not yet a `T`.
+//
+// `ManuallyDrop<T>` is the other half: a value that is initialized but
+// whose destructor the owner runs (or skips) explicitly.
+//
+// `Slot<'a, T>` is what emplacement (`Box::emplace_in`, `Vec::push_with`)
+// hands its closure: a place in allocator memory that can only be
+// given up by initializing it. Writing consumes the slot and yields a
+// `Filled<'a>`; because `'a` is invariant and fresh for each call, the
+// closure cannot return a token from any other slot, so the caller
+// knows the memory holds a `T` once the closure returns.
+
+use std::intrinsics;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+#[allow(unions_with_drop_fields)]
+pub union MaybeUninit<T> {
+    uninit: (),
+    value: T,
+}
+
+impl<T> MaybeUninit<T> {
+    /// An uninitialized value.
+    pub fn uninit() -> MaybeUninit<T> {
+        MaybeUninit { uninit: () }
+    }
+
+    pub fn new(value: T) -> MaybeUninit<T> {
+        MaybeUninit { value: value }
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self as *const MaybeUninit<T> as *const T
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self as *mut MaybeUninit<T> as *mut T
+    }
+
+    /// Initializes the storage (without dropping what was there) and
+    /// returns a reference to the value.
+    pub fn write(&mut self, value: T) -> &mut T {
+        unsafe {
+            ptr::write(self.as_mut_ptr(), value);
+            &mut *self.as_mut_ptr()
+        }
+    }
+
+    /// Extracts the value; the caller must have initialized it.
+    pub unsafe fn assume_init(self) -> T {
+        self.value
+    }
+
+    pub unsafe fn get_ref(&self) -> &T {
+        &*self.as_ptr()
+    }
+
+    pub unsafe fn get_mut(&mut self) -> &mut T {
+        &mut *self.as_mut_ptr()
+    }
+}
+
+/// A `T` whose destructor never runs unless `drop` is called.
+///
+/// Taking a value apart field by field (`ptr::read` each one, then
+/// `forget` the whole) leaves a window in which the fields are owned
+/// twice; wrapping the value first closes it, and replaces the old
+/// drop-flag tricks (`mem::dropped`, `POST_DROP_USIZE`) that marked a
+/// value as already destroyed by scribbling over it.
+#[allow(unions_with_drop_fields)]
+pub union ManuallyDrop<T> {
+    value: T,
+}
+
+impl<T> ManuallyDrop<T> {
+    pub fn new(value: T) -> ManuallyDrop<T> {
+        ManuallyDrop { value: value }
+    }
+
+    pub fn into_inner(slot: ManuallyDrop<T>) -> T {
+        unsafe { slot.value }
+    }
+
+    /// Moves the value out, leaving `slot` logically uninitialized;
+    /// `slot` must not be used again.
+    pub unsafe fn take(slot: &mut ManuallyDrop<T>) -> T {
+        ptr::read(&slot.value)
+    }
+
+    /// Runs the value's destructor in place; `slot` must not be used
+    /// again.
+    pub unsafe fn drop(slot: &mut ManuallyDrop<T>) {
+        intrinsics::drop_in_place(&mut slot.value)
+    }
+}
+
+impl<T> Deref for ManuallyDrop<T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &self.value } }
+}
+
+impl<T> DerefMut for ManuallyDrop<T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut self.value } }
+}
+
+/// Uninitialized memory for one `T` that must be filled; see
+/// `Box::emplace_in`.
+pub struct Slot<'a, T> {
+    ptr: *mut T,
+    _brand: PhantomData<*mut &'a ()>,
+}
+
+/// Proof that the `Slot<'a, _>` was initialized.
+pub struct Filled<'a> {
+    _brand: PhantomData<*mut &'a ()>,
+}
+
+impl<'a, T> Slot<'a, T> {
+    /// Runs `f` on a slot for the memory at `ptr`; returns once the
+    /// memory holds a `T`, or unwinds with it still uninitialized.
+    pub unsafe fn fill<F>(ptr: *mut T, f: F) where F: for<'b> FnOnce(Slot<'b, T>) -> Filled<'b> {
+        f(Slot { ptr: ptr, _brand: PhantomData });
+    }
+
+    pub fn write(self, value: T) -> Filled<'a> {
+        unsafe { ptr::write(self.ptr, value); }
+        Filled { _brand: PhantomData }
+    }
+
+    /// The memory, for initializing the value piecewise.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr
+    }
+
+    /// Declares the value initialized; the caller must have written all
+    /// of it through `as_mut_ptr`.
+    pub unsafe fn assume_init(self) -> Filled<'a> {
+        Filled { _brand: PhantomData }
+    }
+}
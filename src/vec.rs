@@ -0,0 +1,675 @@
+use alloc::{Alloc, AllocError, DefaultAlloc};
+use boxed::Box;
+use raw_vec::{Double, GrowthPolicy, RawVec, Shrink};
+use uninit::{Filled, MaybeUninit, Slot};
+
+use std::fmt;
+use std::intrinsics;
+use std::mem;
+use std::ops::{Deref, DerefMut, InPlace, Place, Placer};
+use std::ptr;
+use std::slice;
+
+/// A contiguous growable array type whose buffer is obtained from
+/// the allocator `A`.
+pub struct Vec<T, A:Alloc = DefaultAlloc, G:GrowthPolicy = Double> {
+    buf: RawVec<T, A, G>,
+    len: usize,
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> Vec<T, A, G> {
+    pub fn new() -> Self where A: Default {
+        Vec { buf: RawVec::new(), len: 0 }
+    }
+
+    /// Creates an empty `Vec` without allocating; usable in constant
+    /// expressions, e.g. `Vec::with_alloc(DefaultAlloc)`.
+    pub const fn with_alloc(a: A) -> Self {
+        Vec { buf: RawVec::with_alloc(a), len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self where A: Default {
+        Vec { buf: RawVec::with_capacity(capacity), len: 0 }
+    }
+
+    pub fn with_capacity_alloc(capacity: usize, a: A) -> Self {
+        Vec { buf: RawVec::with_capacity_alloc(capacity, a), len: 0 }
+    }
+
+    pub unsafe fn from_raw_parts_alloc(ptr: *mut T, length: usize, capacity: usize, a: A) -> Self {
+        Vec { buf: RawVec::from_raw_parts_alloc(ptr, capacity, a), len: length }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.cap()
+    }
+
+    pub fn alloc(&self) -> &A {
+        self.buf.alloc()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub unsafe fn set_len(&mut self, len: usize) {
+        self.len = len;
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.buf.ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.ptr()
+    }
+
+    /// Returns the allocated but unused tail of the buffer, which
+    /// covers everything the allocator actually handed out (see
+    /// `Alloc::usable_size`), not just what was asked for.
+    ///
+    /// After initializing the first `n` slots, call
+    /// `set_len(len() + n)` to claim them.
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        let len = self.len;
+        self.buf.spare_capacity_mut(len)
+    }
+
+    /// The initialized elements and the spare capacity at once, so the
+    /// spare slots can be filled from the elements.
+    pub fn split_at_spare_mut(&mut self) -> (&mut [T], &mut [MaybeUninit<T>]) {
+        let len = self.len;
+        unsafe {
+            let init = slice::from_raw_parts_mut(self.buf.ptr(), len);
+            (init, self.buf.spare_capacity_mut(len))
+        }
+    }
+
+    /// Moves the elements from `at` on into a new vector drawing from
+    /// a clone of this vector's allocator; for a stateful allocator,
+    /// use a handle (`&A`, `Rc<A>`) as the vector's `A`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> Vec<T, A, G> where A: Clone {
+        assert!(at <= self.len, "`at` out of bounds");
+        let n = self.len - at;
+        let mut other = Vec::with_capacity_alloc(n, self.buf.alloc().clone());
+        unsafe {
+            self.set_len(at);
+            ptr::copy_nonoverlapping(self.buf.ptr().offset(at as isize), other.buf.ptr(), n);
+            other.set_len(n);
+        }
+        other
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.buf.reserve(self.len, additional);
+    }
+
+    /// Like `reserve`, but returns the reason instead of panicking or
+    /// aborting when the allocator refuses. The elements are kept
+    /// either way, so a refused growth can be handled and the vector
+    /// used further.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        self.buf.try_reserve(self.len, additional)
+    }
+
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.buf.reserve_exact(self.len, additional);
+    }
+
+    /// Releases unused capacity, unless the growth policy judges the
+    /// savings too small to be worth a reallocation.
+    pub fn shrink_to_fit(&mut self) -> Shrink {
+        self.buf.shrink_to_fit(self.len)
+    }
+
+    /// Gives back the capacity past `len` if the allocator can do so
+    /// without moving the elements; see `RawVec::shrink_keep_ptr`.
+    pub fn shrink_keep_ptr(&mut self) -> Shrink {
+        self.buf.shrink_keep_ptr(self.len)
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        // This will panic or abort if we would allocate > isize::MAX bytes
+        // or if the length increment would overflow for zero-sized types.
+        if self.len == self.buf.cap() { self.buf.double(); }
+        unsafe {
+            let end = self.buf.ptr().offset(self.len as isize);
+            ptr::write(end, value);
+        }
+        self.len += 1;
+    }
+
+    /// Appends an element constructed in place by `init`, which is
+    /// handed the vector's next slot; see `Box::emplace_in`. If `init`
+    /// panics the vector is left as it was.
+    pub fn push_with<F>(&mut self, init: F) where F: for<'a> FnOnce(Slot<'a, T>) -> Filled<'a> {
+        if self.len == self.buf.cap() { self.buf.double(); }
+        unsafe { Slot::fill(self.buf.ptr().offset(self.len as isize), init); }
+        self.len += 1;
+    }
+
+    /// A place at the end of the vector, for the `in` syntax:
+    /// `in v.place_back() { value }` builds `value` directly in the
+    /// spare capacity and yields a reference to it. Prefer `push_with`
+    /// where the placement protocol is unavailable.
+    pub fn place_back(&mut self) -> PlaceBack<T, A, G> {
+        PlaceBack { vec: self }
+    }
+
+    /// Appends a copy of `other`. The buffer grows at most once (in
+    /// place, if the allocator can extend the block) and the elements
+    /// are copied with a single `memcpy`, rather than one `push` each.
+    pub fn extend_from_slice(&mut self, other: &[T]) where T: Copy {
+        let n = other.len();
+        self.reserve(n);
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(),
+                                     self.buf.ptr().offset(self.len as isize), n);
+        }
+        self.len += n;
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            unsafe {
+                self.len -= 1;
+                Some(ptr::read(self.buf.ptr().offset(self.len as isize)))
+            }
+        }
+    }
+
+    /// Inserts `element` at `index`, shifting everything after it to
+    /// the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, element: T) {
+        let len = self.len;
+        assert!(index <= len);
+        if len == self.buf.cap() { self.buf.double(); }
+        unsafe {
+            let p = self.buf.ptr().offset(index as isize);
+            ptr::copy(p, p.offset(1), len - index);
+            ptr::write(p, element);
+        }
+        self.len = len + 1;
+    }
+
+    /// Inserts an element constructed in place by `init` at `index`,
+    /// shifting everything after it to the right; see `push_with`. If
+    /// `init` panics the vector is left as it was.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert_with<F>(&mut self, index: usize, init: F)
+        where F: for<'a> FnOnce(Slot<'a, T>) -> Filled<'a>
+    {
+        let len = self.len;
+        assert!(index <= len);
+        if len == self.buf.cap() { self.buf.double(); }
+        unsafe {
+            let p = self.buf.ptr().offset(index as isize);
+            ptr::copy(p, p.offset(1), len - index);
+            let gap = CloseGap { at: p, tail: len - index };
+            Slot::fill(p, init);
+            mem::forget(gap);
+        }
+        self.len = len + 1;
+    }
+
+    /// Removes and returns the element at `index`, shifting everything
+    /// after it to the left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.len;
+        assert!(index < len);
+        unsafe {
+            let p = self.buf.ptr().offset(index as isize);
+            let ret = ptr::read(p);
+            ptr::copy(p.offset(1), p, len - index - 1);
+            self.len = len - 1;
+            ret
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        unsafe {
+            // drop any extra elements
+            while len < self.len {
+                // decrement len before the drop_in_place(), so a panic on Drop
+                // doesn't re-drop the just-failed value.
+                self.len -= 1;
+                let len = self.len;
+                intrinsics::drop_in_place(self.get_unchecked_mut(len));
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0)
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+
+    /// Keeps only the elements for which `f` returns true, in a single
+    /// pass that moves each survivor at most once.
+    pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&T) -> bool {
+        self.drain_filter(|x| !f(x));
+    }
+
+    /// Removes the elements for which `filter` returns true and yields
+    /// them. Survivors are shifted down over the gap as the iterator
+    /// advances; dropping the iterator early finishes the pass (and
+    /// drops the remaining matches).
+    pub fn drain_filter<F>(&mut self, filter: F) -> DrainFilter<T, A, G, F>
+        where F: FnMut(&mut T) -> bool
+    {
+        let old_len = self.len;
+        // Guard against the vector being observed mid-shift if the
+        // iterator is leaked: until it is done, the vector is empty.
+        unsafe { self.set_len(0); }
+        DrainFilter { vec: self, idx: 0, del: 0, old_len: old_len, pred: filter, panic_flag: false }
+    }
+
+    /// Removes consecutive elements for which `same_bucket(a, b)`
+    /// returns true, where `b` is the element preceding `a` that was
+    /// kept. Runs in linear time.
+    pub fn dedup_by<F>(&mut self, mut same_bucket: F) where F: FnMut(&mut T, &mut T) -> bool {
+        let len = self.len;
+        if len <= 1 { return; }
+
+        // `read` scans every element, `write` is one past the last
+        // kept one; [write, read) is the hole. If `same_bucket` or a
+        // destructor panics, the guard closes the hole.
+        struct Guard<'a, T: 'a, A: Alloc + 'a, G: GrowthPolicy + 'a> {
+            vec: &'a mut Vec<T, A, G>,
+            read: usize,
+            write: usize,
+            len: usize,
+        }
+
+        impl<'a, T, A: Alloc, G: GrowthPolicy> Drop for Guard<'a, T, A, G> {
+            fn drop(&mut self) {
+                unsafe {
+                    let p = self.vec.buf.ptr();
+                    let tail = self.len - self.read;
+                    ptr::copy(p.offset(self.read as isize), p.offset(self.write as isize), tail);
+                    self.vec.set_len(self.write + tail);
+                }
+            }
+        }
+
+        unsafe { self.set_len(0); }
+        let mut g = Guard { vec: self, read: 1, write: 1, len: len };
+        unsafe {
+            let p = g.vec.buf.ptr();
+            while g.read < g.len {
+                let cur = p.offset(g.read as isize);
+                let prev = p.offset(g.write as isize - 1);
+                if same_bucket(&mut *cur, &mut *prev) {
+                    g.read += 1;
+                    intrinsics::drop_in_place(cur);
+                } else {
+                    if g.read != g.write {
+                        ptr::copy_nonoverlapping(cur, p.offset(g.write as isize), 1);
+                    }
+                    g.write += 1;
+                    g.read += 1;
+                }
+            }
+        }
+    }
+
+    /// Moves every element of `other` onto the end of `self`. The
+    /// elements are copied into this vector's buffer and `other`'s
+    /// buffer is returned to its own allocator.
+    pub fn append_from<B:Alloc, H:GrowthPolicy>(&mut self, mut other: Vec<T, B, H>) {
+        let n = other.len;
+        self.reserve(n);
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(),
+                                     self.buf.ptr().offset(self.len as isize), n);
+            // The elements belong to `self` now; dropping `other` only
+            // frees its buffer.
+            other.set_len(0);
+        }
+        self.len += n;
+    }
+
+    /// Moves every element of `other` onto the end of `self`, leaving
+    /// `other` empty. When `self` is empty and both allocators are
+    /// `compatible_with` each other, `other`'s buffer is taken over
+    /// without copying.
+    pub fn append(&mut self, other: &mut Vec<T, A, G>) {
+        if self.len == 0 && self.buf.alloc().compatible_with(other.buf.alloc()) {
+            mem::swap(self, other);
+            return;
+        }
+        let n = other.len;
+        self.reserve(n);
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(),
+                                     self.buf.ptr().offset(self.len as isize), n);
+            other.set_len(0);
+        }
+        self.len += n;
+    }
+
+    /// Moves the elements into a buffer from `b` that just fits them,
+    /// freeing the old one; e.g. to evacuate a vector that must
+    /// outlive the arena it was built in.
+    pub fn rehome_in<B:Alloc>(self, b: B) -> Vec<T, B, G> {
+        let mut v = Vec::with_capacity_alloc(self.len, b);
+        v.append_from(self);
+        v
+    }
+
+    /// Converts the vector into a `Box<[T], A>`, handing the allocator
+    /// over to the box.
+    ///
+    /// Note that this will drop any excess capacity.
+    pub fn into_boxed_slice(mut self) -> Box<[T], A> {
+        unsafe {
+            self.buf.shrink_exact(self.len);
+            let buf = ptr::read(&self.buf);
+            mem::forget(self);
+            buf.into_box()
+        }
+    }
+}
+
+impl<T> Vec<T, DefaultAlloc> {
+    /// Converts into a standard `Vec` without copying; valid because
+    /// `DefaultAlloc` shares the standard heap.
+    pub fn into_std(self) -> ::std::vec::Vec<T> {
+        unsafe {
+            let v = ::std::vec::Vec::from_raw_parts(self.buf.ptr(), self.len, self.buf.cap());
+            mem::forget(self);
+            v
+        }
+    }
+
+    pub fn from_std(mut v: ::std::vec::Vec<T>) -> Self {
+        unsafe {
+            let ret = Vec::from_raw_parts_alloc(v.as_mut_ptr(), v.len(), v.capacity(),
+                                                DefaultAlloc);
+            mem::forget(v);
+            ret
+        }
+    }
+}
+
+// Moves the elements after an empty slot back over it, should
+// `insert_with`'s initializer panic.
+struct CloseGap<T> {
+    at: *mut T,
+    tail: usize,
+}
+
+impl<T> Drop for CloseGap<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::copy(self.at.offset(1), self.at, self.tail); }
+    }
+}
+
+/// The place returned by `Vec::place_back`.
+pub struct PlaceBack<'a, T: 'a, A: Alloc + 'a, G: GrowthPolicy + 'a> {
+    vec: &'a mut Vec<T, A, G>,
+}
+
+impl<'a, T, A:Alloc, G:GrowthPolicy> Placer<T> for PlaceBack<'a, T, A, G> {
+    type Place = PlaceBack<'a, T, A, G>;
+
+    fn make_place(self) -> Self {
+        if self.vec.len == self.vec.buf.cap() { self.vec.buf.double(); }
+        self
+    }
+}
+
+impl<'a, T, A:Alloc, G:GrowthPolicy> Place<T> for PlaceBack<'a, T, A, G> {
+    fn pointer(&mut self) -> *mut T {
+        unsafe { self.vec.buf.ptr().offset(self.vec.len as isize) }
+    }
+}
+
+impl<'a, T, A:Alloc, G:GrowthPolicy> InPlace<T> for PlaceBack<'a, T, A, G> {
+    type Owner = &'a mut T;
+
+    unsafe fn finalize(self) -> &'a mut T {
+        let vec = self.vec;
+        let p = vec.buf.ptr().offset(vec.len as isize);
+        vec.len += 1;
+        &mut *p
+    }
+}
+
+/// The iterator returned by `Vec::drain_filter`.
+pub struct DrainFilter<'a, T: 'a, A: Alloc + 'a, G: GrowthPolicy + 'a, F>
+    where F: FnMut(&mut T) -> bool
+{
+    vec: &'a mut Vec<T, A, G>,
+    // next element to examine
+    idx: usize,
+    // number removed so far; survivors are moved down by this much
+    del: usize,
+    old_len: usize,
+    pred: F,
+    // set while `pred` runs, so a panic in it is not followed by
+    // another call from `drop`
+    panic_flag: bool,
+}
+
+impl<'a, T, A:Alloc, G:GrowthPolicy, F> Iterator for DrainFilter<'a, T, A, G, F>
+    where F: FnMut(&mut T) -> bool
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        unsafe {
+            let p = self.vec.buf.ptr();
+            while self.idx < self.old_len {
+                let i = self.idx;
+                self.panic_flag = true;
+                let remove = (self.pred)(&mut *p.offset(i as isize));
+                self.panic_flag = false;
+                self.idx += 1;
+                if remove {
+                    self.del += 1;
+                    return Some(ptr::read(p.offset(i as isize)));
+                } else if self.del > 0 {
+                    ptr::copy_nonoverlapping(p.offset(i as isize),
+                                             p.offset((i - self.del) as isize), 1);
+                }
+            }
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.old_len - self.idx))
+    }
+}
+
+impl<'a, T, A:Alloc, G:GrowthPolicy, F> Drop for DrainFilter<'a, T, A, G, F>
+    where F: FnMut(&mut T) -> bool
+{
+    fn drop(&mut self) {
+        if !self.panic_flag {
+            for _ in self.by_ref() { }
+        }
+        unsafe {
+            // If the predicate panicked, close the gap over whatever
+            // was not examined (including the element it panicked on)
+            // and keep it.
+            let p = self.vec.buf.ptr();
+            let tail = self.old_len - self.idx;
+            if self.del > 0 && tail > 0 {
+                ptr::copy(p.offset(self.idx as isize),
+                          p.offset((self.idx - self.del) as isize), tail);
+            }
+            self.vec.set_len(self.old_len - self.del);
+        }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> Deref for Vec<T, A, G> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.buf.ptr(), self.len) }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> DerefMut for Vec<T, A, G> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.buf.ptr(), self.len) }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> Extend<T> for Vec<T, A, G> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iterable: I) {
+        let mut iterator = iterable.into_iter();
+        while let Some(element) = iterator.next() {
+            let len = self.len();
+            if len == self.capacity() {
+                let (lower, _) = iterator.size_hint();
+                self.reserve(lower.saturating_add(1));
+            }
+            unsafe {
+                ptr::write(self.get_unchecked_mut(len), element);
+                // NB can't overflow since we would have had to alloc the address space
+                self.set_len(len + 1);
+            }
+        }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> IntoIterator for Vec<T, A, G> {
+    type Item = T;
+    type IntoIter = IntoIter<T, A, G>;
+
+    fn into_iter(self) -> IntoIter<T, A, G> {
+        unsafe {
+            let buf = ptr::read(&self.buf);
+            let len = self.len;
+            mem::forget(self);
+            IntoIter { buf: buf, head: 0, tail: len }
+        }
+    }
+}
+
+impl<T, A:Alloc> IntoIterator for Box<[T], A> {
+    type Item = T;
+    type IntoIter = IntoIter<T, A>;
+
+    fn into_iter(self) -> IntoIter<T, A> {
+        let len = self.len();
+        IntoIter { buf: RawVec::from_box(self), head: 0, tail: len }
+    }
+}
+
+/// A by-value iterator over a `Vec` or `Box<[T], A>`.
+///
+/// The buffer, and with it the allocator, stays alive until the
+/// iterator is dropped; elements that were never yielded are dropped
+/// then, before the buffer goes back to the allocator.
+pub struct IntoIter<T, A:Alloc = DefaultAlloc, G:GrowthPolicy = Double> {
+    buf: RawVec<T, A, G>,
+    // elements in [head, tail) have not been yielded yet
+    head: usize,
+    tail: usize,
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> IntoIter<T, A, G> {
+    /// The elements not yet yielded.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe {
+            slice::from_raw_parts(self.buf.ptr().offset(self.head as isize),
+                                  self.tail - self.head)
+        }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> Iterator for IntoIter<T, A, G> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.head == self.tail { return None; }
+        unsafe {
+            let p = self.buf.ptr().offset(self.head as isize);
+            self.head += 1;
+            Some(ptr::read(p))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.tail - self.head;
+        (n, Some(n))
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> DoubleEndedIterator for IntoIter<T, A, G> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.head == self.tail { return None; }
+        unsafe {
+            self.tail -= 1;
+            Some(ptr::read(self.buf.ptr().offset(self.tail as isize)))
+        }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> ExactSizeIterator for IntoIter<T, A, G> { }
+
+impl<T, A:Alloc, G:GrowthPolicy> Drop for IntoIter<T, A, G> {
+    fn drop(&mut self) {
+        // drop whatever was not yielded; RawVec handles deallocation.
+        // Dropping the rest as one slice keeps going past a panicking
+        // element instead of leaking everything after it.
+        unsafe {
+            let rest = slice::from_raw_parts_mut(self.buf.ptr().offset(self.head as isize),
+                                                 self.tail - self.head);
+            self.head = self.tail;
+            intrinsics::drop_in_place(rest as *mut [T]);
+        }
+    }
+}
+
+impl<T, A:Alloc, G:GrowthPolicy> Drop for Vec<T, A, G> {
+    fn drop(&mut self) {
+        unsafe {
+            // As one slice, so a panicking element does not keep
+            // the ones after it from being dropped.
+            intrinsics::drop_in_place(&mut self[..] as *mut [T]);
+        }
+        // RawVec handles deallocation
+    }
+}
+
+impl<T: fmt::Debug, A:Alloc, G:GrowthPolicy> fmt::Debug for Vec<T, A, G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
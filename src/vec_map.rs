@@ -0,0 +1,202 @@
+// Sorted-vector maps and sets.
+//
+// `VecMap` keeps its entries in a single `Vec<(K, V), A>` ordered by
+// key, and finds them by binary search. Lookups touch a contiguous
+// buffer and there is one allocation for the whole map, which for the
+// small maps typical of arena workloads beats a BTree's node-per-few-
+// entries layout. Insertion and removal shift the tail, so they are
+// O(n); build large maps with `from_sorted_iter_in` instead of
+// repeated `insert`.
+//
+// `VecSet` is a `VecMap` with `()` values.
+
+use alloc::{Alloc, DefaultAlloc};
+use vec::Vec;
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::mem;
+use std::slice;
+
+pub struct VecMap<K: Ord, V, A:Alloc = DefaultAlloc> {
+    entries: Vec<(K, V), A>,
+}
+
+impl<K: Ord, V, A:Alloc> VecMap<K, V, A> {
+    pub fn new() -> Self where A: Default {
+        VecMap { entries: Vec::new() }
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        VecMap { entries: Vec::with_alloc(a) }
+    }
+
+    pub fn with_capacity_alloc(capacity: usize, a: A) -> Self {
+        VecMap { entries: Vec::with_capacity_alloc(capacity, a) }
+    }
+
+    /// Builds a map from an iterator that yields keys in strictly
+    /// ascending order, in a single pass with no searching or shifting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not strictly ascending.
+    pub fn from_sorted_iter_in<I>(iter: I, a: A) -> Self
+        where I: IntoIterator<Item=(K, V)>
+    {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        let mut entries: Vec<(K, V), A> = Vec::with_capacity_alloc(lower, a);
+        for (k, v) in iter {
+            if let Some(last) = entries.last() {
+                assert!(last.0 < k, "from_sorted_iter_in: keys are not strictly ascending");
+            }
+            entries.push((k, v));
+        }
+        VecMap { entries: entries }
+    }
+
+    fn search<Q: ?Sized>(&self, key: &Q) -> Result<usize, usize> where K: Borrow<Q>, Q: Ord {
+        self.entries.binary_search_by(|e| e.0.borrow().cmp(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V> where K: Borrow<Q>, Q: Ord {
+        match self.search(key) {
+            Ok(i) => Some(&self.entries[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q>, Q: Ord {
+        match self.search(key) {
+            Ok(i) => Some(&mut self.entries[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: Ord {
+        self.search(key).is_ok()
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.search(&key) {
+            Ok(i) => Some(mem::replace(&mut self.entries[i].1, value)),
+            Err(i) => { self.entries.insert(i, (key, value)); None }
+        }
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: Ord {
+        match self.search(key) {
+            Ok(i) => Some(self.entries.remove(i).1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    /// Iterates over the entries in ascending key order.
+    pub fn iter(&self) -> Iter<K, V> {
+        Iter { inner: self.entries.iter() }
+    }
+
+    pub fn keys<'a>(&'a self) -> Box<Iterator<Item=&'a K> + 'a> {
+        Box::new(self.entries.iter().map(|e| &e.0))
+    }
+
+    pub fn values<'a>(&'a self) -> Box<Iterator<Item=&'a V> + 'a> {
+        Box::new(self.entries.iter().map(|e| &e.1))
+    }
+}
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next().map(|e| (&e.0, &e.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug, A:Alloc> fmt::Debug for VecMap<K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{:?}: {:?}", k, v));
+        }
+        write!(f, "}}")
+    }
+}
+
+pub struct VecSet<T: Ord, A:Alloc = DefaultAlloc> {
+    map: VecMap<T, (), A>,
+}
+
+impl<T: Ord, A:Alloc> VecSet<T, A> {
+    pub fn new() -> Self where A: Default {
+        VecSet { map: VecMap::new() }
+    }
+
+    pub fn with_alloc(a: A) -> Self {
+        VecSet { map: VecMap::with_alloc(a) }
+    }
+
+    /// Builds a set from strictly ascending elements; see
+    /// `VecMap::from_sorted_iter_in`.
+    pub fn from_sorted_iter_in<I>(iter: I, a: A) -> Self where I: IntoIterator<Item=T> {
+        VecSet { map: VecMap::from_sorted_iter_in(iter.into_iter().map(|t| (t, ())), a) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool where T: Borrow<Q>, Q: Ord {
+        self.map.contains_key(value)
+    }
+
+    /// Adds `value`, returning false if it was already present.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool where T: Borrow<Q>, Q: Ord {
+        self.map.remove(value).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=&'a T> + 'a> {
+        self.map.keys()
+    }
+}
+
+impl<T: Ord + fmt::Debug, A:Alloc> fmt::Debug for VecSet<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,190 @@
+// A debugging wrapper that checks every `dealloc`/`realloc` against
+// the `Kind` its pointer was allocated with.
+//
+// Because `Alloc` asks callers to hand the `Kind` back at
+// deallocation time, a collection that computes the wrong layout
+// (or frees twice) otherwise goes unnoticed until the heap is
+// corrupted. This wrapper panics at the offending call instead. It
+// also checks that the inner allocator honors each requested
+// alignment.
+//
+// A block may be returned with any size between the one requested and
+// the allocator's `usable_size` for it, as the `Alloc` contract allows.
+//
+// Zero-sized kinds are passed through unchecked: allocators
+// commonly return the same sentinel address for all of them.
+//
+// Optionally (see `set_fills`) the wrapper also fills fresh memory
+// with a junk byte and freed memory with a poison byte, so that a
+// collection reading an element it never wrote, or one it already
+// dropped, sees the same garbage on every run and fails the same way,
+// instead of reading whatever the previous owner left behind.
+
+use alloc::{self, Address, AllocError, Capacity, Kind, Size};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ptr;
+use std::rc::Rc;
+
+/// Junk byte for freshly allocated memory.
+pub const DEFAULT_JUNK: u8 = 0xA5;
+/// Poison byte for freed memory (as in `quarantine`).
+pub const DEFAULT_POISON: u8 = 0xDE;
+
+struct State<A> {
+    inner: RefCell<A>,
+    live: RefCell<HashMap<usize, Kind>>,
+    freed: RefCell<HashSet<usize>>,
+    junk: Cell<Option<u8>>,
+    poison: Cell<Option<u8>>,
+}
+
+#[derive(Clone)]
+pub struct Alloc<A> {
+    state: Rc<State<A>>,
+}
+
+impl<A: alloc::Alloc> Alloc<A> {
+    pub fn new(inner: A) -> Alloc<A> {
+        Alloc {
+            state: Rc::new(State {
+                inner: RefCell::new(inner),
+                live: RefCell::new(HashMap::new()),
+                freed: RefCell::new(HashSet::new()),
+                junk: Cell::new(None),
+                poison: Cell::new(None),
+            })
+        }
+    }
+
+    /// Number of allocations currently outstanding.
+    pub fn live_count(&self) -> usize {
+        self.state.live.borrow().len()
+    }
+
+    /// Sets the byte written over newly allocated memory (including
+    /// the part a `realloc` adds) and the byte written over memory
+    /// being freed; `None` leaves it untouched. Both are off by
+    /// default, and the setting is shared by all clones of the handle,
+    /// so it can be flipped at runtime around a suspect section.
+    pub fn set_fills(&self, junk: Option<u8>, poison: Option<u8>) {
+        self.state.junk.set(junk);
+        self.state.poison.set(poison);
+    }
+
+    /// `set_fills(Some(DEFAULT_JUNK), Some(DEFAULT_POISON))`.
+    pub fn enable_default_fills(&self) {
+        self.set_fills(Some(DEFAULT_JUNK), Some(DEFAULT_POISON));
+    }
+}
+
+impl<A: alloc::Alloc> State<A> {
+    unsafe fn fill(&self, pattern: &Cell<Option<u8>>, p: Address, from: usize, to: usize) {
+        if let Some(b) = pattern.get() {
+            if !p.is_null() && to > from {
+                ptr::write_bytes(p.offset(from as isize), b, to - from);
+            }
+        }
+    }
+
+    fn record(&self, p: Address, kind: Kind) {
+        if p.is_null() || kind.size() == 0 { return; }
+        if p as usize % kind.align() != 0 {
+            panic!("verify: allocator returned 0x{:x} for {}, which is not aligned",
+                   p as usize, kind);
+        }
+        self.freed.borrow_mut().remove(&(p as usize));
+        self.live.borrow_mut().insert(p as usize, kind);
+    }
+
+    fn check_and_release(&self, what: &str, p: Address, kind: Kind) {
+        if kind.size() == 0 { return; }
+        let addr = p as usize;
+        match self.live.borrow_mut().remove(&addr) {
+            Some(k) => {
+                let usable = unsafe { self.inner.borrow().usable_size(k) };
+                if k.align() != kind.align() || kind.size() < k.size() || kind.size() > usable {
+                    panic!("verify: {} of 0x{:x} with {}, but it was allocated with {}",
+                           what, addr, kind, k);
+                }
+            }
+            None => {
+                if self.freed.borrow().contains(&addr) {
+                    panic!("verify: {} of 0x{:x} ({}), which was already freed",
+                           what, addr, kind);
+                } else {
+                    panic!("verify: {} of 0x{:x} ({}), which was never allocated",
+                           what, addr, kind);
+                }
+            }
+        }
+        self.freed.borrow_mut().insert(addr);
+    }
+}
+
+impl<A: alloc::Alloc> alloc::Alloc for Alloc<A> {
+    fn compatible_with(&self, other: &Self) -> bool {
+        &*self.state as *const State<A> == &*other.state as *const State<A>
+    }
+
+    unsafe fn alloc(&mut self, kind: Kind) -> Address {
+        let p = self.state.inner.borrow_mut().alloc(kind);
+        self.state.record(p, kind);
+        self.state.fill(&self.state.junk, p, 0, kind.size());
+        p
+    }
+
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) {
+        self.state.check_and_release("dealloc", ptr, kind);
+        self.state.fill(&self.state.poison, ptr, 0, kind.size());
+        self.state.inner.borrow_mut().dealloc(ptr, kind)
+    }
+
+    unsafe fn dealloc_hot(&mut self, ptr: Address, kind: Kind) {
+        self.state.check_and_release("dealloc_hot", ptr, kind);
+        self.state.fill(&self.state.poison, ptr, 0, kind.size());
+        self.state.inner.borrow_mut().dealloc_hot(ptr, kind)
+    }
+
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity {
+        self.state.inner.borrow().usable_size(kind)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.state.check_and_release("grow_in_place", ptr, kind);
+        let r = self.state.inner.borrow_mut().grow_in_place(ptr, kind, new_size);
+        match r {
+            Ok(()) => {
+                self.state.record(ptr, Kind::from_size_align(new_size, kind.align()));
+                self.state.fill(&self.state.junk, ptr, kind.size(), new_size);
+            }
+            Err(_) => self.state.record(ptr, kind),
+        }
+        r
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Result<(), AllocError> {
+        self.state.check_and_release("shrink_in_place", ptr, kind);
+        // The released tail is not poisoned: it may already be unmapped.
+        let r = self.state.inner.borrow_mut().shrink_in_place(ptr, kind, new_size);
+        match r {
+            Ok(()) => self.state.record(ptr, Kind::from_size_align(new_size, kind.align())),
+            Err(_) => self.state.record(ptr, kind),
+        }
+        r
+    }
+
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.state.check_and_release("realloc", ptr, kind);
+        let p = self.state.inner.borrow_mut().realloc(ptr, kind, new_size);
+        if p.is_null() {
+            // the old block is still live
+            self.state.record(ptr, kind);
+        } else {
+            self.state.record(p, Kind::from_size_align(new_size, kind.align()));
+            self.state.fill(&self.state.junk, p, kind.size(), new_size);
+        }
+        p
+    }
+}
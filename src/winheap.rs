@@ -0,0 +1,118 @@
+// An allocator drawing from a private Windows heap.
+//
+// `Alloc::new` creates a heap with HeapCreate; every block comes from
+// HeapAlloc on it, and dropping the allocator calls HeapDestroy, which
+// releases the whole heap at once, including any blocks still live.
+// That makes it the Windows counterpart of the mmap-backed regions:
+// a subsystem can keep all of its allocations in one heap and tear it
+// down in a single call.
+//
+// HeapAlloc only guarantees MEMORY_ALLOCATION_ALIGNMENT (8 or 16
+// bytes). Kinds aligned beyond that are over-allocated by their
+// alignment and the pointer HeapAlloc returned is stored in the word
+// just before the aligned block, to be found again at `dealloc`.
+//
+// Windows heaps are serialized internally, so the allocator serves
+// through `&self` (`ShareAlloc`) and may be shared across threads.
+
+use alloc::{self, Address, Capacity, Kind, ShareAlloc, Size};
+
+use libc::{c_void, size_t};
+
+use std::cmp;
+use std::ptr;
+
+type Handle = *mut c_void;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn HeapCreate(options: u32, initial_size: size_t, maximum_size: size_t) -> Handle;
+    fn HeapDestroy(heap: Handle) -> i32;
+    fn HeapAlloc(heap: Handle, flags: u32, bytes: size_t) -> *mut c_void;
+    fn HeapReAlloc(heap: Handle, flags: u32, mem: *mut c_void, bytes: size_t) -> *mut c_void;
+    fn HeapFree(heap: Handle, flags: u32, mem: *mut c_void) -> i32;
+}
+
+#[cfg(target_pointer_width = "64")]
+const MIN_ALIGN: usize = 16;
+#[cfg(target_pointer_width = "32")]
+const MIN_ALIGN: usize = 8;
+
+pub struct Alloc {
+    heap: Handle,
+}
+
+unsafe impl Send for Alloc { }
+unsafe impl Sync for Alloc { }
+
+impl Alloc {
+    /// Creates a growable private heap; returns `None` if Windows
+    /// refuses.
+    pub fn new() -> Option<Alloc> {
+        Alloc::with_limit(0)
+    }
+
+    /// Creates a private heap that never grows past `max_bytes` (or
+    /// without limit if it is 0).
+    pub fn with_limit(max_bytes: usize) -> Option<Alloc> {
+        let heap = unsafe { HeapCreate(0, 0, max_bytes) };
+        if heap.is_null() { None } else { Some(Alloc { heap: heap }) }
+    }
+
+    fn over_aligned(kind: Kind) -> bool {
+        kind.align() > MIN_ALIGN
+    }
+}
+
+impl Drop for Alloc {
+    fn drop(&mut self) {
+        unsafe { HeapDestroy(self.heap); }
+    }
+}
+
+impl ShareAlloc for Alloc {
+    unsafe fn alloc_shared(&self, kind: Kind) -> Address {
+        if kind.is_zero_sized() { return kind.dangling(); }
+        if !Alloc::over_aligned(kind) {
+            return HeapAlloc(self.heap, 0, kind.size()) as Address;
+        }
+        let raw = HeapAlloc(self.heap, 0, kind.size() + kind.align()) as Address;
+        if raw.is_null() { return raw; }
+        // at least MIN_ALIGN bytes past `raw`, leaving room for the header
+        let aligned = ((raw as usize + kind.align()) & !(kind.align() - 1)) as Address;
+        *(aligned as *mut Address).offset(-1) = raw;
+        aligned
+    }
+
+    unsafe fn dealloc_shared(&self, ptr: Address, kind: Kind) {
+        if kind.is_zero_sized() { return; }
+        let raw = if Alloc::over_aligned(kind) {
+            *(ptr as *mut Address).offset(-1)
+        } else {
+            ptr
+        };
+        HeapFree(self.heap, 0, raw as *mut c_void);
+    }
+
+    unsafe fn realloc_shared(&self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        if kind.is_zero_sized() || new_size == 0 || Alloc::over_aligned(kind) {
+            let new_ptr = self.alloc_shared(Kind::from_size_align(new_size, kind.align()));
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(kind.size(), new_size));
+                self.dealloc_shared(ptr, kind);
+            }
+            return new_ptr;
+        }
+        HeapReAlloc(self.heap, 0, ptr as *mut c_void, new_size) as Address
+    }
+}
+
+impl alloc::Alloc for Alloc {
+    unsafe fn alloc(&mut self, kind: Kind) -> Address { self.alloc_shared(kind) }
+    unsafe fn dealloc(&mut self, ptr: Address, kind: Kind) { self.dealloc_shared(ptr, kind) }
+    unsafe fn usable_size(&self, kind: Kind) -> Capacity { self.usable_size_shared(kind) }
+    unsafe fn realloc(&mut self, ptr: Address, kind: Kind, new_size: Size) -> Address {
+        self.realloc_shared(ptr, kind, new_size)
+    }
+}
+